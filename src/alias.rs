@@ -0,0 +1,174 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use crate::input;
+use crate::logger;
+
+// Maps alternative names to a canonical name, e.g. `XBT` to `BTC`
+pub struct AliasMap {
+    // what is named, e.g. `currency`, used when logging
+    kind: String,
+    aliases: HashMap<String, String>,
+    // aliases which were already logged when first applied
+    logged: RefCell<HashSet<String>>
+}
+
+impl AliasMap {
+    pub fn new(kind: &str) -> AliasMap {
+        AliasMap {
+            kind: kind.to_string(), aliases: HashMap::new(), logged: RefCell::new(HashSet::new())
+        }
+    }
+
+    /// Parse an alias map where each line is `<alias> <canonical_name>`
+    pub fn parse(kind: &str, content: &str) -> Result<AliasMap, String> {
+        let mut alias_map = AliasMap::new(kind);
+        for line in input::content_lines(content) {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.len() != 2 {
+                return Err(format!("Invalid {} alias \"{}\"", kind, line));
+            }
+            alias_map.aliases.insert(tokens[0].to_string(), tokens[1].to_string());
+        }
+
+        // Aliases are resolved once, so a canonical name cannot be an alias itself
+        for canonical_name in alias_map.aliases.values() {
+            if alias_map.aliases.contains_key(canonical_name) {
+                return Err(format!("Canonical {} {} is also an alias", kind, canonical_name));
+            }
+        }
+        Ok(alias_map)
+    }
+
+    // Return the canonical name of `name`, or `name` itself if it is not an alias
+    // The first time an alias is resolved, the decision is logged
+    pub fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        match self.aliases.get(name) {
+            Some(canonical_name) => {
+                if self.logged.borrow_mut().insert(name.to_string()) {
+                    logger::log(&format!("Normalizing {} {} to {}", self.kind, name, canonical_name));
+                }
+                canonical_name
+            },
+            None => name
+        }
+    }
+}
+
+// Orientation in which pairs of currencies are quoted, e.g. `BTC USD` for the price of BTC in USD, so that price
+// updates of feeds quoting USD/BTC are inverted rather than recorded as a second, contradicting quote of the pair
+pub struct CanonicalPairs {
+    // (base currency, quote currency)
+    pairs: HashSet<(String, String)>,
+    // inverted pairs which were already logged when first inverted
+    logged: RefCell<HashSet<(String, String)>>
+}
+
+impl CanonicalPairs {
+    pub fn new() -> CanonicalPairs {
+        CanonicalPairs { pairs: HashSet::new(), logged: RefCell::new(HashSet::new()) }
+    }
+
+    /// Parse canonical pairs where each line is `<base_currency> <quote_currency>`, normalized with `aliases`
+    pub fn parse(content: &str, aliases: &Aliases) -> Result<CanonicalPairs, String> {
+        let mut canonical_pairs = CanonicalPairs::new();
+        for line in input::content_lines(content) {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.len() != 2 || tokens[0] == tokens[1] {
+                return Err(format!("Invalid canonical pair \"{}\"", line));
+            }
+            let (base, quote) = (aliases.currency(tokens[0]).to_string(), aliases.currency(tokens[1]).to_string());
+            if canonical_pairs.pairs.contains(&(quote.clone(), base.clone())) {
+                return Err(format!("Canonical pair {}/{} is also listed as {}/{}", quote, base, base, quote));
+            }
+            canonical_pairs.pairs.insert((base, quote));
+        }
+        Ok(canonical_pairs)
+    }
+
+    // Whether quotes of `source_currency` in `dest_currency` go against the canonical orientation of the pair
+    // The first time a pair is inverted, the decision is logged
+    pub fn is_inverted(&self, source_currency: &str, dest_currency: &str) -> bool {
+        let canonical_pair = (dest_currency.to_string(), source_currency.to_string());
+        if !self.pairs.contains(&canonical_pair) {
+            return false;
+        }
+        if self.logged.borrow_mut().insert(canonical_pair) {
+            logger::log(&format!("Inverting {}/{} quotes to {}/{}", source_currency, dest_currency, dest_currency,
+                source_currency));
+        }
+        true
+    }
+}
+
+impl Default for CanonicalPairs {
+    fn default() -> Self {
+        CanonicalPairs::new()
+    }
+}
+
+// Aliases applied to the names read from input, and the canonical orientation of pairs of currencies
+pub struct Aliases {
+    currencies: AliasMap,
+    exchanges: AliasMap,
+    canonical_pairs: CanonicalPairs
+}
+
+impl Aliases {
+    pub fn new(currencies: AliasMap, exchanges: AliasMap) -> Aliases {
+        Aliases {
+            currencies, exchanges, canonical_pairs: CanonicalPairs::new()
+        }
+    }
+
+    pub fn set_canonical_pairs(&mut self, canonical_pairs: CanonicalPairs) {
+        self.canonical_pairs = canonical_pairs;
+    }
+
+    pub fn none() -> Aliases {
+        Aliases::new(AliasMap::new("currency"), AliasMap::new("exchange"))
+    }
+
+    pub fn currency<'a>(&'a self, currency: &'a str) -> &'a str {
+        self.currencies.resolve(currency)
+    }
+
+    pub fn exchange<'a>(&'a self, exchange: &'a str) -> &'a str {
+        self.exchanges.resolve(exchange)
+    }
+
+    pub fn is_inverted(&self, source_currency: &str, dest_currency: &str) -> bool {
+        self.canonical_pairs.is_inverted(source_currency, dest_currency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_aliases() {
+        let alias_map = AliasMap::parse("currency", "XBT BTC\nUST USDT\n").unwrap();
+        assert_eq!(alias_map.resolve("XBT"), "BTC");
+        assert_eq!(alias_map.resolve("BTC"), "BTC");
+        assert_eq!(alias_map.resolve("ETH"), "ETH");
+    }
+
+    #[test]
+    fn reject_invalid_aliases() {
+        assert!(AliasMap::parse("currency", "XBT").is_err());
+        assert!(AliasMap::parse("currency", "XBT BTC\nBTC XXBT").is_err());
+    }
+
+    #[test]
+    fn invert_against_canonical_pairs() {
+        let aliases = Aliases::new(AliasMap::parse("currency", "XBT BTC\n").unwrap(), AliasMap::new("exchange"));
+        let canonical_pairs = CanonicalPairs::parse("XBT USD\nETH BTC\n", &aliases).unwrap();
+        assert!(canonical_pairs.is_inverted("USD", "BTC"));
+        assert!(!canonical_pairs.is_inverted("BTC", "USD"));
+        assert!(canonical_pairs.is_inverted("BTC", "ETH"));
+        assert!(!canonical_pairs.is_inverted("USD", "ETH"));
+        assert!(CanonicalPairs::parse("BTC USD\nUSD BTC\n", &aliases).is_err());
+        assert!(CanonicalPairs::parse("BTC\n", &aliases).is_err());
+    }
+}
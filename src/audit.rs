@@ -0,0 +1,334 @@
+// Tamper-evident audit trail: every accepted price update and every issued answer is appended to a log as a record
+// chained to the previous one by a SHA-256 hash, so editing, inserting, removing or reordering records breaks the chain
+// Each line is `<hash> <sequence> <kind> [<text>]`, where `<hash>` is the hash of the previous line's hash followed by
+// ` <sequence> <kind> [<text>]`. The first record is chained to `GENESIS_HASH`
+
+use crate::bus::{Event, Subscriber};
+
+#[cfg(feature = "audit")]
+pub use self::chain::{graph_hash, read_records, verify, verify_file, AuditLog};
+#[cfg(not(feature = "audit"))]
+pub use self::unsupported::{graph_hash, read_records, verify, verify_file, AuditLog};
+
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+// An accepted price update, as its input line
+pub const UPDATE_RECORD: &str = "UPDATE";
+// An answered request, as its input line
+pub const REQUEST_RECORD: &str = "REQUEST";
+// The answer issued for the request recorded before it, formatted by `format_answer`
+pub const ANSWER_RECORD: &str = "ANSWER";
+// Appended when a run closes the log, with the `graph_hash` of the graph at the end of the run. A log that does not
+// end with it may have been truncated
+pub const END_RECORD: &str = "END";
+// Appended when a run continues a log whose previous run did not close it
+pub const RESUME_RECORD: &str = "RESUME";
+
+// An answer on one line, with its lines separated by `\n`
+pub fn format_answer(answer: &str) -> String {
+    answer.trim_end_matches('\n').replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+pub struct AuditRecord {
+    kind: String,
+    text: Option<String>
+}
+
+impl AuditRecord {
+    pub fn get_kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn get_text(&self) -> Option<&str> {
+        self.text.as_deref()
+    }
+}
+
+// What verifying a log found
+pub struct AuditSummary {
+    num_records: u64,
+    last_hash: String,
+    // runs that did not close the log, e.g. because they were killed
+    num_unclosed_runs: u64,
+    closed: bool
+}
+
+impl AuditSummary {
+    pub fn get_num_records(&self) -> u64 {
+        self.num_records
+    }
+
+    // Hash of the last record, which can be kept elsewhere to also detect records removed from the end
+    pub fn get_last_hash(&self) -> &str {
+        &self.last_hash
+    }
+
+    pub fn get_num_unclosed_runs(&self) -> u64 {
+        self.num_unclosed_runs
+    }
+
+    // Whether the log ends with an `END` record
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+}
+
+// Price updates and answers are recorded as they are published, the log is closed by the run
+impl Subscriber for AuditLog {
+    fn handle(&mut self, event: &Event) -> Result<(), String> {
+        let recorded = match event {
+            Event::Price(price_update) => self.record_update(price_update),
+            Event::Query(exchange_rate_request, answer, _) => self.record_answer(exchange_rate_request, answer),
+            _ => Ok(())
+        };
+        recorded.map_err(|e| format!("Error encountered while writing audit log: {}", e))
+    }
+}
+
+#[cfg(feature = "audit")]
+mod chain {
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, Write};
+
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::model::{ExchangeRateRequest, GraphResult, PriceUpdate};
+
+    fn to_hex(digest: &[u8]) -> String {
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn hash(previous_hash: &str, record: &str) -> String {
+        to_hex(&Sha256::new().chain_update(previous_hash).chain_update(" ").chain_update(record).finalize())
+    }
+
+    // SHA-256 of every edge of `graph_result` in vertex order, with the exact bits of its weight, so two graphs
+    // built from the same quotes have the same hash only if they are identical
+    pub fn graph_hash(graph_result: &GraphResult) -> String {
+        let mut edges: Vec<_> = graph_result.edges().collect();
+        edges.sort_by(|(from_a, to_a, _), (from_b, to_b, _)| (from_a, to_a).cmp(&(from_b, to_b)));
+        let mut hasher = Sha256::new();
+        for (from_vertex, to_vertex, edge) in edges {
+            // Edges between exchanges are stamped with the time they were added rather than the time of a quote
+            let last_updated = match from_vertex.get_exchange() == to_vertex.get_exchange() {
+                true => edge.get_last_updated(),
+                false => 0
+            };
+            hasher.update(format!("{} {} {:016x} {} {}\n", from_vertex, to_vertex, edge.get_weight().to_bits(),
+                last_updated, edge.is_derived()));
+        }
+        to_hex(&hasher.finalize())
+    }
+
+    // Check every record of the log `content`, failing at the first line that breaks the chain
+    pub fn verify(content: &str) -> Result<AuditSummary, String> {
+        check(content, |_, _| ())
+    }
+
+    // The records of the log `content`, once checked like `verify`
+    pub fn read_records(content: &str) -> Result<Vec<AuditRecord>, String> {
+        let mut records = Vec::new();
+        check(content, |kind, text| records.push(AuditRecord { kind: kind.to_string(), text: text.map(str::to_string) }))?;
+        Ok(records)
+    }
+
+    fn check<F: FnMut(&str, Option<&str>)>(content: &str, mut record_checked: F) -> Result<AuditSummary, String> {
+        let mut summary = AuditSummary {
+            num_records: 0, last_hash: GENESIS_HASH.to_string(), num_unclosed_runs: 0, closed: false
+        };
+        for (index, line) in content.lines().enumerate() {
+            let error = |reason: &str| format!("line {}: {}", index + 1, reason);
+            let (line_hash, record) = line.split_once(' ').ok_or_else(|| error("not a record"))?;
+            let mut tokens = record.splitn(3, ' ');
+            let sequence = tokens.next().and_then(|sequence| sequence.parse::<u64>().ok());
+            if sequence != Some(summary.num_records + 1) {
+                return Err(error(&format!("expected record {}, records were removed, added or reordered",
+                    summary.num_records + 1)));
+            }
+            if hash(&summary.last_hash, record) != line_hash {
+                return Err(error("hash does not match, the record or one before it was modified"));
+            }
+            let kind = match tokens.next() {
+                Some(RESUME_RECORD) => {
+                    summary.num_unclosed_runs += 1;
+                    RESUME_RECORD
+                },
+                Some(kind @ UPDATE_RECORD) | Some(kind @ REQUEST_RECORD) | Some(kind @ ANSWER_RECORD)
+                    | Some(kind @ END_RECORD) => kind,
+                _ => return Err(error("unknown record kind"))
+            };
+            record_checked(kind, tokens.next());
+            summary.closed = kind == END_RECORD;
+            summary.num_records += 1;
+            summary.last_hash = line_hash.to_string();
+        }
+        Ok(summary)
+    }
+
+    pub fn verify_file(file_name: &str) -> Result<AuditSummary, String> {
+        let content = fs::read_to_string(file_name)
+            .map_err(|e| format!("Error encountered while reading audit log {}: {}", file_name, e))?;
+        verify(&content).map_err(|e| format!("Audit log {} is not intact at {}", file_name, e))
+    }
+
+    // Appends records to an audit log, writing each one as it is made
+    pub struct AuditLog {
+        file: File,
+        sequence: u64,
+        last_hash: String
+    }
+
+    impl AuditLog {
+        // Open the log at `file_name` to continue its chain, creating it if needed. An existing log must be intact
+        pub fn open(file_name: &str) -> Result<AuditLog, String> {
+            let summary = match fs::metadata(file_name) {
+                Ok(_) => Some(verify_file(file_name)?),
+                Err(_) => None
+            };
+            let file = OpenOptions::new().create(true).append(true).open(file_name)
+                .map_err(|e| format!("Error encountered while opening audit log {}: {}", file_name, e))?;
+            let mut audit_log = AuditLog { file, sequence: 0, last_hash: GENESIS_HASH.to_string() };
+            if let Some(summary) = summary {
+                audit_log.sequence = summary.num_records;
+                audit_log.last_hash = summary.last_hash;
+                if summary.num_records > 0 && !summary.closed {
+                    audit_log.append(RESUME_RECORD, None)
+                        .map_err(|e| format!("Error encountered while writing audit log {}: {}", file_name, e))?;
+                }
+            }
+            Ok(audit_log)
+        }
+
+        fn append(&mut self, kind: &str, text: Option<&str>) -> io::Result<()> {
+            self.sequence += 1;
+            let record = match text {
+                Some(text) => format!("{} {} {}", self.sequence, kind, text),
+                None => format!("{} {}", self.sequence, kind)
+            };
+            let record_hash = hash(&self.last_hash, &record);
+            self.file.write_all(format!("{} {}\n", record_hash, record).as_bytes())?;
+            self.last_hash = record_hash;
+            Ok(())
+        }
+
+        pub fn record_update(&mut self, price_update: &PriceUpdate) -> io::Result<()> {
+            self.append(UPDATE_RECORD, Some(&price_update.to_string()))
+        }
+
+        pub fn record_answer(&mut self, exchange_rate_request: &ExchangeRateRequest, answer: &str) -> io::Result<()> {
+            self.append(REQUEST_RECORD, Some(&exchange_rate_request.to_string()))?;
+            self.append(ANSWER_RECORD, Some(&format_answer(answer)))
+        }
+
+        // Append the `END` record with the hash of `graph_result`, and make sure every record is on disk
+        pub fn close(mut self, graph_result: &GraphResult) -> io::Result<()> {
+            self.append(END_RECORD, Some(&graph_hash(graph_result)))?;
+            self.file.sync_all()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::testutil::{quote, request, EngineBuilder};
+
+        #[test]
+        fn detect_tampering() {
+            let file_name = std::env::temp_dir().join(format!("audit-test-{}.log", std::process::id()));
+            let file_name = file_name.to_str().unwrap();
+            let _ = fs::remove_file(file_name);
+
+            let mut audit_log = AuditLog::open(file_name).unwrap();
+            audit_log.record_update(&quote(1509529343000, "KRAKEN", "BTC", "USD", 1000.0, 0.0009)).unwrap();
+            audit_log.record_answer(&request("KRAKEN", "BTC", "KRAKEN", "USD"),
+                "BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 1000 VERSION 1\nBEST_RATES_END\n").unwrap();
+            drop(audit_log);
+            // A run that did not close the log is continued with a `RESUME` record
+            AuditLog::open(file_name).unwrap().close(&GraphResult::new()).unwrap();
+            let content = fs::read_to_string(file_name).unwrap();
+            fs::remove_file(file_name).unwrap();
+
+            let lines: Vec<&str> = content.lines().collect();
+            assert_eq!(lines.len(), 5);
+            assert!(lines[0].ends_with(" 1 UPDATE 2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000 0.0009"));
+            assert!(lines[1].ends_with(" 2 REQUEST EXCHANGE_RATE_REQUEST KRAKEN BTC KRAKEN USD"));
+            assert!(lines[2].ends_with(" 3 ANSWER BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 1000 VERSION 1\\nBEST_RATES_END"));
+            assert!(lines[3].ends_with(" 4 RESUME"));
+            assert!(lines[4].ends_with(&format!(" 5 END {}", graph_hash(&GraphResult::new()))));
+            let summary = verify(&content).unwrap();
+            assert_eq!(summary.get_num_records(), 5);
+            assert_eq!(summary.get_num_unclosed_runs(), 1);
+            assert!(summary.is_closed());
+            assert_eq!(summary.get_last_hash(), lines[4].split(' ').next().unwrap());
+            let records = read_records(&content).unwrap();
+            assert_eq!(records.iter().map(AuditRecord::get_kind).collect::<Vec<_>>(),
+                [UPDATE_RECORD, REQUEST_RECORD, ANSWER_RECORD, RESUME_RECORD, END_RECORD]);
+            assert_eq!(records[1].get_text(), Some("EXCHANGE_RATE_REQUEST KRAKEN BTC KRAKEN USD"));
+            assert_eq!(records[3].get_text(), None);
+
+            let modified = content.replace("1000 0.0009", "1001 0.0009");
+            assert_eq!(verify(&modified).err().unwrap(),
+                "line 1: hash does not match, the record or one before it was modified");
+            let removed = [lines[0], lines[2], lines[3]].join("\n");
+            assert_eq!(verify(&removed).err().unwrap(),
+                "line 2: expected record 2, records were removed, added or reordered");
+            assert!(!verify(&lines[..2].join("\n")).unwrap().is_closed());
+        }
+
+        #[test]
+        fn hash_graphs() {
+            let build = |forward_ratio| EngineBuilder::new()
+                .quote("KRAKEN", "BTC", "USD", forward_ratio, 0.0009)
+                .quote("GDAX", "BTC", "USD", 1001.0, 0.0008)
+                .build();
+            assert_eq!(graph_hash(build(1000.0).get_graph_result()), graph_hash(build(1000.0).get_graph_result()));
+            assert_ne!(graph_hash(build(1000.0).get_graph_result()),
+                graph_hash(build(1000.0 + 1e-10).get_graph_result()));
+        }
+    }
+}
+
+#[cfg(not(feature = "audit"))]
+mod unsupported {
+    use std::io;
+
+    use super::{AuditRecord, AuditSummary};
+    use crate::model::{ExchangeRateRequest, GraphResult, PriceUpdate};
+
+    pub fn graph_hash(_graph_result: &GraphResult) -> String {
+        String::new()
+    }
+
+    pub fn read_records(_content: &str) -> Result<Vec<AuditRecord>, String> {
+        Err("Audit logs require the `audit` feature".to_string())
+    }
+
+    pub fn verify(_content: &str) -> Result<AuditSummary, String> {
+        Err("Audit logs require the `audit` feature".to_string())
+    }
+
+    pub fn verify_file(file_name: &str) -> Result<AuditSummary, String> {
+        Err(format!("Verifying audit log {} requires the `audit` feature", file_name))
+    }
+
+    // Stand-in for builds without the `audit` feature, it can never be opened
+    pub struct AuditLog;
+
+    impl AuditLog {
+        pub fn open(file_name: &str) -> Result<AuditLog, String> {
+            Err(format!("Audit log {} requires the `audit` feature", file_name))
+        }
+
+        pub fn record_update(&mut self, _price_update: &PriceUpdate) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub fn record_answer(&mut self, _exchange_rate_request: &ExchangeRateRequest, _answer: &str) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub fn close(self, _graph_result: &GraphResult) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
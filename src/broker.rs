@@ -0,0 +1,286 @@
+// Reading input lines from messages published to a broker such as Redis, NATS or an MQTT broker
+
+use std::io::{self, BufRead};
+#[cfg(any(feature = "redis", feature = "nats", feature = "mqtt"))]
+use std::io::Read;
+#[cfg(any(feature = "redis", feature = "nats", feature = "mqtt"))]
+use std::sync::mpsc::Receiver;
+
+#[cfg(feature = "mqtt")]
+use crate::constants;
+
+// Whether `file_name` names a channel of a broker rather than a local file
+pub fn is_broker_url(file_name: &str) -> bool {
+    file_name.starts_with("redis://") || file_name.starts_with("nats://") || file_name.starts_with("mqtt://")
+}
+
+// Split `<scheme>://<host>[:<port>]/<channel>` into the server URL and the channel
+#[cfg(any(feature = "redis", feature = "nats", feature = "mqtt"))]
+fn split_channel(url: &str) -> Option<(&str, &str)> {
+    let scheme_end = url.find("://")? + 3;
+    let path_start = scheme_end + url[scheme_end..].find('/')?;
+    let channel = &url[path_start + 1..];
+    if channel.is_empty() {
+        return None;
+    }
+    Some((&url[..path_start], channel))
+}
+
+// Turn a JSON price update such as `{"timestamp": "2017-11-01T09:42:23+00:00", "exchange": "KRAKEN",
+// "source_currency": "BTC", "destination_currency": "USD", "forward_factor": 1000.0, "backward_factor": 0.0009}`
+// into a price update line. `backward_factor` can be left out like in a line, and `exchange` too when the message
+// was published to a topic of the exchange, given as `exchange`
+#[cfg(any(feature = "redis", feature = "nats", feature = "mqtt"))]
+fn json_to_line(message: &str, exchange: Option<&str>) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(message).ok()?;
+    let mut tokens = Vec::new();
+    for field in &["timestamp", "exchange", "source_currency", "destination_currency"] {
+        let token = match (value.get(field), exchange) {
+            (None, Some(exchange)) if *field == "exchange" => exchange,
+            (token, _) => token?.as_str()?
+        };
+        tokens.push(token.to_string());
+    }
+    tokens.push(value.get("forward_factor")?.as_f64()?.to_string());
+    if let Some(backward_factor) = value.get("backward_factor") {
+        tokens.push(backward_factor.as_f64()?.to_string());
+    }
+    Some(tokens.join(" "))
+}
+
+// The input lines of a message, JSON price updates are turned into lines and anything else is passed on as is
+// `exchange` is that of the topic the message was published to, if topics are per exchange
+#[cfg(any(feature = "redis", feature = "nats", feature = "mqtt"))]
+fn message_to_lines(message: &str, exchange: Option<&str>) -> String {
+    let message = message.trim_end_matches(['\r', '\n']);
+    let mut lines = if message.trim_start().starts_with('{') {
+        json_to_line(message, exchange).unwrap_or_else(|| message.to_string())
+    } else {
+        message.to_string()
+    };
+    lines.push('\n');
+    lines
+}
+
+// Reads the lines of messages received on a background thread, the end of input is when the thread stops
+#[cfg(any(feature = "redis", feature = "nats", feature = "mqtt"))]
+struct MessageReader {
+    receiver: Receiver<io::Result<String>>,
+    lines: Vec<u8>,
+    // bytes of `lines` already read
+    position: usize
+}
+
+#[cfg(any(feature = "redis", feature = "nats", feature = "mqtt"))]
+impl Read for MessageReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.lines.len() {
+            match self.receiver.recv() {
+                Ok(Ok(lines)) => {
+                    self.lines = lines.into_bytes();
+                    self.position = 0;
+                },
+                Ok(Err(e)) => return Err(e),
+                Err(_) => return Ok(0)
+            }
+        }
+        let num_bytes = buf.len().min(self.lines.len() - self.position);
+        buf[..num_bytes].copy_from_slice(&self.lines[self.position..self.position + num_bytes]);
+        self.position += num_bytes;
+        Ok(num_bytes)
+    }
+}
+
+// Subscribe to the channel of a `redis://<host>[:<port>]/<channel>`, the subject of a
+// `nats://<host>[:<port>]/<subject>` or the topic of a `mqtt://<host>[:<port>]/<topic>` URL, each message is one or
+// more input lines
+// Messages published while not subscribed are missed
+pub fn subscribe(url: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    if url.starts_with("nats://") {
+        subscribe_nats(url)
+    } else if url.starts_with("mqtt://") {
+        subscribe_mqtt(url)
+    } else {
+        subscribe_redis(url)
+    }
+}
+
+#[cfg(feature = "redis")]
+fn subscribe_redis(url: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    use std::sync::mpsc;
+    use std::thread;
+
+    let (server_url, channel) = match split_channel(url) {
+        Some((server_url, channel)) => (server_url, channel.to_string()),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Missing channel in {}", url)))
+    };
+    let client = redis::Client::open(server_url).map_err(io::Error::other)?;
+    let mut connection = client.get_connection().map_err(io::Error::other)?;
+    let (subscribed_sender, subscribed_receiver) = mpsc::channel();
+    let (sender, receiver) = mpsc::sync_channel(1);
+    thread::spawn(move || {
+        // Dropping `pubsub` unsubscribes, so it lives on this thread
+        let mut pubsub = connection.as_pubsub();
+        let subscribed = pubsub.subscribe(&channel).map_err(io::Error::other);
+        let failed = subscribed.is_err();
+        if subscribed_sender.send(subscribed).is_err() || failed {
+            return;
+        }
+        loop {
+            let message = pubsub.get_message()
+                .and_then(|message| message.get_payload::<String>())
+                .map(|message| message_to_lines(&message, None))
+                .map_err(io::Error::other);
+            let failed = message.is_err();
+            if sender.send(message).is_err() || failed {
+                return;
+            }
+        }
+    });
+    // Subscribed before returning so that connection errors are reported when opening the input
+    subscribed_receiver.recv().map_err(io::Error::other)??;
+    Ok(Box::new(io::BufReader::new(MessageReader { receiver, lines: Vec::new(), position: 0 })))
+}
+
+#[cfg(not(feature = "redis"))]
+fn subscribe_redis(url: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    Err(io::Error::other(format!("reading {} requires the `redis` feature", url)))
+}
+
+// The subject can have wildcards, e.g. `ticks.*` with one subject per exchange
+#[cfg(feature = "nats")]
+fn subscribe_nats(url: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    use futures::StreamExt;
+    use std::sync::mpsc;
+    use std::thread;
+
+    let (server_url, subject) = match split_channel(url) {
+        Some((server_url, subject)) => (server_url, subject.to_string()),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Missing subject in {}", url)))
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let (client, mut subscriber) = runtime.block_on(async {
+        let client = async_nats::connect(server_url).await.map_err(io::Error::other)?;
+        let subscriber = client.subscribe(subject).await.map_err(io::Error::other)?;
+        Ok::<_, io::Error>((client, subscriber))
+    })?;
+    let (sender, receiver) = mpsc::sync_channel(1);
+    thread::spawn(move || {
+        // The connection is driven while waiting for the next message
+        let _client = client;
+        while let Some(message) = runtime.block_on(subscriber.next()) {
+            let message = String::from_utf8(message.payload.to_vec())
+                .map(|message| message_to_lines(&message, None))
+                .map_err(io::Error::other);
+            if sender.send(message).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(Box::new(io::BufReader::new(MessageReader { receiver, lines: Vec::new(), position: 0 })))
+}
+
+#[cfg(not(feature = "nats"))]
+fn subscribe_nats(url: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    Err(io::Error::other(format!("reading {} requires the `nats` feature", url)))
+}
+
+// The topic filter can have wildcards, e.g. `ticks/+` with one topic per exchange such as `ticks/KRAKEN`, the last
+// level of the topic being the exchange of JSON price updates without one
+#[cfg(feature = "mqtt")]
+fn subscribe_mqtt(url: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+    use std::process;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    let (server_url, topic) = match split_channel(url) {
+        Some((server_url, topic)) => (server_url, topic.to_string()),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Missing topic in {}", url)))
+    };
+    let address = &server_url["mqtt://".len()..];
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => match port.parse() {
+            Ok(port) => (host, port),
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid port in {}", url)))
+        },
+        None => (address, constants::MQTT_PORT)
+    };
+    let mut options = MqttOptions::new(format!("tenx-challenge-{}", process::id()), host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    let (client, mut connection) = Client::new(options, 10);
+    client.subscribe(topic, QoS::AtLeastOnce).map_err(io::Error::other)?;
+    let (subscribed_sender, subscribed_receiver) = mpsc::channel();
+    let (sender, receiver) = mpsc::sync_channel(1);
+    thread::spawn(move || {
+        // The connection is driven while waiting for the next notification, and the client keeps it open
+        let _client = client;
+        let mut subscribed = false;
+        for notification in connection.iter() {
+            let message = match notification {
+                Ok(Event::Incoming(Packet::SubAck(_))) if !subscribed => {
+                    subscribed = true;
+                    if subscribed_sender.send(Ok(())).is_err() {
+                        return;
+                    }
+                    continue;
+                },
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    let exchange = publish.topic.rsplit('/').next().unwrap_or_default().to_string();
+                    String::from_utf8(publish.payload.to_vec())
+                        .map(|message| message_to_lines(&message, Some(&exchange)))
+                        .map_err(io::Error::other)
+                },
+                Ok(_) => continue,
+                Err(e) if !subscribed => {
+                    let _ = subscribed_sender.send(Err(io::Error::other(e)));
+                    return;
+                },
+                Err(e) => Err(io::Error::other(e))
+            };
+            let failed = message.is_err();
+            if sender.send(message).is_err() || failed {
+                return;
+            }
+        }
+    });
+    // Subscribed before returning so that connection errors are reported when opening the input
+    subscribed_receiver.recv().map_err(io::Error::other)??;
+    Ok(Box::new(io::BufReader::new(MessageReader { receiver, lines: Vec::new(), position: 0 })))
+}
+
+#[cfg(not(feature = "mqtt"))]
+fn subscribe_mqtt(url: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    Err(io::Error::other(format!("reading {} requires the `mqtt` feature", url)))
+}
+
+#[cfg(all(test, any(feature = "redis", feature = "nats", feature = "mqtt")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_channels() {
+        assert_eq!(split_channel("redis://localhost:6379/ticks"), Some(("redis://localhost:6379", "ticks")));
+        assert_eq!(split_channel("redis://localhost:6379/"), None);
+        assert_eq!(split_channel("redis://localhost"), None);
+    }
+
+    #[test]
+    fn json_messages_to_lines() {
+        assert_eq!(message_to_lines("{\"timestamp\": \"2017-11-01T09:42:23+00:00\", \"exchange\": \"KRAKEN\", \
+            \"source_currency\": \"BTC\", \"destination_currency\": \"USD\", \"forward_factor\": 1000.5}", None),
+            "2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000.5\n");
+        assert_eq!(message_to_lines("EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD\n", None),
+            "EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD\n");
+        assert_eq!(message_to_lines("{\"exchange\": \"KRAKEN\"}", None), "{\"exchange\": \"KRAKEN\"}\n");
+        // The exchange of the topic is only used when the message has none
+        let message = "{\"timestamp\": \"2017-11-01T09:42:23+00:00\", \"source_currency\": \"BTC\", \
+            \"destination_currency\": \"USD\", \"forward_factor\": 1000.5, \"backward_factor\": 0.0009}";
+        assert_eq!(message_to_lines(message, Some("GDAX")), "2017-11-01T09:42:23+00:00 GDAX BTC USD 1000.5 0.0009\n");
+        assert_eq!(message_to_lines(message, None), format!("{}\n", message));
+        let message = message.replacen('{', "{\"exchange\": \"KRAKEN\", ", 1);
+        assert_eq!(message_to_lines(&message, Some("GDAX")),
+            "2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000.5 0.0009\n");
+    }
+}
@@ -0,0 +1,147 @@
+// Typed events of a run, which the run loop publishes and every subscribed adapter receives, so that adding an output
+// means subscribing an adapter rather than changing the run loop
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::logger;
+use crate::model::{ExchangeRateRequest, PriceUpdate};
+use crate::watch::RatePoint;
+
+#[derive(Clone, Copy)]
+pub enum Event<'a> {
+    // A price update about to be applied to the graph
+    Price(&'a PriceUpdate),
+    // An answered exchange rate request, with its formatted answer and whether a route was found
+    Query(&'a ExchangeRateRequest, &'a str, bool),
+    // The best rate of a watched pair changed
+    Result(&'a RatePoint),
+    // Something whoever watches the run should know about, e.g. a skipped input line
+    Alert(&'a str)
+}
+
+// An adapter receiving the events of a run, ignoring those it has no use for
+pub trait Subscriber {
+    // An error stops the run
+    fn handle(&mut self, event: &Event) -> Result<(), String>;
+
+    // Deliver anything still buffered, at the end of the run
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Lets the run keep a handle on a subscriber, e.g. to close it once the run ends
+impl<S: Subscriber> Subscriber for Rc<RefCell<S>> {
+    fn handle(&mut self, event: &Event) -> Result<(), String> {
+        self.borrow_mut().handle(event)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.borrow_mut().flush()
+    }
+}
+
+// Every changed best rate, e.g. to chart them at the end of the run
+impl Subscriber for Vec<RatePoint> {
+    fn handle(&mut self, event: &Event) -> Result<(), String> {
+        if let Event::Result(rate_point) = event {
+            self.push((*rate_point).clone());
+        }
+        Ok(())
+    }
+}
+
+// Prints answers to standard output
+pub struct Printer;
+
+impl Subscriber for Printer {
+    fn handle(&mut self, event: &Event) -> Result<(), String> {
+        if let Event::Query(_, answer, _) = event {
+            print!("{}", answer);
+        }
+        Ok(())
+    }
+}
+
+// Logs alerts
+pub struct AlertLogger;
+
+impl Subscriber for AlertLogger {
+    fn handle(&mut self, event: &Event) -> Result<(), String> {
+        if let Event::Alert(alert) = event {
+            logger::log(alert);
+        }
+        Ok(())
+    }
+}
+
+// Delivers every event to the subscribers in the order they subscribed
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Vec<Box<dyn Subscriber>>
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus::default()
+    }
+
+    pub fn subscribe<S: Subscriber + 'static>(&mut self, subscriber: S) {
+        self.subscribers.push(Box::new(subscriber));
+    }
+
+    // Stops at the first subscriber that fails, with its error
+    pub fn publish(&mut self, event: Event) -> Result<(), String> {
+        self.subscribers.iter_mut().try_for_each(|subscriber| subscriber.handle(&event))
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.subscribers.iter_mut().try_for_each(|subscriber| subscriber.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{quote, request};
+
+    // Names of the events it received
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl Subscriber for Recorder {
+        fn handle(&mut self, event: &Event) -> Result<(), String> {
+            let name = match event {
+                Event::Price(price_update) => format!("price {}", price_update.get_exchange()),
+                Event::Query(exchange_rate_request, _, route_found) => {
+                    format!("query {} {}", exchange_rate_request.get_source_currency(), route_found)
+                },
+                Event::Result(rate_point) => format!("result {}", rate_point.get_pair()),
+                Event::Alert(alert) => {
+                    if alert.is_empty() {
+                        return Err("Empty alert".to_string());
+                    }
+                    format!("alert {}", alert)
+                }
+            };
+            self.0.borrow_mut().push(name);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn deliver_events_to_subscribers() {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let history = Rc::new(RefCell::new(Vec::new()));
+        let mut bus = EventBus::new();
+        bus.subscribe(Recorder(received.clone()));
+        bus.subscribe(history.clone());
+        bus.publish(Event::Price(&quote(1, "KRAKEN", "BTC", "USD", 1000.0, 0.0009))).unwrap();
+        bus.publish(Event::Query(&request("KRAKEN", "BTC", "KRAKEN", "USD"), "BEST_RATES_BEGIN", true)).unwrap();
+        bus.publish(Event::Alert("Skipping invalid input")).unwrap();
+        assert!(bus.publish(Event::Alert("")).is_err());
+        bus.flush().unwrap();
+        assert_eq!(*received.borrow(), ["price KRAKEN", "query BTC true", "alert Skipping invalid input"]);
+        assert!(history.borrow().is_empty());
+    }
+}
@@ -0,0 +1,90 @@
+// Answers to exchange rate requests, reused until the graph changes
+
+use std::collections::HashMap;
+
+use crate::model::ExchangeRateRequest;
+
+// The whole request but its request ID, since every other modifier changes the answer
+pub type CacheKey = ExchangeRateRequest;
+
+pub struct QueryCache {
+    // graph version the answers were computed at
+    version: u64,
+    // formatted answer and whether a route was found
+    answers: HashMap<CacheKey, (String, bool)>,
+    num_hits: u64
+}
+
+pub fn cache_key(exchange_rate_request: &ExchangeRateRequest) -> CacheKey {
+    let mut key = exchange_rate_request.clone();
+    key.set_request_id(None);
+    key
+}
+
+impl Default for QueryCache {
+    fn default() -> Self {
+        QueryCache::new()
+    }
+}
+
+impl QueryCache {
+    pub fn new() -> QueryCache {
+        QueryCache { version: 0, answers: HashMap::new(), num_hits: 0 }
+    }
+
+    // The answer computed at `version`, if any
+    pub fn get(&mut self, version: u64, exchange_rate_request: &ExchangeRateRequest) -> Option<(String, bool)> {
+        if version != self.version {
+            self.answers.clear();
+            self.version = version;
+            return None;
+        }
+        let answer = self.answers.get(&cache_key(exchange_rate_request)).cloned();
+        if answer.is_some() {
+            self.num_hits += 1;
+        }
+        answer
+    }
+
+    pub fn insert(&mut self, version: u64, exchange_rate_request: &ExchangeRateRequest, answer: (String, bool)) {
+        if version != self.version {
+            self.answers.clear();
+            self.version = version;
+        }
+        self.answers.insert(cache_key(exchange_rate_request), answer);
+    }
+
+    // Forget every answer, e.g. when fees change without a new graph version
+    pub fn clear(&mut self) {
+        self.answers.clear();
+    }
+
+    pub fn get_num_hits(&self) -> u64 {
+        self.num_hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Currency, Exchange};
+
+    fn request(window: Option<u64>) -> ExchangeRateRequest {
+        ExchangeRateRequest::new(Exchange::new("KRAKEN").unwrap(), Currency::new("BTC").unwrap(),
+            Exchange::new("GDAX").unwrap(), Currency::new("USD").unwrap(), window)
+    }
+
+    #[test]
+    fn reuse_answers_of_same_version() {
+        let mut cache = QueryCache::new();
+        cache.insert(1, &request(None), ("answer".to_string(), true));
+        assert_eq!(cache.get(1, &request(None)), Some(("answer".to_string(), true)));
+        let mut with_request_id = request(None);
+        with_request_id.set_request_id(Some("42".to_string()));
+        assert_eq!(cache.get(1, &with_request_id), Some(("answer".to_string(), true)));
+        assert_eq!(cache.get(1, &request(Some(1000))), None);
+        assert_eq!(cache.get(2, &request(None)), None);
+        assert_eq!(cache.get(1, &request(None)), None);
+        assert_eq!(cache.get_num_hits(), 2);
+    }
+}
@@ -0,0 +1,250 @@
+// Injecting faults into the lines read from an input, so that how a run copes with a flaky feed, i.e. reconnecting,
+// dropping repeats and skipping invalid lines, can be checked before going live: disconnects, duplicated lines,
+// bursts of lines delivered out of order and corrupt lines
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::constants;
+use crate::generate::Random;
+use crate::input;
+
+// Probability of each fault for every line read
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FaultRates {
+    disconnect: f64,
+    duplicate: f64,
+    reorder: f64,
+    corrupt: f64
+}
+
+impl FaultRates {
+    /// Parse comma separated `<fault>=<probability>` pairs, e.g. `disconnect=0.001,duplicate=0.01`, where `<fault>`
+    /// is `disconnect`, `duplicate`, `reorder` or `corrupt` and `<probability>` is from 0 to 1. Faults left out are
+    /// never injected. A disconnect is under 1, since a line that is always disconnected is read again forever
+    pub fn parse(faults: &str) -> Result<FaultRates, String> {
+        let mut rates = FaultRates::default();
+        for fault in faults.split(',') {
+            let (name, probability) = match fault.split_once('=') {
+                Some((name, probability)) => match probability.parse() {
+                    Ok(probability) if (0.0..=1.0).contains(&probability) => (name, probability),
+                    _ => return Err(format!("Invalid probability of fault \"{}\", expected from 0 to 1", fault))
+                },
+                None => return Err(format!("Invalid fault \"{}\", expected <fault>=<probability>", fault))
+            };
+            match name {
+                "disconnect" if probability >= 1.0 => {
+                    return Err(format!("Invalid probability of fault \"{}\", expected from 0 to less than 1 so that \
+                        the input moves forward", fault))
+                },
+                "disconnect" => rates.disconnect = probability,
+                "duplicate" => rates.duplicate = probability,
+                "reorder" => rates.reorder = probability,
+                "corrupt" => rates.corrupt = probability,
+                _ => return Err(format!("Unknown fault {}, expected disconnect, duplicate, reorder or corrupt", name))
+            }
+        }
+        Ok(rates)
+    }
+}
+
+// Number of faults injected so far of each kind
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FaultCounts {
+    disconnects: u64,
+    duplicates: u64,
+    reordered_bursts: u64,
+    corrupt_lines: u64
+}
+
+impl fmt::Display for FaultCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} disconnects, {} duplicates, {} bursts out of order, {} corrupt lines", self.disconnects,
+            self.duplicates, self.reordered_bursts, self.corrupt_lines)
+    }
+}
+
+// What the reader does instead of sending a line as it was read
+#[derive(Debug, PartialEq)]
+pub enum Injected {
+    // (line, start offset, end offset) to send, see `input::send_line`
+    Line(String, u64, u64),
+    // Drop the line and reconnect as after a read error, reading again from its start offset
+    Disconnect
+}
+
+// Lines held back to be delivered out of order
+struct Burst {
+    // offset of the first line
+    start_offset: u64,
+    // offset past the last line
+    end_offset: u64,
+    lines: Vec<String>
+}
+
+// Injects faults into lines at random, at the rates of `FaultRates`. The same seed and rates inject the same faults
+// into the same lines
+pub struct FaultInjector {
+    rates: FaultRates,
+    random: Random,
+    burst: Option<Burst>,
+    // shared with the run, which reads them while lines are being read
+    counts: Arc<Mutex<FaultCounts>>
+}
+
+impl FaultInjector {
+    pub fn new(rates: FaultRates, seed: u64) -> FaultInjector {
+        FaultInjector {
+            rates, random: Random(seed), burst: None, counts: Arc::new(Mutex::new(FaultCounts::default()))
+        }
+    }
+
+    // The counts of faults injected, kept up to date once the injector moves to the reader thread
+    pub fn get_counts(&self) -> Arc<Mutex<FaultCounts>> {
+        self.counts.clone()
+    }
+
+    // What to send in place of `line`, read from `start_offset` to `end_offset`. A line can be corrupted and
+    // duplicated, and the first line of a burst holds it and the next `CHAOS_BURST_LINES - 1` lines back to send them
+    // shuffled. Lines of a burst carry its start offset except the last one sent, so a run resumed in the middle of
+    // one reads it again in full. A disconnect first releases a held burst, since its lines were read before
+    pub fn inject(&mut self, line: String, start_offset: u64, end_offset: u64) -> Vec<Injected> {
+        if self.random.next_f64() < self.rates.disconnect {
+            self.record(|counts| counts.disconnects += 1);
+            let mut injected = self.flush();
+            injected.push(Injected::Disconnect);
+            return injected;
+        }
+        let line = if self.random.next_f64() < self.rates.corrupt {
+            self.record(|counts| counts.corrupt_lines += 1);
+            self.corrupt(&line)
+        } else {
+            line
+        };
+        let mut lines = vec![line];
+        if self.random.next_f64() < self.rates.duplicate {
+            self.record(|counts| counts.duplicates += 1);
+            lines.push(lines[0].clone());
+        }
+        if self.burst.is_none() && self.random.next_f64() < self.rates.reorder {
+            self.record(|counts| counts.reordered_bursts += 1);
+            self.burst = Some(Burst { start_offset, end_offset, lines: Vec::new() });
+        }
+        match &mut self.burst {
+            Some(burst) => {
+                burst.end_offset = end_offset;
+                burst.lines.extend(lines);
+                if burst.lines.len() >= constants::CHAOS_BURST_LINES { self.flush() } else { Vec::new() }
+            },
+            None => lines.into_iter().map(|line| Injected::Line(line, start_offset, end_offset)).collect()
+        }
+    }
+
+    // Release the lines of a burst held back, shuffled, e.g. at the end of input so that they are not held while
+    // waiting for more
+    pub fn flush(&mut self) -> Vec<Injected> {
+        let mut burst = match self.burst.take() {
+            Some(burst) => burst,
+            None => return Vec::new()
+        };
+        // Fisher-Yates
+        for i in (1..burst.lines.len()).rev() {
+            let j = self.random.below(i + 1);
+            burst.lines.swap(i, j);
+        }
+        let (start_offset, end_offset, num_lines) = (burst.start_offset, burst.end_offset, burst.lines.len());
+        burst.lines.into_iter().enumerate()
+            .map(|(i, line)| {
+                let offset = if i + 1 == num_lines { end_offset } else { start_offset };
+                Injected::Line(line, start_offset, offset)
+            })
+            .collect()
+    }
+
+    fn record<F: FnOnce(&mut FaultCounts)>(&self, fault: F) {
+        fault(&mut self.counts.lock().unwrap());
+    }
+
+    // `line` with a random character replaced by U+FFFD, or cut short before a random character
+    fn corrupt(&mut self, line: &str) -> String {
+        let mut chars: Vec<char> = input::clean_line(line).chars().collect();
+        if chars.is_empty() {
+            return line.to_string();
+        }
+        let position = self.random.below(chars.len());
+        if self.random.next_f64() < 0.5 {
+            chars.truncate(position);
+        } else {
+            chars[position] = char::REPLACEMENT_CHARACTER;
+        }
+        chars.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inject_faults() {
+        assert!(FaultRates::parse("duplicate=0.5,corrupt=1").is_ok());
+        assert!(FaultRates::parse("duplicate=1.5").is_err());
+        assert!(FaultRates::parse("delay=0.1").is_err());
+        assert!(FaultRates::parse("duplicate").is_err());
+
+        let lines: Vec<String> = (0..10).map(|i| format!("line {}\n", i)).collect();
+        let inject_all = |injector: &mut FaultInjector| {
+            let mut injected = Vec::new();
+            for (i, line) in lines.iter().enumerate() {
+                injected.extend(injector.inject(line.clone(), i as u64 * 7, (i as u64 + 1) * 7));
+            }
+            injected.extend(injector.flush());
+            injected
+        };
+
+        // Without faults every line goes through as it was read
+        let mut injector = FaultInjector::new(FaultRates::default(), 1);
+        let injected = inject_all(&mut injector);
+        assert_eq!(injected[3], Injected::Line("line 3\n".to_string(), 21, 28));
+        assert_eq!(injected.len(), 10);
+
+        let mut injector = FaultInjector::new(FaultRates::parse("duplicate=1").unwrap(), 1);
+        assert_eq!(inject_all(&mut injector).len(), 20);
+
+        // Every line disconnected would be read again forever
+        assert!(FaultRates::parse("disconnect=1").is_err());
+        let mut injector = FaultInjector::new(FaultRates::parse("disconnect=0.5").unwrap(), 1);
+        let injected = inject_all(&mut injector);
+        let num_disconnects = injected.iter().filter(|injected| **injected == Injected::Disconnect).count();
+        assert!(num_disconnects > 0 && num_disconnects < injected.len());
+
+        // Two bursts of 5 lines, each shuffled, carrying the offset of their start but for the last line sent
+        let mut injector = FaultInjector::new(FaultRates::parse("reorder=1").unwrap(), 1);
+        let injected = inject_all(&mut injector);
+        let offsets: Vec<(u64, u64)> = injected.iter()
+            .map(|injected| match injected {
+                Injected::Line(_, start_offset, end_offset) => (*start_offset, *end_offset),
+                Injected::Disconnect => panic!("Unexpected disconnect")
+            })
+            .collect();
+        assert_eq!(offsets, [(0, 0), (0, 0), (0, 0), (0, 0), (0, 35), (35, 35), (35, 35), (35, 35), (35, 35),
+            (35, 70)]);
+        let mut sorted_lines: Vec<String> = injected.into_iter()
+            .map(|injected| match injected {
+                Injected::Line(line, _, _) => line,
+                Injected::Disconnect => panic!("Unexpected disconnect")
+            })
+            .collect();
+        sorted_lines.sort();
+        assert_eq!(sorted_lines, lines);
+
+        let mut injector = FaultInjector::new(FaultRates::parse("corrupt=1").unwrap(), 1);
+        let injected = inject_all(&mut injector);
+        assert!(injected.iter().zip(&lines).all(|(injected, line)| match injected {
+            Injected::Line(corrupt_line, _, _) => corrupt_line.len() < line.len() || corrupt_line.contains('\u{fffd}'),
+            Injected::Disconnect => false
+        }));
+        assert_eq!(injector.get_counts().lock().unwrap().to_string(),
+            "0 disconnects, 0 duplicates, 0 bursts out of order, 10 corrupt lines");
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::{TimeZone, Utc};
+use plotters::prelude::*;
+
+use tenx_challenge::watch::RatePoint;
+
+pub enum ChartFormat {
+    Svg,
+    Png
+}
+
+impl ChartFormat {
+    pub fn parse(format: &str) -> Option<ChartFormat> {
+        match format {
+            "svg" => Some(ChartFormat::Svg),
+            "png" => Some(ChartFormat::Png),
+            _ => None
+        }
+    }
+
+    fn extension(&self) -> &str {
+        match self {
+            ChartFormat::Svg => "svg",
+            ChartFormat::Png => "png"
+        }
+    }
+}
+
+const CHART_SIZE: (u32, u32) = (1024, 512);
+
+// Render one chart per watched pair into `dir`, named after the pair, e.g. `KRAKEN_BTC-GDAX_USD.svg`
+// Each series is drawn up to `end`, the time of the last price update of the run
+pub fn render(dir: &str, format: &ChartFormat, rate_points: &[RatePoint], end: u64) -> Result<(), String> {
+    let mut series: HashMap<&str, Vec<&RatePoint>> = HashMap::new();
+    for rate_point in rate_points {
+        series.entry(rate_point.get_pair()).or_default().push(rate_point);
+    }
+
+    for (pair, rate_points) in series {
+        let file_name = format!("{}.{}", pair.replace("->", "-").replace(":", "_"), format.extension());
+        let path = Path::new(dir).join(file_name);
+        let result = match format {
+            ChartFormat::Svg => draw(SVGBackend::new(&path, CHART_SIZE).into_drawing_area(), pair, &rate_points, end),
+            ChartFormat::Png => draw(BitMapBackend::new(&path, CHART_SIZE).into_drawing_area(), pair, &rate_points, end)
+        };
+        if let Err(e) = result {
+            return Err(format!("Unable to render chart {}: {}", path.display(), e));
+        }
+    }
+    Ok(())
+}
+
+fn draw<DB: DrawingBackend>(root: DrawingArea<DB, plotters::coord::Shift>, pair: &str, rate_points: &[&RatePoint],
+    end: u64) -> Result<(), Box<dyn std::error::Error>> where DB::ErrorType: 'static
+{
+    let start = rate_points[0].get_datetime();
+    let end = end.max(rate_points[rate_points.len() - 1].get_datetime()).max(start + 1);
+    let min_rate = rate_points.iter().map(|p| p.get_best_rate()).fold(f64::INFINITY, f64::min);
+    let max_rate = rate_points.iter().map(|p| p.get_best_rate()).fold(f64::NEG_INFINITY, f64::max);
+    // Pad the y axis so a flat series is still visible
+    let padding = ((max_rate - min_rate) * 0.1).max(max_rate.abs() * 0.001).max(f64::EPSILON);
+
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .caption(pair, ("sans-serif", 24))
+        .margin(10)
+        .margin_right(60)
+        .x_label_area_size(40)
+        .y_label_area_size(80)
+        .build_cartesian_2d(start..end, (min_rate - padding)..(max_rate + padding))?;
+
+    chart.configure_mesh()
+        .x_labels(6)
+        .x_label_formatter(&|datetime| match Utc.timestamp_millis_opt(*datetime as i64).single() {
+            Some(datetime) => datetime.format("%m-%d %H:%M:%S").to_string(),
+            None => datetime.to_string()
+        })
+        .y_desc("Best rate")
+        .draw()?;
+
+    // The best rate holds until the next change, so draw it as a step line
+    let mut points = Vec::new();
+    for (index, rate_point) in rate_points.iter().enumerate() {
+        if index > 0 {
+            points.push((rate_point.get_datetime(), rate_points[index - 1].get_best_rate()));
+        }
+        points.push((rate_point.get_datetime(), rate_point.get_best_rate()));
+    }
+    points.push((end, rate_points[rate_points.len() - 1].get_best_rate()));
+    chart.draw_series(LineSeries::new(points, &BLUE))?;
+
+    root.present()?;
+    Ok(())
+}
@@ -0,0 +1,249 @@
+// Saving and restoring the state of a run so an interrupted replay can continue where it stopped
+
+use std::fs;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::graph::ShortestPaths;
+use crate::model::{Currency, Exchange, Graph, GraphResult, Vertex};
+
+// A market quote from `from_currency` to `to_currency` on `exchange`
+struct Quote {
+    exchange: Exchange,
+    from_currency: Currency,
+    to_currency: Currency,
+    rate: f64,
+    last_updated: u64,
+    sub_millis: u32,
+    derived: bool,
+    // when the quote was first updated and how many times it was updated since, which its confidence depends on
+    first_updated: u64,
+    num_updates: u64
+}
+
+pub struct Checkpoint {
+    // byte offset in the input of the first line not yet processed
+    offset: u64,
+    latest_datetime: u64,
+    // graph version, so versions keep increasing across a resume
+    version: u64,
+    quotes: Vec<Quote>,
+    // (from, to, best rate, vertex after `from`) of the best path between every pair of vertices, saved so that
+    // the first answers after resuming need not find them again
+    best_paths: Vec<(Vertex, Vertex, f64, Vertex)>
+}
+
+// `<milliseconds>`, followed by `.<nanoseconds>` with 6 digits when updated within a millisecond
+fn format_last_updated(last_updated: u64, sub_millis: u32) -> String {
+    match sub_millis {
+        0 => last_updated.to_string(),
+        sub_millis => format!("{}.{:06}", last_updated, sub_millis)
+    }
+}
+
+fn parse_last_updated(last_updated: &str) -> Option<(u64, u32)> {
+    match last_updated.split_once('.') {
+        Some((millis, sub_millis)) if sub_millis.len() == 6 => {
+            Some((millis.parse().ok()?, sub_millis.parse().ok()?))
+        },
+        Some(_) => None,
+        None => Some((last_updated.parse().ok()?, 0))
+    }
+}
+
+fn parse_vertex(exchange: &str, currency: &str) -> Option<Vertex> {
+    Some(Vertex::new(Exchange::new(exchange).ok()?, Currency::new(currency).ok()?))
+}
+
+// Write the quotes in `graph_result` and the input position to `file_name`, and its best paths with `best_rates`,
+// which must be up to date with the quotes
+// The file is replaced atomically so a crash while saving leaves the previous checkpoint intact
+pub fn save(file_name: &str, offset: u64, latest_datetime: u64, version: u64, graph_result: &GraphResult,
+    best_rates: bool) -> io::Result<()> {
+    let mut content = format!("OFFSET {}\nLATEST_DATETIME {}\nVERSION {}\n", offset, latest_datetime, version);
+    for (from_vertex, to_vertex, edge) in graph_result.edges() {
+        // Edges between exchanges are recreated from the quotes
+        if from_vertex.get_exchange() == to_vertex.get_exchange() {
+            let (first_updated, num_updates) = edge.get_update_history();
+            content.push_str(&format!("QUOTE {} {} {} {} {} UPDATES {} {}{}\n", from_vertex.get_exchange(),
+                from_vertex.get_currency(), to_vertex.get_currency(), edge.get_weight(),
+                format_last_updated(edge.get_last_updated(), edge.get_sub_millis()), first_updated, num_updates,
+                if edge.is_derived() { " DERIVED" } else { "" }));
+        }
+    }
+    if best_rates {
+        for (from_vertex, to_vertex, best_rate, next_vertex) in graph_result.best_paths() {
+            content.push_str(&format!("BEST {} {} {} {} {} {} {}\n", from_vertex.get_exchange(),
+                from_vertex.get_currency(), to_vertex.get_exchange(), to_vertex.get_currency(), best_rate,
+                next_vertex.get_exchange(), next_vertex.get_currency()));
+        }
+    }
+    let temp_file_name = format!("{}.tmp", file_name);
+    let mut file = fs::File::create(&temp_file_name)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_file_name, file_name)
+}
+
+impl Checkpoint {
+    pub fn load(file_name: &str) -> Result<Checkpoint, String> {
+        let content = match fs::read_to_string(file_name) {
+            Ok(content) => content,
+            Err(e) => return Err(format!("Error encountered while reading checkpoint {}: {}", file_name, e))
+        };
+        let mut checkpoint = Checkpoint {
+            offset: 0, latest_datetime: 0, version: 0, quotes: Vec::new(), best_paths: Vec::new()
+        };
+        for (index, line) in content.lines().enumerate() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match tokens[..] {
+                ["OFFSET", offset] => offset.parse().map(|offset| checkpoint.offset = offset).is_ok(),
+                ["LATEST_DATETIME", datetime] => datetime.parse()
+                    .map(|datetime| checkpoint.latest_datetime = datetime).is_ok(),
+                ["VERSION", version] => version.parse().map(|version| checkpoint.version = version).is_ok(),
+                ["QUOTE", exchange, from_currency, to_currency, rate, last_updated, ref flags @ ..] => {
+                    // None for an invalid update history, which must not start after the last update. Checkpoints
+                    // saved before it was kept count the last update only
+                    let (update_history, flags) = match flags {
+                        ["UPDATES", first_updated, num_updates, ref flags @ ..] => {
+                            (first_updated.parse().ok().zip(num_updates.parse().ok()).map(Some), flags)
+                        },
+                        _ => (Some(None), flags)
+                    };
+                    let derived = flags == ["DERIVED"];
+                    match (Exchange::new(exchange), Currency::new(from_currency), Currency::new(to_currency),
+                        rate.parse(), parse_last_updated(last_updated), update_history) {
+                        (Ok(exchange), Ok(from_currency), Ok(to_currency), Ok(rate), Some((last_updated, sub_millis)),
+                            Some(update_history)) if (derived || flags.is_empty())
+                            && update_history.is_none_or(|(first_updated, _)| first_updated <= last_updated) => {
+                            let (first_updated, num_updates) = update_history.unwrap_or((last_updated, 1));
+                            checkpoint.quotes.push(Quote {
+                                exchange, from_currency, to_currency, rate, last_updated, sub_millis, derived,
+                                first_updated, num_updates
+                            });
+                            true
+                        },
+                        _ => false
+                    }
+                },
+                ["BEST", from_exchange, from_currency, to_exchange, to_currency, best_rate, next_exchange,
+                    next_currency] => {
+                    match (parse_vertex(from_exchange, from_currency), parse_vertex(to_exchange, to_currency),
+                        best_rate.parse(), parse_vertex(next_exchange, next_currency)) {
+                        (Some(from_vertex), Some(to_vertex), Ok(best_rate), Some(next_vertex)) => {
+                            checkpoint.best_paths.push((from_vertex, to_vertex, best_rate, next_vertex));
+                            true
+                        },
+                        _ => false
+                    }
+                },
+                _ => false
+            };
+            if !parsed {
+                return Err(format!("Invalid checkpoint {} at line {}: {}", file_name, index + 1, line));
+            }
+        }
+        Ok(checkpoint)
+    }
+
+    pub fn get_offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn get_latest_datetime(&self) -> u64 {
+        self.latest_datetime
+    }
+
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    // Whether the best paths were saved, in which case `restore` restores them too
+    pub fn has_best_rates(&self) -> bool {
+        !self.best_paths.is_empty()
+    }
+
+    // Add the saved quotes to an empty graph, and the saved best paths if any
+    pub fn restore(&self, graph: &mut Graph, graph_result: &mut GraphResult) {
+        for quote in &self.quotes {
+            let from_vertex = Arc::new(Vertex::new(quote.exchange.clone(), quote.from_currency.clone()));
+            let to_vertex = Arc::new(Vertex::new(quote.exchange.clone(), quote.to_currency.clone()));
+            graph_result.add_edge_weight(from_vertex.clone(), to_vertex.clone(), quote.rate, quote.last_updated,
+                quote.sub_millis, quote.derived);
+            graph_result.set_update_history(&from_vertex, &to_vertex, quote.first_updated, quote.num_updates);
+            graph.add_vertex(from_vertex.clone());
+            graph.add_vertex(to_vertex.clone());
+            graph_result.add_edge_weight_for_currency(from_vertex, graph.get_vertices());
+            graph_result.add_edge_weight_for_currency(to_vertex, graph.get_vertices());
+        }
+        if self.has_best_rates() {
+            let mut best_paths = ShortestPaths::new();
+            for (from_vertex, to_vertex, best_rate, next_vertex) in &self.best_paths {
+                best_paths.insert(Arc::new(from_vertex.clone()), Arc::new(to_vertex.clone()), *best_rate,
+                    Arc::new(next_vertex.clone()));
+            }
+            graph_result.restore_best_paths(best_paths);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_restore() {
+        let mut graph_result = GraphResult::new();
+        let kraken_btc = Arc::new(Vertex::new(Exchange::new("KRAKEN").unwrap(), Currency::new("BTC").unwrap()));
+        let kraken_usd = Arc::new(Vertex::new(Exchange::new("KRAKEN").unwrap(), Currency::new("USD").unwrap()));
+        graph_result.add_edge_weight(kraken_btc.clone(), kraken_usd.clone(), 1000.5, 42, 0, false);
+        graph_result.add_edge_weight(kraken_usd.clone(), kraken_btc.clone(), 0.0009, 2, 250, true);
+        graph_result.add_edge_weight(kraken_usd.clone(), kraken_btc.clone(), 0.0009, 42, 250, true);
+
+        let file_name = std::env::temp_dir().join(format!("checkpoint-test-{}", std::process::id()));
+        let file_name = file_name.to_str().unwrap();
+        graph_result.find_best_rates(&[kraken_btc.clone(), kraken_usd.clone()].iter().cloned().collect());
+        save(file_name, 123, 42, 7, &graph_result, true).unwrap();
+        let checkpoint = Checkpoint::load(file_name).unwrap_or_else(|e| panic!("{}", e));
+        fs::remove_file(file_name).unwrap();
+
+        assert_eq!(checkpoint.get_offset(), 123);
+        assert_eq!(checkpoint.get_latest_datetime(), 42);
+        assert_eq!(checkpoint.get_version(), 7);
+        let mut graph = Graph::new();
+        let mut restored = GraphResult::new();
+        checkpoint.restore(&mut graph, &mut restored);
+        assert_eq!(graph.get_vertices().len(), 2);
+        assert_eq!(restored.get_edge_weight(&kraken_btc, &kraken_usd), 1000.5);
+        assert_eq!(restored.get_edge_weight(&kraken_usd, &kraken_btc), 0.0009);
+        assert!(!restored.has_derived_edge(&[kraken_btc.clone(), kraken_usd.clone()]));
+        assert!(restored.has_derived_edge(&[kraken_usd.clone(), kraken_btc.clone()]));
+        assert_eq!(restored.get_edge(&kraken_usd, &kraken_btc).unwrap().get_sub_millis(), 250);
+        // Confidence depends on how often the quote was updated, which is kept
+        let (edge, saved_edge) = (restored.get_edge(&kraken_usd, &kraken_btc).unwrap(),
+            graph_result.get_edge(&kraken_usd, &kraken_btc).unwrap());
+        assert_eq!(edge.get_update_history(), (2, 2));
+        assert_eq!(edge.get_confidence(100), saved_edge.get_confidence(100));
+        assert_eq!(restored.get_edge(&kraken_btc, &kraken_usd).unwrap().get_update_history(), (42, 1));
+        // The best paths are restored rather than found again
+        assert!(checkpoint.has_best_rates());
+        assert_eq!(restored.get_best_rate(&kraken_btc, &kraken_usd), 1000.5);
+        assert!(restored.best_rate_path(&kraken_usd, &kraken_btc) == Some(vec![kraken_usd, kraken_btc]));
+    }
+
+    #[test]
+    fn reject_invalid_checkpoint() {
+        let file_name = std::env::temp_dir().join(format!("checkpoint-invalid-{}", std::process::id()));
+        let file_name = file_name.to_str().unwrap();
+        fs::write(file_name, "OFFSET ten\n").unwrap();
+        assert!(Checkpoint::load(file_name).is_err());
+        fs::write(file_name, "QUOTE KRAKEN BTC USD 1000 42 UPDATES 10 many\n").unwrap();
+        assert!(Checkpoint::load(file_name).is_err());
+        fs::write(file_name, "QUOTE KRAKEN BTC USD 1000 42 UPDATES 100 2\n").unwrap();
+        assert!(Checkpoint::load(file_name).is_err());
+        // Checkpoints saved without the update history still load
+        fs::write(file_name, "QUOTE KRAKEN BTC USD 1000 42 DERIVED\n").unwrap();
+        assert!(Checkpoint::load(file_name).is_ok());
+        fs::remove_file(file_name).unwrap();
+    }
+}
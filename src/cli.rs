@@ -0,0 +1,976 @@
+// Command line options
+
+use std::thread;
+use std::time::Duration;
+
+use tenx_challenge::chaos::FaultRates;
+use tenx_challenge::constants;
+use tenx_challenge::fees::FeeSide;
+use tenx_challenge::model::Precision;
+use tenx_challenge::output::OutputFormat;
+use tenx_challenge::queue::Backpressure;
+use tenx_challenge::remote;
+
+// (flag, value, description) for every supported flag, flags without a value are switches
+pub const FLAGS: &[(&str, &str, &str)] = &[
+    ("--output-format", "<text|json>", "Format of exchange rate request answers, defaults to text"),
+    ("--explain", "", "Explain every answer with the rate of each hop and the next best alternative"),
+    ("--watch-list", "<file>", "File of `<source_exchange> <source_currency> <destination_exchange> <destination_currency>` pairs to track"),
+    ("--csv", "<file>", "Write a CSV row every time the best rate of a watched pair changes"),
+    ("--arrow", "<file>", "Write the best rate between every pair of vertices to an Arrow IPC (Feather) file at the end of the run (requires the `arrow` feature)"),
+    ("--npy-dir", "<dir>", "Write the adjacency and best rate matrices as NumPy .npy files with a vertices.json index to <dir> at the end of the run"),
+    ("--cypher", "<file>", "Write Cypher statements that load the vertices and edges of the graph into Neo4j to <file> at the end of the run"),
+    ("--chart-dir", "<dir>", "Render a chart of the best rate of each watched pair at the end of the run (requires the `chart` feature)"),
+    ("--chart-format", "<svg|png>", "Image format of the charts, defaults to svg"),
+    ("--kafka-topic", "<topic>", "Publish a message to <topic> every time the best rate of a watched pair changes (requires the `kafka` feature)"),
+    ("--kafka-brokers", "<host:port,...>", "Kafka brokers to connect to, defaults to localhost:9092"),
+    ("--plugin", "<file>", "Load feed and sink adapters from a plugin dynamic library (requires the `plugins` feature)"),
+    ("--sink", "<name[:argument]>", "Publish every change to the best rate of a watched pair to a sink registered by a plugin"),
+    ("--dashboard", "", "Show a live terminal dashboard of the watched pairs (requires the `dashboard` feature)"),
+    ("--top", "<n>", "Number of watched pairs shown on the dashboard, defaults to 10"),
+    ("--strict", "", "Exit with code 4 if any exchange rate request has no route"),
+    ("--verify", "", "Check best rates against an exhaustive search of every path on graphs of up to 8 vertices, exit with code 6 if they differ"),
+    ("--audit-log", "<file>", "Append every price update and answer to a hash-chained audit log (requires the `audit` feature)"),
+    ("--summary", "", "Print a summary of the run to standard error, it is always printed when interrupted"),
+    ("--latency", "", "Print the p50, p95 and p99 latencies of applying price updates and of answering exchange rate requests to standard error at the end of the run"),
+    ("--daemon", "", "Keep running at the end of input and wait for more, reopening the input after read errors"),
+    ("--pid-file", "<file>", "Write the process id to <file> and remove it on exit"),
+    ("--log-file", "<file>", "Write diagnostics to <file> instead of standard error, reopened on SIGHUP"),
+    ("--currency-aliases", "<file>", "File of `<alias> <currency>` lines, e.g. `XBT BTC`, applied to every currency read"),
+    ("--exchange-aliases", "<file>", "File of `<alias> <exchange>` lines, e.g. `GDAX COINBASE`, applied to every exchange read"),
+    ("--canonical-pairs", "<file>", "File of `<base_currency> <quote_currency>` lines, e.g. `BTC USD`, price updates quoting a pair the other way are inverted"),
+    ("--partitions", "<file>", "File of `<name> <currency> ...` lines, e.g. `fiat USD EUR`, that requests can confine their routes to with `PARTITION <name>`"),
+    ("--liquidity", "<file>", "File of `<exchange> <source_currency> <destination_currency> <depth> <slippage>` tiers that requests with `SPLIT <amount>` split conversions across paths by"),
+    ("--fees", "<file>", "Fee schedule of `<exchange> [min_volume] <maker_fee> <taker_fee>` lines, rates are routed net of fees"),
+    ("--volume", "<amount>", "Trading volume used to select the fee tier, defaults to 0"),
+    ("--fee-side", "<maker|taker>", "Whether maker or taker fees are charged, defaults to taker"),
+    ("--notional", "<amount>", "Amount of the source currency that total fees are reported for, defaults to 1"),
+    ("--assumed-spread", "<fraction>", "Spread used to derive the backward ratio of a price update without one, defaults to 0.001"),
+    ("--allow-crossed", "", "Accept price updates whose forward and backward ratios multiply to more than 1, answers only reachable through the resulting arbitrage cycles are reported UNBOUNDED"),
+    ("--timestamp-precision", "<ms|us|ns>", "Resolution of the timestamps of price updates, finer ones order updates within the same millisecond, defaults to ms"),
+    ("--hop-penalty", "<factor>", "Multiply the score of a route by <factor> (0 to 1) per hop to prefer shorter routes"),
+    ("--dedup", "<n>", "Drop price updates with the exchange, pair and timestamp of one of the last <n>, e.g. redelivered by an at-least-once transport"),
+    ("--quarantine", "<multiple>", "Hold back price updates over <multiple> times, or under 1/<multiple> times, the recent rates of their market until confirmed"),
+    ("--stale-after", "<duration>", "Flag answers whose best path uses a quote older than <duration>, e.g. 5m, with STALE_DATA"),
+    ("--confidence", "", "Report the confidence in the quotes of the best path of every answer, which decays as they age"),
+    ("--min-confidence", "<fraction>", "Answer with no route when the confidence of the best path is below <fraction> (0 to 1), implies --confidence"),
+    ("--weight-script", "<file>", "Rhai script turning the ratio of every quote into the weight of its edge (requires the `scripting` feature)"),
+    ("--http-header-file", "<file>", "File with a `<name>: <value>` header sent when the input is an HTTP(S) URL, e.g. for auth"),
+    ("--refetch-every", "<duration>", "Fetch a URL input again on this interval, e.g. 30s, and process the lines added since"),
+    ("--threads", "<n>", "Number of threads answering exchange rate requests, defaults to the number of CPUs"),
+    ("--profile", "<file>", "Sample what the engine spends its time on and write the samples as folded stacks for flame graphs to <file> at the end of the run"),
+    ("--max-memory", "<size>", "Evict the stalest quotes when the estimated graph memory nears <size>, e.g. 512MB"),
+    ("--recompute-every", "<duration>", "Find the best rates in the background on this interval, e.g. 500ms, when price updates changed them"),
+    ("--recompute-after", "<n>", "Find the best rates in the background once <n> price updates changed them"),
+    ("--max-updates-per-sec", "<n>", "Process at most <n> price updates per second, evenly spaced"),
+    ("--buffer-size", "<lines>", "Number of input lines buffered ahead of processing, defaults to 1024"),
+    ("--backpressure", "<block|drop-oldest|coalesce>", "What to do with input when the buffer is full, defaults to block"),
+    ("--chaos", "<fault=probability,...>", "Inject disconnect, duplicate, reorder or corrupt faults into the lines read, each with a probability per line, to test how a run copes with a flaky feed"),
+    ("--chaos-seed", "<n>", "Seed of the faults injected by --chaos, the same seed injects the same faults into the same input, defaults to 1"),
+    ("--checkpoint", "<file>", "Periodically save the rates and input position, and when the run ends"),
+    ("--checkpoint-every", "<lines>", "Number of input lines between checkpoints, defaults to 100000"),
+    ("--resume", "<file>", "Continue an interrupted run from a checkpoint"),
+    ("--warm-start", "", "Save the best rates with checkpoints too, so answers after resuming need not find them again"),
+];
+
+pub enum Command {
+    // Process an input file
+    Run(Box<Options>),
+    // Print the completion script of a shell
+    Completions(String),
+    // Run the golden-file scenarios of a directory
+    VerifyScenarios(String),
+    // Query the state built from an input file with SQL, running the query if given or reading queries from stdin
+    Sql(String, Option<String>),
+    // Check the hash chain of an audit log, and that its last hash is the one given, if any
+    VerifyAudit(String, Option<String>),
+    // Replay an audit log, given as the input file, with the options of the recorded runs
+    VerifyReplay(Box<Options>),
+    // Print a synthetic input
+    Generate(GenerateOptions),
+    // Time finding best rates and answering requests on a random graph
+    Stress(StressOptions)
+}
+
+// Settings of the synthetic input printed by `generate`, see `generate::Generator`
+pub struct GenerateOptions {
+    num_exchanges: usize,
+    num_currencies: usize,
+    num_updates: u64,
+    tick_rate: f64,
+    volatility: f64,
+    arbitrage_rate: f64,
+    request_rate: f64,
+    seed: u64
+}
+
+impl GenerateOptions {
+    pub fn get_num_exchanges(&self) -> usize {
+        self.num_exchanges
+    }
+
+    pub fn get_num_currencies(&self) -> usize {
+        self.num_currencies
+    }
+
+    pub fn get_num_updates(&self) -> u64 {
+        self.num_updates
+    }
+
+    pub fn get_tick_rate(&self) -> f64 {
+        self.tick_rate
+    }
+
+    pub fn get_volatility(&self) -> f64 {
+        self.volatility
+    }
+
+    pub fn get_arbitrage_rate(&self) -> f64 {
+        self.arbitrage_rate
+    }
+
+    pub fn get_request_rate(&self) -> f64 {
+        self.request_rate
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+// Settings of the random graph built by `stress`, see `generate::random_graph`
+pub struct StressOptions {
+    num_exchanges: usize,
+    num_currencies: usize,
+    density: f64,
+    num_queries: usize,
+    seed: u64,
+    threads: usize
+}
+
+impl StressOptions {
+    pub fn get_num_exchanges(&self) -> usize {
+        self.num_exchanges
+    }
+
+    pub fn get_num_currencies(&self) -> usize {
+        self.num_currencies
+    }
+
+    pub fn get_density(&self) -> f64 {
+        self.density
+    }
+
+    pub fn get_num_queries(&self) -> usize {
+        self.num_queries
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn get_threads(&self) -> usize {
+        self.threads
+    }
+}
+
+pub const STRESS_FLAGS: &[(&str, &str, &str)] = &[
+    ("--exchanges", "<n>", "Number of exchanges, defaults to 10"),
+    ("--currencies", "<n>", "Number of currencies, at least 2, defaults to 20"),
+    ("--density", "<fraction>", "Fraction of the pairs of currencies quoted on each exchange, defaults to 0.5"),
+    ("--queries", "<n>", "Number of exchange rate requests between random vertices, defaults to 1000"),
+    ("--seed", "<n>", "Seed of the random numbers, defaults to 1"),
+    ("--threads", "<n>", "Number of threads answering the requests, defaults to the number of CPUs"),
+];
+
+pub const GENERATE_FLAGS: &[(&str, &str, &str)] = &[
+    ("--exchanges", "<n>", "Number of exchanges, defaults to 3"),
+    ("--currencies", "<n>", "Number of currencies, at least 2, defaults to 4"),
+    ("--updates", "<n>", "Number of price updates, defaults to 1000"),
+    ("--tick-rate", "<n>", "Price updates per second of generated time, defaults to 10"),
+    ("--volatility", "<fraction>", "Standard deviation of the relative price change of a currency per update, defaults to 0.0001"),
+    ("--arbitrage-rate", "<fraction>", "Fraction of price updates mispricing their market by 1 to 3%, defaults to 0.01"),
+    ("--request-rate", "<fraction>", "Fraction of price updates followed by an exchange rate request, defaults to 0.1"),
+    ("--seed", "<n>", "Seed of the random numbers, the same seed and options generate the same input, defaults to 1"),
+];
+
+pub struct Options {
+    input_file: String,
+    watch_list_file: Option<String>,
+    csv_file: Option<String>,
+    arrow_file: Option<String>,
+    npy_dir: Option<String>,
+    cypher_file: Option<String>,
+    chart_dir: Option<String>,
+    chart_format: String,
+    kafka_topic: Option<String>,
+    kafka_brokers: String,
+    plugin_file: Option<String>,
+    sink: Option<String>,
+    dashboard: bool,
+    top: usize,
+    strict: bool,
+    summary: bool,
+    latency: bool,
+    verify: bool,
+    daemon: bool,
+    pid_file: Option<String>,
+    log_file: Option<String>,
+    currency_aliases_file: Option<String>,
+    exchange_aliases_file: Option<String>,
+    canonical_pairs_file: Option<String>,
+    partitions_file: Option<String>,
+    liquidity_file: Option<String>,
+    fees_file: Option<String>,
+    audit_log_file: Option<String>,
+    weight_script_file: Option<String>,
+    volume: f64,
+    fee_side: FeeSide,
+    max_updates_per_sec: Option<f64>,
+    // bytes
+    max_memory: Option<usize>,
+    threads: usize,
+    profile_file: Option<String>,
+    buffer_size: usize,
+    backpressure: Backpressure,
+    checkpoint_file: Option<String>,
+    checkpoint_every: u64,
+    warm_start: bool,
+    resume_file: Option<String>,
+    output_format: OutputFormat,
+    explain: bool,
+    notional: f64,
+    hop_penalty: Option<f64>,
+    // milliseconds
+    stale_after: Option<u64>,
+    // number of keys of price updates kept to drop repeats
+    dedup: Option<usize>,
+    quarantine: Option<f64>,
+    // 0 with `--confidence` alone
+    min_confidence: Option<f64>,
+    assumed_spread: f64,
+    allow_crossed: bool,
+    timestamp_precision: Precision,
+    http_header_file: Option<String>,
+    refetch_every: Option<Duration>,
+    recompute_every: Option<Duration>,
+    // number of price updates
+    recompute_after: Option<u64>,
+    chaos: Option<FaultRates>,
+    chaos_seed: u64
+}
+
+impl Options {
+    pub fn get_input_file(&self) -> &str {
+        &self.input_file
+    }
+
+    pub fn get_watch_list_file(&self) -> Option<&str> {
+        self.watch_list_file.as_deref()
+    }
+
+    pub fn get_csv_file(&self) -> Option<&str> {
+        self.csv_file.as_deref()
+    }
+
+    pub fn get_arrow_file(&self) -> Option<&str> {
+        self.arrow_file.as_deref()
+    }
+
+    pub fn get_npy_dir(&self) -> Option<&str> {
+        self.npy_dir.as_deref()
+    }
+
+    pub fn get_cypher_file(&self) -> Option<&str> {
+        self.cypher_file.as_deref()
+    }
+
+    pub fn get_chart_dir(&self) -> Option<&str> {
+        self.chart_dir.as_deref()
+    }
+
+    pub fn get_chart_format(&self) -> &str {
+        &self.chart_format
+    }
+
+    pub fn get_kafka_topic(&self) -> Option<&str> {
+        self.kafka_topic.as_deref()
+    }
+
+    pub fn get_kafka_brokers(&self) -> &str {
+        &self.kafka_brokers
+    }
+
+    pub fn get_plugin_file(&self) -> Option<&str> {
+        self.plugin_file.as_deref()
+    }
+
+    pub fn get_sink(&self) -> Option<&str> {
+        self.sink.as_deref()
+    }
+
+    pub fn is_dashboard(&self) -> bool {
+        self.dashboard
+    }
+
+    pub fn get_top(&self) -> usize {
+        self.top
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn is_summary(&self) -> bool {
+        self.summary
+    }
+
+    pub fn is_latency(&self) -> bool {
+        self.latency
+    }
+
+    pub fn is_verify(&self) -> bool {
+        self.verify
+    }
+
+    pub fn is_daemon(&self) -> bool {
+        self.daemon
+    }
+
+    pub fn get_pid_file(&self) -> Option<&str> {
+        self.pid_file.as_deref()
+    }
+
+    pub fn get_log_file(&self) -> Option<&str> {
+        self.log_file.as_deref()
+    }
+
+    pub fn get_currency_aliases_file(&self) -> Option<&str> {
+        self.currency_aliases_file.as_deref()
+    }
+
+    pub fn get_exchange_aliases_file(&self) -> Option<&str> {
+        self.exchange_aliases_file.as_deref()
+    }
+
+    pub fn get_canonical_pairs_file(&self) -> Option<&str> {
+        self.canonical_pairs_file.as_deref()
+    }
+
+    pub fn get_partitions_file(&self) -> Option<&str> {
+        self.partitions_file.as_deref()
+    }
+
+    pub fn get_liquidity_file(&self) -> Option<&str> {
+        self.liquidity_file.as_deref()
+    }
+
+    pub fn get_audit_log_file(&self) -> Option<&str> {
+        self.audit_log_file.as_deref()
+    }
+
+    pub fn get_fees_file(&self) -> Option<&str> {
+        self.fees_file.as_deref()
+    }
+
+    pub fn get_weight_script_file(&self) -> Option<&str> {
+        self.weight_script_file.as_deref()
+    }
+
+    pub fn get_volume(&self) -> f64 {
+        self.volume
+    }
+
+    pub fn get_fee_side(&self) -> FeeSide {
+        self.fee_side
+    }
+
+    pub fn get_max_updates_per_sec(&self) -> Option<f64> {
+        self.max_updates_per_sec
+    }
+
+    pub fn get_max_memory(&self) -> Option<usize> {
+        self.max_memory
+    }
+
+    pub fn get_threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn get_profile_file(&self) -> Option<&str> {
+        self.profile_file.as_deref()
+    }
+
+    pub fn get_buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    pub fn get_backpressure(&self) -> Backpressure {
+        self.backpressure
+    }
+
+    pub fn get_chaos(&self) -> Option<&FaultRates> {
+        self.chaos.as_ref()
+    }
+
+    pub fn get_chaos_seed(&self) -> u64 {
+        self.chaos_seed
+    }
+
+    pub fn get_checkpoint_file(&self) -> Option<&str> {
+        self.checkpoint_file.as_deref()
+    }
+
+    pub fn get_checkpoint_every(&self) -> u64 {
+        self.checkpoint_every
+    }
+
+    pub fn is_warm_start(&self) -> bool {
+        self.warm_start
+    }
+
+    pub fn get_resume_file(&self) -> Option<&str> {
+        self.resume_file.as_deref()
+    }
+
+    pub fn get_output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
+    pub fn is_explain(&self) -> bool {
+        self.explain
+    }
+
+    pub fn get_notional(&self) -> f64 {
+        self.notional
+    }
+
+    pub fn get_hop_penalty(&self) -> Option<f64> {
+        self.hop_penalty
+    }
+
+    pub fn get_stale_after(&self) -> Option<u64> {
+        self.stale_after
+    }
+
+    pub fn get_dedup(&self) -> Option<usize> {
+        self.dedup
+    }
+
+    pub fn get_quarantine(&self) -> Option<f64> {
+        self.quarantine
+    }
+
+    pub fn get_min_confidence(&self) -> Option<f64> {
+        self.min_confidence
+    }
+
+    pub fn get_assumed_spread(&self) -> f64 {
+        self.assumed_spread
+    }
+
+    pub fn is_allow_crossed(&self) -> bool {
+        self.allow_crossed
+    }
+
+    pub fn get_timestamp_precision(&self) -> Precision {
+        self.timestamp_precision
+    }
+
+    pub fn get_http_header_file(&self) -> Option<&str> {
+        self.http_header_file.as_deref()
+    }
+
+    pub fn get_refetch_every(&self) -> Option<Duration> {
+        self.refetch_every
+    }
+
+    pub fn get_recompute_every(&self) -> Option<Duration> {
+        self.recompute_every
+    }
+
+    pub fn get_recompute_after(&self) -> Option<u64> {
+        self.recompute_after
+    }
+}
+
+pub fn usage() -> String {
+    let mut usage = String::from("Usage: cargo run [options] <input_file>, e.g. cargo run input.txt\n\
+        Pass `-` as the input file to read from standard input\n\n\
+        Subcommands:\n  completions <bash|zsh|fish>\n      Print a shell completion script\n  \
+        verify-scenarios <dir>\n      Check that the input.txt of every subdirectory of <dir> prints its expected_output.txt\n  \
+        sql <input_file> [<query>]\n      Query the edges, best_rates and updates_history tables built from <input_file>, \
+        reading queries from standard input if none is given\n  \
+        verify-audit <file> [<last_hash>]\n      Check that an audit log is intact and closed, and ends with <last_hash> if given\n  \
+        verify-replay <file> [options]\n      Replay an audit log with the options of the recorded runs and check every answer and graph is reproduced\n  \
+        generate [generate options]\n      Print a synthetic input of random walk prices with occasional arbitrage\n  \
+        stress [stress options]\n      Time finding the best rates and answering requests on a random graph built in memory\n\n\
+        Options:");
+    for (flag, value, description) in FLAGS {
+        usage.push_str(&format!("\n  {} {}\n      {}", flag, value, description));
+    }
+    usage.push_str("\n\nGenerate options:");
+    for (flag, value, description) in GENERATE_FLAGS {
+        usage.push_str(&format!("\n  {} {}\n      {}", flag, value, description));
+    }
+    usage.push_str("\n\nStress options:");
+    for (flag, value, description) in STRESS_FLAGS {
+        usage.push_str(&format!("\n  {} {}\n      {}", flag, value, description));
+    }
+    usage
+}
+
+/// Parse the command line arguments, excluding the program name, into a subcommand or a run
+pub fn parse_command(args: &[String]) -> Result<Command, String> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("completions") => match args {
+            [_, shell] => Ok(Command::Completions(shell.to_string())),
+            _ => Err("Usage: completions <bash|zsh|fish>".to_string())
+        },
+        Some("verify-scenarios") => match args {
+            [_, dir] => Ok(Command::VerifyScenarios(dir.to_string())),
+            _ => Err("Usage: verify-scenarios <dir>".to_string())
+        },
+        Some("sql") => match args {
+            [_, input_file] => Ok(Command::Sql(input_file.to_string(), None)),
+            [_, input_file, query] => Ok(Command::Sql(input_file.to_string(), Some(query.to_string()))),
+            _ => Err("Usage: sql <input_file> [<query>]".to_string())
+        },
+        Some("verify-audit") => match args {
+            [_, file_name] => Ok(Command::VerifyAudit(file_name.to_string(), None)),
+            [_, file_name, last_hash] => Ok(Command::VerifyAudit(file_name.to_string(), Some(last_hash.to_string()))),
+            _ => Err("Usage: verify-audit <file> [<last_hash>]".to_string())
+        },
+        Some("verify-replay") => Ok(Command::VerifyReplay(Box::new(parse_args(&args[1..])?))),
+        Some("generate") => Ok(Command::Generate(parse_generate_args(&args[1..])?)),
+        Some("stress") => Ok(Command::Stress(parse_stress_args(&args[1..])?)),
+        _ => Ok(Command::Run(Box::new(parse_args(args)?)))
+    }
+}
+
+// Parse the `--flag value` pairs of a subcommand taking the options `flags`, passing each one to `handle`
+fn parse_subcommand_flags<F>(subcommand: &str, flags: &[(&str, &str, &str)], args: &[String], mut handle: F)
+    -> Result<(), String>
+    where F: FnMut(&str, &str) -> Option<()> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match args.next() {
+            Some(value) if flags.iter().any(|(flag, _, _)| flag == arg) => handle(arg, value)
+                .ok_or_else(|| format!("Invalid value for {}: {}", arg, value))?,
+            Some(_) => return Err(format!("Unknown {} option {}", subcommand, arg)),
+            None => return Err(format!("Missing value for {}", arg))
+        }
+    }
+    Ok(())
+}
+
+// One thread per CPU, or a single one if their number is unknown
+fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+fn parse_fraction(value: &str) -> Option<f64> {
+    value.parse().ok().filter(|fraction| (0.0..=1.0).contains(fraction))
+}
+
+/// Parse the options of `generate`
+pub fn parse_generate_args(args: &[String]) -> Result<GenerateOptions, String> {
+    let mut options = GenerateOptions {
+        num_exchanges: 3, num_currencies: 4, num_updates: 1000, tick_rate: 10.0, volatility: 0.0001,
+        arbitrage_rate: 0.01, request_rate: 0.1, seed: 1
+    };
+    parse_subcommand_flags("generate", GENERATE_FLAGS, args, |flag, value| {
+        match flag {
+            "--exchanges" => options.num_exchanges = value.parse().ok().filter(|&n| n > 0)?,
+            "--currencies" => options.num_currencies = value.parse().ok().filter(|&n| n >= 2)?,
+            "--updates" => options.num_updates = value.parse().ok()?,
+            "--tick-rate" => options.tick_rate = value.parse().ok().filter(|&rate: &f64| rate > 0.0)?,
+            "--volatility" => options.volatility = value.parse().ok().filter(|&volatility: &f64| volatility >= 0.0)?,
+            "--arbitrage-rate" => options.arbitrage_rate = parse_fraction(value)?,
+            "--request-rate" => options.request_rate = parse_fraction(value)?,
+            _ => options.seed = value.parse().ok()?
+        }
+        Some(())
+    })?;
+    Ok(options)
+}
+
+/// Parse the options of `stress`
+pub fn parse_stress_args(args: &[String]) -> Result<StressOptions, String> {
+    let mut options = StressOptions { num_exchanges: 10, num_currencies: 20, density: 0.5, num_queries: 1000, seed: 1,
+        threads: default_threads() };
+    parse_subcommand_flags("stress", STRESS_FLAGS, args, |flag, value| {
+        match flag {
+            "--exchanges" => options.num_exchanges = value.parse().ok().filter(|&n| n > 0)?,
+            "--currencies" => options.num_currencies = value.parse().ok().filter(|&n| n >= 2)?,
+            "--density" => options.density = parse_fraction(value)?,
+            "--queries" => options.num_queries = value.parse().ok()?,
+            "--seed" => options.seed = value.parse().ok()?,
+            _ => options.threads = value.parse().ok().filter(|&n| n > 0)?
+        }
+        Some(())
+    })?;
+    Ok(options)
+}
+
+/// Parse the options of a run
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut input_file = None;
+    let mut watch_list_file = None;
+    let mut csv_file = None;
+    let mut arrow_file = None;
+    let mut npy_dir = None;
+    let mut cypher_file = None;
+    let mut chart_dir = None;
+    let mut chart_format = "svg".to_string();
+    let mut kafka_topic = None;
+    let mut kafka_brokers = None;
+    let mut plugin_file = None;
+    let mut sink = None;
+    let mut dashboard = false;
+    let mut top = 10;
+    let mut strict = false;
+    let mut summary = false;
+    let mut latency = false;
+    let mut verify = false;
+    let mut daemon = false;
+    let mut pid_file = None;
+    let mut log_file = None;
+    let mut currency_aliases_file = None;
+    let mut exchange_aliases_file = None;
+    let mut canonical_pairs_file = None;
+    let mut partitions_file = None;
+    let mut liquidity_file = None;
+    let mut fees_file = None;
+    let mut audit_log_file = None;
+    let mut weight_script_file = None;
+    let mut volume = 0.0;
+    let mut fee_side = FeeSide::Taker;
+    let mut max_updates_per_sec = None;
+    let mut max_memory = None;
+    let mut threads = None;
+    let mut profile_file = None;
+    let mut buffer_size = constants::INPUT_BUFFER_SIZE;
+    let mut backpressure = Backpressure::Block;
+    let mut checkpoint_file = None;
+    let mut checkpoint_every = None;
+    let mut warm_start = false;
+    let mut resume_file = None;
+    let mut output_format = OutputFormat::Text;
+    let mut explain = false;
+    let mut notional = 1.0;
+    let mut hop_penalty = None;
+    let mut stale_after = None;
+    let mut dedup = None;
+    let mut quarantine = None;
+    let mut confidence = false;
+    let mut min_confidence = None;
+    let mut assumed_spread = constants::ASSUMED_SPREAD;
+    let mut allow_crossed = false;
+    let mut timestamp_precision = Precision::Millis;
+    let mut http_header_file = None;
+    let mut refetch_every = None;
+    let mut recompute_every = None;
+    let mut recompute_after = None;
+    let mut chaos = None;
+    let mut chaos_seed = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg.starts_with("--") {
+            let takes_value = match FLAGS.iter().find(|(flag, _, _)| flag == arg) {
+                Some((_, value, _)) => !value.is_empty(),
+                None => return Err(format!("Unknown option {}", arg))
+            };
+            if !takes_value {
+                match arg.as_str() {
+                    "--dashboard" => dashboard = true,
+                    "--strict" => strict = true,
+                    "--explain" => explain = true,
+                    "--summary" => summary = true,
+                    "--latency" => latency = true,
+                    "--verify" => verify = true,
+                    "--daemon" => daemon = true,
+                    "--warm-start" => warm_start = true,
+                    "--confidence" => confidence = true,
+                    "--allow-crossed" => allow_crossed = true,
+                    _ => return Err(format!("Unknown option {}", arg))
+                }
+                continue;
+            }
+
+            let value = match args.next() {
+                Some(value) => value.to_string(),
+                None => return Err(format!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--watch-list" => watch_list_file = Some(value),
+                "--csv" => csv_file = Some(value),
+                "--arrow" => arrow_file = Some(value),
+                "--npy-dir" => npy_dir = Some(value),
+                "--cypher" => cypher_file = Some(value),
+                "--chart-dir" => chart_dir = Some(value),
+                "--chart-format" => chart_format = value,
+                "--kafka-topic" => kafka_topic = Some(value),
+                "--kafka-brokers" => kafka_brokers = Some(value),
+                "--plugin" => plugin_file = Some(value),
+                "--sink" => sink = Some(value),
+                "--pid-file" => pid_file = Some(value),
+                "--log-file" => log_file = Some(value),
+                "--currency-aliases" => currency_aliases_file = Some(value),
+                "--exchange-aliases" => exchange_aliases_file = Some(value),
+                "--canonical-pairs" => canonical_pairs_file = Some(value),
+                "--partitions" => partitions_file = Some(value),
+                "--liquidity" => liquidity_file = Some(value),
+                "--fees" => fees_file = Some(value),
+                "--audit-log" => audit_log_file = Some(value),
+                "--weight-script" => weight_script_file = Some(value),
+                "--volume" => volume = match value.parse() {
+                    Ok(volume) if volume >= 0.0 => volume,
+                    _ => return Err(format!("Invalid volume {}", value))
+                },
+                "--max-updates-per-sec" => max_updates_per_sec = match value.parse() {
+                    Ok(max_updates_per_sec) if max_updates_per_sec > 0.0 => Some(max_updates_per_sec),
+                    _ => return Err(format!("Invalid number of updates per second {}", value))
+                },
+                "--max-memory" => max_memory = match crate::parse_size(&value) {
+                    Some(max_memory) if max_memory > 0 => Some(max_memory),
+                    _ => return Err(format!("Invalid memory size {}", value))
+                },
+                "--threads" => threads = match value.parse() {
+                    Ok(threads) if threads > 0 => Some(threads),
+                    _ => return Err(format!("Invalid number of threads {}", value))
+                },
+                "--profile" => profile_file = Some(value),
+                "--buffer-size" => buffer_size = match value.parse() {
+                    Ok(buffer_size) if buffer_size > 0 => buffer_size,
+                    _ => return Err(format!("Invalid buffer size {}", value))
+                },
+                "--backpressure" => backpressure = match Backpressure::parse(&value) {
+                    Some(backpressure) => backpressure,
+                    None => return Err(format!("Unknown backpressure policy {}", value))
+                },
+                "--chaos" => chaos = Some(FaultRates::parse(&value)?),
+                "--chaos-seed" => chaos_seed = match value.parse() {
+                    Ok(seed) => Some(seed),
+                    Err(_) => return Err(format!("Invalid seed {}", value))
+                },
+                "--output-format" => output_format = match OutputFormat::parse(&value) {
+                    Some(output_format) => output_format,
+                    None => return Err(format!("Unknown output format {}", value))
+                },
+                "--checkpoint" => checkpoint_file = Some(value),
+                "--http-header-file" => http_header_file = Some(value),
+                "--refetch-every" => refetch_every = match crate::parse_duration(&value) {
+                    Some(millis) if millis > 0 => Some(Duration::from_millis(millis)),
+                    _ => return Err(format!("Invalid duration {}", value))
+                },
+                "--recompute-every" => recompute_every = match crate::parse_duration(&value) {
+                    Some(millis) if millis > 0 => Some(Duration::from_millis(millis)),
+                    _ => return Err(format!("Invalid duration {}", value))
+                },
+                "--recompute-after" => recompute_after = match value.parse() {
+                    Ok(recompute_after) if recompute_after > 0 => Some(recompute_after),
+                    _ => return Err(format!("Invalid number of price updates {}", value))
+                },
+                "--checkpoint-every" => checkpoint_every = match value.parse() {
+                    Ok(checkpoint_every) if checkpoint_every > 0 => Some(checkpoint_every),
+                    _ => return Err(format!("Invalid number of lines {}", value))
+                },
+                "--resume" => resume_file = Some(value),
+                "--notional" => notional = match value.parse() {
+                    Ok(notional) if notional > 0.0 => notional,
+                    _ => return Err(format!("Invalid notional {}", value))
+                },
+                "--dedup" => dedup = match value.parse() {
+                    Ok(dedup) if dedup > 0 => Some(dedup),
+                    _ => return Err(format!("Invalid dedup retention {}, expected a number of price updates", value))
+                },
+                "--quarantine" => quarantine = match value.parse() {
+                    Ok(multiple) if multiple > 1.0 => Some(multiple),
+                    _ => return Err(format!("Invalid quarantine multiple {}, expected more than 1", value))
+                },
+                "--min-confidence" => min_confidence = match value.parse() {
+                    Ok(fraction) if (0.0..=1.0).contains(&fraction) => Some(fraction),
+                    _ => return Err(format!("Invalid confidence {}", value))
+                },
+                "--stale-after" => stale_after = match crate::parse_duration(&value) {
+                    Some(millis) => Some(millis),
+                    None => return Err(format!("Invalid duration {}", value))
+                },
+                "--hop-penalty" => hop_penalty = match value.parse() {
+                    Ok(hop_penalty) if hop_penalty > 0.0 && hop_penalty <= 1.0 => Some(hop_penalty),
+                    _ => return Err(format!("Invalid hop penalty {}", value))
+                },
+                "--timestamp-precision" => timestamp_precision = match Precision::parse(&value) {
+                    Some(precision) => precision,
+                    None => return Err(format!("Invalid timestamp precision {}, expected ms, us or ns", value))
+                },
+                "--assumed-spread" => assumed_spread = match value.parse() {
+                    Ok(assumed_spread) if (0.0..1.0).contains(&assumed_spread) => assumed_spread,
+                    _ => return Err(format!("Invalid spread {}", value))
+                },
+                "--fee-side" => fee_side = match FeeSide::parse(&value) {
+                    Some(fee_side) => fee_side,
+                    None => return Err(format!("Unknown fee side {}", value))
+                },
+                "--top" => top = match value.parse() {
+                    Ok(top) => top,
+                    Err(_) => return Err(format!("Invalid number of pairs {}", value))
+                },
+                _ => return Err(format!("Unknown option {}", arg))
+            }
+        } else if input_file.is_none() {
+            input_file = Some(arg.to_string());
+        } else {
+            return Err(format!("Unexpected argument {}", arg));
+        }
+    }
+
+    let input_file = match input_file {
+        Some(input_file) => input_file,
+        None => return Err("Missing input file".to_string())
+    };
+    if csv_file.is_some() && watch_list_file.is_none() {
+        return Err("--csv requires --watch-list".to_string());
+    }
+    if chart_dir.is_some() && watch_list_file.is_none() {
+        return Err("--chart-dir requires --watch-list".to_string());
+    }
+    if kafka_topic.is_some() && watch_list_file.is_none() {
+        return Err("--kafka-topic requires --watch-list".to_string());
+    }
+    if sink.is_some() && watch_list_file.is_none() {
+        return Err("--sink requires --watch-list".to_string());
+    }
+    if kafka_brokers.is_some() && kafka_topic.is_none() {
+        return Err("--kafka-brokers requires --kafka-topic".to_string());
+    }
+    if chart_format != "svg" && chart_format != "png" {
+        return Err(format!("Unknown chart format {}", chart_format));
+    }
+    if checkpoint_every.is_some() && checkpoint_file.is_none() {
+        return Err("--checkpoint-every requires --checkpoint".to_string());
+    }
+    if warm_start && checkpoint_file.is_none() {
+        return Err("--warm-start requires --checkpoint".to_string());
+    }
+    if (checkpoint_file.is_some() || resume_file.is_some()) && input_file == "-" {
+        return Err("Standard input cannot be checkpointed or resumed".to_string());
+    }
+    if http_header_file.is_some() && !remote::is_http_url(&input_file) {
+        return Err("--http-header-file requires an http:// or https:// input".to_string());
+    }
+    if refetch_every.is_some() && !remote::is_url(&input_file) {
+        return Err("--refetch-every requires a URL input".to_string());
+    }
+    if chaos_seed.is_some() && chaos.is_none() {
+        return Err("--chaos-seed requires --chaos".to_string());
+    }
+    if dashboard && watch_list_file.is_none() {
+        return Err("--dashboard requires --watch-list".to_string());
+    }
+
+    Ok(Options {
+        input_file, watch_list_file, csv_file, arrow_file, npy_dir, cypher_file, chart_dir, chart_format,
+        kafka_topic,
+        kafka_brokers: kafka_brokers.unwrap_or_else(|| constants::KAFKA_BROKERS.to_string()), plugin_file, sink,
+        dashboard, top, strict, summary, latency, verify, daemon, pid_file, log_file, currency_aliases_file,
+        exchange_aliases_file, canonical_pairs_file, partitions_file, liquidity_file, fees_file, audit_log_file,
+        weight_script_file,
+        volume, fee_side, max_updates_per_sec, max_memory,
+        threads: threads.unwrap_or_else(default_threads), profile_file, buffer_size, backpressure, checkpoint_file,
+        checkpoint_every: checkpoint_every.unwrap_or(constants::CHECKPOINT_EVERY), warm_start, resume_file,
+        output_format, explain, notional, hop_penalty, stale_after, dedup, quarantine,
+        min_confidence: min_confidence.or(if confidence { Some(0.0) } else { None }), assumed_spread,
+        allow_crossed, timestamp_precision, http_header_file, refetch_every, recompute_every, recompute_after,
+        chaos, chaos_seed: chaos_seed.unwrap_or(1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_input_file_and_options() {
+        let options = parse_args(&to_args(&["--watch-list", "watch.txt", "input.txt", "--csv", "out.csv"])).unwrap();
+        assert_eq!(options.get_input_file(), "input.txt");
+        assert_eq!(options.get_watch_list_file(), Some("watch.txt"));
+        assert_eq!(options.get_csv_file(), Some("out.csv"));
+        assert!(!options.is_dashboard());
+
+        let options = parse_args(&to_args(&["-", "--dashboard", "--watch-list", "watch.txt", "--top", "5"])).unwrap();
+        assert_eq!(options.get_input_file(), "-");
+        assert!(options.is_dashboard());
+        assert_eq!(options.get_top(), 5);
+
+        assert_eq!(parse_args(&to_args(&["input.txt", "--threads", "2"])).unwrap().get_threads(), 2);
+        assert!(parse_args(&to_args(&["input.txt"])).unwrap().get_threads() >= 1);
+        assert_eq!(parse_args(&to_args(&["input.txt", "--confidence"])).unwrap().get_min_confidence(), Some(0.0));
+        assert_eq!(parse_args(&to_args(&["input.txt", "--min-confidence", "0.5"])).unwrap().get_min_confidence(), Some(0.5));
+        assert!(parse_args(&to_args(&["input.txt", "--allow-crossed"])).unwrap().is_allow_crossed());
+        assert!(parse_args(&to_args(&["input.txt", "--latency"])).unwrap().is_latency());
+        assert_eq!(parse_args(&to_args(&["input.txt", "--dedup", "100"])).unwrap().get_dedup(), Some(100));
+    }
+
+    #[test]
+    fn parse_subcommand() {
+        match parse_command(&to_args(&["completions", "bash"])).unwrap() {
+            Command::Completions(shell) => assert_eq!(shell, "bash"),
+            _ => panic!("Expected completions subcommand")
+        }
+        match parse_command(&to_args(&["verify-scenarios", "scenarios"])).unwrap() {
+            Command::VerifyScenarios(dir) => assert_eq!(dir, "scenarios"),
+            _ => panic!("Expected verify-scenarios subcommand")
+        }
+        match parse_command(&to_args(&["sql", "input.txt", "SELECT * FROM edges"])).unwrap() {
+            Command::Sql(input_file, query) => {
+                assert_eq!(input_file, "input.txt");
+                assert_eq!(query.as_deref(), Some("SELECT * FROM edges"));
+            },
+            _ => panic!("Expected sql subcommand")
+        }
+        match parse_command(&to_args(&["verify-audit", "audit.log"])).unwrap() {
+            Command::VerifyAudit(file_name, last_hash) => assert!(file_name == "audit.log" && last_hash.is_none()),
+            _ => panic!("Expected verify-audit subcommand")
+        }
+        match parse_command(&to_args(&["verify-replay", "audit.log", "--explain"])).unwrap() {
+            Command::VerifyReplay(options) => assert!(options.get_input_file() == "audit.log" && options.is_explain()),
+            _ => panic!("Expected verify-replay subcommand")
+        }
+        match parse_command(&to_args(&["generate", "--exchanges", "5", "--arbitrage-rate", "0.5"])).unwrap() {
+            Command::Generate(options) => assert!(options.get_num_exchanges() == 5 && options.get_arbitrage_rate() == 0.5
+                && options.get_num_currencies() == 4),
+            _ => panic!("Expected generate subcommand")
+        }
+        assert!(parse_command(&to_args(&["generate", "--currencies", "1"])).is_err());
+        assert!(parse_command(&to_args(&["generate", "--request-rate", "2"])).is_err());
+        assert!(parse_command(&to_args(&["generate", "--updates"])).is_err());
+        assert!(parse_command(&to_args(&["completions"])).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_arguments() {
+        assert!(parse_args(&to_args(&[])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--unknown", "value"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--watch-list"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--csv", "out.csv"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--refetch-every", "30s"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--threads", "0"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--quarantine", "1"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--dedup", "0"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--recompute-after", "0"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--chaos", "corrupt=2"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--chaos-seed", "7"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--recompute-every", "0s"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--min-confidence", "1.5"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--timestamp-precision", "ps"])).is_err());
+        assert!(parse_args(&to_args(&["input.txt", "--kafka-topic", "best-rates"])).is_err());
+        assert!(parse_args(&to_args(&["https://example.com/quotes.txt", "--refetch-every", "30"])).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+// Shell completion scripts generated from the command line flags
+
+use crate::cli::{FLAGS, GENERATE_FLAGS, STRESS_FLAGS};
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+const PROGRAM: &str = "tenx-challenge";
+
+// How the value of a flag is completed
+enum ValueKind {
+    None,
+    File,
+    Dir,
+    Choices(Vec<String>),
+    Other
+}
+
+// Derived from the value placeholder of a flag, e.g. `<file>` or `<svg|png>`
+fn value_kind(value: &str) -> ValueKind {
+    let value = value.trim_start_matches('<').trim_end_matches('>');
+    match value {
+        "" => ValueKind::None,
+        "file" => ValueKind::File,
+        "dir" => ValueKind::Dir,
+        _ if value.contains('|') => ValueKind::Choices(value.split('|').map(|choice| choice.to_string()).collect()),
+        _ => ValueKind::Other
+    }
+}
+
+pub fn generate(shell: &str) -> Result<String, String> {
+    match shell {
+        "bash" => Ok(bash()),
+        "zsh" => Ok(zsh()),
+        "fish" => Ok(fish()),
+        _ => Err(format!("Unsupported shell {}, expected one of {}", shell, SHELLS.join(", ")))
+    }
+}
+
+fn bash() -> String {
+    let mut cases = String::new();
+    for (flag, value, _) in FLAGS {
+        let reply = match value_kind(value) {
+            ValueKind::None => continue,
+            ValueKind::File => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+            ValueKind::Dir => "COMPREPLY=($(compgen -d -- \"$cur\"))".to_string(),
+            ValueKind::Choices(choices) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", choices.join(" ")),
+            ValueKind::Other => "COMPREPLY=()".to_string()
+        };
+        cases.push_str(&format!("        {})\n            {}\n            return\n            ;;\n", flag, reply));
+    }
+    let flags: Vec<&str> = FLAGS.iter().map(|(flag, _, _)| *flag).collect();
+    let generate_flags: Vec<&str> = GENERATE_FLAGS.iter().map(|(flag, _, _)| *flag).collect();
+    let stress_flags: Vec<&str> = STRESS_FLAGS.iter().map(|(flag, _, _)| *flag).collect();
+
+    format!(r#"_tenx_challenge() {{
+    local cur prev
+    cur="${{COMP_WORDS[COMP_CWORD]}}"
+    prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+
+    if [[ "${{COMP_WORDS[1]}}" == "completions" ]]; then
+        [[ $COMP_CWORD -eq 2 ]] && COMPREPLY=($(compgen -W "{shells}" -- "$cur"))
+        return
+    fi
+    if [[ "${{COMP_WORDS[1]}}" == "verify-scenarios" ]]; then
+        [[ $COMP_CWORD -eq 2 ]] && COMPREPLY=($(compgen -d -- "$cur"))
+        return
+    fi
+    if [[ "${{COMP_WORDS[1]}}" == "sql" || "${{COMP_WORDS[1]}}" == "verify-audit" ]]; then
+        [[ $COMP_CWORD -eq 2 ]] && COMPREPLY=($(compgen -f -- "$cur"))
+        return
+    fi
+    if [[ "${{COMP_WORDS[1]}}" == "generate" ]]; then
+        [[ "$cur" == -* ]] && COMPREPLY=($(compgen -W "{generate_flags}" -- "$cur"))
+        return
+    fi
+    if [[ "${{COMP_WORDS[1]}}" == "stress" ]]; then
+        [[ "$cur" == -* ]] && COMPREPLY=($(compgen -W "{stress_flags}" -- "$cur"))
+        return
+    fi
+
+    case "$prev" in
+{cases}    esac
+
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "{flags}" -- "$cur"))
+    elif [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W "completions verify-scenarios sql verify-audit verify-replay generate stress" -- "$cur") $(compgen -f -- "$cur"))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}}
+complete -F _tenx_challenge {program}
+"#, shells = SHELLS.join(" "), cases = cases, flags = flags.join(" "), generate_flags = generate_flags.join(" "),
+        stress_flags = stress_flags.join(" "), program = PROGRAM)
+}
+
+// Escape characters that are special inside a zsh `_arguments` spec
+fn zsh_escape(description: &str) -> String {
+    description.replace('\'', "'\\''").replace('[', "\\[").replace(']', "\\]").replace(':', "\\:")
+}
+
+fn zsh() -> String {
+    let mut specs = String::new();
+    for (flag, value, description) in FLAGS {
+        let action = match value_kind(value) {
+            ValueKind::None => String::new(),
+            ValueKind::File => ":file:_files".to_string(),
+            ValueKind::Dir => ":directory:_files -/".to_string(),
+            ValueKind::Choices(choices) => format!(":value:({})", choices.join(" ")),
+            ValueKind::Other => format!(":{}: ", value.trim_start_matches('<').trim_end_matches('>'))
+        };
+        specs.push_str(&format!("    '{}[{}]{}' \\\n", flag, zsh_escape(description), action));
+    }
+    let subcommand_specs = |flags: &[(&str, &str, &str)]| flags.iter()
+        .map(|(flag, value, description)| format!("'{}[{}]:{}: '", flag, zsh_escape(description),
+            value.trim_start_matches('<').trim_end_matches('>')))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!(r#"#compdef {program}
+
+if [[ "$words[2]" == "completions" ]]; then
+    _arguments '2:shell:({shells})'
+    return
+fi
+if [[ "$words[2]" == "verify-scenarios" ]]; then
+    _arguments '2:directory:_files -/'
+    return
+fi
+if [[ "$words[2]" == "sql" ]]; then
+    _arguments '2:input file:_files' '3:query: '
+    return
+fi
+if [[ "$words[2]" == "verify-audit" ]]; then
+    _arguments '2:audit log:_files' '3:last hash: '
+    return
+fi
+if [[ "$words[2]" == "generate" ]]; then
+    _arguments {generate_specs}
+    return
+fi
+if [[ "$words[2]" == "stress" ]]; then
+    _arguments {stress_specs}
+    return
+fi
+
+_arguments \
+{specs}    '1:input file or subcommand:{{_alternative "subcommands:subcommand:(completions verify-scenarios sql verify-audit verify-replay generate stress)" "files:input file:_files"}}'
+"#, program = PROGRAM, shells = SHELLS.join(" "), specs = specs,
+        generate_specs = subcommand_specs(GENERATE_FLAGS), stress_specs = subcommand_specs(STRESS_FLAGS))
+}
+
+fn fish() -> String {
+    let mut script = format!(
+        "complete -c {program} -n '__fish_use_subcommand' -a completions -d 'Generate shell completions'\n\
+        complete -c {program} -n '__fish_seen_subcommand_from completions' -x -a '{shells}'\n\
+        complete -c {program} -n '__fish_use_subcommand' -a verify-scenarios -d 'Run golden-file scenarios'\n\
+        complete -c {program} -n '__fish_seen_subcommand_from verify-scenarios' -x -a '(__fish_complete_directories)'\n\
+        complete -c {program} -n '__fish_use_subcommand' -a sql -d 'Query the state built from an input with SQL'\n\
+        complete -c {program} -n '__fish_seen_subcommand_from sql' -r -F\n\
+        complete -c {program} -n '__fish_use_subcommand' -a verify-audit -d 'Check the hash chain of an audit log'\n\
+        complete -c {program} -n '__fish_seen_subcommand_from verify-audit' -r -F\n\
+        complete -c {program} -n '__fish_use_subcommand' -a verify-replay -d 'Replay an audit log and check it is reproduced'\n\
+        complete -c {program} -n '__fish_use_subcommand' -a generate -d 'Print a synthetic input'\n\
+        complete -c {program} -n '__fish_use_subcommand' -a stress -d 'Time the algorithm on a random graph'\n",
+        program = PROGRAM, shells = SHELLS.join(" "));
+    for (flag, value, description) in FLAGS {
+        let arguments = match value_kind(value) {
+            ValueKind::None => String::new(),
+            ValueKind::File => " -r -F".to_string(),
+            ValueKind::Dir => " -r -x -a '(__fish_complete_directories)'".to_string(),
+            ValueKind::Choices(choices) => format!(" -x -a '{}'", choices.join(" ")),
+            ValueKind::Other => " -x".to_string()
+        };
+        script.push_str(&format!("complete -c {} -l {}{} -d '{}'\n", PROGRAM, flag.trim_start_matches("--"),
+            arguments, description.replace('\'', "\\'")));
+    }
+    for (subcommand, flags) in &[("generate", GENERATE_FLAGS), ("stress", STRESS_FLAGS)] {
+        for (flag, _, description) in flags.iter() {
+            script.push_str(&format!("complete -c {} -n '__fish_seen_subcommand_from {}' -l {} -x -d '{}'\n", PROGRAM,
+                subcommand, flag.trim_start_matches("--"), description.replace('\'', "\\'")));
+        }
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_completions_for_every_flag() {
+        for shell in SHELLS {
+            let script = generate(shell).unwrap();
+            for (flag, _, _) in FLAGS.iter().chain(GENERATE_FLAGS).chain(STRESS_FLAGS) {
+                assert!(script.contains(flag.trim_start_matches("--")), "{} completion is missing {}", shell, flag);
+            }
+            assert!(script.contains("svg png"));
+        }
+        assert!(generate("powershell").is_err());
+    }
+}
@@ -1,3 +1,75 @@
 pub const NUM_TOKEN_PRICE_UPDATE: u32 = 6;
+// A price update without the backward ratio
+pub const NUM_TOKEN_SHORTHAND_PRICE_UPDATE: u32 = 5;
 pub const NUM_TOKEN_EXCHANGE_RATE_REQUEST: u32 = 5;
-pub const DATETIME_FORMAT: &str = "%+";
\ No newline at end of file
+pub const DATETIME_FORMAT: &str = "%+";
+pub const EXCHANGE_RATE_REQUEST: &str = "EXCHANGE_RATE_REQUEST";
+pub const WINDOW_MODIFIER: &str = "WINDOW";
+pub const PARETO_MODIFIER: &str = "PARETO";
+pub const VIA_MODIFIER: &str = "VIA";
+// Kinds of vertices a `VIA` modifier routes through
+pub const VIA_EXCHANGE: &str = "EXCHANGE";
+pub const VIA_CURRENCY: &str = "CURRENCY";
+pub const AVOID_MODIFIER: &str = "AVOID";
+pub const PARTITION_MODIFIER: &str = "PARTITION";
+// Client request ID echoed back with the answer
+pub const REQUEST_ID_MODIFIER: &str = "ID";
+// Amount of the source currency to split across the best paths
+pub const SPLIT_MODIFIER: &str = "SPLIT";
+// Partition that every currency belongs to
+pub const ALL_PARTITION: &str = "all";
+// Input line replaced with the lines of another file
+pub const INCLUDE_DIRECTIVE: &str = "INCLUDE";
+// Number of input lines read ahead of processing
+pub const INPUT_BUFFER_SIZE: usize = 1024;
+pub const CHECKPOINT_EVERY: u64 = 100000;
+pub const KAFKA_BROKERS: &str = "localhost:9092";
+// Port of `mqtt://` URLs without one
+pub const MQTT_PORT: u16 = 1883;
+// Spread assumed between the quoted forward ratio and the derived backward ratio
+pub const ASSUMED_SPREAD: f64 = 0.001;
+// Longest exchange or currency name
+pub const MAX_NAME_LENGTH: usize = 32;
+pub const REQUEST_BATCH_SIZE: usize = 1024;
+// Largest graph checked by `--verify`, the exhaustive search takes exponential time
+pub const VERIFY_MAX_VERTICES: usize = 8;
+// Largest relative difference between best rates that `--verify` accepts
+pub const VERIFY_EPSILON: f64 = 1e-9;
+// Backoff between attempts to read more input in daemon mode
+pub const FOLLOW_INITIAL_BACKOFF_MS: u64 = 100;
+pub const FOLLOW_MAX_BACKOFF_MS: u64 = 5000;
+// Number of lines of an input that `--chaos` shuffles when it delivers a burst out of order
+pub const CHAOS_BURST_LINES: usize = 5;
+// Estimated bytes of memory taken by a vertex, by an edge, and by the best path between a pair of vertices
+pub const VERTEX_MEMORY: usize = 256;
+pub const EDGE_MEMORY: usize = 64;
+pub const BEST_PATH_MEMORY: usize = 96;
+// Fractions of `--max-memory` at which the stalest quotes start being evicted, and down to which they are
+pub const EVICT_MEMORY_FRACTION: f64 = 0.9;
+pub const EVICTED_MEMORY_FRACTION: f64 = 0.8;
+// Number of latest rates of a market that `--quarantine` compares an update with, and how many it needs to judge one
+pub const QUARANTINE_HISTORY: usize = 20;
+pub const QUARANTINE_MIN_HISTORY: usize = 3;
+// Half-life of the confidence in a quote updated once, of a quote in average intervals between its updates, and the
+// shortest half-life, see `EdgeWeight::get_confidence`
+pub const CONFIDENCE_HALF_LIFE_MS: u64 = 5 * 60 * 1000;
+pub const CONFIDENCE_HALF_LIFE_INTERVALS: u64 = 10;
+pub const CONFIDENCE_MIN_HALF_LIFE_MS: u64 = 1000;
+// Number of best paths that `SPLIT` spreads an amount across, the most hops they may have, and the number of equal
+// slices the amount is allocated in
+pub const SPLIT_PATHS: usize = 3;
+pub const SPLIT_MAX_HOPS: usize = 6;
+pub const SPLIT_SLICES: u32 = 100;
+// Relative difference between a best rate and the product of the rates of its path above which the rate went around
+// an arbitrage cycle, see `GraphResult::is_unbounded`
+pub const UNBOUNDED_RATE_TOLERANCE: f64 = 1e-9;
+// Interval between samples of `--profile`
+pub const PROFILE_SAMPLE_INTERVAL_MS: u64 = 1;
+
+// Exit codes
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_PARSE_ERROR: i32 = 2;
+pub const EXIT_IO_ERROR: i32 = 3;
+pub const EXIT_NO_ROUTE: i32 = 4;
+pub const EXIT_CONFIG_ERROR: i32 = 5;
+pub const EXIT_VERIFY_FAILED: i32 = 6;
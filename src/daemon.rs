@@ -0,0 +1,35 @@
+// Support for running as a long lived service
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::Mutex;
+
+static PID_FILE: Mutex<Option<String>> = Mutex::new(None);
+
+// Write the process id to `path`, failing if it belongs to another running process
+pub fn write_pid_file(path: &str) -> Result<(), String> {
+    if let Ok(content) = fs::read_to_string(path) {
+        let running = match content.trim().parse::<u32>() {
+            Ok(pid) => pid != process::id() && Path::new(&format!("/proc/{}", pid)).exists(),
+            Err(_) => false
+        };
+        if running {
+            return Err(format!("PID file {} belongs to a running process {}", path, content.trim()));
+        }
+    }
+    if let Err(e) = fs::write(path, format!("{}\n", process::id())) {
+        return Err(format!("Error encountered while writing PID file: {}", e));
+    }
+    *PID_FILE.lock().unwrap() = Some(path.to_string());
+    Ok(())
+}
+
+// Remove the PID file written by this process, if any
+pub fn remove_pid_file() -> Result<(), io::Error> {
+    match PID_FILE.lock().unwrap().take() {
+        Some(path) => fs::remove_file(path),
+        None => Ok(())
+    }
+}
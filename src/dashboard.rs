@@ -0,0 +1,171 @@
+// Live terminal view of watched pairs, update throughput and recent alerts
+
+#[cfg(feature = "dashboard")]
+pub use self::terminal::Dashboard;
+#[cfg(not(feature = "dashboard"))]
+pub use self::unsupported::Dashboard;
+
+use tenx_challenge::bus::{Event, Subscriber};
+
+// Printing would corrupt the dashboard, so answers and alerts are shown in its list of alerts, answers by their rate
+impl Subscriber for Dashboard {
+    fn handle(&mut self, event: &Event) -> Result<(), String> {
+        match event {
+            Event::Price(_) => self.record_update(),
+            Event::Query(_, answer, _) => self.alert(answer.lines().next().unwrap_or_default().to_string()),
+            Event::Result(rate_point) => self.alert(format!("{} best rate changed to {} ({} hops)",
+                rate_point.get_pair(), rate_point.get_best_rate(), rate_point.get_hops())),
+            Event::Alert(alert) => self.alert(alert.to_string())
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "dashboard")]
+mod terminal {
+    use std::collections::VecDeque;
+    use std::time::{Duration, Instant};
+
+    use ratatui::crossterm::event::{self, Event, KeyCode};
+    use ratatui::layout::{Constraint, Layout};
+    use ratatui::style::{Modifier, Style};
+    use ratatui::widgets::{Block, Borders, List, Paragraph, Row, Table};
+    use ratatui::{DefaultTerminal, Frame};
+
+    use tenx_challenge::watch::WatchedPair;
+
+    const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+    const THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+    const MAX_ALERTS: usize = 50;
+
+    pub struct Dashboard {
+        terminal: DefaultTerminal,
+        // number of watched pairs shown
+        top: usize,
+        started: Instant,
+        last_refresh: Instant,
+        num_updates: u64,
+        // time of each update within the last `THROUGHPUT_WINDOW`
+        recent_updates: VecDeque<Instant>,
+        // most recent first
+        alerts: VecDeque<String>
+    }
+
+    impl Dashboard {
+        pub fn start(top: usize) -> Result<Dashboard, String> {
+            let terminal = match ratatui::try_init() {
+                Ok(terminal) => terminal,
+                Err(e) => return Err(format!("Unable to start dashboard: {}", e))
+            };
+            Ok(Dashboard {
+                terminal, top, started: Instant::now(), last_refresh: Instant::now(), num_updates: 0,
+                recent_updates: VecDeque::new(), alerts: VecDeque::new()
+            })
+        }
+
+        pub fn record_update(&mut self) {
+            self.num_updates += 1;
+            self.recent_updates.push_back(Instant::now());
+        }
+
+        pub fn alert(&mut self, alert: String) {
+            self.alerts.push_front(alert);
+            self.alerts.truncate(MAX_ALERTS);
+        }
+
+        // Redraw if the refresh interval has elapsed. Return false if the user asked to quit
+        pub fn refresh(&mut self, pairs: &[WatchedPair]) -> bool {
+            if self.last_refresh.elapsed() < REFRESH_INTERVAL {
+                return true;
+            }
+            self.draw(pairs);
+            !quit_requested(Duration::from_millis(0))
+        }
+
+        // Keep showing the final state until the user quits, then restore the terminal
+        pub fn finish(mut self, pairs: &[WatchedPair]) {
+            self.alert("End of input, press q to quit".to_string());
+            loop {
+                self.draw(pairs);
+                if quit_requested(REFRESH_INTERVAL) {
+                    break;
+                }
+            }
+            ratatui::restore();
+        }
+
+        fn draw(&mut self, pairs: &[WatchedPair]) {
+            while let Some(instant) = self.recent_updates.front() {
+                if instant.elapsed() <= THROUGHPUT_WINDOW {
+                    break;
+                }
+                self.recent_updates.pop_front();
+            }
+            self.last_refresh = Instant::now();
+
+            let header = format!("Updates: {}   Throughput: {} updates/s   Uptime: {}s   (press q to quit)",
+                self.num_updates, self.recent_updates.len(), self.started.elapsed().as_secs());
+            let rows: Vec<Row> = pairs.iter().take(self.top).map(|pair| {
+                let path = pair.get_path().iter()
+                    .map(|vertex| vertex.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                Row::new(vec![pair.label(), pair.get_best_rate().to_string(), path])
+            }).collect();
+            let alerts: Vec<String> = self.alerts.iter().cloned().collect();
+
+            let result = self.terminal.draw(|frame: &mut Frame| {
+                let [header_area, pairs_area, alerts_area] = Layout::vertical([
+                    Constraint::Length(3), Constraint::Percentage(60), Constraint::Fill(1)
+                ]).areas(frame.area());
+
+                frame.render_widget(Paragraph::new(header)
+                    .block(Block::default().borders(Borders::ALL).title("tenx-challenge")), header_area);
+                frame.render_widget(Table::new(rows, [
+                    Constraint::Length(32), Constraint::Length(16), Constraint::Fill(1)
+                ])
+                    .header(Row::new(vec!["Pair", "Best rate", "Path"]).style(Style::default().add_modifier(Modifier::BOLD)))
+                    .block(Block::default().borders(Borders::ALL).title("Watched pairs")), pairs_area);
+                frame.render_widget(List::new(alerts)
+                    .block(Block::default().borders(Borders::ALL).title("Recent alerts")), alerts_area);
+            });
+            if let Err(e) = result {
+                ratatui::restore();
+                panic!("Error encountered while drawing dashboard: {}\nExiting...", e);
+            }
+        }
+    }
+
+    fn quit_requested(timeout: Duration) -> bool {
+        if let Ok(true) = event::poll(timeout) {
+            if let Ok(Event::Key(key)) = event::read() {
+                return key.code == KeyCode::Char('q') || key.code == KeyCode::Esc;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(not(feature = "dashboard"))]
+mod unsupported {
+    use tenx_challenge::watch::WatchedPair;
+
+    // Stand-in for builds without the `dashboard` feature, it can never be started
+    pub struct Dashboard;
+
+    impl Dashboard {
+        pub fn start(_top: usize) -> Result<Dashboard, String> {
+            Err("The dashboard is not supported by this build, rebuild with `--features dashboard`".to_string())
+        }
+
+        pub fn record_update(&mut self) {}
+
+        pub fn alert(&mut self, _alert: String) {}
+
+        pub fn refresh(&mut self, _pairs: &[WatchedPair]) -> bool {
+            true
+        }
+
+        pub fn finish(self, _pairs: &[WatchedPair]) {}
+    }
+}
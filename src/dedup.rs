@@ -0,0 +1,67 @@
+// Dropping price updates delivered more than once, e.g. by at-least-once transports such as Kafka or webhooks, which
+// would otherwise count as updates of their quotes and bump the graph version. An update is a repeat when the same
+// exchange quoted the same pair at the same timestamp, and only the keys of the latest updates are kept
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::model::PriceUpdate;
+
+// (exchange, source currency, destination currency, milliseconds, nanoseconds past the millisecond)
+type DedupKey = (String, String, String, u64, u32);
+
+pub struct Deduplicator {
+    // number of keys kept, the oldest one is forgotten when a new one comes
+    retention: usize,
+    keys: HashSet<DedupKey>,
+    // keys in the order they came
+    order: VecDeque<DedupKey>,
+    num_duplicates: u64
+}
+
+impl Deduplicator {
+    pub fn new(retention: usize) -> Deduplicator {
+        Deduplicator { retention, keys: HashSet::new(), order: VecDeque::new(), num_duplicates: 0 }
+    }
+
+    // Whether `price_update` repeats one of the last `retention` updates, remembering it if it does not
+    pub fn is_duplicate(&mut self, price_update: &PriceUpdate) -> bool {
+        let key = (price_update.get_exchange().to_string(), price_update.get_source_currency().to_string(),
+            price_update.get_dest_currency().to_string(), price_update.get_datetime(), price_update.get_sub_millis());
+        if self.keys.contains(&key) {
+            self.num_duplicates += 1;
+            return true;
+        }
+        if self.order.len() == self.retention {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+        self.keys.insert(key.clone());
+        self.order.push_back(key);
+        false
+    }
+
+    pub fn get_num_duplicates(&self) -> u64 {
+        self.num_duplicates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::quote;
+
+    #[test]
+    fn drop_repeated_price_updates() {
+        let mut dedup = Deduplicator::new(2);
+        assert!(!dedup.is_duplicate(&quote(1, "KRAKEN", "BTC", "USD", 1000.0, 0.0009)));
+        assert!(dedup.is_duplicate(&quote(1, "KRAKEN", "BTC", "USD", 1000.0, 0.0009)));
+        // Another timestamp, exchange or pair is another update
+        assert!(!dedup.is_duplicate(&quote(2, "KRAKEN", "BTC", "USD", 1000.0, 0.0009)));
+        assert!(!dedup.is_duplicate(&quote(2, "GDAX", "BTC", "USD", 1000.0, 0.0009)));
+        // Only the last 2 keys are kept
+        assert!(!dedup.is_duplicate(&quote(1, "KRAKEN", "BTC", "USD", 1000.0, 0.0009)));
+        assert!(dedup.is_duplicate(&quote(2, "GDAX", "BTC", "USD", 1001.0, 0.0009)));
+        assert_eq!(dedup.get_num_duplicates(), 2);
+    }
+}
@@ -0,0 +1,1271 @@
+// The graph built from price updates and the answers computed from it
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cache::{self, QueryCache};
+use crate::checkpoint::Checkpoint;
+use crate::constants;
+use crate::export;
+use crate::fees::FeeModel;
+use crate::graph::Weight;
+use crate::model::{ExchangeRateRequest, Graph, GraphResult, PriceUpdate, Vertex, VertexSet, Via};
+use crate::logger;
+use crate::output::{self, Explanation, FeeBreakdown, OutputFormat, RateResult};
+use crate::partition::Partitions;
+use crate::profile;
+use crate::script::WeightScript;
+use crate::split::{self, Liquidity};
+use crate::timing::{Phase, PhaseTimings};
+use crate::verify::{self, Divergence};
+use crate::watch::{RatePoint, WatchList};
+
+// A change of the best rate or best path between a subscribed pair of vertices
+pub struct RateChange {
+    from_vertex: Arc<Vertex>,
+    to_vertex: Arc<Vertex>,
+    // 0 when there is no longer a route
+    best_rate: f64,
+    path: Option<Vec<Arc<Vertex>>>,
+    version: u64,
+    datetime: u64
+}
+
+impl RateChange {
+    pub fn get_from_vertex(&self) -> &Arc<Vertex> {
+        &self.from_vertex
+    }
+
+    pub fn get_to_vertex(&self) -> &Arc<Vertex> {
+        &self.to_vertex
+    }
+
+    pub fn get_best_rate(&self) -> f64 {
+        self.best_rate
+    }
+
+    pub fn get_path(&self) -> Option<&[Arc<Vertex>]> {
+        self.path.as_deref()
+    }
+
+    // Graph version that produced the change
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    // Timestamp of the price update that produced the change
+    pub fn get_datetime(&self) -> u64 {
+        self.datetime
+    }
+}
+
+// Best rates before fees being found on a background thread, see `Engine::schedule_recompute`
+struct Recompute {
+    // graph version the best rates are found at
+    version: u64,
+    // the graph with its best rates found, and how long finding them took
+    receiver: Receiver<(GraphResult, Duration)>
+}
+
+struct Subscription {
+    from_vertex: Arc<Vertex>,
+    to_vertex: Arc<Vertex>,
+    best_rate: f64,
+    path: Option<Vec<Arc<Vertex>>>,
+    sender: Sender<RateChange>
+}
+
+pub struct Engine {
+    graph: Graph,
+    graph_result: GraphResult,
+    // incremented on every accepted price update
+    version: u64,
+    // version the best rates before fees of `graph_result` were last found at
+    best_rates_version: Option<u64>,
+    // timestamp of the latest price update seen so far
+    latest_datetime: u64,
+    cache: QueryCache,
+    output_format: OutputFormat,
+    // whether answers explain the rate of every hop and the next best alternative
+    explain: bool,
+    // amount of the source currency that fees are reported for
+    notional: f64,
+    // factor every hop multiplies the score of a path by when choosing routes, none to route on the rate alone
+    hop_penalty: Option<f64>,
+    // milliseconds after which a quote on the best path makes an answer flagged as stale, none to never flag them
+    stale_after: Option<u64>,
+    // confidence below which a path is not returned, none to leave confidence out of answers
+    min_confidence: Option<f64>,
+    // turns the ratios of price updates into edge weights, none to use the ratios as they are
+    weight_script: Option<WeightScript>,
+    // bytes the estimated memory of the graph is kept under, none for no limit
+    max_memory: Option<usize>,
+    // partitions of the currencies that requests can confine their routes to
+    partitions: Partitions,
+    // depth of the markets that conversions with a `SPLIT` modifier are split across paths by
+    liquidity: Liquidity,
+    // interval and number of price updates after which best rates out of date are found in the background, none to
+    // only find them when a request needs them
+    recompute_every: Option<Duration>,
+    recompute_after: Option<u64>,
+    recompute: Option<Recompute>,
+    // when the last background recompute started, and the graph version it started at
+    last_recompute: (Instant, u64),
+    phase_timings: PhaseTimings,
+    subscriptions: Vec<Subscription>
+}
+
+impl Engine {
+    pub fn new(output_format: OutputFormat) -> Engine {
+        Engine {
+            graph: Graph::new(), graph_result: GraphResult::new(), version: 0, best_rates_version: None,
+            latest_datetime: 0,
+            cache: QueryCache::new(), output_format, explain: false, notional: 1.0,
+            hop_penalty: None, stale_after: None, min_confidence: None, weight_script: None, max_memory: None,
+            partitions: Partitions::new(), liquidity: Liquidity::new(), recompute_every: None, recompute_after: None,
+            recompute: None, last_recompute: (Instant::now(), 0), phase_timings: PhaseTimings::new(),
+            subscriptions: Vec::new()
+        }
+    }
+
+    pub fn set_explain(&mut self, explain: bool) {
+        self.explain = explain;
+        self.cache.clear();
+    }
+
+    pub fn set_notional(&mut self, notional: f64) {
+        self.notional = notional;
+        self.cache.clear();
+    }
+
+    // Prefer shorter paths: a path is chosen by its rate times `hop_penalty` to the power of its number of hops,
+    // while answers still report its rate
+    pub fn set_hop_penalty(&mut self, hop_penalty: Option<f64>) {
+        self.hop_penalty = hop_penalty;
+        self.cache.clear();
+    }
+
+    // Flag answers whose best path uses a quote older than `stale_after` milliseconds before the latest price update
+    pub fn set_stale_after(&mut self, stale_after: Option<u64>) {
+        self.stale_after = stale_after;
+    }
+
+    // Report the confidence of the best path of every answer, see `EdgeWeight::get_confidence`, and answer with no
+    // route when it is below `min_confidence`
+    pub fn set_min_confidence(&mut self, min_confidence: Option<f64>) {
+        self.min_confidence = min_confidence;
+        self.cache.clear();
+    }
+
+    // Report every answer that can go around an arbitrage cycle as unbounded, see `GraphResult::is_unbounded`, rather
+    // than only those whose best rate went around it, since crossed markets make cycles that every route can reach
+    pub fn set_allow_crossed(&mut self, allow_crossed: bool) {
+        self.graph_result.set_allow_crossed(allow_crossed);
+        self.best_rates_version = None;
+        self.cache.clear();
+    }
+
+    // Weigh the edges of later price updates with `weight_script`. Edges already in the graph are left as they are
+    pub fn set_weight_script(&mut self, weight_script: Option<WeightScript>) {
+        self.weight_script = weight_script;
+    }
+
+    // Keep the estimated memory of the graph under `max_memory` bytes by evicting the stalest quotes, see
+    // `evict_stalest_quotes`
+    pub fn set_max_memory(&mut self, max_memory: Option<usize>) {
+        self.max_memory = max_memory;
+    }
+
+    // Answer requests with a `PARTITION` modifier by routing only between the currencies of their partition
+    pub fn set_partitions(&mut self, partitions: Partitions) {
+        self.partitions = partitions;
+        self.cache.clear();
+    }
+
+    pub fn get_partitions(&self) -> &Partitions {
+        &self.partitions
+    }
+
+    // Split the conversions of requests with a `SPLIT` modifier across paths by the depth of their markets
+    pub fn set_liquidity(&mut self, liquidity: Liquidity) {
+        self.liquidity = liquidity;
+        self.cache.clear();
+    }
+
+    // Have `schedule_recompute` find best rates out of date in the background every `recompute_every`, or once
+    // `recompute_after` price updates changed them, whichever comes first
+    pub fn set_recompute(&mut self, recompute_every: Option<Duration>, recompute_after: Option<u64>) {
+        self.recompute_every = recompute_every;
+        self.recompute_after = recompute_after;
+    }
+
+    // Estimated bytes of memory taken by the graph and the best paths between its vertices
+    pub fn estimate_memory(&self) -> usize {
+        let num_vertices = self.graph.get_vertices().len();
+        num_vertices * constants::VERTEX_MEMORY + self.graph_result.num_edges() * constants::EDGE_MEMORY
+            + num_vertices * num_vertices * constants::BEST_PATH_MEMORY
+    }
+
+    pub fn get_graph_result(&self) -> &GraphResult {
+        &self.graph_result
+    }
+
+    pub fn get_vertices(&self) -> &VertexSet {
+        self.graph.get_vertices()
+    }
+
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn get_latest_datetime(&self) -> u64 {
+        self.latest_datetime
+    }
+
+    // Durations of the phases of applying price updates and answering requests so far
+    pub fn get_phase_timings(&self) -> &PhaseTimings {
+        &self.phase_timings
+    }
+
+    pub fn get_num_cache_hits(&self) -> u64 {
+        self.cache.get_num_hits()
+    }
+
+    // Bring the best rates before fees of `get_graph_result` up to date with the latest price update
+    pub fn find_best_rates(&mut self) {
+        if self.has_best_rates() {
+            return;
+        }
+        // Waiting for a background recompute of the latest price update is quicker than starting over
+        if self.recompute.as_ref().is_some_and(|recompute| recompute.version == self.version) {
+            self.install_recompute(true);
+            if self.has_best_rates() {
+                return;
+            }
+        }
+        self.best_rates_version = Some(self.version);
+        let _span = profile::span("find_best_rates");
+        let started = Instant::now();
+        self.graph_result.find_best_rates(self.graph.get_vertices());
+        self.phase_timings.record(Phase::BestRates, started.elapsed());
+    }
+
+    // Start finding the best rates before fees on a copy of the graph in a background thread when they are out of date
+    // and due, see `set_recompute`, and swap in those found by an earlier call once they are ready. Requests then
+    // rarely wait for the best rates to be found. Only one recompute runs at a time, and one that price updates made
+    // out of date before it finished is discarded
+    pub fn schedule_recompute(&mut self) {
+        self.install_recompute(false);
+        if self.has_best_rates() || self.recompute.is_some() {
+            return;
+        }
+        let (last_started, last_version) = self.last_recompute;
+        let due = self.recompute_every.is_some_and(|recompute_every| last_started.elapsed() >= recompute_every)
+            || self.recompute_after.is_some_and(|recompute_after| {
+                self.version.saturating_sub(last_version) >= recompute_after
+            });
+        if !due {
+            return;
+        }
+        let mut graph_result = self.graph_result.map_edges(|_, _, edge| Some(edge.get_weight()));
+        let vertices = self.graph.get_vertices().clone();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let started = Instant::now();
+            graph_result.find_best_rates(&vertices);
+            // The engine may have moved on and dropped the receiver
+            let _ = sender.send((graph_result, started.elapsed()));
+        });
+        self.recompute = Some(Recompute { version: self.version, receiver });
+        self.last_recompute = (Instant::now(), self.version);
+    }
+
+    // Swap in the best rates of a finished background recompute if they are still up to date, waiting for it to
+    // finish if `wait`
+    fn install_recompute(&mut self, wait: bool) {
+        let recompute = match self.recompute.take() {
+            Some(recompute) => recompute,
+            None => return
+        };
+        let received = if wait {
+            recompute.receiver.recv().ok()
+        } else {
+            match recompute.receiver.try_recv() {
+                Ok(received) => Some(received),
+                Err(TryRecvError::Empty) => {
+                    self.recompute = Some(recompute);
+                    return;
+                },
+                Err(TryRecvError::Disconnected) => None
+            }
+        };
+        if let Some((graph_result, duration)) = received {
+            if recompute.version == self.version && !self.has_best_rates() {
+                self.graph_result = graph_result;
+                self.best_rates_version = Some(self.version);
+                self.phase_timings.record(Phase::BestRates, duration);
+            }
+        }
+    }
+
+    // Compare the best rates before fees with an exhaustive search of every path, see `verify::verify`.
+    // None when the graph has more than `max_vertices` vertices, since the search takes exponential time
+    pub fn verify(&mut self, max_vertices: usize, epsilon: f64) -> Option<Vec<Divergence>> {
+        if self.graph.get_vertices().len() > max_vertices {
+            return None;
+        }
+        self.find_best_rates();
+        Some(verify::verify(&self.graph_result, self.graph.get_vertices(), epsilon))
+    }
+
+    // Forget cached answers when something other than a price update changes them, e.g. fees
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
+    // Whether the best rates before fees of `get_graph_result` are up to date with the latest price update
+    pub fn has_best_rates(&self) -> bool {
+        self.best_rates_version == Some(self.version)
+    }
+
+    // Continue from the quotes saved in a checkpoint, and from its best rates if it has them
+    pub fn restore(&mut self, checkpoint: &Checkpoint) {
+        checkpoint.restore(&mut self.graph, &mut self.graph_result);
+        self.latest_datetime = checkpoint.get_latest_datetime();
+        self.version = checkpoint.get_version();
+        // a recompute of the graph before the checkpoint could be at the same version
+        self.recompute = None;
+        self.best_rates_version = if checkpoint.has_best_rates() { Some(self.version) } else { None };
+    }
+
+    pub fn apply_price_update(&mut self, price_update: PriceUpdate) {
+        let _span = profile::span("apply_price_update");
+        self.latest_datetime = self.latest_datetime.max(price_update.get_datetime());
+        let (forward_weight, backward_weight) = match &self.weight_script {
+            Some(weight_script) => (
+                script_weight(weight_script, &price_update.get_source_vertex(), &price_update.get_dest_vertex(),
+                    price_update.get_forward_ratio(), price_update.get_datetime(), price_update.is_forward_derived()),
+                script_weight(weight_script, &price_update.get_dest_vertex(), &price_update.get_source_vertex(),
+                    price_update.get_backward_ratio(), price_update.get_datetime(), price_update.is_backward_derived())
+            ),
+            None => (Some(price_update.get_forward_ratio()), Some(price_update.get_backward_ratio()))
+        };
+        handle_price_update(&mut self.graph, &mut self.graph_result, price_update, forward_weight, backward_weight,
+            &mut self.phase_timings);
+        if let Some(max_memory) = self.max_memory {
+            if self.estimate_memory() as f64 >= max_memory as f64 * constants::EVICT_MEMORY_FRACTION {
+                self.evict_stalest_quotes(max_memory);
+            }
+        }
+        self.version += 1;
+        self.notify_subscribers();
+    }
+
+    // Remove the stalest markets, i.e. the quotes both ways between two currencies of an exchange, until the estimated
+    // memory is under `EVICTED_MEMORY_FRACTION` of `max_memory`. Vertices left without quotes are removed too,
+    // since the best paths between every pair of vertices take most of the memory
+    fn evict_stalest_quotes(&mut self, max_memory: usize) {
+        let _span = profile::span("evict_stalest_quotes");
+        let mut markets = Vec::new();
+        for (from_vertex, to_vertex, edge) in self.graph_result.edges() {
+            if from_vertex.get_exchange() != to_vertex.get_exchange() {
+                continue;
+            }
+            // Each market once, by the latest of its two quotes
+            let reverse = self.graph_result.get_edge(to_vertex, from_vertex);
+            if reverse.is_none() || from_vertex < to_vertex {
+                let last_updated = reverse.map_or(0, |reverse| reverse.get_last_updated()).max(edge.get_last_updated());
+                markets.push((last_updated, from_vertex.clone(), to_vertex.clone()));
+            }
+        }
+        markets.sort();
+
+        let estimate = self.estimate_memory();
+        let target = (max_memory as f64 * constants::EVICTED_MEMORY_FRACTION) as usize;
+        let mut num_evicted = 0;
+        let mut latest_evicted = 0;
+        for (last_updated, from_vertex, to_vertex) in markets {
+            if self.estimate_memory() < target {
+                break;
+            }
+            self.graph_result.remove_edge(&from_vertex, &to_vertex);
+            self.graph_result.remove_edge(&to_vertex, &from_vertex);
+            for vertex in [from_vertex, to_vertex].iter() {
+                let graph_result = &self.graph_result;
+                let quoted = self.graph.get_vertices().iter().any(|other| {
+                    other.get_exchange() == vertex.get_exchange()
+                        && (graph_result.get_edge(vertex, other).is_some() || graph_result.get_edge(other, vertex).is_some())
+                });
+                if !quoted {
+                    self.graph_result.remove_vertex(vertex);
+                    self.graph.remove_vertex(vertex);
+                }
+            }
+            num_evicted += 1;
+            latest_evicted = last_updated;
+        }
+        logger::log(&format!("Estimated graph memory of {} bytes is near the limit of {} bytes, evicted the {} stalest \
+            market(s), last quoted at {} or earlier, leaving {} bytes", estimate, max_memory, num_evicted,
+            export::format_datetime(latest_evicted), self.estimate_memory()));
+    }
+
+    // Receive a `RateChange` whenever the best rate or best path from `from_vertex` to `to_vertex` changes,
+    // until the receiver is dropped. Rates are before fees
+    pub fn subscribe(&mut self, from_vertex: Vertex, to_vertex: Vertex) -> Receiver<RateChange> {
+        let (sender, receiver) = mpsc::channel();
+        let from_vertex = Arc::new(from_vertex);
+        let to_vertex = Arc::new(to_vertex);
+        self.find_best_rates();
+        self.subscriptions.push(Subscription {
+            best_rate: self.graph_result.get_best_rate(&from_vertex, &to_vertex),
+            path: self.graph_result.best_rate_path(&from_vertex, &to_vertex),
+            from_vertex, to_vertex, sender
+        });
+        receiver
+    }
+
+    // Send the changes of subscribed pairs, and forget subscriptions whose receiver is gone
+    fn notify_subscribers(&mut self) {
+        if self.subscriptions.is_empty() {
+            return;
+        }
+        self.find_best_rates();
+        let graph_result = &self.graph_result;
+        let (version, datetime) = (self.version, self.latest_datetime);
+        self.subscriptions.retain_mut(|subscription| {
+            let best_rate = graph_result.get_best_rate(&subscription.from_vertex, &subscription.to_vertex);
+            let path = graph_result.best_rate_path(&subscription.from_vertex, &subscription.to_vertex);
+            if best_rate == subscription.best_rate && path == subscription.path {
+                return true;
+            }
+            subscription.best_rate = best_rate;
+            subscription.path = path.clone();
+            subscription.sender.send(RateChange {
+                from_vertex: subscription.from_vertex.clone(), to_vertex: subscription.to_vertex.clone(),
+                best_rate, path, version, datetime
+            }).is_ok()
+        });
+    }
+
+    // Recompute best rates after a price update and return the watched pairs whose best rate changed
+    pub fn update_watched_pairs(&mut self, watch_list: &mut WatchList, fee_model: Option<&FeeModel>) -> Vec<RatePoint> {
+        let _span = profile::span("update_watched_pairs");
+        match fee_model {
+            Some(fee_model) => {
+                let mut fee_graph_result = fee_model.apply(&self.graph_result);
+                fee_graph_result.find_best_rates(self.graph.get_vertices());
+                watch_list.update(self.latest_datetime, &fee_graph_result)
+            },
+            None => {
+                self.find_best_rates();
+                watch_list.update(self.latest_datetime, &self.graph_result)
+            }
+        }
+    }
+
+    // Answer requests that arrived without a price update in between, in the order of the requests
+    // Requests answered before at the same graph version are served from the cache, the rest are split among
+    // up to `num_workers` threads, computing a request repeated within the batch only once. Requests differing only in
+    // their request ID are the same request
+    pub fn answer(&mut self, exchange_rate_requests: &[ExchangeRateRequest], fee_model: Option<&FeeModel>,
+        num_workers: usize
+    ) -> Vec<(String, bool)> {
+        let _span = profile::span("answer");
+        let mut answers = vec![None; exchange_rate_requests.len()];
+        let mut uncached = Vec::new();
+        let mut uncached_keys = HashSet::new();
+        let mut repeated = Vec::new();
+        for (i, exchange_rate_request) in exchange_rate_requests.iter().enumerate() {
+            answers[i] = self.cache.get(self.version, exchange_rate_request);
+            if answers[i].is_none() {
+                if uncached_keys.insert(cache::cache_key(exchange_rate_request)) {
+                    uncached.push(i);
+                } else {
+                    repeated.push(i);
+                }
+            }
+        }
+        if !uncached.is_empty() {
+            // Rates net of fees are found on a copy, see `handle_exchange_rate_requests`
+            if fee_model.is_none() {
+                self.find_best_rates();
+            }
+            let uncached_requests: Vec<&ExchangeRateRequest> = uncached.iter().map(|&i| &exchange_rate_requests[i]).collect();
+            let context = AnswerContext {
+                graph: &self.graph, query_time: self.latest_datetime, version: self.version,
+                output_format: self.output_format, explain: self.explain, notional: self.notional,
+                hop_penalty: self.hop_penalty, stale_after: self.stale_after, min_confidence: self.min_confidence,
+                partitions: &self.partitions, liquidity: &self.liquidity
+            };
+            let computed = handle_exchange_rate_requests(&context, &self.graph_result, &uncached_requests,
+                fee_model, num_workers, &mut self.phase_timings);
+            for (i, answer) in uncached.into_iter().zip(computed) {
+                self.cache.insert(self.version, &exchange_rate_requests[i], answer.clone());
+                answers[i] = Some(answer);
+            }
+        }
+        for i in repeated {
+            answers[i] = self.cache.get(self.version, &exchange_rate_requests[i]);
+        }
+        // Answers are cached without request IDs, which are added last
+        answers.into_iter().zip(exchange_rate_requests)
+            .filter_map(|(answer, exchange_rate_request)| answer.map(|(output, route_found)| {
+                let request_id = exchange_rate_request.get_request_id();
+                (output::echo_request_id(self.output_format, output, request_id), route_found)
+            }))
+            .collect()
+    }
+}
+
+// Weight of an edge given by `weight_script`, None when the script leaves it out or fails, which is logged
+fn script_weight(weight_script: &WeightScript, from_vertex: &Vertex, to_vertex: &Vertex, ratio: f64, datetime: u64,
+    derived: bool
+) -> Option<f64> {
+    match weight_script.weight(from_vertex, to_vertex, ratio, datetime, derived) {
+        Ok(weight) => weight,
+        Err(e) => {
+            logger::log(&format!("{}, leaving out the edge {} -> {}", e, from_vertex, to_vertex));
+            None
+        }
+    }
+}
+
+// 1. Add edges between vertices, weighted `forward_weight` and `backward_weight` unless left out
+// 2. Add vertices
+// 3. Add edges for same currency across different exchanges
+fn handle_price_update(graph: &mut Graph, graph_result: &mut GraphResult, price_update: PriceUpdate,
+    forward_weight: Option<f64>, backward_weight: Option<f64>, phase_timings: &mut PhaseTimings
+) {
+    let started = Instant::now();
+    let from_vertex = price_update.get_source_vertex();
+    let to_vertex = price_update.get_dest_vertex();
+
+    let arc_from_vertex = Arc::new(from_vertex);
+    let arc_to_vertex = Arc::new(to_vertex);
+
+    // Add edges
+    if let Some(forward_weight) = forward_weight {
+        graph_result.add_edge_weight(arc_from_vertex.clone(), arc_to_vertex.clone(),
+            forward_weight, price_update.get_datetime(), price_update.get_sub_millis(), price_update.is_forward_derived()
+        );
+    }
+    if let Some(backward_weight) = backward_weight {
+        graph_result.add_edge_weight(arc_to_vertex.clone(), arc_from_vertex.clone(),
+            backward_weight, price_update.get_datetime(), price_update.get_sub_millis(), price_update.is_backward_derived()
+        );
+    }
+
+    let arc_from_vertex_clone = arc_from_vertex.clone();
+    let arc_to_vertex_clone = arc_to_vertex.clone();
+
+    // Add vertices
+    graph.add_vertex(arc_from_vertex);
+    graph.add_vertex(arc_to_vertex);
+
+    let vertices = graph.get_vertices();
+    let fan_out_started = Instant::now();
+    phase_timings.record(Phase::EdgeInsertion, fan_out_started - started);
+
+    // Add edges for same currency across different exchanges
+    graph_result.add_edge_weight_for_currency(arc_from_vertex_clone, vertices);
+    graph_result.add_edge_weight_for_currency(arc_to_vertex_clone, vertices);
+    phase_timings.record(Phase::CurrencyFanOut, fan_out_started.elapsed());
+}
+
+// What answers to a batch of requests are computed from, besides the best rates
+struct AnswerContext<'a> {
+    graph: &'a Graph,
+    // timestamp of the latest price update, which `WINDOW` is relative to
+    query_time: u64,
+    version: u64,
+    output_format: OutputFormat,
+    explain: bool,
+    notional: f64,
+    hop_penalty: Option<f64>,
+    stale_after: Option<u64>,
+    min_confidence: Option<f64>,
+    partitions: &'a Partitions,
+    liquidity: &'a Liquidity
+}
+
+// Age of the oldest quote on `path` at `query_time`, if older than `stale_after`
+// Moving a currency between exchanges is not quoted, so only hops within an exchange count
+fn stale_age(graph_result: &GraphResult, path: &[Arc<Vertex>], query_time: u64, stale_after: u64) -> Option<u64> {
+    let oldest = path.windows(2)
+        .filter(|hop| hop[0].get_exchange() == hop[1].get_exchange())
+        .filter_map(|hop| graph_result.get_edge(&hop[0], &hop[1]))
+        .map(|edge| edge.get_last_updated())
+        .min()?;
+    Some(query_time.saturating_sub(oldest)).filter(|&age| age > stale_after)
+}
+
+// Product of the confidences in the quotes of `path` at `query_time`
+// Moving a currency between exchanges is not quoted, so it is fully trusted
+fn path_confidence(graph_result: &GraphResult, path: &[Arc<Vertex>], query_time: u64) -> f64 {
+    path.windows(2)
+        .filter(|hop| hop[0].get_exchange() == hop[1].get_exchange())
+        .filter_map(|hop| graph_result.get_edge(&hop[0], &hop[1]))
+        .map(|edge| edge.get_confidence(query_time))
+        .product()
+}
+
+// Product of the rates of the hops of `path`
+fn path_rate(graph_result: &GraphResult, path: &[Arc<Vertex>]) -> f64 {
+    path.windows(2)
+        .map(|hop| graph_result.get_edge_weight(&hop[0], &hop[1]))
+        .product()
+}
+
+// The rate of every hop of `path`, and the best alternative that avoids at least one of its hops
+// Paths are scored by `graph_result` and their rates taken from `rates`, which differ under a hop penalty
+fn explain(graph: &Graph, graph_result: &GraphResult, rates: &GraphResult, path: &[Arc<Vertex>]) -> Explanation {
+    let hops: Vec<(Arc<Vertex>, Arc<Vertex>, f64)> = path.windows(2)
+        .map(|hop| (hop[0].clone(), hop[1].clone(), rates.get_edge_weight(&hop[0], &hop[1])))
+        .collect();
+    let (from_vertex, to_vertex) = (&path[0], &path[path.len() - 1]);
+    let mut next_best: Option<(f64, f64, Vec<Arc<Vertex>>)> = None;
+    for (hop_from, hop_to, _) in &hops {
+        let mut alternative = graph_result.map_edges(|edge_from, edge_to, edge| {
+            if edge_from == hop_from && edge_to == hop_to { None } else { Some(edge.get_weight()) }
+        });
+        alternative.find_best_rates(graph.get_vertices());
+        if let Some(alternative_path) = alternative.best_rate_path(from_vertex, to_vertex) {
+            let alternative_score = alternative.get_best_rate(from_vertex, to_vertex);
+            if next_best.as_ref().is_none_or(|(next_best_score, _, _)| alternative_score > *next_best_score) {
+                next_best = Some((alternative_score, path_rate(rates, &alternative_path), alternative_path));
+            }
+        }
+    }
+    Explanation::new(hops, next_best.map(|(_, rate, path)| (rate, path)))
+}
+
+// Copy of `graph_result` without the edges that `exchange_rate_request` leaves out: those older than its window,
+// and those to or from its avoided currencies except at the source and destination. None when nothing is left out
+fn filter_edges(graph_result: &GraphResult, exchange_rate_request: &ExchangeRateRequest, query_time: u64,
+    from_vertex: &Arc<Vertex>, to_vertex: &Arc<Vertex>
+) -> Option<GraphResult> {
+    let avoid = exchange_rate_request.get_avoid();
+    let windowed = exchange_rate_request.get_window()
+        .map(|window| graph_result.edges_since(query_time.saturating_sub(window)));
+    if avoid.is_empty() {
+        return windowed;
+    }
+    let is_avoided = |vertex: &Arc<Vertex>| {
+        vertex != from_vertex && vertex != to_vertex && avoid.iter().any(|currency| currency.as_str() == vertex.get_currency())
+    };
+    Some(windowed.as_ref().unwrap_or(graph_result).map_edges(|edge_from, edge_to, edge| {
+        if is_avoided(edge_from) || is_avoided(edge_to) { None } else { Some(edge.get_weight()) }
+    }))
+}
+
+// Copy of `graph_result` keeping only the edges between currencies of `partition`
+fn partition_edges(graph_result: &GraphResult, partitions: &Partitions, partition: &str) -> GraphResult {
+    graph_result.map_edges(|from_vertex, to_vertex, edge| {
+        let in_partition = |vertex: &Arc<Vertex>| partitions.contains(partition, vertex.get_currency());
+        if in_partition(from_vertex) && in_partition(to_vertex) {
+            Some(edge.get_weight())
+        } else {
+            None
+        }
+    })
+}
+
+// The best route from `from_vertex` to `to_vertex` that passes through a vertex matching `via`, composed of the best
+// route to that vertex and the best route from it, and its rate in `graph_result`
+fn best_route_via(graph: &Graph, graph_result: &GraphResult, from_vertex: &Arc<Vertex>, to_vertex: &Arc<Vertex>,
+    via: &Via
+) -> (f64, Option<Vec<Arc<Vertex>>>) {
+    let mut via_vertices: Vec<&Arc<Vertex>> = graph.get_vertices().iter()
+        .filter(|vertex| via.matches(vertex))
+        .collect();
+    via_vertices.sort();
+
+    let mut best_route = (0.0, None);
+    for via_vertex in via_vertices {
+        // The source or destination itself satisfies the constraint
+        if via_vertex == from_vertex || via_vertex == to_vertex {
+            if let Some(path) = graph_result.best_rate_path(from_vertex, to_vertex) {
+                let rate = graph_result.get_best_rate(from_vertex, to_vertex);
+                if best_route.1.is_none() || rate > best_route.0 {
+                    best_route = (rate, Some(path));
+                }
+            }
+            continue;
+        }
+        let first_leg = graph_result.best_rate_path(from_vertex, via_vertex);
+        let second_leg = graph_result.best_rate_path(via_vertex, to_vertex);
+        if let (Some(mut path), Some(second_leg)) = (first_leg, second_leg) {
+            let rate = graph_result.get_best_rate(from_vertex, via_vertex)
+                .combine(graph_result.get_best_rate(via_vertex, to_vertex));
+            if best_route.1.is_none() || rate > best_route.0 {
+                path.extend(second_leg.into_iter().skip(1));
+                best_route = (rate, Some(path));
+            }
+        }
+    }
+    best_route
+}
+
+// Get the best rate path from `graph_result`, whose best rates are already computed,
+// and return the formatted result, and whether a route was found
+// `rates` holds the rates of the hops, which differ from `graph_result` when it is scored with a hop penalty
+// `gross_graph_result` holds the rates before fees when `rates` are net of fees
+fn handle_exchange_rate_request(context: &AnswerContext, graph_result: &GraphResult, rates: &GraphResult,
+    gross_graph_result: Option<&GraphResult>, exchange_rate_request: &ExchangeRateRequest
+) -> (String, bool) {
+    let graph = context.graph;
+    let query_time = context.query_time;
+    let arc_from_vertex = Arc::new(exchange_rate_request.get_source_vertex());
+    let arc_to_vertex = Arc::new(exchange_rate_request.get_dest_vertex());
+
+    // Windows and avoided currencies are applied to a copy of the graph so the shared result is left intact
+    let mut filtered_graph_result;
+    let graph_result = match filter_edges(graph_result, exchange_rate_request, query_time, &arc_from_vertex, &arc_to_vertex) {
+        Some(filtered) => {
+            filtered_graph_result = filtered;
+            filtered_graph_result.find_best_rates(graph.get_vertices());
+            &filtered_graph_result
+        },
+        None => graph_result
+    };
+
+    let (best_score, best_rate_path) = match exchange_rate_request.get_via() {
+        Some(via) => best_route_via(graph, graph_result, &arc_from_vertex, &arc_to_vertex, via),
+        None => (graph_result.get_best_rate(&arc_from_vertex, &arc_to_vertex),
+            graph_result.best_rate_path(&arc_from_vertex, &arc_to_vertex))
+    };
+    let best_rate = match (&best_rate_path, context.hop_penalty) {
+        (Some(best_rate_path), Some(_)) => path_rate(rates, best_rate_path),
+        _ => best_score
+    };
+    // Going around an arbitrage cycle once more always gives a better rate, so the rate found is meaningless
+    let unbounded = graph_result.is_unbounded(&arc_from_vertex, &arc_to_vertex);
+    let (best_rate, best_rate_path) = if unbounded { (0.0, None) } else { (best_rate, best_rate_path) };
+    let confidence = context.min_confidence.zip(best_rate_path.as_ref())
+        .map(|(min_confidence, best_rate_path)| (min_confidence, path_confidence(rates, best_rate_path, query_time)));
+    // A path that cannot be trusted enough is withheld, the answer still tells its confidence
+    let (best_rate, best_rate_path) = match confidence {
+        Some((min_confidence, confidence)) if confidence < min_confidence => (0.0, None),
+        _ => (best_rate, best_rate_path)
+    };
+    let route_found = best_rate_path.is_some();
+    // The front and the split are computed from the rates of the hops, a hop penalty makes no difference to them
+    let filtered_rates = if exchange_rate_request.is_pareto() || exchange_rate_request.get_split().is_some() {
+        filter_edges(rates, exchange_rate_request, query_time, &arc_from_vertex, &arc_to_vertex)
+    } else {
+        None
+    };
+    let hop_rates = filtered_rates.as_ref().unwrap_or(rates);
+    let pareto_front = if exchange_rate_request.is_pareto() {
+        Some(hop_rates.pareto_front(&arc_from_vertex, &arc_to_vertex, graph.get_vertices().len().saturating_sub(1)))
+    } else {
+        None
+    };
+    let split = exchange_rate_request.get_split().filter(|_| route_found).map(|amount| {
+        let paths = hop_rates.top_paths(&arc_from_vertex, &arc_to_vertex, constants::SPLIT_PATHS,
+            constants::SPLIT_MAX_HOPS);
+        split::split(hop_rates, context.liquidity, paths.into_iter().map(|(_, path)| path).collect(), amount)
+    });
+    let mut rate_result = RateResult::new(arc_from_vertex, arc_to_vertex, best_rate, best_rate_path, context.version);
+    rate_result.set_confidence(confidence.map(|(_, confidence)| confidence));
+    rate_result.set_unbounded(unbounded);
+    if let Some(best_rate_path) = rate_result.get_path() {
+        let synthetic = !graph_result.has_direct_quote(
+            exchange_rate_request.get_source_currency(), exchange_rate_request.get_dest_currency());
+        let explanation = if context.explain { Some(explain(graph, graph_result, rates, best_rate_path)) } else { None };
+        let fees = gross_graph_result.map(|gross_graph_result| {
+            FeeBreakdown::new(path_rate(gross_graph_result, best_rate_path), context.notional)
+        });
+        let derived = graph_result.has_derived_edge(best_rate_path);
+        let stale_age = context.stale_after
+            .and_then(|stale_after| stale_age(rates, best_rate_path, query_time, stale_after));
+        rate_result.set_synthetic(synthetic);
+        rate_result.set_derived(derived);
+        rate_result.set_stale_age(stale_age);
+        if let Some(explanation) = explanation {
+            rate_result.set_explanation(explanation);
+        }
+        if let Some(fees) = fees {
+            rate_result.set_fees(fees);
+        }
+    }
+    if let Some(pareto_front) = pareto_front {
+        rate_result.set_pareto_front(pareto_front);
+    }
+    if let Some(split) = split {
+        rate_result.set_split(split);
+    }
+    (output::format_best_rates(context.output_format, &rate_result), route_found)
+}
+
+// Best rates net of fees are computed once, those before fees being already up to date in `graph_result`, then the
+// requests are split among up to `num_workers` threads that share the result, and the answers are returned in the
+// order of the requests
+fn handle_exchange_rate_requests(context: &AnswerContext, graph_result: &GraphResult,
+    exchange_rate_requests: &[&ExchangeRateRequest], fee_model: Option<&FeeModel>, num_workers: usize,
+    phase_timings: &mut PhaseTimings
+) -> Vec<(String, bool)> {
+    let started = Instant::now();
+    // Fees are applied to a copy of the graph so the shared result is left intact
+    let fee_graph_result = fee_model.map(|fee_model| {
+        let mut fee_graph_result = {
+            let _span = profile::span("apply_fees");
+            fee_model.apply(graph_result)
+        };
+        let _span = profile::span("find_best_rates");
+        fee_graph_result.find_best_rates(context.graph.get_vertices());
+        fee_graph_result
+    });
+    let (rates, gross_graph_result) = match &fee_graph_result {
+        Some(fee_graph_result) => (fee_graph_result, Some(graph_result)),
+        None => (graph_result, None)
+    };
+    // Routes are chosen on a copy where every hop is penalised, and their rates taken from `rates`
+    let penalised_graph_result = context.hop_penalty.map(|hop_penalty| {
+        let _span = profile::span("penalise_hops");
+        let mut penalised_graph_result = rates.map_edges(|_, _, edge| Some(edge.get_weight() * hop_penalty));
+        penalised_graph_result.find_best_rates(context.graph.get_vertices());
+        penalised_graph_result
+    });
+    let graph_result = penalised_graph_result.as_ref().unwrap_or(rates);
+    // Best rates within each partition requested are found once, on copies keeping only the edges between its
+    // currencies, along with a copy of `rates` to take the rates of routes from when they differ
+    let requested_partitions: HashSet<&str> = exchange_rate_requests.iter()
+        .filter_map(|exchange_rate_request| exchange_rate_request.get_partition())
+        .filter(|partition| *partition != constants::ALL_PARTITION)
+        .collect();
+    let partitioned: HashMap<&str, (GraphResult, Option<GraphResult>)> = requested_partitions.into_iter()
+        .map(|partition| {
+            let _span = profile::span("partition");
+            let mut partition_graph_result = partition_edges(graph_result, context.partitions, partition);
+            partition_graph_result.find_best_rates(context.graph.get_vertices());
+            let partition_rates = penalised_graph_result.as_ref()
+                .map(|_| partition_edges(rates, context.partitions, partition));
+            (partition, (partition_graph_result, partition_rates))
+        })
+        .collect();
+    if fee_graph_result.is_some() || penalised_graph_result.is_some() || !partitioned.is_empty() {
+        phase_timings.record(Phase::BestRates, started.elapsed());
+    }
+
+    // Workers are not profiled, their time is spent within this span of the profiled thread
+    let _span = profile::span("find_paths");
+    let timed_answer = |exchange_rate_request: &&ExchangeRateRequest| -> ((String, bool), Duration) {
+        let started = Instant::now();
+        let partition = exchange_rate_request.get_partition().and_then(|partition| partitioned.get(partition));
+        let (graph_result, rates) = match partition {
+            Some((partition_graph_result, partition_rates)) => {
+                (partition_graph_result, partition_rates.as_ref().unwrap_or(partition_graph_result))
+            },
+            None => (graph_result, rates)
+        };
+        let answer = handle_exchange_rate_request(context, graph_result, rates, gross_graph_result, exchange_rate_request);
+        (answer, started.elapsed())
+    };
+    let num_workers = num_workers.min(exchange_rate_requests.len());
+    let timed_answers: Vec<_> = if num_workers <= 1 {
+        exchange_rate_requests.iter().map(timed_answer).collect()
+    } else {
+        let chunk_size = exchange_rate_requests.len().div_ceil(num_workers);
+        thread::scope(|scope| {
+            let workers: Vec<_> = exchange_rate_requests.chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || chunk.iter().map(timed_answer).collect::<Vec<_>>()))
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+        })
+    };
+    timed_answers.into_iter()
+        .map(|(answer, duration)| {
+            phase_timings.record(Phase::PathReconstruction, duration);
+            answer
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Currency, Exchange, InputType};
+    use crate::testutil::{quote, request};
+
+    fn price_update(datetime: u64, exchange: &str, forward_ratio: f64) -> PriceUpdate {
+        quote(datetime, exchange, "BTC", "USD", forward_ratio, 0.0009)
+    }
+
+    #[test]
+    fn answer_requests_in_order() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        let requests: Vec<ExchangeRateRequest> = (0..3)
+            .flat_map(|_| vec![request("KRAKEN", "BTC", "KRAKEN", "USD"), request("KRAKEN", "USD", "KRAKEN", "BTC"),
+                request("KRAKEN", "BTC", "GDAX", "USD")])
+            .collect();
+        let answers = engine.answer(&requests, None, 4);
+        let first_lines: Vec<&str> = answers.iter().map(|(output, _)| output.lines().next().unwrap()).collect();
+        for chunk in first_lines.chunks(3) {
+            assert_eq!(chunk, ["BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 1000 VERSION 1",
+                "BEST_RATES_BEGIN KRAKEN USD KRAKEN BTC 0.0009 VERSION 1", "BEST_RATES_BEGIN KRAKEN BTC GDAX USD 0 VERSION 1"]);
+        }
+        assert_eq!(answers.iter().filter(|(_, route_found)| *route_found).count(), 6);
+    }
+
+    #[test]
+    fn echo_request_ids() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        let requests: Vec<ExchangeRateRequest> = ["a", "b"].iter()
+            .map(|request_id| {
+                let mut exchange_rate_request = request("KRAKEN", "BTC", "KRAKEN", "USD");
+                exchange_rate_request.set_request_id(Some(request_id.to_string()));
+                exchange_rate_request
+            })
+            .collect();
+        let answers = engine.answer(&requests, None, 1);
+        assert!(answers[0].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 1000 VERSION 1 ID a\n"));
+        assert!(answers[1].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 1000 VERSION 1 ID b\n"));
+        assert_eq!(engine.get_num_cache_hits(), 1);
+    }
+
+    #[test]
+    fn recompute_in_background() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.set_recompute(None, Some(2));
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        engine.schedule_recompute();
+        assert!(engine.recompute.is_none());
+
+        engine.apply_price_update(price_update(2, "GDAX", 1001.0));
+        engine.schedule_recompute();
+        assert!(engine.recompute.is_some());
+        while !engine.has_best_rates() {
+            thread::sleep(Duration::from_millis(1));
+            engine.schedule_recompute();
+        }
+        let answers = engine.answer(&[request("KRAKEN", "BTC", "GDAX", "USD")], None, 1);
+        assert!(answers[0].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC GDAX USD 1001 VERSION 2\n"));
+
+        // A recompute that a later price update made out of date is not used
+        engine.apply_price_update(price_update(3, "GDAX", 1002.0));
+        engine.apply_price_update(price_update(4, "GDAX", 1003.0));
+        engine.schedule_recompute();
+        engine.apply_price_update(price_update(5, "GDAX", 1004.0));
+        let answers = engine.answer(&[request("KRAKEN", "BTC", "GDAX", "USD")], None, 1);
+        assert!(answers[0].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC GDAX USD 1004 VERSION 5\n"));
+        assert!(engine.has_best_rates());
+    }
+
+    #[test]
+    fn flag_stale_quotes() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.set_stale_after(Some(60 * 1000));
+        engine.apply_price_update(price_update(0, "KRAKEN", 1000.0));
+        engine.apply_price_update(quote(42 * 60 * 1000, "GDAX", "ETH", "USD", 300.0, 0.003));
+        let answers = engine.answer(&[request("KRAKEN", "BTC", "GDAX", "USD"), request("GDAX", "ETH", "GDAX", "USD")],
+            None, 1);
+        assert!(answers[0].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC GDAX USD 1000 STALE_DATA age=42m VERSION 2\n"));
+        assert!(answers[1].0.starts_with("BEST_RATES_BEGIN GDAX ETH GDAX USD 300 VERSION 2\n"));
+    }
+
+    #[test]
+    fn withhold_paths_under_min_confidence() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.set_min_confidence(Some(0.6));
+        // Updated every 10s, the KRAKEN quote's confidence halves every 100s
+        engine.apply_price_update(price_update(0, "KRAKEN", 1000.0));
+        engine.apply_price_update(price_update(10 * 1000, "KRAKEN", 1000.0));
+        engine.apply_price_update(quote(100 * 1000, "GDAX", "ETH", "USD", 300.0, 0.003));
+        let answers = engine.answer(&[request("KRAKEN", "BTC", "KRAKEN", "USD"), request("GDAX", "ETH", "GDAX", "USD")],
+            None, 1);
+        assert_eq!(answers[0], ("BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 0 CONFIDENCE 0.536 VERSION 3\nBEST_RATES_END\n"
+            .to_string(), false));
+        assert!(answers[1].0.starts_with("BEST_RATES_BEGIN GDAX ETH GDAX USD 300 CONFIDENCE 1.000 VERSION 3\n"));
+    }
+
+    #[test]
+    fn report_rates_unbounded_via_crossed_markets() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.set_allow_crossed(true);
+        // Selling BTC on KRAKEN and buying it back gains 10%
+        engine.apply_price_update(quote(1, "KRAKEN", "BTC", "USD", 1000.0, 0.0011));
+        engine.apply_price_update(quote(2, "GDAX", "ETH", "EUR", 300.0, 0.003));
+        let answers = engine.answer(&[request("KRAKEN", "BTC", "KRAKEN", "USD"), request("GDAX", "ETH", "GDAX", "EUR")],
+            None, 1);
+        assert_eq!(answers[0], ("BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 0 UNBOUNDED via=arbitrage_cycle VERSION 2\n\
+BEST_RATES_END\n".to_string(), false));
+        assert!(answers[1].0.starts_with("BEST_RATES_BEGIN GDAX ETH GDAX EUR 300 VERSION 2\n"));
+    }
+
+    #[test]
+    fn route_across_exchanges_without_crossed_markets() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        // Buying BTC on GDAX and selling it on KRAKEN gains a little, which is not a crossed market
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        engine.apply_price_update(quote(2, "GDAX", "BTC", "USD", 980.0, 0.00101));
+        let answers = engine.answer(&[request("KRAKEN", "BTC", "GDAX", "USD")], None, 1);
+        assert!(answers[0].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC GDAX USD 1000 VERSION 2\n"));
+        assert!(answers[0].1);
+
+        // With crossed markets allowed, reaching the cycle is enough
+        engine.set_allow_crossed(true);
+        let answers = engine.answer(&[request("KRAKEN", "BTC", "GDAX", "USD")], None, 1);
+        assert!(answers[0].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC GDAX USD 0 UNBOUNDED via=arbitrage_cycle VERSION 2\n"));
+    }
+
+    #[test]
+    fn report_rates_unbounded_via_arbitrage_across_exchanges() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        let mut generator = crate::generate::Generator::new(4, 4, 1).unwrap();
+        generator.set_request_rate(0.2);
+        let mut num_unbounded = 0;
+        for input in generator.take(360) {
+            match input {
+                InputType::PriceUpdate(price_update) => engine.apply_price_update(price_update),
+                InputType::ExchangeRateRequest(exchange_rate_request) => {
+                    let (output, route_found) = engine.answer(&[exchange_rate_request], None, 1).remove(0);
+                    let rate: f64 = output.split(' ').nth(5).unwrap().parse().unwrap();
+                    // A rate that went around a cycle is neither reported nor given without its route
+                    assert!(rate.is_finite() && (route_found || rate == 0.0), "{}", output);
+                    if output.contains(" UNBOUNDED ") {
+                        num_unbounded += 1;
+                    }
+                },
+                InputType::Invalid(reason) => panic!("Generated invalid input: {}", reason)
+            }
+        }
+        assert!(num_unbounded > 0);
+    }
+
+    #[test]
+    fn evict_stalest_quotes_over_memory_limit() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.set_max_memory(Some(3000));
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        assert_eq!(engine.estimate_memory(), 2 * constants::VERTEX_MEMORY + 2 * constants::EDGE_MEMORY
+            + 4 * constants::BEST_PATH_MEMORY);
+        // 4 vertices and 8 edges would take 3072 bytes, so the older KRAKEN market is evicted
+        engine.apply_price_update(price_update(2, "GDAX", 1001.0));
+        assert_eq!(engine.get_vertices().len(), 2);
+        assert_eq!(engine.get_graph_result().num_edges(), 2);
+        assert!(engine.answer(&[request("GDAX", "BTC", "GDAX", "USD")], None, 1)[0].1);
+        assert!(!engine.answer(&[request("KRAKEN", "BTC", "GDAX", "USD")], None, 1)[0].1);
+    }
+
+    #[test]
+    fn penalise_hops() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        engine.apply_price_update(price_update(2, "GDAX", 1001.0));
+        let requests = [request("KRAKEN", "BTC", "KRAKEN", "USD")];
+        let answers = engine.answer(&requests, None, 1);
+        assert!(answers[0].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 1001 VERSION 2\n<KRAKEN, BTC>\n<GDAX, BTC>"));
+
+        engine.set_hop_penalty(Some(0.99));
+        let answers = engine.answer(&requests, None, 1);
+        assert_eq!(answers[0].0, "BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 1000 VERSION 2\n<KRAKEN, BTC>\n<KRAKEN, USD>\nBEST_RATES_END\n");
+    }
+
+    #[test]
+    fn list_pareto_front() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        engine.apply_price_update(price_update(2, "GDAX", 1001.0));
+        let mut pareto_request = request("KRAKEN", "BTC", "KRAKEN", "USD");
+        pareto_request.set_pareto(true);
+        let answers = engine.answer(&[pareto_request], None, 1);
+        assert!(answers[0].0.ends_with("BEST_RATES_END
+PARETO_BEGIN
+HOPS 1 RATE 1000
+<KRAKEN, BTC>
+<KRAKEN, USD>
+HOPS 3 RATE 1001
+<KRAKEN, BTC>
+<GDAX, BTC>
+<GDAX, USD>
+<KRAKEN, USD>
+PARETO_END
+"));
+    }
+
+    #[test]
+    fn split_across_paths_by_liquidity() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.set_liquidity(Liquidity::parse("GDAX BTC USD 2 0\n", &crate::alias::Aliases::none()).unwrap());
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        engine.apply_price_update(price_update(2, "GDAX", 1001.0));
+        let mut split_request = request("KRAKEN", "BTC", "KRAKEN", "USD");
+        split_request.set_split(Some(4.0));
+        // GDAX only fills 2 BTC at its better rate, the rest goes through KRAKEN
+        let answers = engine.answer(&[split_request], None, 1);
+        assert!(answers[0].0.ends_with("BEST_RATES_END
+SPLIT_BEGIN AMOUNT 4 OUTPUT 4001.9999999999977 UNFILLED 0
+ALLOCATION 2 OUTPUT 2001.9999999999977
+<KRAKEN, BTC>
+<GDAX, BTC>
+<GDAX, USD>
+<KRAKEN, USD>
+ALLOCATION 2 OUTPUT 2000
+<KRAKEN, BTC>
+<KRAKEN, USD>
+SPLIT_END
+"));
+    }
+
+    #[test]
+    fn route_via_exchange_or_currency() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        engine.apply_price_update(quote(2, "KRAKEN", "BTC", "EUR", 900.0, 0.0011));
+        engine.apply_price_update(quote(3, "KRAKEN", "USD", "EUR", 0.85, 1.1));
+        let mut via_usd = request("KRAKEN", "BTC", "KRAKEN", "EUR");
+        via_usd.set_via(Some(Via::Currency(Currency::new("USD").unwrap())));
+        let mut via_gdax = request("KRAKEN", "BTC", "KRAKEN", "EUR");
+        via_gdax.set_via(Some(Via::Exchange(Exchange::new("GDAX").unwrap())));
+        // No exchange is named USD, the currency does not count
+        let mut via_usd_exchange = request("KRAKEN", "BTC", "KRAKEN", "EUR");
+        via_usd_exchange.set_via(Some(Via::Exchange(Exchange::new("USD").unwrap())));
+        let answers = engine.answer(&[request("KRAKEN", "BTC", "KRAKEN", "EUR"), via_usd, via_gdax, via_usd_exchange],
+            None, 1);
+        assert!(answers[0].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC KRAKEN EUR 900 VERSION 3"));
+        assert_eq!(answers[1].0, "BEST_RATES_BEGIN KRAKEN BTC KRAKEN EUR 850 VERSION 3
+<KRAKEN, BTC>
+<KRAKEN, USD>
+<KRAKEN, EUR>
+BEST_RATES_END
+");
+        assert!(!answers[2].1);
+        assert!(!answers[3].1);
+    }
+
+    #[test]
+    fn route_within_partitions() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        let partitions = "fiat USD EUR\ncrypto BTC ETH\n";
+        engine.set_partitions(Partitions::parse(partitions, &crate::alias::Aliases::none()).unwrap());
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        engine.apply_price_update(quote(2, "KRAKEN", "USD", "EUR", 0.9, 1.0));
+        engine.apply_price_update(quote(3, "KRAKEN", "BTC", "EUR", 1100.0, 0.0008));
+        let requests: Vec<ExchangeRateRequest> = [None, Some("fiat"), Some("crypto"), Some("all")].iter()
+            .map(|partition| {
+                let mut exchange_rate_request = request("KRAKEN", "USD", "KRAKEN", "EUR");
+                exchange_rate_request.set_partition(partition.map(str::to_string));
+                exchange_rate_request
+            })
+            .collect();
+        let answers = engine.answer(&requests, None, 2);
+        // Bridging through BTC gives about 0.99
+        assert_eq!(answers[0].0.lines().nth(2), Some("<KRAKEN, BTC>"));
+        assert_eq!(answers[1].0, "BEST_RATES_BEGIN KRAKEN USD KRAKEN EUR 0.9 VERSION 3\n<KRAKEN, USD>\n<KRAKEN, EUR>\n\
+BEST_RATES_END\n");
+        assert!(!answers[2].1);
+        assert_eq!(answers[3], answers[0]);
+    }
+
+    #[test]
+    fn avoid_currencies() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        engine.apply_price_update(quote(2, "KRAKEN", "USD", "EUR", 0.9, 1.1));
+        engine.apply_price_update(quote(3, "KRAKEN", "BTC", "EUR", 850.0, 0.0011));
+        let mut avoid_usd = request("KRAKEN", "BTC", "KRAKEN", "EUR");
+        avoid_usd.set_avoid(vec![Currency::new("USD").unwrap()]);
+        let mut avoid_eur = request("KRAKEN", "BTC", "KRAKEN", "EUR");
+        avoid_eur.set_avoid(vec![Currency::new("EUR").unwrap()]);
+        let answers = engine.answer(&[request("KRAKEN", "BTC", "KRAKEN", "EUR"), avoid_usd, avoid_eur], None, 1);
+        assert!(answers[0].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC KRAKEN EUR 900 VERSION 3\n<KRAKEN, BTC>\n<KRAKEN, USD>"));
+        assert_eq!(answers[1].0, "BEST_RATES_BEGIN KRAKEN BTC KRAKEN EUR 850 VERSION 3
+<KRAKEN, BTC>
+<KRAKEN, EUR>
+BEST_RATES_END
+");
+        // The destination is never avoided
+        assert!(answers[2].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC KRAKEN EUR 900 VERSION 3"));
+    }
+
+    #[test]
+    fn mark_synthetic_rates() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        engine.apply_price_update(quote(2, "KRAKEN", "ETH", "USD", 250.0, 0.004));
+        let answers = engine.answer(&[request("KRAKEN", "BTC", "KRAKEN", "USD"), request("KRAKEN", "BTC", "KRAKEN", "ETH")],
+            None, 1);
+        assert!(answers[0].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 1000 VERSION 2"));
+        assert!(answers[1].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC KRAKEN ETH 4 SYNTHETIC VERSION 2"));
+    }
+
+    #[test]
+    fn explain_hops_and_next_best() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.set_explain(true);
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        engine.apply_price_update(price_update(2, "GDAX", 1001.0));
+        engine.apply_price_update(quote(3, "GDAX", "USD", "EUR", 0.5, 1.9));
+        let answers = engine.answer(&[request("KRAKEN", "BTC", "GDAX", "EUR")], None, 1);
+        assert_eq!(answers[0].0, "BEST_RATES_BEGIN KRAKEN BTC GDAX EUR 500.5 SYNTHETIC VERSION 3
+<KRAKEN, BTC>
+<GDAX, BTC>
+<GDAX, USD>
+<GDAX, EUR>
+BEST_RATES_END
+EXPLAIN_BEGIN
+HOP <KRAKEN, BTC> -> <GDAX, BTC> RATE 1 PRODUCT 1
+HOP <GDAX, BTC> -> <GDAX, USD> RATE 1001 PRODUCT 1001
+HOP <GDAX, USD> -> <GDAX, EUR> RATE 0.5 PRODUCT 500.5
+NEXT_BEST 500 WORSE_BY 0.0999000999000999%
+<KRAKEN, BTC>
+<KRAKEN, USD>
+<GDAX, USD>
+<GDAX, EUR>
+EXPLAIN_END
+");
+    }
+
+    #[test]
+    fn notify_subscribers_of_changes() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        let changes = engine.subscribe(Vertex::new(Exchange::new("KRAKEN").unwrap(), Currency::new("BTC").unwrap()),
+            Vertex::new(Exchange::new("KRAKEN").unwrap(), Currency::new("USD").unwrap()));
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        engine.apply_price_update(price_update(2, "KRAKEN", 1000.0));
+        engine.apply_price_update(price_update(3, "GDAX", 900.0));
+        engine.apply_price_update(price_update(4, "KRAKEN", 1100.0));
+
+        let changes: Vec<RateChange> = changes.try_iter().collect();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].get_best_rate(), 1000.0);
+        assert_eq!(changes[0].get_path().map(|path| path.len()), Some(2));
+        assert_eq!((changes[1].get_best_rate(), changes[1].get_version(), changes[1].get_datetime()), (1100.0, 4, 4));
+        // Requests reuse the best rates found for the subscribers
+        assert!(engine.has_best_rates());
+        assert_eq!(engine.get_phase_timings().get(Phase::BestRates).get_count(), 5);
+        engine.answer(&[request("KRAKEN", "BTC", "GDAX", "USD")], None, 1);
+        assert_eq!(engine.get_phase_timings().get(Phase::BestRates).get_count(), 5);
+    }
+
+    #[test]
+    fn reuse_best_rates_of_watched_pairs() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        let mut watch_list = WatchList::parse("KRAKEN BTC KRAKEN USD\n", &crate::alias::Aliases::none()).unwrap();
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        assert_eq!(engine.update_watched_pairs(&mut watch_list, None).len(), 1);
+        assert!(engine.has_best_rates());
+        engine.answer(&[request("KRAKEN", "BTC", "KRAKEN", "USD")], None, 1);
+        assert_eq!(engine.get_phase_timings().get(Phase::BestRates).get_count(), 1);
+    }
+
+    #[test]
+    fn cache_answers_until_price_update() {
+        let mut engine = Engine::new(OutputFormat::Text);
+        engine.apply_price_update(price_update(1, "KRAKEN", 1000.0));
+        let requests = [request("KRAKEN", "BTC", "KRAKEN", "USD"), request("KRAKEN", "BTC", "KRAKEN", "USD")];
+        engine.answer(&requests, None, 1);
+        assert_eq!(engine.get_num_cache_hits(), 1);
+
+        engine.apply_price_update(price_update(2, "KRAKEN", 1100.0));
+        let answers = engine.answer(&requests[..1], None, 1);
+        assert!(answers[0].0.starts_with("BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 1100 VERSION 2"));
+        assert_eq!(engine.get_num_cache_hits(), 1);
+    }
+}
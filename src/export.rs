@@ -0,0 +1,310 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use chrono::{TimeZone, Utc};
+
+use crate::bus::{Event, Subscriber};
+use crate::model::{GraphResult, Vertex, VertexSet};
+use crate::output;
+use crate::watch::RatePoint;
+
+// Format a millisecond timestamp as RFC 3339
+pub fn format_datetime(datetime: u64) -> String {
+    format_precise_datetime(datetime, 0)
+}
+
+// Format a millisecond timestamp and `sub_millis` nanoseconds past it as RFC 3339, with 3, 6 or 9 fractional digits
+// when they are not zero
+pub fn format_precise_datetime(datetime: u64, sub_millis: u32) -> String {
+    match Utc.timestamp_millis_opt(datetime as i64).single() {
+        Some(datetime) => (datetime + chrono::Duration::nanoseconds(sub_millis as i64)).to_rfc3339(),
+        None => datetime.to_string()
+    }
+}
+
+// A change in the best rate of a watched pair as one line of JSON, e.g. for a message broker
+pub fn format_rate_point_json(rate_point: &RatePoint) -> String {
+    format!("{{\"timestamp\":{},\"pair\":{},\"best_rate\":{},\"hops\":{}}}",
+        output::json_string(&format_datetime(rate_point.get_datetime())), output::json_string(rate_point.get_pair()),
+        rate_point.get_best_rate(), rate_point.get_hops())
+}
+
+// Writes the best rate time series of watched pairs as CSV
+pub struct CsvWriter {
+    writer: BufWriter<File>
+}
+
+impl CsvWriter {
+    pub fn create(file_name: &str) -> Result<CsvWriter, io::Error> {
+        let mut writer = BufWriter::new(File::create(file_name)?);
+        writeln!(writer, "timestamp,pair,best_rate,hops")?;
+        Ok(CsvWriter {
+            writer
+        })
+    }
+
+    pub fn write(&mut self, rate_point: &RatePoint) -> Result<(), io::Error> {
+        writeln!(self.writer, "{},{},{},{}", format_datetime(rate_point.get_datetime()),
+            rate_point.get_pair(), rate_point.get_best_rate(), rate_point.get_hops())
+    }
+
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        self.writer.flush()
+    }
+}
+
+impl Subscriber for CsvWriter {
+    fn handle(&mut self, event: &Event) -> Result<(), String> {
+        match event {
+            Event::Result(rate_point) => self.write(rate_point)
+                .map_err(|e| format!("Error encountered while writing CSV: {}", e)),
+            _ => Ok(())
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        CsvWriter::flush(self).map_err(|e| format!("Error encountered while writing CSV: {}", e))
+    }
+}
+
+// Write the best rate between every pair of `vertices` with a route as an Arrow IPC file, also known as Feather,
+// with one `from_exchange`, `from_currency`, `to_exchange`, `to_currency`, `best_rate`, `hops` row per pair.
+// `hops` is null when the route could loop through an arbitrage cycle. `graph_result` must have its best rates found
+#[cfg(feature = "arrow")]
+pub fn write_best_rates_arrow(file_name: &str, graph_result: &GraphResult, vertices: &VertexSet) -> io::Result<()> {
+    use std::sync::Arc;
+
+    use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt32Array};
+    use arrow_ipc::writer::FileWriter;
+    use arrow_schema::{DataType, Field, Schema};
+
+    let mut vertices: Vec<_> = vertices.iter().collect();
+    vertices.sort();
+    let mut columns: (Vec<&str>, Vec<&str>, Vec<&str>, Vec<&str>) = Default::default();
+    let mut best_rates = Vec::new();
+    let mut hops = Vec::new();
+    for from_vertex in vertices.iter() {
+        for to_vertex in vertices.iter() {
+            let best_rate = graph_result.get_best_rate(from_vertex, to_vertex);
+            if from_vertex == to_vertex || best_rate == 0.0 {
+                continue;
+            }
+            columns.0.push(from_vertex.get_exchange());
+            columns.1.push(from_vertex.get_currency());
+            columns.2.push(to_vertex.get_exchange());
+            columns.3.push(to_vertex.get_currency());
+            best_rates.push(best_rate);
+            hops.push(graph_result.best_rate_path(from_vertex, to_vertex).map(|path| path.len() as u32 - 1));
+        }
+    }
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("from_exchange", DataType::Utf8, false),
+        Field::new("from_currency", DataType::Utf8, false),
+        Field::new("to_exchange", DataType::Utf8, false),
+        Field::new("to_currency", DataType::Utf8, false),
+        Field::new("best_rate", DataType::Float64, false),
+        Field::new("hops", DataType::UInt32, true)
+    ]));
+    let arrays: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(columns.0)), Arc::new(StringArray::from(columns.1)),
+        Arc::new(StringArray::from(columns.2)), Arc::new(StringArray::from(columns.3)),
+        Arc::new(Float64Array::from(best_rates)), Arc::new(UInt32Array::from(hops))
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), arrays).map_err(io::Error::other)?;
+    let mut writer = FileWriter::try_new(BufWriter::new(File::create(file_name)?), &schema).map_err(io::Error::other)?;
+    writer.write(&batch).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+#[cfg(not(feature = "arrow"))]
+pub fn write_best_rates_arrow(file_name: &str, _graph_result: &GraphResult, _vertices: &VertexSet) -> io::Result<()> {
+    Err(io::Error::other(format!("writing {} requires the `arrow` feature", file_name)))
+}
+
+pub const ADJACENCY_NPY_FILE: &str = "adjacency.npy";
+pub const BEST_RATES_NPY_FILE: &str = "best_rates.npy";
+pub const VERTICES_JSON_FILE: &str = "vertices.json";
+
+// Write the rate of the direct edge and the best rate between every pair of `vertices` to `dir` as NumPy float64
+// matrices `adjacency.npy` and `best_rates.npy`, where row and column `i` are the vertex at index `i` of the
+// `vertices.json` array. Pairs without an edge or a route are 0, and a vertex converts to itself at 1 in
+// `best_rates.npy`. `graph_result` must have its best rates found
+pub fn write_matrices_npy(dir: &str, graph_result: &GraphResult, vertices: &VertexSet) -> io::Result<()> {
+    let mut vertices: Vec<_> = vertices.iter().collect();
+    vertices.sort();
+    let num_vertices = vertices.len();
+    let mut adjacency = vec![0.0; num_vertices * num_vertices];
+    for (from_vertex, to_vertex, edge) in graph_result.edges() {
+        if let (Ok(row), Ok(column)) = (vertices.binary_search(&from_vertex), vertices.binary_search(&to_vertex)) {
+            adjacency[row * num_vertices + column] = edge.get_weight();
+        }
+    }
+    let mut best_rates = Vec::with_capacity(num_vertices * num_vertices);
+    for from_vertex in vertices.iter() {
+        for to_vertex in vertices.iter() {
+            best_rates.push(if from_vertex == to_vertex { 1.0 } else { graph_result.get_best_rate(from_vertex, to_vertex) });
+        }
+    }
+
+    let dir = Path::new(dir);
+    fs::create_dir_all(dir)?;
+    write_npy(&dir.join(ADJACENCY_NPY_FILE), num_vertices, &adjacency)?;
+    write_npy(&dir.join(BEST_RATES_NPY_FILE), num_vertices, &best_rates)?;
+    let vertices_json: Vec<String> = vertices.iter()
+        .map(|vertex| format!("{{\"exchange\":{},\"currency\":{}}}", output::json_string(vertex.get_exchange()),
+            output::json_string(vertex.get_currency())))
+        .collect();
+    fs::write(dir.join(VERTICES_JSON_FILE), format!("[{}]\n", vertices_json.join(",")))
+}
+
+// Write the `size` x `size` matrix `values`, in row-major order, as a version 1.0 `.npy` file
+fn write_npy(path: &Path, size: usize, values: &[f64]) -> io::Result<()> {
+    let mut header = format!("{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}), }}", size, size);
+    // The magic string, version and header length take 10 bytes, and the data must start on a multiple of 64
+    header.push_str(&" ".repeat(63 - (10 + header.len()) % 64));
+    header.push('\n');
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(b"\x93NUMPY\x01\x00")?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+// A float literal for Cypher, which reads numbers without a fraction or an exponent as integers
+fn cypher_float(value: f64) -> String {
+    format!("{:?}", value)
+}
+
+// Write the graph to `file_name` as Cypher statements that load it into Neo4j, e.g. with `cypher-shell -f`: a
+// `Vertex` node per vertex with its `exchange` and `currency`, a `QUOTE` relationship per edge within an exchange with
+// its `rate`, `updated` timestamp and whether it is `derived` from the quote the other way, and a `TRANSFER`
+// relationship per edge moving a currency between exchanges with its `rate`. Statements `MERGE` rather than create,
+// so loading a later graph into the same database updates the rates
+pub fn write_graph_cypher(file_name: &str, graph_result: &GraphResult, vertices: &VertexSet) -> io::Result<()> {
+    // Cypher strings take the same escapes as JSON ones
+    let vertex_pattern = |name: &str, vertex: &Vertex| format!("({}:Vertex {{exchange: {}, currency: {}}})", name,
+        output::json_string(vertex.get_exchange()), output::json_string(vertex.get_currency()));
+    let mut vertices: Vec<_> = vertices.iter().collect();
+    vertices.sort();
+    let mut edges: Vec<_> = graph_result.edges().collect();
+    edges.sort_by(|(from_vertex, to_vertex, _), (other_from_vertex, other_to_vertex, _)| {
+        (from_vertex, to_vertex).cmp(&(other_from_vertex, other_to_vertex))
+    });
+
+    let mut writer = BufWriter::new(File::create(file_name)?);
+    writeln!(writer, "CREATE CONSTRAINT vertex_key IF NOT EXISTS FOR (v:Vertex) REQUIRE (v.exchange, v.currency) \
+        IS UNIQUE;")?;
+    for vertex in vertices {
+        writeln!(writer, "MERGE {};", vertex_pattern("", vertex))?;
+    }
+    for (from_vertex, to_vertex, edge) in edges {
+        let properties = if from_vertex.get_exchange() == to_vertex.get_exchange() {
+            format!("QUOTE]->(b) SET r.rate = {}, r.updated = datetime({}), r.derived = {}",
+                cypher_float(edge.get_weight()), output::json_string(&format_datetime(edge.get_last_updated())),
+                edge.is_derived())
+        } else {
+            format!("TRANSFER]->(b) SET r.rate = {}", cypher_float(edge.get_weight()))
+        };
+        writeln!(writer, "MATCH {}, {} MERGE (a)-[r:{};", vertex_pattern("a", from_vertex),
+            vertex_pattern("b", to_vertex), properties)?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::EngineBuilder;
+    use std::convert::TryInto;
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn write_and_read_best_rates() {
+        use arrow_array::{Float64Array, StringArray, UInt32Array};
+        use arrow_ipc::reader::FileReader;
+
+        let mut engine = EngineBuilder::new()
+            .quote("KRAKEN", "BTC", "USD", 1000.0, 0.0009)
+            .quote("GDAX", "BTC", "USD", 1001.0, 0.0008)
+            .build();
+        engine.find_best_rates();
+        let file_name = std::env::temp_dir().join(format!("best-rates-test-{}.arrow", std::process::id()));
+        let file_name = file_name.to_str().unwrap();
+        write_best_rates_arrow(file_name, engine.get_graph_result(), engine.get_vertices()).unwrap();
+        let batches: Vec<_> = FileReader::try_new(File::open(file_name).unwrap(), None).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        std::fs::remove_file(file_name).unwrap();
+
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        // Every pair of the 4 vertices has a route
+        assert_eq!(batch.num_rows(), 12);
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+        let from_exchanges = column("from_exchange");
+        let from_exchanges = from_exchanges.as_any().downcast_ref::<StringArray>().unwrap();
+        let to_currencies = column("to_currency");
+        let to_currencies = to_currencies.as_any().downcast_ref::<StringArray>().unwrap();
+        let best_rates = column("best_rate");
+        let best_rates = best_rates.as_any().downcast_ref::<Float64Array>().unwrap();
+        let hops = column("hops");
+        let hops = hops.as_any().downcast_ref::<UInt32Array>().unwrap();
+        // <GDAX, BTC> is the first vertex and <GDAX, USD> its first destination
+        assert_eq!((from_exchanges.value(0), to_currencies.value(0), best_rates.value(0), hops.value(0)),
+            ("GDAX", "USD", 1001.0, 1));
+        assert_eq!((to_currencies.value(1), best_rates.value(1)), ("BTC", 1.0));
+    }
+
+    #[test]
+    fn write_matrices() {
+        let mut engine = EngineBuilder::new()
+            .quote("KRAKEN", "BTC", "USD", 1000.0, 0.0009)
+            .build();
+        engine.find_best_rates();
+        let dir = std::env::temp_dir().join(format!("npy-test-{}", std::process::id()));
+        write_matrices_npy(dir.to_str().unwrap(), engine.get_graph_result(), engine.get_vertices()).unwrap();
+        let best_rates = fs::read(dir.join(BEST_RATES_NPY_FILE)).unwrap();
+        let adjacency = fs::read(dir.join(ADJACENCY_NPY_FILE)).unwrap();
+        let vertices = fs::read_to_string(dir.join(VERTICES_JSON_FILE)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vertices, "[{\"exchange\":\"KRAKEN\",\"currency\":\"BTC\"},\
+            {\"exchange\":\"KRAKEN\",\"currency\":\"USD\"}]\n");
+        assert_eq!(&best_rates[..10], b"\x93NUMPY\x01\x00\x76\x00");
+        assert!(String::from_utf8_lossy(&best_rates[10..128]).starts_with("{'descr': '<f8', 'fortran_order': False, \
+            'shape': (2, 2), }"));
+        assert_eq!(best_rates[127], b'\n');
+        let values = |npy: &[u8]| npy[128..].chunks(8)
+            .map(|bytes| f64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(values(&best_rates), [1.0, 1000.0, 0.0009, 1.0]);
+        assert_eq!(values(&adjacency), [0.0, 1000.0, 0.0009, 0.0]);
+    }
+
+    #[test]
+    fn write_cypher() {
+        let engine = EngineBuilder::new()
+            .quote("KRAKEN", "BTC", "USD", 1000.0, 0.0009)
+            .quote("GDAX", "BTC", "USD", 1001.0, 0.0008)
+            .build();
+        let file_name = std::env::temp_dir().join(format!("graph-test-{}.cypher", std::process::id()));
+        let file_name = file_name.to_str().unwrap();
+        write_graph_cypher(file_name, engine.get_graph_result(), engine.get_vertices()).unwrap();
+        let cypher = fs::read_to_string(file_name).unwrap();
+        fs::remove_file(file_name).unwrap();
+
+        let lines: Vec<&str> = cypher.lines().collect();
+        // A constraint, 4 vertices, 4 quotes and 4 transfers
+        assert_eq!(lines.len(), 13);
+        assert!(lines[0].starts_with("CREATE CONSTRAINT vertex_key IF NOT EXISTS FOR (v:Vertex) "));
+        assert_eq!(lines[1], "MERGE (:Vertex {exchange: \"GDAX\", currency: \"BTC\"});");
+        assert_eq!(lines[5], "MATCH (a:Vertex {exchange: \"GDAX\", currency: \"BTC\"}), \
+            (b:Vertex {exchange: \"GDAX\", currency: \"USD\"}) MERGE (a)-[r:QUOTE]->(b) SET r.rate = 1001.0, \
+            r.updated = datetime(\"1970-01-01T00:00:00.002+00:00\"), r.derived = false;");
+        assert!(lines[6].ends_with("MERGE (a)-[r:TRANSFER]->(b) SET r.rate = 1.0;"));
+    }
+}
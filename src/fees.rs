@@ -0,0 +1,166 @@
+// Exchange fee schedules and fee-aware routing
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::SystemTime;
+
+use crate::alias::Aliases;
+use crate::input;
+use crate::logger;
+use crate::model::GraphResult;
+
+#[derive(Clone, Copy)]
+pub enum FeeSide {
+    Maker,
+    Taker
+}
+
+impl FeeSide {
+    pub fn parse(side: &str) -> Option<FeeSide> {
+        match side {
+            "maker" => Some(FeeSide::Maker),
+            "taker" => Some(FeeSide::Taker),
+            _ => None
+        }
+    }
+}
+
+// Fees charged once the trading volume reaches `min_volume`
+struct FeeTier {
+    min_volume: f64,
+    maker_fee: f64,
+    taker_fee: f64
+}
+
+pub struct FeeSchedule {
+    // tiers of each exchange, ordered by `min_volume`
+    tiers: HashMap<String, Vec<FeeTier>>
+}
+
+impl FeeSchedule {
+    /// Parse a fee schedule where each line is either `<exchange> <maker_fee> <taker_fee>` for the base tier
+    /// or `<exchange> <min_volume> <maker_fee> <taker_fee>` for the tier starting at `min_volume`
+    /// Fees are fractions of the traded amount, e.g. `0.0026` for 0.26%
+    pub fn parse(content: &str, aliases: &Aliases) -> Result<FeeSchedule, String> {
+        let mut tiers: HashMap<String, Vec<FeeTier>> = HashMap::new();
+        for line in input::content_lines(content) {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let numbers: Vec<f64> = match tokens[1..].iter().map(|token| token.parse()).collect() {
+                Ok(numbers) => numbers,
+                Err(_) => return Err(format!("Invalid fee \"{}\"", line))
+            };
+            let tier = match numbers[..] {
+                [maker_fee, taker_fee] => FeeTier { min_volume: 0.0, maker_fee, taker_fee },
+                [min_volume, maker_fee, taker_fee] if min_volume >= 0.0 => FeeTier { min_volume, maker_fee, taker_fee },
+                _ => return Err(format!("Invalid fee \"{}\"", line))
+            };
+            if !(0.0..1.0).contains(&tier.maker_fee) || !(0.0..1.0).contains(&tier.taker_fee) {
+                return Err(format!("Fees must be at least 0 and less than 1 \"{}\"", line));
+            }
+            tiers.entry(aliases.exchange(tokens[0]).to_string()).or_default().push(tier);
+        }
+        for exchange_tiers in tiers.values_mut() {
+            exchange_tiers.sort_by(|a, b| a.min_volume.partial_cmp(&b.min_volume).unwrap());
+        }
+        Ok(FeeSchedule {
+            tiers
+        })
+    }
+
+    // Fee charged by `exchange` at a trading volume of `volume`, 0 for exchanges without a schedule
+    pub fn get_fee(&self, exchange: &str, volume: f64, side: FeeSide) -> f64 {
+        let tier = self.tiers.get(exchange)
+            .and_then(|tiers| tiers.iter().rev().find(|tier| volume >= tier.min_volume));
+        match (tier, side) {
+            (Some(tier), FeeSide::Maker) => tier.maker_fee,
+            (Some(tier), FeeSide::Taker) => tier.taker_fee,
+            (None, _) => 0.0
+        }
+    }
+}
+
+// Routes on rates net of the fees in a schedule file, reloaded when the file changes
+pub struct FeeModel {
+    file_name: String,
+    modified: Option<SystemTime>,
+    schedule: FeeSchedule,
+    volume: f64,
+    side: FeeSide
+}
+
+impl FeeModel {
+    pub fn load(file_name: &str, volume: f64, side: FeeSide, aliases: &Aliases) -> Result<FeeModel, String> {
+        let (schedule, modified) = FeeModel::read(file_name, aliases)?;
+        Ok(FeeModel {
+            file_name: file_name.to_string(), modified, schedule, volume, side
+        })
+    }
+
+    fn read(file_name: &str, aliases: &Aliases) -> Result<(FeeSchedule, Option<SystemTime>), String> {
+        let modified = fs::metadata(file_name).and_then(|metadata| metadata.modified()).ok();
+        match fs::read_to_string(file_name) {
+            Ok(content) => Ok((FeeSchedule::parse(&content, aliases)?, modified)),
+            Err(e) => Err(format!("Error encountered while reading fee schedule: {}", e))
+        }
+    }
+
+    // Reload the schedule if the file was modified since it was read, and return whether it was reloaded
+    // An invalid schedule is logged and the previous one is kept
+    pub fn reload_if_changed(&mut self, aliases: &Aliases) -> bool {
+        let modified = fs::metadata(&self.file_name).and_then(|metadata| metadata.modified()).ok();
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        match FeeModel::read(&self.file_name, aliases) {
+            Ok((schedule, modified)) => {
+                self.schedule = schedule;
+                self.modified = modified;
+                logger::log(&format!("Reloaded fee schedule {}", self.file_name));
+                true
+            },
+            Err(e) => {
+                self.modified = modified;
+                logger::log(&format!("{}, keeping the previous fee schedule", e));
+                false
+            }
+        }
+    }
+
+    // Fraction of an amount left after trading on `exchange`
+    pub fn get_multiplier(&self, exchange: &str) -> f64 {
+        1.0 - self.schedule.get_fee(exchange, self.volume, self.side)
+    }
+
+    // Copy of `graph_result` where every quote is net of the fee of its exchange
+    // Moving a currency between exchanges is not a trade, so it is not charged
+    pub fn apply(&self, graph_result: &GraphResult) -> GraphResult {
+        graph_result.map_edges(|from_vertex, to_vertex, edge| {
+            if from_vertex.get_exchange() == to_vertex.get_exchange() {
+                Some(edge.get_weight() * self.get_multiplier(from_vertex.get_exchange()))
+            } else {
+                Some(edge.get_weight())
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_fee_tier_by_volume() {
+        let schedule = FeeSchedule::parse("KRAKEN 0.0016 0.0026\nKRAKEN 50000 0.0014 0.0024\n", &Aliases::none()).unwrap();
+        assert_eq!(schedule.get_fee("KRAKEN", 0.0, FeeSide::Taker), 0.0026);
+        assert_eq!(schedule.get_fee("KRAKEN", 50000.0, FeeSide::Taker), 0.0024);
+        assert_eq!(schedule.get_fee("KRAKEN", 60000.0, FeeSide::Maker), 0.0014);
+        assert_eq!(schedule.get_fee("GDAX", 0.0, FeeSide::Taker), 0.0);
+    }
+
+    #[test]
+    fn reject_invalid_fees() {
+        assert!(FeeSchedule::parse("KRAKEN 0.0016", &Aliases::none()).is_err());
+        assert!(FeeSchedule::parse("KRAKEN 0.0016 abc", &Aliases::none()).is_err());
+        assert!(FeeSchedule::parse("KRAKEN 0.0016 1.5", &Aliases::none()).is_err());
+    }
+}
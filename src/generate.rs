@@ -0,0 +1,245 @@
+// Synthetic market data, e.g. for load tests or to reproduce a bug without sharing real quotes
+// The price of every currency follows a random walk, and every exchange quotes its markets around those prices with
+// a spread of its own, occasionally mispricing a market enough to open an arbitrage cycle with another exchange
+
+use crate::model::{Currency, Exchange, ExchangeRateRequest, InputType, PriceUpdate};
+
+// Datetime of the first price update, the one of the sample input
+pub const START_DATETIME: u64 = 1509529343000;
+
+// Names and starting prices in the first currency of the first currencies, later ones are `CUR<n>` at 1
+const CURRENCIES: &[(&str, f64)] = &[
+    ("USD", 1.0), ("BTC", 6500.0), ("ETH", 300.0), ("EUR", 1.17), ("LTC", 55.0), ("XRP", 0.2), ("ADA", 0.1),
+    ("SOL", 20.0), ("DOT", 5.0), ("DOGE", 0.01)
+];
+// Names of the first exchanges, later ones are `EXCHANGE<n>`
+const EXCHANGES: &[&str] = &["KRAKEN", "GDAX", "BINANCE", "BITSTAMP", "GEMINI", "BITFINEX", "OKX", "HUOBI"];
+
+fn exchanges(num_exchanges: usize) -> Result<Vec<Exchange>, String> {
+    (0..num_exchanges)
+        .map(|index| match EXCHANGES.get(index) {
+            Some(name) => Exchange::new(name),
+            None => Exchange::new(&format!("EXCHANGE{}", index + 1))
+        })
+        .collect()
+}
+
+// Currencies and the natural logarithm of their starting price in the first one
+fn currencies(num_currencies: usize) -> Result<(Vec<Currency>, Vec<f64>), String> {
+    let currencies = (0..num_currencies)
+        .map(|index| match CURRENCIES.get(index) {
+            Some((name, price)) => Currency::new(name).map(|currency| (currency, price.ln())),
+            None => Currency::new(&format!("CUR{}", index + 1)).map(|currency| (currency, 0.0))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(currencies.into_iter().unzip())
+}
+
+// Small, fast and reproducible pseudo-random numbers, SplitMix64
+pub(crate) struct Random(pub(crate) u64);
+
+impl Random {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub(crate) fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    // Standard normal, by the Box-Muller transform
+    fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.next_f64();
+        (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * self.next_f64()).cos()
+    }
+}
+
+// An endless sequence of price updates, each followed by an exchange rate request at the request rate
+// The same seed and settings always generate the same sequence
+pub struct Generator {
+    exchanges: Vec<Exchange>,
+    currencies: Vec<Currency>,
+    // (exchange, base currency, quote currency) indices of every market, quoted as the price of the base currency in
+    // the quote currency: every currency against the first one, and against the second one
+    markets: Vec<(usize, usize, usize)>,
+    // Natural logarithm of the price of every currency in the first one
+    log_prices: Vec<f64>,
+    num_updates: u64,
+    tick_rate: f64,
+    volatility: f64,
+    arbitrage_rate: f64,
+    request_rate: f64,
+    random: Random,
+    pending_request: Option<ExchangeRateRequest>
+}
+
+impl Generator {
+    pub fn new(num_exchanges: usize, num_currencies: usize, seed: u64) -> Result<Generator, String> {
+        if num_exchanges == 0 || num_currencies < 2 {
+            return Err("Generating markets requires at least 1 exchange and 2 currencies".to_string());
+        }
+        let exchanges = exchanges(num_exchanges)?;
+        let (currencies, log_prices) = currencies(num_currencies)?;
+        let mut markets = Vec::new();
+        for exchange in 0..num_exchanges {
+            markets.extend((1..num_currencies).map(|base| (exchange, base, 0)));
+            markets.extend((2..num_currencies).map(|base| (exchange, base, 1)));
+        }
+        Ok(Generator {
+            exchanges, currencies, markets, log_prices, num_updates: 0, tick_rate: 10.0, volatility: 0.0001,
+            arbitrage_rate: 0.01, request_rate: 0.1, random: Random(seed), pending_request: None
+        })
+    }
+
+    // Price updates per second of generated time, defaults to 10
+    pub fn set_tick_rate(&mut self, tick_rate: f64) {
+        self.tick_rate = tick_rate;
+    }
+
+    // Standard deviation of the relative change of the price of a currency at each of its updates, defaults to 0.0001
+    pub fn set_volatility(&mut self, volatility: f64) {
+        self.volatility = volatility;
+    }
+
+    // Fraction of price updates mispricing their market by 1 to 3%, defaults to 0.01
+    pub fn set_arbitrage_rate(&mut self, arbitrage_rate: f64) {
+        self.arbitrage_rate = arbitrage_rate;
+    }
+
+    // Fraction of price updates followed by an exchange rate request, defaults to 0.1
+    pub fn set_request_rate(&mut self, request_rate: f64) {
+        self.request_rate = request_rate;
+    }
+
+    fn next_price_update(&mut self) -> PriceUpdate {
+        let (exchange, base, quote) = self.markets[self.random.below(self.markets.len())];
+        self.log_prices[base] += self.volatility * self.random.normal();
+        // Exchanges quote slightly apart, and with spreads of 0.1 to 0.3%
+        let mut mid = (self.log_prices[base] - self.log_prices[quote] + 0.0001 * self.random.normal()).exp();
+        if self.random.next_f64() < self.arbitrage_rate {
+            let mispricing = 0.01 + 0.02 * self.random.next_f64();
+            mid *= if self.random.next_f64() < 0.5 { 1.0 + mispricing } else { 1.0 - mispricing };
+        }
+        let half_spread = (0.001 + 0.0005 * (exchange % 5) as f64) / 2.0;
+        let datetime = START_DATETIME + (self.num_updates as f64 * 1000.0 / self.tick_rate) as u64;
+        self.num_updates += 1;
+        PriceUpdate::new(datetime, self.exchanges[exchange].clone(), self.currencies[base].clone(),
+            self.currencies[quote].clone(), mid * (1.0 - half_spread), (1.0 - half_spread) / mid)
+    }
+
+    fn next_request(&mut self) -> ExchangeRateRequest {
+        let num_vertices = self.exchanges.len() * self.currencies.len();
+        let source = self.random.below(num_vertices);
+        let dest = (source + 1 + self.random.below(num_vertices - 1)) % num_vertices;
+        let num_currencies = self.currencies.len();
+        ExchangeRateRequest::new(
+            self.exchanges[source / num_currencies].clone(), self.currencies[source % num_currencies].clone(),
+            self.exchanges[dest / num_currencies].clone(), self.currencies[dest % num_currencies].clone(), None
+        )
+    }
+}
+
+impl Iterator for Generator {
+    type Item = InputType;
+
+    fn next(&mut self) -> Option<InputType> {
+        if let Some(exchange_rate_request) = self.pending_request.take() {
+            return Some(InputType::ExchangeRateRequest(exchange_rate_request));
+        }
+        let price_update = self.next_price_update();
+        if self.random.next_f64() < self.request_rate {
+            self.pending_request = Some(self.next_request());
+        }
+        Some(InputType::PriceUpdate(price_update))
+    }
+}
+
+// Price updates quoting a `density` fraction of the pairs of currencies on every exchange, chosen at random, at the
+// starting prices of the currencies with a spread of 0.1%, so that the graph has no arbitrage cycle
+pub fn random_graph(num_exchanges: usize, num_currencies: usize, density: f64, seed: u64)
+    -> Result<Vec<PriceUpdate>, String> {
+    let exchanges = exchanges(num_exchanges)?;
+    let (currencies, log_prices) = currencies(num_currencies)?;
+    let mut random = Random(seed);
+    let mut price_updates = Vec::new();
+    for exchange in exchanges.iter() {
+        for base in 0..num_currencies {
+            for quote in 0..base {
+                if random.next_f64() >= density {
+                    continue;
+                }
+                let mid = (log_prices[base] - log_prices[quote]).exp();
+                price_updates.push(PriceUpdate::new(START_DATETIME, exchange.clone(), currencies[base].clone(),
+                    currencies[quote].clone(), mid * 0.9995, 0.9995 / mid));
+            }
+        }
+    }
+    Ok(price_updates)
+}
+
+// `num_requests` exchange rate requests between random vertices of the exchanges and currencies of `random_graph`
+pub fn random_requests(num_exchanges: usize, num_currencies: usize, num_requests: usize, seed: u64)
+    -> Result<Vec<ExchangeRateRequest>, String> {
+    let mut generator = Generator::new(num_exchanges, num_currencies, seed)?;
+    Ok((0..num_requests).map(|_| generator.next_request()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(generator: Generator, num_lines: usize) -> Vec<String> {
+        generator.take(num_lines).map(|input| match input {
+            InputType::PriceUpdate(price_update) => price_update.to_string(),
+            InputType::ExchangeRateRequest(exchange_rate_request) => exchange_rate_request.to_string(),
+            InputType::Invalid(reason) => panic!("Generated invalid input: {}", reason)
+        }).collect()
+    }
+
+    #[test]
+    fn generate_markets() {
+        let mut generator = Generator::new(2, 3, 42).unwrap();
+        generator.set_arbitrage_rate(0.5);
+        generator.set_request_rate(0.5);
+        let mut price_updates = Vec::new();
+        let mut num_requests = 0;
+        for input in generator.take(200) {
+            match input {
+                InputType::PriceUpdate(price_update) => price_updates.push(price_update),
+                _ => num_requests += 1
+            }
+        }
+        assert!(num_requests > 0);
+        assert!(price_updates.windows(2).all(|pair| pair[0].get_datetime() < pair[1].get_datetime()));
+        for price_update in price_updates.iter() {
+            let both_ratio = price_update.get_forward_ratio() * price_update.get_backward_ratio();
+            assert!(both_ratio > 0.99 && both_ratio < 1.0, "{}", price_update);
+            assert!(["USD", "BTC"].contains(&price_update.get_dest_currency()));
+        }
+        assert_eq!(price_updates[0].get_datetime(), START_DATETIME);
+        assert_eq!(price_updates[1].get_datetime(), START_DATETIME + 100);
+
+        // Reproducible from the seed
+        assert_eq!(lines(Generator::new(2, 3, 42).unwrap(), 50), lines(Generator::new(2, 3, 42).unwrap(), 50));
+        assert_ne!(lines(Generator::new(2, 3, 42).unwrap(), 50), lines(Generator::new(2, 3, 43).unwrap(), 50));
+        assert!(Generator::new(2, 1, 42).is_err());
+        assert_eq!(Generator::new(10, 12, 1).unwrap().exchanges[9].as_str(), "EXCHANGE10");
+    }
+
+    #[test]
+    fn generate_random_graphs() {
+        assert_eq!(random_graph(2, 4, 1.0, 1).unwrap().len(), 12);
+        let num_price_updates = random_graph(10, 10, 0.5, 1).unwrap().len();
+        assert!(num_price_updates > 150 && num_price_updates < 300, "{}", num_price_updates);
+        assert_eq!(random_requests(2, 4, 5, 1).unwrap().len(), 5);
+    }
+}
@@ -0,0 +1,502 @@
+// Directed graphs and the best path between every pair of nodes. Nodes and weights carry no meaning here,
+// the exchange rate graph of `model` is built on top of these
+
+#[cfg(feature = "ordered-graph")]
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(not(feature = "ordered-graph"))]
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+// Anything that can identify a node
+pub trait Node: Clone + Eq + Hash + Ord {}
+
+impl<N: Clone + Eq + Hash + Ord> Node for N {}
+
+// Sets and maps of nodes. With the `ordered-graph` feature they are B-trees, so iteration follows the order of
+// nodes and is the same across runs and platforms, otherwise they are hash tables
+#[cfg(not(feature = "ordered-graph"))]
+pub type NodeSet<N> = HashSet<N>;
+#[cfg(not(feature = "ordered-graph"))]
+pub type NodeMap<N, V> = HashMap<N, V>;
+#[cfg(feature = "ordered-graph")]
+pub type NodeSet<N> = BTreeSet<N>;
+#[cfg(feature = "ordered-graph")]
+pub type NodeMap<N, V> = BTreeMap<N, V>;
+
+// Weight of an edge or a path. Paths are extended and compared with these, so the same algorithms work on rates
+// multiplying along a path, on sums of logarithms, on decimals or on composite weights such as (rate, hops)
+pub trait Weight: Copy {
+    // weight of a path without hops
+    fn identity() -> Self;
+    // weight of a path with this weight followed by one with `other`
+    fn combine(self, other: Self) -> Self;
+    // whether a path with this weight is better than one with `other`
+    fn is_better(self, other: Self) -> bool;
+    // the weight kept when going around a cycle better than the identity grows it without bound, see
+    // `Graph::shortest_paths`
+    fn saturate(self) -> Self {
+        self
+    }
+}
+
+// Rates multiply along a path and a higher rate is better
+impl Weight for f64 {
+    fn identity() -> f64 {
+        1.0
+    }
+
+    fn combine(self, other: f64) -> f64 {
+        self * other
+    }
+
+    fn is_better(self, other: f64) -> bool {
+        self > other
+    }
+
+    // Rates saturate at the largest finite rate, so that a cycle of rates multiplying to more than 1 cannot grow a
+    // rate to infinity
+    fn saturate(self) -> f64 {
+        self.min(f64::MAX)
+    }
+}
+
+// (rate, hops): a higher rate is better, and fewer hops break ties
+impl Weight for (f64, usize) {
+    fn identity() -> (f64, usize) {
+        (1.0, 0)
+    }
+
+    fn combine(self, other: (f64, usize)) -> (f64, usize) {
+        (self.0.combine(other.0), self.1 + other.1)
+    }
+
+    fn is_better(self, other: (f64, usize)) -> bool {
+        self.0 > other.0 || (self.0 == other.0 && self.1 < other.1)
+    }
+
+    fn saturate(self) -> (f64, usize) {
+        (self.0.saturate(), self.1)
+    }
+}
+
+// A directed graph with at most one edge of type `E` from a node to another
+#[cfg(not(feature = "petgraph"))]
+pub struct Graph<N, E> {
+    // edges[from][to] is the edge from `from` to `to`
+    edges: NodeMap<N, NodeMap<N, E>>
+}
+
+// With the `petgraph` feature the nodes and edges are stored in a petgraph `Graph`, see `as_petgraph`
+#[cfg(feature = "petgraph")]
+pub struct Graph<N, E> {
+    graph: petgraph::Graph<N, E>,
+    // index of each node in `graph`
+    indices: NodeMap<N, petgraph::graph::NodeIndex>
+}
+
+impl<N: Node, E> Default for Graph<N, E> {
+    fn default() -> Self {
+        Graph::new()
+    }
+}
+
+#[cfg(not(feature = "petgraph"))]
+impl<N: Node, E> Graph<N, E> {
+    pub fn new() -> Graph<N, E> {
+        Graph {
+            edges: NodeMap::new()
+        }
+    }
+
+    pub fn get_edge(&self, from: &N, to: &N) -> Option<&E> {
+        self.edges.get(from).and_then(|inner_map| inner_map.get(to))
+    }
+
+    pub fn get_edge_mut(&mut self, from: &N, to: &N) -> Option<&mut E> {
+        self.edges.get_mut(from).and_then(|inner_map| inner_map.get_mut(to))
+    }
+
+    // Add the edge from `from` to `to`, replacing any existing one
+    pub fn set_edge(&mut self, from: N, to: N, edge: E) {
+        self.edges.entry(from).or_default().insert(to, edge);
+    }
+
+    pub fn remove_edge(&mut self, from: &N, to: &N) {
+        if let Some(inner_map) = self.edges.get_mut(from) {
+            inner_map.remove(to);
+        }
+    }
+
+    // Remove `node` and every edge going in or out of it
+    pub fn remove_node(&mut self, node: &N) {
+        self.edges.remove(node);
+        for inner_map in self.edges.values_mut() {
+            inner_map.remove(node);
+        }
+    }
+
+    pub fn num_edges(&self) -> usize {
+        self.edges.values().map(|inner_map| inner_map.len()).sum()
+    }
+
+    // Nodes with at least one edge going out
+    pub fn sources(&self) -> impl Iterator<Item = &N> {
+        self.edges.keys()
+    }
+
+    // Every edge going out of `from` as (to, edge)
+    pub fn edges_from<'a>(&'a self, from: &N) -> impl Iterator<Item = (&'a N, &'a E)> {
+        self.edges.get(from).into_iter().flat_map(|inner_map| inner_map.iter())
+    }
+
+    // Every edge as (from, to, edge)
+    pub fn edges(&self) -> impl Iterator<Item = (&N, &N, &E)> {
+        self.edges.iter()
+            .flat_map(|(from, inner_map)| inner_map.iter().map(move |(to, edge)| (from, to, edge)))
+    }
+}
+
+#[cfg(feature = "petgraph")]
+impl<N: Node, E> Graph<N, E> {
+    pub fn new() -> Graph<N, E> {
+        Graph {
+            graph: petgraph::Graph::new(),
+            indices: NodeMap::new()
+        }
+    }
+
+    // The petgraph `Graph` holding the nodes and edges, e.g. to render it with `petgraph::dot::Dot` or to run
+    // other petgraph algorithms on it
+    pub fn as_petgraph(&self) -> &petgraph::Graph<N, E> {
+        &self.graph
+    }
+
+    fn find_edge(&self, from: &N, to: &N) -> Option<petgraph::graph::EdgeIndex> {
+        self.graph.find_edge(*self.indices.get(from)?, *self.indices.get(to)?)
+    }
+
+    // Index of `node` in `graph`, adding it if it is not there yet
+    fn add_node(&mut self, node: N) -> petgraph::graph::NodeIndex {
+        match self.indices.get(&node) {
+            Some(index) => *index,
+            None => {
+                let index = self.graph.add_node(node.clone());
+                self.indices.insert(node, index);
+                index
+            }
+        }
+    }
+
+    pub fn get_edge(&self, from: &N, to: &N) -> Option<&E> {
+        self.find_edge(from, to).and_then(|edge| self.graph.edge_weight(edge))
+    }
+
+    pub fn get_edge_mut(&mut self, from: &N, to: &N) -> Option<&mut E> {
+        self.find_edge(from, to).and_then(move |edge| self.graph.edge_weight_mut(edge))
+    }
+
+    // Add the edge from `from` to `to`, replacing any existing one
+    pub fn set_edge(&mut self, from: N, to: N, edge: E) {
+        let from = self.add_node(from);
+        let to = self.add_node(to);
+        self.graph.update_edge(from, to, edge);
+    }
+
+    pub fn remove_edge(&mut self, from: &N, to: &N) {
+        if let Some(edge) = self.find_edge(from, to) {
+            self.graph.remove_edge(edge);
+        }
+    }
+
+    // Remove `node` and every edge going in or out of it
+    pub fn remove_node(&mut self, node: &N) {
+        if let Some(index) = self.indices.remove(node) {
+            self.graph.remove_node(index);
+            // petgraph moves the last node to the index of the removed one
+            if let Some(moved) = self.graph.node_weight(index) {
+                self.indices.insert(moved.clone(), index);
+            }
+        }
+    }
+
+    pub fn num_edges(&self) -> usize {
+        self.graph.edge_count()
+    }
+
+    // Nodes with at least one edge going out
+    pub fn sources(&self) -> impl Iterator<Item = &N> {
+        self.graph.node_indices()
+            .filter(move |index| self.graph.neighbors(*index).next().is_some())
+            .map(move |index| &self.graph[index])
+    }
+
+    // Every edge going out of `from` as (to, edge)
+    pub fn edges_from<'a>(&'a self, from: &N) -> impl Iterator<Item = (&'a N, &'a E)> {
+        use petgraph::visit::EdgeRef;
+
+        self.indices.get(from).into_iter()
+            .flat_map(move |index| self.graph.edges(*index))
+            .map(move |edge| (&self.graph[edge.target()], edge.weight()))
+    }
+
+    // Every edge as (from, to, edge)
+    pub fn edges(&self) -> impl Iterator<Item = (&N, &N, &E)> {
+        use petgraph::visit::EdgeRef;
+
+        self.graph.edge_references()
+            .map(move |edge| (&self.graph[edge.source()], &self.graph[edge.target()], edge.weight()))
+    }
+}
+
+impl<N: Node, E> Graph<N, E> {
+    // Copy of this graph with every edge replaced by `map(from, to, edge)`. Edges for which it returns None are left out
+    pub fn map_edges<F, T>(&self, map: F) -> Graph<N, T>
+        where F: Fn(&N, &N, &E) -> Option<T>
+    {
+        let mut graph = Graph::new();
+        for (from, to, edge) in self.edges() {
+            if let Some(edge) = map(from, to, edge) {
+                graph.set_edge(from.clone(), to.clone(), edge);
+            }
+        }
+        graph
+    }
+
+    // The best path between every pair of `nodes` where each edge has the weight `weight(edge)`, by Floyd-Warshall:
+    // a path through `k` is better when best[i][k] combined with best[k][j] is better than best[i][j].
+    // Nodes are visited in a fixed order so that ties between equally good paths are broken the same way every run.
+    // A cycle better than the identity, such as arbitrage, grows the weights going around it, so they saturate instead
+    // of overflowing, and the nodes on such cycles are found, see `ShortestPaths::is_unbounded`
+    // Runtime: O(N^3)
+    pub fn shortest_paths<W, F>(&self, nodes: &NodeSet<N>, weight: F) -> ShortestPaths<N, W>
+        where W: Weight, F: Fn(&E) -> W
+    {
+        let mut paths = ShortestPaths::new();
+        // For all edges, add edge in best[i][j], add j in next[i][j]
+        for (i, j, edge) in self.edges() {
+            paths.best.entry(i.clone()).or_default().insert(j.clone(), weight(edge));
+            paths.next.entry(i.clone()).or_default().insert(j.clone(), j.clone());
+        }
+
+        let mut nodes: Vec<&N> = nodes.iter().collect();
+        nodes.sort();
+
+        for k in nodes.iter() {
+            for i in nodes.iter() {
+                // No path from `i` through `k`
+                let ik_weight = match paths.get_weight(i, k) {
+                    Some(ik_weight) if i != k => ik_weight,
+                    _ => continue
+                };
+                for j in nodes.iter() {
+                    // Skip weight comparison if any pair of ij, ik, kj are the same nodes
+                    if i == j || k == j {
+                        continue;
+                    }
+                    let kj_weight = match paths.get_weight(k, j) {
+                        Some(kj_weight) => kj_weight,
+                        None => continue
+                    };
+                    let ikj_weight = ik_weight.combine(kj_weight).saturate();
+                    if paths.get_weight(i, j).is_none_or(|ij_weight| ikj_weight.is_better(ij_weight)) {
+                        // Update next[i][j] to next[i][k]
+                        let ik_next = paths.next[*i][*k].clone();
+                        paths.best.get_mut(*i).unwrap().insert((*j).clone(), ikj_weight);
+                        paths.next.get_mut(*i).unwrap().insert((*j).clone(), ik_next);
+                    }
+                }
+            }
+        }
+        paths.find_cycles();
+        paths
+    }
+}
+
+// The best path between every pair of nodes of a graph
+pub struct ShortestPaths<N, W> {
+    // best[i][j] is the weight of the best path from `i` to `j`
+    best: NodeMap<N, NodeMap<N, W>>,
+    // next[i][j] is the node after `i` on the best path from `i` to `j`
+    next: NodeMap<N, NodeMap<N, N>>,
+    // nodes on a cycle whose weight is better than the identity, see `find_cycles`
+    cycle_nodes: NodeSet<N>
+}
+
+impl<N: Node, W: Weight> Default for ShortestPaths<N, W> {
+    fn default() -> Self {
+        ShortestPaths::new()
+    }
+}
+
+impl<N: Node, W: Weight> ShortestPaths<N, W> {
+    // No paths at all
+    pub fn new() -> ShortestPaths<N, W> {
+        ShortestPaths {
+            best: NodeMap::new(),
+            next: NodeMap::new(),
+            cycle_nodes: NodeSet::new()
+        }
+    }
+
+    // Weight of the best path from `from` to `to`, None if there is no path
+    pub fn get_weight(&self, from: &N, to: &N) -> Option<W> {
+        self.best.get(from).and_then(|inner_map| inner_map.get(to)).copied()
+    }
+
+    // (from, to, weight, next) of the best path between every pair of nodes with a path
+    pub fn entries(&self) -> impl Iterator<Item = (&N, &N, W, &N)> {
+        self.best.iter().flat_map(move |(from, best)| {
+            best.iter().map(move |(to, weight)| (from, to, *weight, &self.next[from][to]))
+        })
+    }
+
+    // Set the best path from `from` to `to`, e.g. to restore paths found before
+    pub fn insert(&mut self, from: N, to: N, weight: W, next: N) {
+        self.best.entry(from.clone()).or_default().insert(to.clone(), weight);
+        self.next.entry(from).or_default().insert(to, next);
+    }
+
+    // The nodes of the best path from `from` to `to`, both included
+    pub fn path(&self, from: &N, to: &N) -> Option<Vec<N>> {
+        self.next.get(from)?.get(to)?;
+
+        let mut path = vec![from.clone()];
+        let mut node = from.clone();
+        while node != *to {
+            node = self.next[&node][to].clone();
+            path.push(node.clone());
+            // A cycle whose weight is better than the identity, such as rates multiplying to more than 1 (arbitrage),
+            // can be repeated forever, so there is no best path
+            if path.len() > self.next.len() + 1 {
+                return None;
+            }
+        }
+        Some(path)
+    }
+
+    // Find the nodes on a cycle whose weight is better than the identity, such as rates multiplying to more than 1
+    // (arbitrage). Best paths never return to their first node, so `k` is on such a cycle when going from `k` to some
+    // `m` and back is better than staying. Call it again after `insert`ing paths
+    // Runtime: O(N^2)
+    pub fn find_cycles(&mut self) {
+        let identity = W::identity();
+        self.cycle_nodes = self.best.iter()
+            .filter(|(k, best)| best.iter().any(|(m, km_weight)| {
+                self.get_weight(m, k).is_some_and(|mk_weight| km_weight.combine(mk_weight).is_better(identity))
+            }))
+            .map(|(k, _)| k.clone())
+            .collect();
+    }
+
+    // Whether paths from `from` to `to` can go through a cycle better than the identity as many times as they like,
+    // so that no path is the best
+    pub fn is_unbounded(&self, from: &N, to: &N) -> bool {
+        self.cycle_nodes.iter().any(|k| {
+            (k == from || self.get_weight(from, k).is_some()) && (k == to || self.get_weight(k, to).is_some())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortest_paths() {
+        let mut graph: Graph<u32, f64> = Graph::new();
+        graph.set_edge(1, 2, 2.0);
+        graph.set_edge(2, 3, 3.0);
+        graph.set_edge(1, 3, 5.0);
+        graph.set_edge(3, 4, 0.5);
+        let nodes: NodeSet<u32> = (1..=4).collect();
+        let paths = graph.shortest_paths(&nodes, |weight| *weight);
+        assert_eq!(paths.get_weight(&1, &4), Some(3.0));
+        assert_eq!(paths.path(&1, &4), Some(vec![1, 2, 3, 4]));
+        assert_eq!(paths.get_weight(&4, &1), None);
+        assert_eq!(paths.path(&4, &1), None);
+
+        // Fewer hops break ties between rates
+        let paths = graph.map_edges(|_, _, weight| Some((*weight, 1))).shortest_paths(&nodes, |weight| *weight);
+        graph.set_edge(1, 3, 6.0);
+        let tied = graph.map_edges(|_, _, weight| Some((*weight, 1))).shortest_paths(&nodes, |weight| *weight);
+        assert_eq!(paths.path(&1, &3), Some(vec![1, 2, 3]));
+        assert_eq!(tied.path(&1, &3), Some(vec![1, 3]));
+        assert_eq!(tied.get_weight(&1, &4), Some((3.0, 2)));
+        assert!(!paths.is_unbounded(&1, &4));
+    }
+
+    #[test]
+    fn shortest_paths_of_any_length_in_node_order() {
+        // Each node only reaches the one before it, so the best path from 5 to 1 takes every hop
+        let mut graph: Graph<u32, f64> = Graph::new();
+        for node in 2..=5 {
+            graph.set_edge(node, node - 1, 2.0);
+        }
+        let nodes: NodeSet<u32> = (1..=5).collect();
+        let paths = graph.shortest_paths(&nodes, |weight| *weight);
+        assert_eq!(paths.get_weight(&5, &1), Some(16.0));
+        assert_eq!(paths.path(&5, &1), Some(vec![5, 4, 3, 2, 1]));
+
+        // Equally good paths are broken by the order of the nodes, whatever order the edges were added in
+        for through in [[3, 2], [2, 3]] {
+            let mut graph: Graph<u32, f64> = Graph::new();
+            for node in through {
+                graph.set_edge(1, node, 2.0);
+                graph.set_edge(node, 4, 3.0);
+            }
+            let paths = graph.shortest_paths(&(1..=4).collect(), |weight| *weight);
+            assert_eq!(paths.path(&1, &4), Some(vec![1, 2, 4]));
+        }
+    }
+
+    #[test]
+    fn detect_unbounded_paths() {
+        let mut graph: Graph<u32, f64> = Graph::new();
+        graph.set_edge(1, 2, 2.0);
+        graph.set_edge(2, 3, f64::MAX);
+        graph.set_edge(3, 2, 1.0);
+        graph.set_edge(3, 4, 1.0);
+        graph.set_edge(5, 1, 1.0);
+        let nodes: NodeSet<u32> = (1..=5).collect();
+        let paths = graph.shortest_paths(&nodes, |weight| *weight);
+        assert!(paths.is_unbounded(&1, &4));
+        assert!(paths.is_unbounded(&2, &3));
+        assert!(!paths.is_unbounded(&5, &1));
+        assert!(!paths.is_unbounded(&4, &1));
+        // The rate saturates instead of reaching infinity, and the path found does not show the cycle
+        assert_eq!(paths.get_weight(&1, &4), Some(f64::MAX));
+        assert_eq!(paths.path(&1, &4), Some(vec![1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn remove_edges_and_nodes() {
+        let mut graph: Graph<u32, f64> = Graph::new();
+        graph.set_edge(1, 2, 2.0);
+        graph.set_edge(2, 1, 0.5);
+        graph.set_edge(2, 3, 3.0);
+        graph.set_edge(3, 1, 0.1);
+        graph.remove_edge(&1, &2);
+        graph.remove_edge(&1, &3);
+        assert_eq!(graph.num_edges(), 3);
+        graph.remove_node(&1);
+        assert_eq!(graph.edges().map(|(from, to, _)| (*from, *to)).collect::<Vec<_>>(), [(2, 3)]);
+        graph.set_edge(3, 4, 4.0);
+        assert_eq!(graph.get_edge(&3, &4), Some(&4.0));
+        assert_eq!(graph.num_edges(), 2);
+    }
+
+    #[cfg(feature = "petgraph")]
+    #[test]
+    fn petgraph_backend() {
+        let mut graph: Graph<&str, f64> = Graph::new();
+        graph.set_edge("BTC", "USD", 1000.0);
+        graph.set_edge("BTC", "USD", 1001.0);
+        graph.set_edge("USD", "EUR", 0.9);
+        assert_eq!(graph.get_edge(&"BTC", &"USD"), Some(&1001.0));
+        assert_eq!(graph.sources().collect::<Vec<_>>(), [&"BTC", &"USD"]);
+        let petgraph = graph.as_petgraph();
+        assert_eq!(petgraph.edge_count(), 2);
+        let (btc, eur) = (petgraph.node_indices().next().unwrap(), petgraph.node_indices().next_back().unwrap());
+        assert!(petgraph::algo::has_path_connecting(petgraph, btc, eur, None));
+    }
+}
@@ -0,0 +1,310 @@
+// Reading input lines
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::backoff::Backoff;
+use crate::broker;
+use crate::chaos::{FaultInjector, Injected};
+use crate::constants;
+use crate::logger;
+use crate::plugin;
+use crate::queue::{self, Backpressure, LineReceiver, LineSender};
+use crate::remote;
+
+// `line` without a byte order mark, its line ending and surrounding whitespace, which files written on Windows or
+// exported from spreadsheets may have
+pub fn clean_line(line: &str) -> &str {
+    line.trim_start_matches('\u{feff}').trim()
+}
+
+// The lines of a file such as an alias map or a fee schedule, cleaned with `clean_line`, leaving out blank ones
+pub fn content_lines(content: &str) -> impl Iterator<Item = &str> {
+    content.lines().map(clean_line).filter(|line| !line.is_empty())
+}
+
+// Open an object store or HTTP(S) URL from byte `offset`, `header` is only sent to HTTP(S) URLs
+// `etag` is the ETag of the previous fetch and is updated, an unchanged URL reads as empty
+fn open_url(url: &str, offset: u64, header: Option<&(String, String)>, etag: &mut Option<String>)
+    -> Result<Box<dyn BufRead + Send>, io::Error> {
+    let input = if remote::is_object_url(url) {
+        remote::open_object(url, offset, etag)?
+    } else {
+        remote::open_http(url, offset, header, etag)?
+    };
+    Ok(input.unwrap_or_else(|| Box::new(io::empty())))
+}
+
+// Open the input for reading line by line, `-` reads from standard input as lines arrive
+fn open_input(file_name: &str, header: Option<&(String, String)>, etag: &mut Option<String>)
+    -> Result<Box<dyn BufRead + Send>, io::Error> {
+    if file_name == "-" {
+        return Ok(Box::new(BufReader::new(io::stdin())));
+    }
+    if let Some(source) = plugin::find_source(file_name) {
+        return source.open(file_name, 0);
+    }
+    if broker::is_broker_url(file_name) {
+        return broker::subscribe(file_name);
+    }
+    if remote::is_url(file_name) {
+        return open_url(file_name, 0, header, etag);
+    }
+    Ok(Box::new(BufReader::new(File::open(file_name)?)))
+}
+
+// Reopen a file input and continue from `offset`, standard input cannot be reopened
+// A broker channel is subscribed to again, messages cannot be replayed from `offset`
+fn reopen_input(file_name: &str, offset: u64, header: Option<&(String, String)>, etag: &mut Option<String>)
+    -> Result<Box<dyn BufRead + Send>, io::Error> {
+    if file_name == "-" {
+        return Err(io::Error::other("standard input cannot be reopened"));
+    }
+    if let Some(source) = plugin::find_source(file_name) {
+        return source.open(file_name, offset);
+    }
+    if broker::is_broker_url(file_name) {
+        return broker::subscribe(file_name);
+    }
+    if remote::is_url(file_name) {
+        return open_url(file_name, offset, header, etag);
+    }
+    let mut file = File::open(file_name)?;
+    // Pipes cannot seek, they continue where the writer is
+    let _ = file.seek(SeekFrom::Start(offset));
+    Ok(Box::new(BufReader::new(file)))
+}
+
+// Send `line` cleaned with `clean_line`, or the lines of the file it names if it is an `INCLUDE <path>` line. Paths
+// are relative to `dir`, the directory of the including file, `including` holds the files being included to stop cycles
+// Without `dir`, for input that is not a local file, `INCLUDE` lines are sent as they are, so that whoever can publish
+// to a channel or serve a URL cannot have local files read
+// Included lines carry `start_offset`, the position before the `INCLUDE` line, except the last one which carries
+// `end_offset`, so a run resumed in the middle of an included file reads it again from the start
+// Fails once the receiver is gone
+fn send_line(sender: &LineSender, line: String, start_offset: u64, end_offset: u64, dir: Option<&Path>,
+    including: &mut Vec<PathBuf>
+) -> Result<(), ()> {
+    let line = clean_line(&line).to_string();
+    let include = dir.zip(line.strip_prefix(constants::INCLUDE_DIRECTIVE).and_then(|rest| rest.strip_prefix(' ')));
+    let path = match include {
+        Some((dir, path)) => {
+            let path = dir.join(path.trim());
+            fs::canonicalize(&path).unwrap_or(path)
+        },
+        None => return sender.send(Ok((line, end_offset)))
+    };
+    if including.contains(&path) {
+        return sender.send(Err(io::Error::other(format!("{} includes itself", path.display()))));
+    }
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) => return sender.send(Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e))))
+    };
+    let included_dir = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+    including.push(path);
+    let lines: Vec<&str> = content.lines().collect();
+    for (index, included_line) in lines.iter().enumerate() {
+        let offset = if index + 1 == lines.len() { end_offset } else { start_offset };
+        send_line(sender, included_line.to_string(), start_offset, offset, Some(&included_dir), including)?;
+    }
+    including.pop();
+    Ok(())
+}
+
+// Directory that `INCLUDE` paths of `file_name` are relative to, the directory of a local input file. Standard input,
+// URLs and plugin sources are not trusted to read local files, their `INCLUDE` lines are sent as they are
+fn include_dir(file_name: &str) -> Option<PathBuf> {
+    if file_name == "-" || remote::is_url(file_name) || broker::is_broker_url(file_name)
+        || plugin::find_source(file_name).is_some() {
+        None
+    } else {
+        Some(Path::new(file_name).parent().map_or_else(PathBuf::new, Path::to_path_buf))
+    }
+}
+
+// What to send for `line`, read from `start_offset` to `end_offset`, once `faults` are injected into it, if any
+fn inject(faults: &mut Option<FaultInjector>, line: String, start_offset: u64, end_offset: u64) -> Vec<Injected> {
+    match faults {
+        Some(faults) => faults.inject(line, start_offset, end_offset),
+        None => vec![Injected::Line(line, start_offset, end_offset)]
+    }
+}
+
+// Send the lines of `injected` with `send_line`, and whether it injected a disconnect
+// Fails once the receiver is gone
+fn send_injected(sender: &LineSender, injected: Vec<Injected>, dir: Option<&Path>) -> Result<bool, ()> {
+    let mut disconnected = false;
+    for injected in injected {
+        match injected {
+            Injected::Line(line, start_offset, end_offset) => {
+                send_line(sender, line, start_offset, end_offset, dir, &mut Vec::new())?
+            },
+            Injected::Disconnect => disconnected = true
+        }
+    }
+    Ok(disconnected)
+}
+
+// Read lines on a separate thread so that waiting for input does not delay a shutdown
+// When `follow` is set the end of input is not final: reading is retried with backoff, like `tail -f`,
+// and the input is reopened after a read error
+// At most `buffer_size` lines are queued, `backpressure` decides what happens when processing falls behind
+// Reading starts at byte `offset`, which standard input does not support
+// `INCLUDE <path>` lines of a local file are replaced with the lines of the file at `<path>`
+// `header` is sent with every request when the input is an HTTP(S) URL
+// With `refetch_every` a URL is fetched again on that interval after the end of input, from the end of the last
+// complete line, and skipped while its ETag is unchanged. URLs are expected to only grow, like a log
+// With `faults` the lines read go through `FaultInjector::inject` first, and an injected disconnect is handled like a
+// read error
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_reader(file_name: &str, follow: bool, buffer_size: usize, backpressure: Backpressure, offset: u64,
+    header: Option<(String, String)>, refetch_every: Option<Duration>, mut faults: Option<FaultInjector>
+) -> Result<LineReceiver, io::Error> {
+    let mut etag = None;
+    let mut input = if offset > 0 {
+        reopen_input(file_name, offset, header.as_ref(), &mut etag)?
+    } else {
+        open_input(file_name, header.as_ref(), &mut etag)?
+    };
+    let dir = include_dir(file_name);
+    let file_name = file_name.to_string();
+    let (sender, receiver) = queue::channel(buffer_size, backpressure);
+
+    thread::spawn(move || {
+        let dir = dir.as_deref();
+        let mut backoff = Backoff::new(Duration::from_millis(constants::FOLLOW_INITIAL_BACKOFF_MS),
+            Duration::from_millis(constants::FOLLOW_MAX_BACKOFF_MS));
+        // bytes of complete lines read so far
+        let mut offset = offset;
+        let mut line = String::new();
+        // a disconnect injected into the last line read, which then reads as an error
+        let mut disconnected = false;
+        loop {
+            let read = if disconnected {
+                disconnected = false;
+                Err(io::Error::new(io::ErrorKind::ConnectionReset, "injected disconnect"))
+            } else {
+                input.read_line(&mut line)
+            };
+            match read {
+                Ok(num_bytes) if num_bytes > 0 && line.ends_with('\n') => {
+                    let start_offset = offset;
+                    offset += line.len() as u64;
+                    backoff.reset();
+                    let complete_line = line.clone();
+                    line.clear();
+                    match send_injected(&sender, inject(&mut faults, complete_line, start_offset, offset), dir) {
+                        Ok(false) => {},
+                        // The line is read again after reconnecting, unless the input cannot replay it
+                        Ok(true) => {
+                            offset = start_offset;
+                            disconnected = true;
+                        },
+                        Err(()) => return
+                    }
+                },
+                // End of input, possibly in the middle of a line that is still being written
+                Ok(_) => {
+                    if !follow && refetch_every.is_none() && !line.is_empty() {
+                        let start_offset = offset;
+                        offset += line.len() as u64;
+                        let last_line = std::mem::take(&mut line);
+                        match send_injected(&sender, inject(&mut faults, last_line, start_offset, offset), dir) {
+                            Ok(false) => {},
+                            Ok(true) => {
+                                offset = start_offset;
+                                disconnected = true;
+                                continue;
+                            },
+                            Err(()) => return
+                        }
+                    }
+                    // Lines held back are not held while waiting for more
+                    let held = faults.as_mut().map_or_else(Vec::new, FaultInjector::flush);
+                    if send_injected(&sender, held, dir).is_err() {
+                        return;
+                    }
+                    if let Some(interval) = refetch_every {
+                        // The partial line is read again in full by the next fetch
+                        line.clear();
+                        thread::sleep(interval);
+                        match reopen_input(&file_name, offset, header.as_ref(), &mut etag) {
+                            Ok(refetched) => input = refetched,
+                            Err(e) => logger::log(&format!("Unable to fetch {} again: {}", file_name, e))
+                        }
+                        continue;
+                    }
+                    if !follow {
+                        return;
+                    }
+                    thread::sleep(backoff.next_delay());
+                },
+                Err(e) => {
+                    if !follow {
+                        let _ = sender.send(Err(e));
+                        return;
+                    }
+                    logger::log(&format!("Error encountered while reading {}: {}, reconnecting", file_name, e));
+                    thread::sleep(backoff.next_delay());
+                    line.clear();
+                    match reopen_input(&file_name, offset, header.as_ref(), &mut etag) {
+                        Ok(reopened) => input = reopened,
+                        Err(e) => logger::log(&format!("Unable to reopen {}: {}", file_name, e))
+                    }
+                }
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send_included_lines() {
+        let dir = std::env::temp_dir().join(format!("include-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("fixtures")).unwrap();
+        fs::write(dir.join("fixtures/quotes.txt"), "quote 1\nINCLUDE more.txt\n").unwrap();
+        fs::write(dir.join("fixtures/more.txt"), "\u{feff}quote 2 \r\n").unwrap();
+        fs::write(dir.join("fixtures/cycle.txt"), "INCLUDE cycle.txt\n").unwrap();
+
+        let (sender, receiver) = queue::channel(10, Backpressure::Block);
+        send_line(&sender, "INCLUDE fixtures/quotes.txt".to_string(), 5, 33, Some(&dir), &mut Vec::new()).unwrap();
+        send_line(&sender, "request\r\n".to_string(), 33, 41, Some(&dir), &mut Vec::new()).unwrap();
+        send_line(&sender, "INCLUDE fixtures/cycle.txt".to_string(), 41, 68, Some(&dir), &mut Vec::new()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut lines = Vec::new();
+        while let Ok(line) = receiver.recv_timeout(Duration::from_millis(0)) {
+            lines.push(line.map_err(|e| e.to_string()));
+        }
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[..3], [Ok(("quote 1".to_string(), 5)), Ok(("quote 2".to_string(), 33)),
+            Ok(("request".to_string(), 41))]);
+        assert!(lines[3].as_ref().is_err_and(|e| e.ends_with("cycle.txt includes itself")));
+
+        let content = "\u{feff}XBT BTC\r\n\r\n\tUST  USDT \r\n";
+        assert_eq!(content_lines(content).collect::<Vec<_>>(), ["XBT BTC", "UST  USDT"]);
+    }
+
+    #[test]
+    fn pass_include_lines_of_untrusted_input_through() {
+        assert_eq!(include_dir("fixtures/quotes.txt"), Some(PathBuf::from("fixtures")));
+        assert_eq!(include_dir("quotes.txt"), Some(PathBuf::new()));
+        for file_name in ["-", "https://example.com/quotes.txt", "s3://bucket/quotes.txt", "redis://localhost/quotes"] {
+            assert_eq!(include_dir(file_name), None);
+        }
+
+        let (sender, receiver) = queue::channel(10, Backpressure::Block);
+        send_line(&sender, "INCLUDE /etc/passwd\n".to_string(), 0, 20, None, &mut Vec::new()).unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(0)).unwrap().unwrap(),
+            ("INCLUDE /etc/passwd".to_string(), 20));
+        assert!(receiver.recv_timeout(Duration::from_millis(0)).is_err());
+    }
+}
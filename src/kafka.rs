@@ -0,0 +1,100 @@
+// Publishing changes to the best rate of watched pairs to a Kafka topic
+
+#[cfg(feature = "kafka")]
+pub use self::producer::KafkaSink;
+#[cfg(not(feature = "kafka"))]
+pub use self::unsupported::KafkaSink;
+
+use tenx_challenge::bus::{Event, Subscriber};
+
+impl Subscriber for KafkaSink {
+    fn handle(&mut self, event: &Event) -> Result<(), String> {
+        match event {
+            Event::Result(rate_point) => self.publish(rate_point),
+            _ => Ok(())
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        KafkaSink::flush(self)
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod producer {
+    use std::time::Duration;
+
+    use rdkafka::config::ClientConfig;
+    use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+    use rdkafka::producer::{BaseProducer, BaseRecord, Producer};
+
+    use tenx_challenge::export;
+    use tenx_challenge::watch::RatePoint;
+
+    const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+    // Messages are keyed by pair, so the changes of a pair stay in order on one partition
+    pub struct KafkaSink {
+        producer: BaseProducer,
+        topic: String
+    }
+
+    impl KafkaSink {
+        pub fn connect(brokers: &str, topic: &str) -> Result<KafkaSink, String> {
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", brokers)
+                .create()
+                .map_err(|e| format!("Error encountered while creating Kafka producer: {}", e))?;
+            Ok(KafkaSink {
+                producer,
+                topic: topic.to_string()
+            })
+        }
+
+        // Queue a message for the change, it is delivered in the background
+        pub fn publish(&mut self, rate_point: &RatePoint) -> Result<(), String> {
+            let message = export::format_rate_point_json(rate_point);
+            let mut record = BaseRecord::to(&self.topic).key(rate_point.get_pair()).payload(&message);
+            loop {
+                match self.producer.send(record) {
+                    Ok(()) => break,
+                    // Wait for queued messages to be delivered to make room
+                    Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), unsent)) => {
+                        record = unsent;
+                        self.producer.poll(Duration::from_millis(100));
+                    },
+                    Err((e, _)) => return Err(format!("Error encountered while publishing to Kafka: {}", e))
+                }
+            }
+            self.producer.poll(Duration::ZERO);
+            Ok(())
+        }
+
+        // Wait for queued messages to be delivered
+        pub fn flush(&mut self) -> Result<(), String> {
+            self.producer.flush(FLUSH_TIMEOUT).map_err(|e| format!("Error encountered while publishing to Kafka: {}", e))
+        }
+    }
+}
+
+#[cfg(not(feature = "kafka"))]
+mod unsupported {
+    use tenx_challenge::watch::RatePoint;
+
+    // Stand-in for builds without the `kafka` feature, it can never be connected
+    pub struct KafkaSink;
+
+    impl KafkaSink {
+        pub fn connect(_brokers: &str, _topic: &str) -> Result<KafkaSink, String> {
+            Err("Kafka is not supported by this build, rebuild with `--features kafka`".to_string())
+        }
+
+        pub fn publish(&mut self, _rate_point: &RatePoint) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub fn flush(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+}
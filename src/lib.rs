@@ -0,0 +1,39 @@
+//! Best exchange rates between currencies on different exchanges, kept up to date from a stream of price updates
+//!
+//! [`engine::Engine`] holds the graph of price updates and answers exchange rate requests. The command line
+//! front-end in `main.rs` reads input lines, parses them and reports the answers
+
+pub mod alias;
+pub mod audit;
+pub mod backoff;
+pub mod broker;
+pub mod bus;
+pub mod cache;
+pub mod chaos;
+pub mod checkpoint;
+pub mod constants;
+pub mod dedup;
+pub mod engine;
+pub mod export;
+pub mod fees;
+pub mod generate;
+pub mod graph;
+pub mod input;
+pub mod logger;
+pub mod model;
+pub mod output;
+pub mod partition;
+pub mod plugin;
+pub mod profile;
+pub mod quarantine;
+pub mod queue;
+pub mod remote;
+pub mod script;
+pub mod split;
+pub mod summary;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+pub mod throttle;
+pub mod timing;
+pub mod verify;
+pub mod watch;
@@ -0,0 +1,39 @@
+// Diagnostics are written to standard error, or to a log file which can be reopened after log rotation
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+// (path, file) of the log file
+static LOG_FILE: Mutex<Option<(String, File)>> = Mutex::new(None);
+
+fn open(path: &str) -> Result<File, io::Error> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// Write diagnostics to `path` instead of standard error
+pub fn init(path: &str) -> Result<(), io::Error> {
+    let file = open(path)?;
+    *LOG_FILE.lock().unwrap() = Some((path.to_string(), file));
+    Ok(())
+}
+
+// Reopen the log file, e.g. after it was moved away by logrotate
+pub fn reopen() -> Result<(), io::Error> {
+    let mut log_file = LOG_FILE.lock().unwrap();
+    if let Some((path, file)) = log_file.as_mut() {
+        *file = open(path)?;
+    }
+    Ok(())
+}
+
+pub fn log(message: &str) {
+    let mut log_file = LOG_FILE.lock().unwrap();
+    match log_file.as_mut() {
+        // Fall back to standard error if the log file can no longer be written
+        Some((_, file)) => if writeln!(file, "{}", message).is_err() {
+            eprintln!("{}", message);
+        },
+        None => eprintln!("{}", message)
+    }
+}
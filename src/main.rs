@@ -1,21 +1,38 @@
+use std::cell::RefCell;
 use std::env;
 use std::io;
+use std::process;
 use std::fs::File;
-use std::io::Read;
+use std::io::{IsTerminal, Read, Write};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
 
 // Third party libraries
 use chrono::DateTime;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::flag;
 
 // Custom modules
-mod constants;
-mod model;
+#[cfg(feature = "chart")]
+mod chart;
+mod cli;
+mod completions;
+mod daemon;
+mod dashboard;
+mod kafka;
+mod scenarios;
+mod sql;
+mod stress;
+use tenx_challenge::{
+    alias, audit, bus, chaos, checkpoint, constants, dedup, engine, export, fees, generate, input, logger, model,
+    output, partition, plugin, profile, quarantine, queue, remote, script, split, summary, throttle, timing, watch
+};
 
 fn read_file(file_name: &str) ->  Result<String, io::Error> {
-    let mut file = match File::open(file_name) {
-        Ok(file) => file,
-        Err(e) => return Err(e)
-    };
+    let mut file = File::open(file_name)?;
 
     let mut buffer = String::new();
     match file.read_to_string(&mut buffer) {
@@ -25,154 +42,1106 @@ fn read_file(file_name: &str) ->  Result<String, io::Error> {
     Ok(buffer)
 }
 
+// Set the returned flag on SIGINT or SIGTERM, a second signal terminates immediately
+fn register_shutdown() -> Result<Arc<AtomicBool>, io::Error> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in &[SIGINT, SIGTERM] {
+        flag::register_conditional_shutdown(*signal, 1, Arc::clone(&shutdown))?;
+        flag::register(*signal, Arc::clone(&shutdown))?;
+    }
+    Ok(shutdown)
+}
+
+// Set the returned flag on SIGHUP
+fn register_hangup() -> Result<Arc<AtomicBool>, io::Error> {
+    let hangup = Arc::new(AtomicBool::new(false));
+    flag::register(SIGHUP, Arc::clone(&hangup))?;
+    Ok(hangup)
+}
+
+/// Parse a duration such as `500ms`, `30s`, `5m`, `2h` or `1d` into milliseconds
+fn parse_duration(input: &str) -> Option<u64> {
+    let split_at = input.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = input.split_at(split_at);
+    let amount: u64 = amount.parse().ok()?;
+    let multiplier = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => return None
+    };
+    amount.checked_mul(multiplier)
+}
+
+/// Parse a size such as `1048576`, `64KB`, `512MB` or `2GB` into bytes, where a KB is 1024 bytes
+fn parse_size(input: &str) -> Option<usize> {
+    let split_at = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    let (amount, unit) = input.split_at(split_at);
+    let amount: usize = amount.parse().ok()?;
+    let multiplier = match unit {
+        "" | "B" => 1,
+        "KB" => 1 << 10,
+        "MB" => 1 << 20,
+        "GB" => 1 << 30,
+        _ => return None
+    };
+    amount.checked_mul(multiplier)
+}
+
+// How price updates are read, besides the aliases of their exchanges and currencies
+#[derive(Clone, Copy)]
+struct ParseOptions {
+    // taken off the forward ratio to derive a missing backward ratio
+    assumed_spread: f64,
+    // of the timestamps
+    precision: model::Precision,
+    // accept ratios multiplying to more than 1, whose market is crossed
+    allow_crossed: bool
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions { assumed_spread: constants::ASSUMED_SPREAD, precision: model::Precision::Millis, allow_crossed: false }
+    }
+}
+
+impl ParseOptions {
+    fn new(options: &cli::Options) -> ParseOptions {
+        ParseOptions {
+            assumed_spread: options.get_assumed_spread(), precision: options.get_timestamp_precision(),
+            allow_crossed: options.is_allow_crossed()
+        }
+    }
+}
+
 /// Determine whether an input line is a price update or exchange rate request or invalid
 /// If it is a valid input, parse into the respective input type and return it, else invalid type
-fn parse_input(input: &str) -> model::InputType {
-    let tokens: Vec<&str> = input.split(" ").collect();
+/// Exchanges and currencies are normalized with `aliases`
+/// A price update without a backward ratio gets one derived from the forward ratio, less the assumed spread
+fn parse_input(input: &str, aliases: &alias::Aliases, parse_options: &ParseOptions) -> model::InputType {
+    let ParseOptions { assumed_spread, precision, allow_crossed } = *parse_options;
+    let tokens: Vec<&str> = input.split_whitespace().collect();
     let num_tokens: u32 = tokens.len() as u32;
 
-    // parse price update
-    if num_tokens == constants::NUM_TOKEN_PRICE_UPDATE {
-        let datetime: u64 = match DateTime::parse_from_str(tokens[0], constants::DATETIME_FORMAT) {
-            Ok(d) => d.timestamp_millis() as u64,
+    let is_request = tokens.first() == Some(&constants::EXCHANGE_RATE_REQUEST);
+    if is_request && num_tokens >= constants::NUM_TOKEN_EXCHANGE_RATE_REQUEST {
+        // parse exchange rate request
+        let (source_exchange, source_currency, dest_exchange, dest_currency) = match (
+            model::Exchange::new(aliases.exchange(tokens[1])), model::Currency::new(aliases.currency(tokens[2])),
+            model::Exchange::new(aliases.exchange(tokens[3])), model::Currency::new(aliases.currency(tokens[4]))
+        ) {
+            (Ok(source_exchange), Ok(source_currency), Ok(dest_exchange), Ok(dest_currency)) =>
+                (source_exchange, source_currency, dest_exchange, dest_currency),
+            (Err(e), _, _, _) | (_, Err(e), _, _) | (_, _, Err(e), _) | (_, _, _, Err(e)) =>
+                return model::InputType::Invalid(e)
+        };
+
+        // parse optional modifiers following the request, e.g. `WINDOW 5m`
+        let mut window = None;
+        let mut pareto = false;
+        let mut via = None;
+        let mut avoid = Vec::new();
+        let mut partition = None;
+        let mut request_id = None;
+        let mut split = None;
+        let mut index = constants::NUM_TOKEN_EXCHANGE_RATE_REQUEST as usize;
+        while index < tokens.len() {
+            match tokens[index] {
+                constants::WINDOW_MODIFIER => {
+                    window = match tokens.get(index + 1).and_then(|token| parse_duration(token)) {
+                        Some(duration) => Some(duration),
+                        None => return model::InputType::Invalid("Invalid window duration".to_string())
+                    };
+                    index += 2;
+                },
+                constants::PARETO_MODIFIER => {
+                    pareto = true;
+                    index += 1;
+                },
+                // `EXCHANGE <exchange>` or `CURRENCY <currency>`, normalized with the aliases of its kind
+                constants::VIA_MODIFIER => {
+                    let parsed = match (tokens.get(index + 1), tokens.get(index + 2)) {
+                        (Some(&constants::VIA_EXCHANGE), Some(name)) => {
+                            model::Exchange::new(aliases.exchange(name)).map(model::Via::Exchange)
+                        },
+                        (Some(&constants::VIA_CURRENCY), Some(name)) => {
+                            model::Currency::new(aliases.currency(name)).map(model::Via::Currency)
+                        },
+                        _ => return model::InputType::Invalid(format!("Expected {} {} <exchange> or {} {} <currency>",
+                            constants::VIA_MODIFIER, constants::VIA_EXCHANGE, constants::VIA_MODIFIER,
+                            constants::VIA_CURRENCY))
+                    };
+                    via = match parsed {
+                        Ok(parsed) => Some(parsed),
+                        Err(e) => return model::InputType::Invalid(e)
+                    };
+                    index += 3;
+                },
+                // may be repeated to avoid several currencies
+                constants::AVOID_MODIFIER => {
+                    match tokens.get(index + 1) {
+                        Some(token) => match model::Currency::new(aliases.currency(token)) {
+                            Ok(currency) => avoid.push(currency),
+                            Err(e) => return model::InputType::Invalid(e)
+                        },
+                        None => return model::InputType::Invalid("Missing avoided currency".to_string())
+                    };
+                    index += 2;
+                },
+                // checked against the partitions of the run before the request is answered
+                constants::PARTITION_MODIFIER => {
+                    partition = match tokens.get(index + 1) {
+                        Some(token) => Some(token.to_string()),
+                        None => return model::InputType::Invalid("Missing partition".to_string())
+                    };
+                    index += 2;
+                },
+                constants::SPLIT_MODIFIER => {
+                    split = match tokens.get(index + 1).map(|token| token.parse::<f64>()) {
+                        Some(Ok(amount)) if amount > 0.0 && amount.is_finite() => Some(amount),
+                        _ => return model::InputType::Invalid("Invalid split amount".to_string())
+                    };
+                    index += 2;
+                },
+                constants::REQUEST_ID_MODIFIER => {
+                    request_id = match tokens.get(index + 1) {
+                        Some(token) => Some(token.to_string()),
+                        None => return model::InputType::Invalid("Missing request ID".to_string())
+                    };
+                    index += 2;
+                },
+                _ => return model::InputType::Invalid(format!("Unknown request modifier {}", tokens[index]))
+            }
+        }
+
+        let mut exchange_rate_request = model::ExchangeRateRequest::new(
+            source_exchange, source_currency, dest_exchange, dest_currency, window
+        );
+        exchange_rate_request.set_pareto(pareto);
+        exchange_rate_request.set_via(via);
+        exchange_rate_request.set_avoid(avoid);
+        exchange_rate_request.set_partition(partition);
+        exchange_rate_request.set_request_id(request_id);
+        exchange_rate_request.set_split(split);
+        model::InputType::ExchangeRateRequest(exchange_rate_request)
+    } else if num_tokens == constants::NUM_TOKEN_PRICE_UPDATE || num_tokens == constants::NUM_TOKEN_SHORTHAND_PRICE_UPDATE {
+        // parse price update
+        let (datetime, sub_millis) = match DateTime::parse_from_str(tokens[0], constants::DATETIME_FORMAT) {
+            Ok(d) => (d.timestamp_millis() as u64, precision.truncate(d.timestamp_subsec_nanos() % 1_000_000)),
             Err(_) => return model::InputType::Invalid("Invalid date".to_string())
         };
-        let exchange = tokens[1].to_string();
-        let source_currency = tokens[2].to_string();
-        let dest_currency = tokens[3].to_string();
+        let (exchange, source_currency, dest_currency) = match (model::Exchange::new(aliases.exchange(tokens[1])),
+            model::Currency::new(aliases.currency(tokens[2])), model::Currency::new(aliases.currency(tokens[3]))
+        ) {
+            (Ok(exchange), Ok(source_currency), Ok(dest_currency)) => (exchange, source_currency, dest_currency),
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return model::InputType::Invalid(e)
+        };
+        if source_currency == dest_currency {
+            return model::InputType::Invalid("Source and destination currency are the same".to_string())
+        }
         let forward_ratio: f64 = match tokens[4].parse() {
             Ok(num) => num,
             Err(_) => return model::InputType::Invalid("Invalid forward ratio".to_string())
         };
-        let backward_ratio: f64 = match tokens[5].parse() {
-            Ok(num) => num,
-            Err(_) => return model::InputType::Invalid("Invalid backward ratio".to_string())
+        let backward_derived = num_tokens == constants::NUM_TOKEN_SHORTHAND_PRICE_UPDATE;
+        let backward_ratio: f64 = if backward_derived {
+            if !(forward_ratio.is_finite() && forward_ratio > 0.0) {
+                return model::InputType::Invalid("Invalid forward ratio".to_string())
+            }
+            1.0 / forward_ratio * (1.0 - assumed_spread)
+        } else {
+            match tokens[5].parse() {
+                Ok(num) => num,
+                Err(_) => return model::InputType::Invalid("Invalid backward ratio".to_string())
+            }
         };
 
         let both_ratio = forward_ratio * backward_ratio;
-        if both_ratio <= 0.0 || both_ratio > 1.0 {
+        if !both_ratio.is_finite() || both_ratio <= 0.0 || (both_ratio > 1.0 && !allow_crossed) {
             return model::InputType::Invalid("Resultant ratios is invalid".to_string())
         }
-        model::InputType::PriceUpdate(model::PriceUpdate::new (
+        let mut price_update = model::PriceUpdate::new (
             datetime, exchange, source_currency, dest_currency, forward_ratio, backward_ratio
-        ))
-    } else if num_tokens == constants::NUM_TOKEN_EXCHANGE_RATE_REQUEST {
-        // parse exchange rate request
-        let source_exchange = tokens[1].to_string();
-        let source_currency = tokens[2].to_string();
-        let dest_exchange = tokens[3].to_string();
-        let dest_currency = tokens[4].to_string();
-
-        model::InputType::ExchangeRateRequest(model::ExchangeRateRequest::new(
-            source_exchange, source_currency, dest_exchange, dest_currency
-        ))
+        );
+        price_update.set_backward_derived(backward_derived);
+        price_update.set_sub_millis(sub_millis);
+        if aliases.is_inverted(price_update.get_source_currency(), price_update.get_dest_currency()) {
+            price_update = price_update.invert();
+        }
+        model::InputType::PriceUpdate(price_update)
     } else {
         model::InputType::Invalid("Input is neither a price update nor exchange rate request".to_string())
     }
 }
 
-// 1. Add edges between vertices
-// 2. Add vertices
-// 3. Add edges for same currency across different exchanges
-fn handle_price_update(
-    graph: &mut model::Graph, graph_result: &mut model::GraphResult, price_update: model::PriceUpdate
+// Parse every line of `input_file` like a run without options, passing each one to `handle`
+fn replay<F: FnMut(model::InputType)>(input_file: &str, mut handle: F) -> Result<(), String> {
+    let lines = input::spawn_reader(input_file, false, constants::INPUT_BUFFER_SIZE, queue::Backpressure::Block, 0,
+        None, None, None).map_err(|e| format!("Error encountered while reading {}: {}", input_file, e))?;
+    let aliases = alias::Aliases::new(alias::AliasMap::new("currency"), alias::AliasMap::new("exchange"));
+    loop {
+        match lines.recv_timeout(Duration::from_secs(60)) {
+            Ok(Ok((line, _))) => {
+                handle(parse_input(&line, &aliases, &ParseOptions::default()))
+            },
+            Ok(Err(e)) => return Err(format!("Error encountered while reading {}: {}", input_file, e)),
+            Err(RecvTimeoutError::Timeout) => return Err(format!("Timed out reading {}", input_file)),
+            Err(RecvTimeoutError::Disconnected) => return Ok(())
+        }
+    }
+}
+
+// Answer the pending requests and report the answers
+fn flush_exchange_rate_requests(pending: &mut Vec<model::ExchangeRateRequest>, engine: &mut engine::Engine,
+    fee_model: Option<&fees::FeeModel>, summary: &mut summary::RunSummary, bus: &mut bus::EventBus, num_workers: usize,
+    latencies: Option<&mut timing::Latencies>
 ) {
-    let from_vertex = model::Vertex::new(
-        price_update.get_exchange().to_string(),
-        price_update.get_source_currency().to_string()
-    );
-    let to_vertex = model::Vertex::new(
-        price_update.get_exchange().to_string(),
-        price_update.get_dest_currency().to_string()
-    );
+    if pending.is_empty() {
+        return;
+    }
+    let answers = engine.answer(pending, fee_model, num_workers);
+    let _span = profile::span("write_answers");
+    for ((output, route_found), exchange_rate_request) in answers.into_iter().zip(pending.iter()) {
+        summary.record_exchange_rate_request(route_found);
+        publish(bus, bus::Event::Query(exchange_rate_request, &output, route_found));
+    }
+    if let Some(latencies) = latencies {
+        latencies.finish_queries(pending.len());
+    }
+    pending.clear();
+}
 
-    let arc_from_vertex = Rc::new(from_vertex);
-    let arc_to_vertex = Rc::new(to_vertex);
+// Deliver `event` to the subscribers of `bus`, exiting if one of them fails
+fn publish(bus: &mut bus::EventBus, event: bus::Event) {
+    if let Err(e) = bus.publish(event) {
+        exit_with(constants::EXIT_IO_ERROR, &e);
+    }
+}
 
-    // Add edges
-    graph_result.add_edge_weight(arc_from_vertex.clone(), arc_to_vertex.clone(),
-        price_update.get_forward_ratio(), price_update.get_datetime()
-    );
-    graph_result.add_edge_weight(arc_to_vertex.clone(), arc_from_vertex.clone(),
-        price_update.get_backward_ratio(), price_update.get_datetime()
-    );
+// Check the best rates against an exhaustive search, logging every divergence
+// A graph too large to check is logged once, when `too_large` is not set yet
+fn verify_best_rates(engine: &mut engine::Engine, summary: &mut summary::RunSummary, too_large: &mut bool) {
+    match engine.verify(constants::VERIFY_MAX_VERTICES, constants::VERIFY_EPSILON) {
+        Some(divergences) => {
+            for divergence in divergences.iter() {
+                logger::log(&format!("Verification failed at version {}: {}", engine.get_version(), divergence));
+            }
+            summary.record_divergences(divergences.len() as u64);
+        },
+        None => {
+            if !*too_large {
+                logger::log(&format!("Not verifying graphs of more than {} vertices", constants::VERIFY_MAX_VERTICES));
+                *too_large = true;
+            }
+        }
+    }
+}
 
-    let arc_from_vertex_clone = arc_from_vertex.clone();
-    let arc_to_vertex_clone = arc_to_vertex.clone();
+#[cfg(feature = "chart")]
+fn render_charts(dir: &str, format: &str, rate_history: &[watch::RatePoint], end: u64) -> Result<(), String> {
+    let format = chart::ChartFormat::parse(format).unwrap();
+    chart::render(dir, &format, rate_history, end)
+}
 
-    // Add vertices
-    graph.add_vertex(arc_from_vertex);
-    graph.add_vertex(arc_to_vertex);
+#[cfg(not(feature = "chart"))]
+fn render_charts(_dir: &str, _format: &str, _rate_history: &[watch::RatePoint], _end: u64) -> Result<(), String> {
+    Err("Charts are not supported by this build, rebuild with `--features chart`".to_string())
+}
+
+// Run the scenarios in `dir`, printing whether each one passed, and exit
+fn verify_scenarios(dir: &str) -> ! {
+    let results = match scenarios::verify_scenarios(dir) {
+        Ok(results) => results,
+        Err(e) => exit_with(constants::EXIT_IO_ERROR, &e)
+    };
+    for result in results.iter() {
+        match result.get_difference() {
+            Some(difference) => println!("FAIL {}: {}", result.get_name(), difference),
+            None => println!("PASS {}", result.get_name())
+        }
+    }
+    let num_failed = results.iter().filter(|result| result.get_difference().is_some()).count();
+    if num_failed > 0 {
+        exit_with(constants::EXIT_VERIFY_FAILED, &format!("{} of {} scenario(s) failed", num_failed, results.len()));
+    }
+    exit(constants::EXIT_OK)
+}
 
-    let vertices = graph.get_vertices();
+// Load the tables of `input_file` and print the result of `query`, or of each query read from stdin until `.quit`
+fn run_sql(input_file: &str, query: Option<&str>) {
+    if !cfg!(feature = "sqlite") {
+        exit_with(constants::EXIT_CONFIG_ERROR, "SQL is not supported by this build, rebuild with `--features sqlite`");
+    }
+    let mut engine = engine::Engine::new(output::OutputFormat::Text);
+    let mut price_updates = Vec::new();
+    if let Err(e) = replay(input_file, |input| {
+        if let model::InputType::PriceUpdate(price_update) = input {
+            price_updates.push(price_update.clone());
+            engine.apply_price_update(price_update);
+        }
+    }) {
+        exit_with(constants::EXIT_IO_ERROR, &e);
+    }
+    let database = match sql::Database::load(&mut engine, &price_updates) {
+        Ok(database) => database,
+        Err(e) => exit_with(constants::EXIT_IO_ERROR, &e)
+    };
+    if let Some(query) = query {
+        match database.query(query) {
+            Ok(result) => print!("{}", result),
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        }
+        return;
+    }
+
+    let interactive = io::stdin().is_terminal();
+    let mut line = String::new();
+    loop {
+        if interactive {
+            print!("sql> ");
+            let _ = io::stdout().flush();
+        }
+        line.clear();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) => return,
+            Ok(_) => (),
+            Err(e) => exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while reading query: {}", e))
+        }
+        match line.trim() {
+            "" => (),
+            ".quit" => return,
+            query => match database.query(query) {
+                Ok(result) => print!("{}", result),
+                Err(e) => eprintln!("{}", e)
+            }
+        }
+    }
+}
 
-    // Add edges for same currency across different exchanges
-    graph_result.add_edge_weight_for_currency(arc_from_vertex_clone, vertices);
-    graph_result.add_edge_weight_for_currency(arc_to_vertex_clone, vertices)
+// Check the audit log `file_name`, and that it ends with `last_hash` if given, and exit
+fn verify_audit(file_name: &str, last_hash: Option<&str>) -> ! {
+    if !cfg!(feature = "audit") {
+        exit_with(constants::EXIT_CONFIG_ERROR, "Audit logs are not supported by this build, rebuild with `--features audit`");
+    }
+    let content = match read_file(file_name) {
+        Ok(content) => content,
+        Err(e) => exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while reading audit log: {}", e))
+    };
+    let summary = match audit::verify(&content) {
+        Ok(summary) => summary,
+        Err(e) => {
+            println!("TAMPERED {}", e);
+            exit(constants::EXIT_VERIFY_FAILED)
+        }
+    };
+    if last_hash.is_some_and(|last_hash| last_hash != summary.get_last_hash()) {
+        println!("TRUNCATED last hash is {}, expected {}", summary.get_last_hash(), last_hash.unwrap_or_default());
+        exit(constants::EXIT_VERIFY_FAILED);
+    }
+    if !summary.is_closed() {
+        println!("TRUNCATED log does not end with an {} record", audit::END_RECORD);
+        exit(constants::EXIT_VERIFY_FAILED);
+    }
+    println!("INTACT {} records, {} unclosed run(s), last hash {}", summary.get_num_records(),
+        summary.get_num_unclosed_runs(), summary.get_last_hash());
+    exit(constants::EXIT_OK)
 }
 
-// Get best rate between every pair of vertices
-// Get the best rate path
-fn handle_exchange_rate_request(graph: & model::Graph,
-    graph_result: &mut model::GraphResult, exchange_rate_request: model::ExchangeRateRequest
-) {
-    graph_result.find_best_rates(graph.get_vertices());
-
-    let arc_from_vertex = Rc::new(model::Vertex::new(
-        exchange_rate_request.get_source_exchange().to_string(),
-        exchange_rate_request.get_source_currency().to_string()
-    ));
-    let arc_to_vertex = Rc::new(model::Vertex::new(
-        exchange_rate_request.get_dest_exchange().to_string(),
-        exchange_rate_request.get_dest_currency().to_string()
-    ));
-    
-    // Print result
-    println!("BEST_RATES_BEGIN {} {} {} {} {}", exchange_rate_request.get_source_exchange(),
-        exchange_rate_request.get_source_currency(), exchange_rate_request.get_dest_exchange(),
-        exchange_rate_request.get_dest_currency(), graph_result.get_best_rate(&arc_from_vertex, &arc_to_vertex)
-    );
+// Replay the audit log given as the input file of `options` on an engine configured by `options`, which should be the
+// options of the recorded runs, checking that every answer and graph hash is reproduced exactly, and exit
+// Each run in the log is replayed on a fresh engine, so runs resumed from a checkpoint cannot be replayed
+fn verify_replay(options: &cli::Options) -> ! {
+    if !cfg!(feature = "audit") {
+        exit_with(constants::EXIT_CONFIG_ERROR, "Audit logs are not supported by this build, rebuild with `--features audit`");
+    }
+    let content = match read_file(options.get_input_file()) {
+        Ok(content) => content,
+        Err(e) => exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while reading audit log: {}", e))
+    };
+    let records = match audit::read_records(&content) {
+        Ok(records) => records,
+        Err(e) => {
+            println!("TAMPERED {}", e);
+            exit(constants::EXIT_VERIFY_FAILED)
+        }
+    };
+    let aliases = load_aliases(options);
+    let fee_model = options.get_fees_file().map(|file_name|
+        match fees::FeeModel::load(file_name, options.get_volume(), options.get_fee_side(), &aliases) {
+            Ok(fee_model) => fee_model,
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        });
 
-    match graph_result.best_rate_path(&arc_from_vertex, &arc_to_vertex) {
-        Some(best_rate_path) => {
-            for vertex in best_rate_path {
-                println!("<{}, {}>", vertex.get_exchange(), vertex.get_currency());
+    let partitions = load_partitions(options, &aliases);
+    let liquidity = load_liquidity(options, &aliases);
+    let mut engine = build_engine(options, load_weight_script(options), partitions.clone(), liquidity.clone());
+    let mut exchange_rate_request = None;
+    let (mut num_answers, mut num_graphs, mut num_mismatches) = (0, 0, 0);
+    let parse_options = ParseOptions::new(options);
+    for (index, record) in records.iter().enumerate() {
+        let text = record.get_text().unwrap_or_default();
+        let reproduced = match record.get_kind() {
+            audit::UPDATE_RECORD => match parse_input(text, &aliases, &parse_options) {
+                model::InputType::PriceUpdate(price_update) => {
+                    engine.apply_price_update(price_update);
+                    Ok(())
+                },
+                _ => Err("not a valid price update".to_string())
+            },
+            audit::REQUEST_RECORD => match parse_input(text, &aliases, &parse_options) {
+                model::InputType::ExchangeRateRequest(request) => {
+                    exchange_rate_request = Some(request);
+                    Ok(())
+                },
+                _ => Err("not a valid exchange rate request".to_string())
+            },
+            audit::ANSWER_RECORD => {
+                num_answers += 1;
+                match exchange_rate_request.take() {
+                    Some(request) => {
+                        let answer = engine.answer(&[request], fee_model.as_ref(), 1).remove(0).0;
+                        match audit::format_answer(&answer) {
+                            answer if answer == text => Ok(()),
+                            answer => Err(format!("expected answer {}, got {}", text, answer))
+                        }
+                    },
+                    None => Err("no request recorded before the answer".to_string())
+                }
+            },
+            audit::END_RECORD => {
+                num_graphs += 1;
+                let graph_hash = audit::graph_hash(engine.get_graph_result());
+                engine = build_engine(options, load_weight_script(options), partitions.clone(), liquidity.clone());
+                if graph_hash == text { Ok(()) } else { Err(format!("expected graph hash {}, got {}", text, graph_hash)) }
+            },
+            // The previous run ended without closing the log, the next one starts from scratch
+            _ => {
+                engine = build_engine(options, load_weight_script(options), partitions.clone(), liquidity.clone());
+                Ok(())
             }
+        };
+        if let Err(mismatch) = reproduced {
+            println!("MISMATCH record {}: {}", index + 1, mismatch);
+            num_mismatches += 1;
+        }
+    }
+    if num_mismatches > 0 {
+        exit_with(constants::EXIT_VERIFY_FAILED, &format!("{} of {} record(s) were not reproduced", num_mismatches,
+            records.len()));
+    }
+    println!("REPRODUCED {} answer(s) and {} graph hash(es)", num_answers, num_graphs);
+    exit(constants::EXIT_OK)
+}
+
+// Print the synthetic input `options` describe, and exit
+fn generate(options: &cli::GenerateOptions) -> ! {
+    let mut generator = match generate::Generator::new(options.get_num_exchanges(), options.get_num_currencies(),
+        options.get_seed()) {
+        Ok(generator) => generator,
+        Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+    };
+    generator.set_tick_rate(options.get_tick_rate());
+    generator.set_volatility(options.get_volatility());
+    generator.set_arbitrage_rate(options.get_arbitrage_rate());
+    generator.set_request_rate(options.get_request_rate());
+
+    let stdout = io::stdout();
+    let mut writer = io::BufWriter::new(stdout.lock());
+    let mut num_updates = 0;
+    for input in generator {
+        let written = match input {
+            model::InputType::PriceUpdate(price_update) => {
+                if num_updates == options.get_num_updates() {
+                    break;
+                }
+                num_updates += 1;
+                writeln!(writer, "{}", price_update)
+            },
+            model::InputType::ExchangeRateRequest(exchange_rate_request) => writeln!(writer, "{}", exchange_rate_request),
+            model::InputType::Invalid(_) => Ok(())
+        };
+        if let Err(e) = written {
+            exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while writing output: {}", e));
+        }
+    }
+    if let Err(e) = writer.flush() {
+        exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while writing output: {}", e));
+    }
+    exit(constants::EXIT_OK)
+}
+
+// Clean up and exit with one of the exit codes in `constants`
+fn exit(code: i32) -> ! {
+    if let Err(e) = daemon::remove_pid_file() {
+        logger::log(&format!("Error encountered while removing PID file: {}", e));
+    }
+    process::exit(code)
+}
+
+// Log the error and exit with one of the exit codes in `constants`
+fn exit_with(code: i32, message: &str) -> ! {
+    logger::log(&format!("{}\nExiting...", message));
+    exit(code)
+}
+
+// Save a checkpoint, with the best rates when `warm_start`, a failure is logged and the run continues
+fn save_checkpoint(file_name: &str, offset: u64, engine: &mut engine::Engine, warm_start: bool) {
+    if warm_start {
+        engine.find_best_rates();
+    }
+    if let Err(e) = checkpoint::save(file_name, offset, engine.get_latest_datetime(), engine.get_version(),
+        engine.get_graph_result(), warm_start) {
+        logger::log(&format!("Error encountered while saving checkpoint {}: {}", file_name, e));
+    }
+}
+
+// Read a file given as an option, e.g. the watch list, exiting if it cannot be read
+fn read_option_file(file_name: &str, description: &str) -> String {
+    match read_file(file_name) {
+        Ok(content) => content,
+        Err(e) => exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while reading {}: {}", description, e))
+    }
+}
+
+// Load the weight script of `options`, if any, exiting if it is invalid
+fn load_weight_script(options: &cli::Options) -> Option<script::WeightScript> {
+    options.get_weight_script_file().map(|file_name| match script::WeightScript::load(file_name) {
+        Ok(weight_script) => weight_script,
+        Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+    })
+}
+
+// Load the partitions of `options`, or only the built-in one if there are none, exiting if they are invalid
+fn load_partitions(options: &cli::Options, aliases: &alias::Aliases) -> partition::Partitions {
+    match options.get_partitions_file() {
+        Some(file_name) => match partition::Partitions::parse(&read_option_file(file_name, "partitions"), aliases) {
+            Ok(partitions) => partitions,
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        },
+        None => partition::Partitions::new()
+    }
+}
+
+// Load the liquidity tiers of `options`, or markets as deep as needed if there are none, exiting if they are invalid
+fn load_liquidity(options: &cli::Options, aliases: &alias::Aliases) -> split::Liquidity {
+    match options.get_liquidity_file() {
+        Some(file_name) => match split::Liquidity::parse(&read_option_file(file_name, "liquidity tiers"), aliases) {
+            Ok(liquidity) => liquidity,
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
         },
-        None => ()
+        None => split::Liquidity::new()
     }
-    println!("BEST_RATES_END");
 }
 
+// An empty engine answering requests as `options` ask
+fn build_engine(options: &cli::Options, weight_script: Option<script::WeightScript>,
+    partitions: partition::Partitions, liquidity: split::Liquidity
+) -> engine::Engine {
+    let mut engine = engine::Engine::new(options.get_output_format());
+    engine.set_explain(options.is_explain());
+    engine.set_notional(options.get_notional());
+    engine.set_hop_penalty(options.get_hop_penalty());
+    engine.set_stale_after(options.get_stale_after());
+    engine.set_min_confidence(options.get_min_confidence());
+    engine.set_allow_crossed(options.is_allow_crossed());
+    engine.set_weight_script(weight_script);
+    engine.set_max_memory(options.get_max_memory());
+    engine.set_partitions(partitions);
+    engine.set_liquidity(liquidity);
+    engine.set_recompute(options.get_recompute_every(), options.get_recompute_after());
+    engine
+}
+
+// Load the alias map of `kind` from `file_name`, or an empty map if there is none
+fn load_alias_map(kind: &str, file_name: Option<&str>) -> alias::AliasMap {
+    match file_name {
+        Some(file_name) => match alias::AliasMap::parse(kind, &read_option_file(file_name, &format!("{} aliases", kind))) {
+            Ok(alias_map) => alias_map,
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        },
+        None => alias::AliasMap::new(kind)
+    }
+}
+
+// Load the alias maps and canonical pairs of `options`, exiting if any of them is invalid
+fn load_aliases(options: &cli::Options) -> alias::Aliases {
+    let mut aliases = alias::Aliases::new(
+        load_alias_map("currency", options.get_currency_aliases_file()),
+        load_alias_map("exchange", options.get_exchange_aliases_file())
+    );
+    if let Some(file_name) = options.get_canonical_pairs_file() {
+        match alias::CanonicalPairs::parse(&read_option_file(file_name, "canonical pairs"), &aliases) {
+            Ok(canonical_pairs) => aliases.set_canonical_pairs(canonical_pairs),
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        }
+    }
+    aliases
+}
+
+// Read the alias maps, canonical pairs, watch list and fee schedule of `options` again, keeping the graph, the input and the
+// connections of the run. Nothing is changed if any of them cannot be read
+fn reload_config(options: &cli::Options, aliases: &mut alias::Aliases, watch_list: &mut watch::WatchList,
+    fee_model: &mut Option<fees::FeeModel>) -> Result<(), String> {
+    let read_alias_map = |kind: &str, file_name: Option<&str>| match file_name {
+        Some(file_name) => read_file(file_name)
+            .map_err(|e| format!("Error encountered while reading {} aliases: {}", kind, e))
+            .and_then(|content| alias::AliasMap::parse(kind, &content)),
+        None => Ok(alias::AliasMap::new(kind))
+    };
+    let mut new_aliases = alias::Aliases::new(
+        read_alias_map("currency", options.get_currency_aliases_file())?,
+        read_alias_map("exchange", options.get_exchange_aliases_file())?
+    );
+    if let Some(file_name) = options.get_canonical_pairs_file() {
+        new_aliases.set_canonical_pairs(read_file(file_name)
+            .map_err(|e| format!("Error encountered while reading canonical pairs: {}", e))
+            .and_then(|content| alias::CanonicalPairs::parse(&content, &new_aliases))?);
+    }
+    let mut new_watch_list = match options.get_watch_list_file() {
+        Some(file_name) => read_file(file_name)
+            .map_err(|e| format!("Error encountered while reading watch list: {}", e))
+            .and_then(|content| watch::WatchList::parse(&content, &new_aliases))?,
+        None => watch::WatchList::new()
+    };
+    let new_fee_model = match options.get_fees_file() {
+        Some(file_name) => Some(fees::FeeModel::load(file_name, options.get_volume(), options.get_fee_side(),
+            &new_aliases)?),
+        None => None
+    };
+    new_watch_list.keep_rates(watch_list);
+    *aliases = new_aliases;
+    *watch_list = new_watch_list;
+    *fee_model = new_fee_model;
+    Ok(())
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    let mut graph_result = model::GraphResult::new();
-    let mut graph = model::Graph::new();
+    let options = match cli::parse_command(&args[1..]) {
+        Ok(cli::Command::Run(options)) => options,
+        Ok(cli::Command::Completions(shell)) => {
+            match completions::generate(&shell) {
+                Ok(script) => print!("{}", script),
+                Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+            }
+            return;
+        },
+        Ok(cli::Command::VerifyScenarios(dir)) => verify_scenarios(&dir),
+        Ok(cli::Command::VerifyAudit(file_name, last_hash)) => verify_audit(&file_name, last_hash.as_deref()),
+        Ok(cli::Command::VerifyReplay(options)) => verify_replay(&options),
+        Ok(cli::Command::Generate(options)) => generate(&options),
+        Ok(cli::Command::Stress(options)) => match stress::run(&options) {
+            Ok(report) => {
+                println!("{}", report);
+                exit(constants::EXIT_OK)
+            },
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        },
+        Ok(cli::Command::Sql(input_file, query)) => {
+            run_sql(&input_file, query.as_deref());
+            return;
+        },
+        Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &format!("{}\n\n{}", e, cli::usage()))
+    };
+    if options.is_dashboard() && !cfg!(feature = "dashboard") {
+        exit_with(constants::EXIT_CONFIG_ERROR,
+            "The dashboard is not supported by this build, rebuild with `--features dashboard`");
+    }
+    if options.get_chart_dir().is_some() && !cfg!(feature = "chart") {
+        exit_with(constants::EXIT_CONFIG_ERROR, "Charts are not supported by this build, rebuild with `--features chart`");
+    }
+    if options.get_arrow_file().is_some() && !cfg!(feature = "arrow") {
+        exit_with(constants::EXIT_CONFIG_ERROR, "Arrow files are not supported by this build, rebuild with `--features arrow`");
+    }
+    if options.get_weight_script_file().is_some() && !cfg!(feature = "scripting") {
+        exit_with(constants::EXIT_CONFIG_ERROR,
+            "Weight scripts are not supported by this build, rebuild with `--features scripting`");
+    }
+    if options.get_kafka_topic().is_some() && !cfg!(feature = "kafka") {
+        exit_with(constants::EXIT_CONFIG_ERROR, "Kafka is not supported by this build, rebuild with `--features kafka`");
+    }
+    if options.get_audit_log_file().is_some() && !cfg!(feature = "audit") {
+        exit_with(constants::EXIT_CONFIG_ERROR, "Audit logs are not supported by this build, rebuild with `--features audit`");
+    }
+    if options.get_plugin_file().is_some() && !cfg!(feature = "plugins") {
+        exit_with(constants::EXIT_CONFIG_ERROR, "Plugins are not supported by this build, rebuild with `--features plugins`");
+    }
+    if let Some(log_file) = options.get_log_file() {
+        if let Err(e) = logger::init(log_file) {
+            exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while opening log file: {}", e));
+        }
+    }
+    if let Some(pid_file) = options.get_pid_file() {
+        if let Err(e) = daemon::write_pid_file(pid_file) {
+            exit_with(constants::EXIT_CONFIG_ERROR, &e);
+        }
+    }
 
-    if args.len() != 2 {
-        panic!("Usage: cargo run <input_file>, e.g. cargo run input.txt");
+    // Before the input is opened, since it can be read by a plugin source
+    if let Some(plugin_file) = options.get_plugin_file() {
+        if let Err(e) = plugin::load(plugin_file) {
+            exit_with(constants::EXIT_CONFIG_ERROR, &e);
+        }
     }
 
-    let file_content = match read_file(&args[1]) {
-        Ok(content) => content,
-        Err(e) => {
-            panic!("Error encountered while reading file: {}\nExiting...", e);
+    let checkpoint = match options.get_resume_file() {
+        Some(file_name) => match checkpoint::Checkpoint::load(file_name) {
+            Ok(checkpoint) => Some(checkpoint),
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        },
+        None => None
+    };
+    // Byte offset in the input following the last line processed
+    let mut offset = checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.get_offset());
+    // Kept in a file rather than on the command line so that credentials do not show in the process list
+    let http_header = options.get_http_header_file().map(|file_name| {
+        match remote::parse_header(&read_option_file(file_name, "HTTP header")) {
+            Ok(header) => header,
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &format!("{} in {}", e, file_name))
         }
+    });
+    let faults = options.get_chaos()
+        .map(|rates| chaos::FaultInjector::new(rates.clone(), options.get_chaos_seed()));
+    // Counted by the reader thread as it injects them
+    let fault_counts = faults.as_ref().map(chaos::FaultInjector::get_counts);
+    let lines = match input::spawn_reader(options.get_input_file(), options.is_daemon(),
+        options.get_buffer_size(), options.get_backpressure(), offset, http_header,
+        options.get_refetch_every(), faults) {
+        Ok(lines) => lines,
+        Err(e) => exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while reading file: {}", e))
     };
 
-    let splitted_lines = file_content.split("\n");
-    for line in splitted_lines {
-        match parse_input(line) {
-            model::InputType::PriceUpdate(price_update) => handle_price_update(
-                &mut graph, &mut graph_result, price_update
-            ),
-            model::InputType::ExchangeRateRequest(exchange_rate_request) => handle_exchange_rate_request(
-                &graph, &mut graph_result, exchange_rate_request),
-            model::InputType::Invalid(_) => continue
+    let mut aliases = load_aliases(&options);
+    let parse_options = ParseOptions::new(&options);
+    let mut watch_list = match options.get_watch_list_file() {
+        Some(file_name) => match watch::WatchList::parse(&read_option_file(file_name, "watch list"), &aliases) {
+            Ok(watch_list) => watch_list,
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        },
+        None => watch::WatchList::new()
+    };
+    let mut fee_model = match options.get_fees_file() {
+        Some(file_name) => match fees::FeeModel::load(file_name, options.get_volume(), options.get_fee_side(), &aliases) {
+            Ok(fee_model) => Some(fee_model),
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        },
+        None => None
+    };
+    let weight_script = load_weight_script(&options);
+    let partitions = load_partitions(&options, &aliases);
+    // Adapters receiving the price updates, answers, changed best rates and alerts of the run, in this order
+    let mut bus = bus::EventBus::new();
+    // Kept to be closed with the graph at the end of the run
+    let audit_log = match options.get_audit_log_file() {
+        Some(file_name) => match audit::AuditLog::open(file_name) {
+            Ok(audit_log) => Some(Rc::new(RefCell::new(audit_log))),
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        },
+        None => None
+    };
+    if let Some(audit_log) = &audit_log {
+        bus.subscribe(audit_log.clone());
+    }
+    if let Some(file_name) = options.get_csv_file() {
+        match export::CsvWriter::create(file_name) {
+            Ok(csv_writer) => bus.subscribe(csv_writer),
+            Err(e) => exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while creating CSV file: {}", e))
+        }
+    }
+    if let Some(topic) = options.get_kafka_topic() {
+        match kafka::KafkaSink::connect(options.get_kafka_brokers(), topic) {
+            Ok(kafka_sink) => bus.subscribe(kafka_sink),
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        }
+    }
+    if let Some(spec) = options.get_sink() {
+        match plugin::create_sink(spec) {
+            Ok(sink) => bus.subscribe(sink),
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        }
+    }
+    // Changes to the best rate of watched pairs, kept when a chart is rendered at the end of the run
+    let rate_history = options.get_chart_dir().map(|_| Rc::new(RefCell::new(Vec::new())));
+    if let Some(rate_history) = &rate_history {
+        bus.subscribe(rate_history.clone());
+    }
+    let mut throttle = options.get_max_updates_per_sec().map(throttle::Throttle::new);
+    let mut dedup = options.get_dedup().map(dedup::Deduplicator::new);
+    let mut quarantine = options.get_quarantine().map(quarantine::Quarantine::new);
+    // Also refreshed by the run loop, and kept showing the final state at the end of the run
+    let dashboard = if options.is_dashboard() {
+        match dashboard::Dashboard::start(options.get_top()) {
+            Ok(dashboard) => Some(Rc::new(RefCell::new(dashboard))),
+            Err(e) => exit_with(constants::EXIT_IO_ERROR, &e)
+        }
+    } else {
+        None
+    };
+    match &dashboard {
+        Some(dashboard) => bus.subscribe(dashboard.clone()),
+        // Without a dashboard, answers are printed and alerts logged
+        None => {
+            bus.subscribe(bus::Printer);
+            bus.subscribe(bus::AlertLogger);
+        }
+    }
+
+    let (shutdown, hangup) = match (register_shutdown(), register_hangup()) {
+        (Ok(shutdown), Ok(hangup)) => (shutdown, hangup),
+        (Err(e), _) | (_, Err(e)) => exit_with(constants::EXIT_IO_ERROR,
+            &format!("Error encountered while registering signals: {}", e))
+    };
+
+    let mut engine = build_engine(&options, weight_script, partitions, load_liquidity(&options, &aliases));
+    let profiler = options.get_profile_file().map(|_| {
+        match profile::Profiler::start(Duration::from_millis(constants::PROFILE_SAMPLE_INTERVAL_MS)) {
+            Ok(profiler) => profiler,
+            Err(e) => exit_with(constants::EXIT_CONFIG_ERROR, &e)
+        }
+    });
+    if let Some(checkpoint) = &checkpoint {
+        engine.restore(checkpoint);
+        logger::log(&format!("Resuming from byte {} of {}", offset, options.get_input_file()));
+    }
+    let mut summary = summary::RunSummary::new();
+    if options.is_verify() {
+        summary.record_divergences(0);
+    }
+    // whether `--verify` met a graph too large to check
+    let mut too_large_to_verify = false;
+    let mut lines_since_checkpoint = 0;
+    // Requests are answered together once there is no more input ready or a price update changes the graph
+    let mut pending_requests = Vec::new();
+    let mut latencies = if options.is_latency() { Some(timing::Latencies::new()) } else { None };
+
+    loop {
+        if let Some(dashboard) = &dashboard {
+            if !dashboard.borrow_mut().refresh(watch_list.get_pairs()) {
+                break;
+            }
+        }
+        // Stop accepting input, the rest of the run is wrapped up as if the input ended
+        if shutdown.load(Ordering::Relaxed) {
+            summary.set_interrupted();
+            break;
+        }
+        if hangup.swap(false, Ordering::Relaxed) {
+            if let Err(e) = logger::reopen() {
+                logger::log(&format!("Error encountered while reopening log file: {}", e));
+            }
+            match reload_config(&options, &mut aliases, &mut watch_list, &mut fee_model) {
+                Ok(()) => {
+                    engine.clear_cache();
+                    logger::log("Reloaded configuration");
+                },
+                Err(e) => logger::log(&format!("{}, keeping the previous configuration", e))
+            }
+        }
+
+        if let Some(file_name) = options.get_checkpoint_file() {
+            if lines_since_checkpoint >= options.get_checkpoint_every() && pending_requests.is_empty() {
+                save_checkpoint(file_name, offset, &mut engine, options.is_warm_start());
+                lines_since_checkpoint = 0;
+            }
+        }
+
+        engine.schedule_recompute();
+        // Only wait for more input once there are no requests waiting to be answered
+        let timeout = if pending_requests.is_empty() { Duration::from_millis(100) } else { Duration::from_millis(0) };
+        let received = {
+            let _span = profile::span("wait_for_input");
+            lines.recv_timeout(timeout)
         };
+        let line = match received {
+            Ok(Ok((line, line_end))) => {
+                offset = line_end;
+                line
+            },
+            Ok(Err(e)) => exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while reading file: {}", e)),
+            Err(RecvTimeoutError::Timeout) => {
+                flush_exchange_rate_requests(&mut pending_requests, &mut engine, fee_model.as_ref(), &mut summary,
+                    &mut bus, options.get_threads(), latencies.as_mut());
+                continue;
+            },
+            Err(RecvTimeoutError::Disconnected) => break
+        };
+        if line.is_empty() {
+            continue;
+        }
+        let read_at = Instant::now();
+        summary.record_line();
+        lines_since_checkpoint += 1;
+        if let Some(fee_model) = &mut fee_model {
+            if fee_model.reload_if_changed(&aliases) {
+                engine.clear_cache();
+            }
+        }
+        let input = {
+            let _span = profile::span("parse_input");
+            let started = Instant::now();
+            let input = parse_input(&line, &aliases, &parse_options);
+            summary.record_parse(started.elapsed());
+            input
+        };
+        match input {
+            model::InputType::PriceUpdate(price_update) => {
+                // A repeat leaves the graph as it is, so pending requests can wait for the next update
+                if dedup.as_mut().is_some_and(|dedup| dedup.is_duplicate(&price_update)) {
+                    summary.record_price_update();
+                    continue;
+                }
+                flush_exchange_rate_requests(&mut pending_requests, &mut engine, fee_model.as_ref(), &mut summary,
+                    &mut bus, options.get_threads(), latencies.as_mut());
+                if let Some(throttle) = &mut throttle {
+                    throttle.acquire();
+                }
+                summary.record_price_update();
+                if let Some(quarantine) = &mut quarantine {
+                    let verdict = quarantine.check(&price_update);
+                    let message = match verdict {
+                        quarantine::Verdict::Accept => None,
+                        quarantine::Verdict::Quarantine => Some(format!("Quarantining price update \"{}\", far off \
+                            the recent rates of its market", line)),
+                        quarantine::Verdict::Confirm => Some(format!("Price update \"{}\" confirms the quarantined \
+                            one of its market", line)),
+                        quarantine::Verdict::Reject => Some(format!("Dropping the quarantined price update of {} {}/{}, \
+                            \"{}\" is back to its usual rate", price_update.get_exchange(),
+                            price_update.get_source_currency(), price_update.get_dest_currency(), line))
+                    };
+                    if let Some(message) = message {
+                        publish(&mut bus, bus::Event::Alert(&message));
+                    }
+                    if verdict == quarantine::Verdict::Quarantine {
+                        continue;
+                    }
+                }
+                publish(&mut bus, bus::Event::Price(&price_update));
+                engine.apply_price_update(price_update);
+                if let Some(latencies) = &mut latencies {
+                    latencies.record_update(read_at.elapsed());
+                }
+                if options.is_verify() {
+                    verify_best_rates(&mut engine, &mut summary, &mut too_large_to_verify);
+                }
+                let rate_points = if watch_list.is_empty() {
+                    Vec::new()
+                } else {
+                    engine.update_watched_pairs(&mut watch_list, fee_model.as_ref())
+                };
+                for rate_point in &rate_points {
+                    publish(&mut bus, bus::Event::Result(rate_point));
+                }
+            },
+            model::InputType::ExchangeRateRequest(exchange_rate_request) => {
+                if let Some(partition) = exchange_rate_request.get_partition()
+                    .filter(|partition| !engine.get_partitions().is_defined(partition))
+                {
+                    summary.record_invalid();
+                    publish(&mut bus, bus::Event::Alert(&format!("Skipping invalid input \"{}\": Unknown partition {}",
+                        line, partition)));
+                    continue;
+                }
+                pending_requests.push(exchange_rate_request);
+                if let Some(latencies) = &mut latencies {
+                    latencies.start_query(read_at);
+                }
+                if pending_requests.len() >= constants::REQUEST_BATCH_SIZE {
+                    flush_exchange_rate_requests(&mut pending_requests, &mut engine, fee_model.as_ref(), &mut summary,
+                        &mut bus, options.get_threads(), latencies.as_mut());
+                }
+            },
+            model::InputType::Invalid(reason) => {
+                summary.record_invalid();
+                publish(&mut bus, bus::Event::Alert(&format!("Skipping invalid input \"{}\": {}", line, reason)));
+            }
+        };
+    }
+    flush_exchange_rate_requests(&mut pending_requests, &mut engine, fee_model.as_ref(), &mut summary,
+        &mut bus, options.get_threads(), latencies.as_mut());
+    if let Some(file_name) = options.get_checkpoint_file() {
+        save_checkpoint(file_name, offset, &mut engine, options.is_warm_start());
+    }
+    summary.set_num_cache_hits(engine.get_num_cache_hits());
+    summary.add_phase_timings(engine.get_phase_timings());
+    if let Some(dedup) = &dedup {
+        summary.set_num_duplicates(dedup.get_num_duplicates());
+    }
+    if let Some(quarantine) = &quarantine {
+        summary.set_quarantined(quarantine.get_num_quarantined(), quarantine.get_num_released());
+    }
+    summary.set_backpressure(lines.get_num_dropped(), lines.get_num_coalesced());
+    if let Some(fault_counts) = &fault_counts {
+        summary.set_faults(*fault_counts.lock().unwrap());
+    }
+    if let Err(e) = bus.flush() {
+        exit_with(constants::EXIT_IO_ERROR, &e);
+    }
+    // Leaves the run the only owner of the subscribers it kept a handle on
+    drop(bus);
+    if let Some(Ok(dashboard)) = dashboard.map(Rc::try_unwrap) {
+        dashboard.into_inner().finish(watch_list.get_pairs());
+    }
+    if let Some(Ok(audit_log)) = audit_log.map(Rc::try_unwrap) {
+        if let Err(e) = audit_log.into_inner().close(engine.get_graph_result()) {
+            exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while writing audit log: {}", e));
+        }
+    }
+    if let Some(file_name) = options.get_arrow_file() {
+        engine.find_best_rates();
+        if let Err(e) = export::write_best_rates_arrow(file_name, engine.get_graph_result(), engine.get_vertices()) {
+            exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while writing Arrow file: {}", e));
+        }
+    }
+    if let Some(dir) = options.get_npy_dir() {
+        engine.find_best_rates();
+        if let Err(e) = export::write_matrices_npy(dir, engine.get_graph_result(), engine.get_vertices()) {
+            exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while writing .npy files: {}", e));
+        }
+    }
+    if let Some(file_name) = options.get_cypher_file() {
+        if let Err(e) = export::write_graph_cypher(file_name, engine.get_graph_result(), engine.get_vertices()) {
+            exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while writing Cypher file: {}", e));
+        }
+    }
+    if let (Some(dir), Some(rate_history)) = (options.get_chart_dir(), &rate_history) {
+        if let Err(e) = render_charts(dir, options.get_chart_format(), &rate_history.borrow(), engine.get_latest_datetime()) {
+            exit_with(constants::EXIT_IO_ERROR, &e);
+        }
+    }
+    if let (Some(file_name), Some(profiler)) = (options.get_profile_file(), profiler) {
+        let profile = profiler.finish();
+        if let Err(e) = profile.save(file_name) {
+            exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while writing profile: {}", e));
+        }
+        logger::log(&format!("Wrote {} profile sample(s) to {}", profile.get_num_samples(), file_name));
+    }
+
+    if let Err(e) = io::stdout().flush() {
+        exit_with(constants::EXIT_IO_ERROR, &format!("Error encountered while writing output: {}", e));
+    }
+    if options.is_summary() || summary.is_interrupted() {
+        logger::log(&summary.to_string());
+    }
+    if let Some(latencies) = &latencies {
+        logger::log(&latencies.to_string());
+    }
+
+    if options.is_strict() && summary.get_num_no_route() > 0 {
+        logger::log(&format!("No route found for {} exchange rate request(s)", summary.get_num_no_route()));
+        exit(constants::EXIT_NO_ROUTE);
+    }
+    if summary.get_num_divergences() > 0 {
+        logger::log(&format!("Best rates differed from an exhaustive search for {} pair(s)", summary.get_num_divergences()));
+        exit(constants::EXIT_VERIFY_FAILED);
     }
+    if summary.get_num_invalid() > 0 {
+        logger::log(&format!("Skipped {} invalid input line(s)", summary.get_num_invalid()));
+        exit(constants::EXIT_PARSE_ERROR);
+    }
+    exit(constants::EXIT_OK);
 }
 
 #[cfg(test)]
@@ -183,4 +1152,178 @@ mod tests {
     fn read_invalid_file() {
         read_file("non-existent-file.txt").unwrap_err();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_window_duration() {
+        assert_eq!(parse_duration("500ms"), Some(500));
+        assert_eq!(parse_duration("5m"), Some(5 * 60 * 1000));
+        assert_eq!(parse_duration("2h"), Some(2 * 60 * 60 * 1000));
+        assert_eq!(parse_duration("5"), None);
+        assert_eq!(parse_duration("m"), None);
+    }
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("1048576"), Some(1 << 20));
+        assert_eq!(parse_size("64KB"), Some(64 << 10));
+        assert_eq!(parse_size("512MB"), Some(512 << 20));
+        assert_eq!(parse_size("2GB"), Some(2 << 30));
+        assert_eq!(parse_size("2TB"), None);
+        assert_eq!(parse_size("MB"), None);
+    }
+
+    // At millisecond precision, like a run without `--timestamp-precision`
+    fn parse(input: &str, aliases: &alias::Aliases, assumed_spread: f64) -> model::InputType {
+        parse_input(input, aliases, &ParseOptions { assumed_spread, ..ParseOptions::default() })
+    }
+
+    #[test]
+    fn parse_request_with_modifiers() {
+        match parse("EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD WINDOW 5m", &alias::Aliases::none(), 0.0) {
+            model::InputType::ExchangeRateRequest(request) => assert_eq!(request.get_window(), Some(5 * 60 * 1000)),
+            _ => panic!("Expected an exchange rate request")
+        }
+        let line = "EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD PARETO WINDOW 1h VIA CURRENCY USD AVOID EUR AVOID GBP \
+            PARTITION fiat SPLIT 2.5 ID 7";
+        match parse(line, &alias::Aliases::none(), 0.0) {
+            model::InputType::ExchangeRateRequest(request) => {
+                assert_eq!(request.get_avoid(), [model::Currency::new("EUR").unwrap(), model::Currency::new("GBP").unwrap()]);
+                assert!(request.is_pareto());
+                assert_eq!(request.get_via(), Some(&model::Via::Currency(model::Currency::new("USD").unwrap())));
+                assert_eq!(request.get_window(), Some(60 * 60 * 1000));
+                assert_eq!(request.get_partition(), Some("fiat"));
+                assert_eq!(request.get_request_id(), Some("7"));
+                assert_eq!(request.get_split(), Some(2.5));
+            },
+            _ => panic!("Expected an exchange rate request")
+        }
+        // The kind is given rather than guessed, so a name used for both an exchange and a currency is not mixed up
+        match parse("EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD VIA EXCHANGE usd", &alias::Aliases::none(), 0.0) {
+            model::InputType::ExchangeRateRequest(request) => {
+                assert_eq!(request.get_via(), Some(&model::Via::Exchange(model::Exchange::new("USD").unwrap())));
+                assert!(request.to_string().ends_with(" VIA EXCHANGE USD"));
+            },
+            _ => panic!("Expected an exchange rate request")
+        }
+        for line in ["EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD WINDOW",
+            "EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD PARTITION", "EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD SPLIT 0",
+            "EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD VIA GDAX", "EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD VIA EXCHANGE"
+        ] {
+            match parse(line, &alias::Aliases::none(), 0.0) {
+                model::InputType::Invalid(_) => (),
+                _ => panic!("Expected an invalid input")
+            }
+        }
+    }
+
+    #[test]
+    fn parse_price_update_with_aliases() {
+        let aliases = alias::Aliases::new(
+            alias::AliasMap::parse("currency", "XBT BTC\nUST USDT").unwrap(),
+            alias::AliasMap::parse("exchange", "GDAX COINBASE").unwrap()
+        );
+        match parse("2017-11-01T09:42:23+00:00 GDAX XBT USD 1000.0 0.0009", &aliases, 0.0) {
+            model::InputType::PriceUpdate(price_update) => {
+                assert_eq!(price_update.get_exchange(), "COINBASE");
+                assert_eq!(price_update.get_source_currency(), "BTC");
+            },
+            _ => panic!("Expected a price update")
+        }
+        match parse("2017-11-01T09:42:23+00:00 KRAKEN UST USDT 1.0 1.0", &aliases, 0.0) {
+            model::InputType::Invalid(_) => (),
+            _ => panic!("Expected an invalid input")
+        }
+    }
+
+    #[test]
+    fn parse_normalized_names() {
+        match parse("2017-11-01T09:42:23+00:00 kraken btc usd 1000.0 0.0009", &alias::Aliases::none(), 0.0) {
+            model::InputType::PriceUpdate(price_update) => {
+                assert_eq!(price_update.get_exchange(), "KRAKEN");
+                assert_eq!(price_update.get_dest_currency(), "USD");
+            },
+            _ => panic!("Expected a price update")
+        }
+        match parse("EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX U$D", &alias::Aliases::none(), 0.0) {
+            model::InputType::Invalid(reason) => assert!(reason.starts_with("Invalid currency \"U$D\"")),
+            _ => panic!("Expected an invalid input")
+        }
+    }
+
+    #[test]
+    fn parse_price_update_with_derived_backward_ratio() {
+        match parse("2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000.0", &alias::Aliases::none(), 0.01) {
+            model::InputType::PriceUpdate(price_update) => {
+                assert_eq!(price_update.get_backward_ratio(), 1.0 / 1000.0 * 0.99);
+                assert!(price_update.is_backward_derived());
+            },
+            _ => panic!("Expected a price update")
+        }
+        match parse("2017-11-01T09:42:23+00:00 KRAKEN BTC USD 0", &alias::Aliases::none(), 0.01) {
+            model::InputType::Invalid(_) => (),
+            _ => panic!("Expected an invalid input")
+        }
+    }
+
+    #[test]
+    fn parse_non_finite_ratios() {
+        let parse_options = ParseOptions { allow_crossed: true, ..ParseOptions::default() };
+        for ratios in ["inf", "NaN", "inf 0.001", "NaN 0.001", "1000.0 inf", "1000.0 NaN"] {
+            let line = format!("2017-11-01T09:42:23+00:00 KRAKEN BTC USD {}", ratios);
+            let crossed = parse_input(&line, &alias::Aliases::none(), &parse_options);
+            for parsed in [parse(&line, &alias::Aliases::none(), 0.01), crossed] {
+                match parsed {
+                    model::InputType::Invalid(_) => (),
+                    _ => panic!("Expected an invalid input for {}", ratios)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parse_crossed_price_update_when_allowed() {
+        let line = "2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000.0 0.0011";
+        match parse(line, &alias::Aliases::none(), 0.0) {
+            model::InputType::Invalid(reason) => assert_eq!(reason, "Resultant ratios is invalid"),
+            _ => panic!("Expected an invalid input")
+        }
+        let parse_options = ParseOptions { allow_crossed: true, ..ParseOptions::default() };
+        match parse_input(line, &alias::Aliases::none(), &parse_options) {
+            model::InputType::PriceUpdate(price_update) => assert_eq!(price_update.get_backward_ratio(), 0.0011),
+            _ => panic!("Expected a price update")
+        }
+    }
+
+    #[test]
+    fn parse_sub_millisecond_timestamps() {
+        let line = "2017-11-01T09:42:23.123456789+00:00 KRAKEN BTC USD 1000.0";
+        for (precision, sub_millis, datetime) in [(model::Precision::Millis, 0, "2017-11-01T09:42:23.123+00:00"),
+            (model::Precision::Micros, 456_000, "2017-11-01T09:42:23.123456+00:00"),
+            (model::Precision::Nanos, 456_789, "2017-11-01T09:42:23.123456789+00:00")] {
+            match parse_input(line, &alias::Aliases::none(), &ParseOptions { precision, ..ParseOptions::default() }) {
+                model::InputType::PriceUpdate(price_update) => {
+                    assert_eq!((price_update.get_datetime(), price_update.get_sub_millis()), (1509529343123, sub_millis));
+                    assert!(price_update.to_string().starts_with(datetime));
+                },
+                _ => panic!("Expected a price update")
+            }
+        }
+    }
+
+    #[test]
+    fn parse_inverted_price_update() {
+        let mut aliases = alias::Aliases::none();
+        aliases.set_canonical_pairs(alias::CanonicalPairs::parse("BTC USD\n", &aliases).unwrap());
+        match parse("2017-11-01T09:42:23+00:00 KRAKEN USD BTC 0.0009 1000.0", &aliases, 0.0) {
+            model::InputType::PriceUpdate(price_update) => {
+                assert_eq!((price_update.get_source_currency(), price_update.get_dest_currency()), ("BTC", "USD"));
+                assert_eq!((price_update.get_forward_ratio(), price_update.get_backward_ratio()), (1000.0, 0.0009));
+            },
+            _ => panic!("Expected a price update")
+        }
+        match parse("2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000.0 0.0009", &aliases, 0.0) {
+            model::InputType::PriceUpdate(price_update) => assert_eq!(price_update.get_source_currency(), "BTC"),
+            _ => panic!("Expected a price update")
+        }
+    }
+}
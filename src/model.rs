@@ -1,30 +1,123 @@
-use std::collections::{HashMap, HashSet};
-use std::rc::Rc;
+use std::fmt;
+use std::sync::Arc;
 
 use chrono::Utc;
 
+use crate::constants;
+use crate::export;
+use crate::graph::{self, NodeMap, NodeSet, ShortestPaths, Weight};
+
+// Resolution of the timestamps of price updates, finer ones tell apart updates within the same millisecond, e.g. from
+// colocated feeds
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Precision {
+  Millis,
+  Micros,
+  Nanos
+}
+
+impl Precision {
+  pub fn parse(precision: &str) -> Option<Precision> {
+    match precision {
+      "ms" => Some(Precision::Millis),
+      "us" => Some(Precision::Micros),
+      "ns" => Some(Precision::Nanos),
+      _ => None
+    }
+  }
+
+  // `sub_millis` nanoseconds past a millisecond, truncated to this precision
+  pub fn truncate(self, sub_millis: u32) -> u32 {
+    match self {
+      Precision::Millis => 0,
+      Precision::Micros => sub_millis / 1000 * 1000,
+      Precision::Nanos => sub_millis
+    }
+  }
+}
+
 pub enum InputType {
     ExchangeRateRequest(ExchangeRateRequest),
     PriceUpdate(PriceUpdate),
     Invalid(String)
 }
 
+// Trim and upper-case a `kind` name, which must have 1 to `MAX_NAME_LENGTH` ASCII letters, digits, `_`, `-` or `.`
+fn normalize_name(kind: &str, name: &str) -> Result<String, String> {
+  let name = name.trim();
+  if name.is_empty() || name.len() > constants::MAX_NAME_LENGTH {
+    return Err(format!("Invalid {} \"{}\", expected 1 to {} characters", kind, name, constants::MAX_NAME_LENGTH));
+  }
+  if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.') {
+    return Err(format!("Invalid {} \"{}\", expected letters, digits, `_`, `-` or `.`", kind, name));
+  }
+  Ok(name.to_ascii_uppercase())
+}
+
+// A validated exchange name such as `KRAKEN`
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
+pub struct Exchange(String);
+
+impl Exchange {
+  pub fn new(exchange: &str) -> Result<Exchange, String> {
+    normalize_name("exchange", exchange).map(Exchange)
+  }
+
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+impl fmt::Display for Exchange {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+// A validated currency name such as `BTC`
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
+pub struct Currency(String);
+
+impl Currency {
+  pub fn new(currency: &str) -> Result<Currency, String> {
+    normalize_name("currency", currency).map(Currency)
+  }
+
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+impl fmt::Display for Currency {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+#[derive(Clone)]
 pub struct PriceUpdate {
     datetime: u64,  // millisecond
-    exchange: String,
-    source_currency: String,
-    dest_currency: String,
+    exchange: Exchange,
+    source_currency: Currency,
+    dest_currency: Currency,
     forward_ratio: f64,
-    backward_ratio: f64
+    backward_ratio: f64,
+    // the backward ratio was derived from the forward ratio because the source only quoted one direction
+    backward_derived: bool,
+    // the forward ratio was derived from the backward ratio, i.e. a quote of the one direction was inverted
+    forward_derived: bool,
+    // nanoseconds past `datetime`, under a millisecond, 0 unless parsed at a finer `Precision`
+    sub_millis: u32
 }
 
 impl PriceUpdate {
   pub fn new(
-    datetime: u64, exchange: String, source_currency: String, dest_currency: String,
+    datetime: u64, exchange: Exchange, source_currency: Currency, dest_currency: Currency,
     forward_ratio: f64, backward_ratio: f64
   ) -> PriceUpdate {
     PriceUpdate {
-      datetime, exchange, source_currency, dest_currency, forward_ratio, backward_ratio
+      datetime, exchange, source_currency, dest_currency, forward_ratio, backward_ratio, backward_derived: false,
+      forward_derived: false, sub_millis: 0
     }
   }
 
@@ -32,16 +125,32 @@ impl PriceUpdate {
     self.datetime
   }
 
+  pub fn get_sub_millis(&self) -> u32 {
+    self.sub_millis
+  }
+
+  pub fn set_sub_millis(&mut self, sub_millis: u32) {
+    self.sub_millis = sub_millis;
+  }
+
   pub fn get_exchange(&self) -> &str {
-    &self.exchange[..]
+    self.exchange.as_str()
   }
 
   pub fn get_source_currency(&self) -> &str {
-    &self.source_currency[..]
+    self.source_currency.as_str()
   }
 
   pub fn get_dest_currency(&self) -> &str {
-    &self.dest_currency[..]
+    self.dest_currency.as_str()
+  }
+
+  pub fn get_source_vertex(&self) -> Vertex {
+    Vertex::new(self.exchange.clone(), self.source_currency.clone())
+  }
+
+  pub fn get_dest_vertex(&self) -> Vertex {
+    Vertex::new(self.exchange.clone(), self.dest_currency.clone())
   }
 
   pub fn get_forward_ratio(&self) -> f64 {
@@ -51,79 +160,275 @@ impl PriceUpdate {
   pub fn get_backward_ratio(&self) -> f64 {
     self.backward_ratio
   }
+
+  pub fn is_backward_derived(&self) -> bool {
+    self.backward_derived
+  }
+
+  pub fn set_backward_derived(&mut self, backward_derived: bool) {
+    self.backward_derived = backward_derived;
+  }
+
+  pub fn is_forward_derived(&self) -> bool {
+    self.forward_derived
+  }
+
+  // The same quote with the source and destination currencies swapped, e.g. BTC/USD for USD/BTC
+  pub fn invert(self) -> PriceUpdate {
+    PriceUpdate {
+      datetime: self.datetime, exchange: self.exchange, source_currency: self.dest_currency,
+      dest_currency: self.source_currency, forward_ratio: self.backward_ratio, backward_ratio: self.forward_ratio,
+      backward_derived: self.forward_derived, forward_derived: self.backward_derived, sub_millis: self.sub_millis
+    }
+  }
+}
+
+// The input line of the price update, without the backward ratio when it was derived
+// An inverted update quoting only one direction is written as it was quoted
+impl fmt::Display for PriceUpdate {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.forward_derived {
+      return write!(f, "{} {} {} {} {}", export::format_precise_datetime(self.datetime, self.sub_millis), self.exchange,
+        self.dest_currency, self.source_currency, self.backward_ratio);
+    }
+    write!(f, "{} {} {} {} {}", export::format_precise_datetime(self.datetime, self.sub_millis), self.exchange, self.source_currency,
+      self.dest_currency, self.forward_ratio)?;
+    if !self.backward_derived {
+      write!(f, " {}", self.backward_ratio)?;
+    }
+    Ok(())
+  }
+}
+
+// (hops, rate, path) of a route on the Pareto front of rate and hops
+pub type ParetoRoute = (usize, f64, Vec<Arc<Vertex>>);
+
+// What a route must pass through, any vertex of an exchange or any vertex of a currency
+#[derive(Hash, PartialEq, Eq, Clone, Debug)]
+pub enum Via {
+  Exchange(Exchange),
+  Currency(Currency)
+}
+
+impl Via {
+  pub fn matches(&self, vertex: &Vertex) -> bool {
+    match self {
+      Via::Exchange(exchange) => vertex.get_exchange() == exchange.as_str(),
+      Via::Currency(currency) => vertex.get_currency() == currency.as_str()
+    }
+  }
+}
+
+// The arguments of the `VIA` modifier, e.g. `EXCHANGE GDAX`
+impl fmt::Display for Via {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Via::Exchange(exchange) => write!(f, "{} {}", constants::VIA_EXCHANGE, exchange),
+      Via::Currency(currency) => write!(f, "{} {}", constants::VIA_CURRENCY, currency)
+    }
+  }
 }
 
+#[derive(Hash, PartialEq, Eq, Clone)]
 pub struct ExchangeRateRequest {
-    source_exchange: String,
-    source_currency: String,
-    dest_exchange: String,
-    dest_currency: String
+    source_exchange: Exchange,
+    source_currency: Currency,
+    dest_exchange: Exchange,
+    dest_currency: Currency,
+    window: Option<u64>,  // millisecond
+    pareto: bool,
+    // exchange or currency the route must pass through
+    via: Option<Via>,
+    // currencies the route must not pass through
+    avoid: Vec<Currency>,
+    // name of the partition of currencies the route must stay within
+    partition: Option<String>,
+    // given by the client to correlate the answer with the request, it does not change the answer otherwise
+    request_id: Option<String>,
+    // amount of the source currency to split across the best paths, as the bits of the f64 so requests stay hashable
+    split: Option<u64>
 }
 
 impl ExchangeRateRequest {
-  pub fn new(source_exchange: String, source_currency: String,
-    dest_exchange: String, dest_currency: String
+  pub fn new(source_exchange: Exchange, source_currency: Currency,
+    dest_exchange: Exchange, dest_currency: Currency, window: Option<u64>
   ) -> ExchangeRateRequest {
     ExchangeRateRequest {
-      source_exchange, source_currency, dest_exchange, dest_currency
+      source_exchange, source_currency, dest_exchange, dest_currency, window, pareto: false, via: None,
+      avoid: Vec::new(), partition: None, request_id: None, split: None
     }
   }
 
   pub fn get_source_exchange(&self) -> &str {
-    &self.source_exchange[..]
+    self.source_exchange.as_str()
   }
 
   pub fn get_source_currency(&self) -> &str {
-    &self.source_currency[..]
+    self.source_currency.as_str()
   }
 
   pub fn get_dest_exchange(&self) -> &str {
-    &self.dest_exchange[..]
+    self.dest_exchange.as_str()
   }
 
   pub fn get_dest_currency(&self) -> &str {
-    &self.dest_currency[..]
+    self.dest_currency.as_str()
+  }
+
+  pub fn get_source_vertex(&self) -> Vertex {
+    Vertex::new(self.source_exchange.clone(), self.source_currency.clone())
+  }
+
+  pub fn get_dest_vertex(&self) -> Vertex {
+    Vertex::new(self.dest_exchange.clone(), self.dest_currency.clone())
+  }
+
+  // Only edges updated within this many milliseconds of the query time are used
+  pub fn get_window(&self) -> Option<u64> {
+    self.window
+  }
+
+  // Whether the answer also lists the best rate for each number of hops that beats every shorter route
+  pub fn is_pareto(&self) -> bool {
+    self.pareto
+  }
+
+  pub fn set_pareto(&mut self, pareto: bool) {
+    self.pareto = pareto;
+  }
+
+  pub fn get_via(&self) -> Option<&Via> {
+    self.via.as_ref()
+  }
+
+  pub fn set_via(&mut self, via: Option<Via>) {
+    self.via = via;
+  }
+
+  pub fn get_avoid(&self) -> &[Currency] {
+    &self.avoid
+  }
+
+  pub fn set_avoid(&mut self, avoid: Vec<Currency>) {
+    self.avoid = avoid;
+  }
+
+  pub fn get_partition(&self) -> Option<&str> {
+    self.partition.as_deref()
+  }
+
+  pub fn set_partition(&mut self, partition: Option<String>) {
+    self.partition = partition;
+  }
+
+  pub fn get_request_id(&self) -> Option<&str> {
+    self.request_id.as_deref()
+  }
+
+  pub fn set_request_id(&mut self, request_id: Option<String>) {
+    self.request_id = request_id;
+  }
+
+  // Amount of the source currency whose conversion the answer also splits across the best paths
+  pub fn get_split(&self) -> Option<f64> {
+    self.split.map(f64::from_bits)
+  }
+
+  pub fn set_split(&mut self, split: Option<f64>) {
+    self.split = split.map(f64::to_bits);
   }
 }
 
-#[derive(Hash, PartialEq, Eq, Clone)]
+// The input line of the request, with its window in milliseconds
+impl fmt::Display for ExchangeRateRequest {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} {} {} {} {}", constants::EXCHANGE_RATE_REQUEST, self.source_exchange, self.source_currency,
+      self.dest_exchange, self.dest_currency)?;
+    if let Some(window) = self.window {
+      write!(f, " {} {}ms", constants::WINDOW_MODIFIER, window)?;
+    }
+    if self.pareto {
+      write!(f, " {}", constants::PARETO_MODIFIER)?;
+    }
+    if let Some(via) = &self.via {
+      write!(f, " {} {}", constants::VIA_MODIFIER, via)?;
+    }
+    for currency in self.avoid.iter() {
+      write!(f, " {} {}", constants::AVOID_MODIFIER, currency)?;
+    }
+    if let Some(partition) = &self.partition {
+      write!(f, " {} {}", constants::PARTITION_MODIFIER, partition)?;
+    }
+    if let Some(split) = self.get_split() {
+      write!(f, " {} {}", constants::SPLIT_MODIFIER, split)?;
+    }
+    if let Some(request_id) = &self.request_id {
+      write!(f, " {} {}", constants::REQUEST_ID_MODIFIER, request_id)?;
+    }
+    Ok(())
+  }
+}
+
+// Ordered by exchange, then currency
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Vertex {
-  exchange: String,
-  currency: String
+  exchange: Exchange,
+  currency: Currency
 }
 
 impl Vertex {
-  pub fn new(exchange: String, currency: String) -> Vertex {
+  pub fn new(exchange: Exchange, currency: Currency) -> Vertex {
     Vertex {
       exchange, currency
     }
   }
 
   pub fn get_exchange(&self) -> &str {
-    &self.exchange
+    self.exchange.as_str()
   }
 
   pub fn get_currency(&self) -> &str {
-    &self.currency
+    self.currency.as_str()
   }
 }
 
+// `<KRAKEN, BTC>`, as in the paths of answers
+impl fmt::Display for Vertex {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "<{}, {}>", self.exchange, self.currency)
+  }
+}
+
+// Sets and maps of vertices, B-trees with the `ordered-graph` feature and hash tables otherwise
+pub type VertexSet = NodeSet<Arc<Vertex>>;
+pub type VertexMap<V> = NodeMap<Arc<Vertex>, V>;
+
 pub struct Graph {
-  vertices: HashSet<Rc<Vertex>>
+  vertices: VertexSet
+}
+
+impl Default for Graph {
+  fn default() -> Self {
+    Graph::new()
+  }
 }
 
 impl Graph {
   pub fn new() -> Graph {
     Graph {
-      vertices: HashSet::new()
+      vertices: VertexSet::new()
     }
   }
 
-  pub fn get_vertices(&self) -> &HashSet<Rc<Vertex>> {
+  pub fn get_vertices(&self) -> &VertexSet {
     &self.vertices
   }
 
-  pub fn add_vertex(&mut self, vertex: Rc<Vertex>) {
+  pub fn remove_vertex(&mut self, vertex: &Arc<Vertex>) {
+    self.vertices.remove(vertex);
+  }
+
+  pub fn add_vertex(&mut self, vertex: Arc<Vertex>) {
     match self.vertices.get(&vertex) {
       Some(_) => (),
       None => {
@@ -133,32 +438,59 @@ impl Graph {
   }
 }
 
-pub struct EdgeWeight {
-  weight: f64,
-  last_updated: u64
+pub struct EdgeWeight<W = f64> {
+  weight: W,
+  last_updated: u64,
+  // nanoseconds past `last_updated`, under a millisecond
+  sub_millis: u32,
+  // the weight was derived from the quote in the other direction rather than quoted
+  derived: bool,
+  // when the weight was first set, and how many times it was set since, which give how often the quote is updated
+  first_updated: u64,
+  num_updates: u64
 }
 
 impl Default for EdgeWeight {
   fn default() -> Self {
+    let now = Utc::now().timestamp_millis() as u64;
     EdgeWeight {
       weight: 0.0,
-      last_updated: Utc::now().timestamp_millis() as u64
+      last_updated: now,
+      sub_millis: 0,
+      derived: false,
+      first_updated: now,
+      num_updates: 1
     }
   }
 }
 
-impl EdgeWeight {
-  pub fn new(weight: f64, last_updated: u64) -> EdgeWeight {
+impl<W: Weight> EdgeWeight<W> {
+  pub fn new(weight: W, last_updated: u64) -> EdgeWeight<W> {
     EdgeWeight {
-      weight, last_updated
+      weight, last_updated, sub_millis: 0, derived: false, first_updated: last_updated, num_updates: 1
     }
   }
 
-  pub fn get_weight(&self) -> f64 {
+  // Weight set `sub_millis` nanoseconds past `datetime`, which is the last update unless it is older
+  fn update(&mut self, weight: W, datetime: u64, sub_millis: u32, derived: bool) {
+    if (datetime, sub_millis) > (self.last_updated, self.sub_millis) {
+      self.weight = weight;
+      self.last_updated = datetime;
+      self.sub_millis = sub_millis;
+      self.derived = derived;
+      self.num_updates += 1;
+    }
+  }
+
+  pub fn is_derived(&self) -> bool {
+    self.derived
+  }
+
+  pub fn get_weight(&self) -> W {
     self.weight
   }
 
-  pub fn set_weight(&mut self, weight: f64) {
+  pub fn set_weight(&mut self, weight: W) {
     self.weight = weight;
   }
 
@@ -169,139 +501,183 @@ impl EdgeWeight {
   pub fn set_last_updated(&mut self, last_updated: u64) {
     self.last_updated = last_updated;
   }
+
+  pub fn get_sub_millis(&self) -> u32 {
+    self.sub_millis
+  }
+
+  // When the weight was first set, and how many times it was set since
+  pub fn get_update_history(&self) -> (u64, u64) {
+    (self.first_updated, self.num_updates)
+  }
+
+  // How much the quote can be trusted at `query_time`, from 1 when just updated down towards 0 as it ages
+  // Confidence halves every `CONFIDENCE_HALF_LIFE_INTERVALS` times the average interval between updates, so a quote
+  // updated every second is doubtful after a silent minute while an hourly one is not, and every
+  // `CONFIDENCE_HALF_LIFE_MS` for a quote updated only once
+  pub fn get_confidence(&self, query_time: u64) -> f64 {
+    let half_life = match self.num_updates {
+      0 | 1 => constants::CONFIDENCE_HALF_LIFE_MS,
+      num_updates => ((self.last_updated - self.first_updated) / (num_updates - 1)
+        * constants::CONFIDENCE_HALF_LIFE_INTERVALS).max(constants::CONFIDENCE_MIN_HALF_LIFE_MS)
+    };
+    0.5f64.powf(query_time.saturating_sub(self.last_updated) as f64 / half_life as f64)
+  }
+}
+
+// `1000 UPDATED 2017-11-01T09:42:23+00:00`, followed by ` DERIVED` for a derived weight
+impl<W: fmt::Display> fmt::Display for EdgeWeight<W> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} UPDATED {}", self.weight, export::format_precise_datetime(self.last_updated, self.sub_millis))?;
+    if self.derived {
+      f.write_str(" DERIVED")?;
+    }
+    Ok(())
+  }
 }
 
-pub struct GraphResult {
+// Edges and the best paths between every pair of vertices, with weights of type `W`, rates by default
+pub struct GraphResult<W = f64> {
   // stores the edge weights between each pair of vertex
-  adj_matrix: HashMap<Rc<Vertex>, HashMap<Rc<Vertex>, EdgeWeight>>,
-  // stores the best rate between each pair of vertex
-  best_rate: HashMap<Rc<Vertex>, HashMap<Rc<Vertex>, f64>>,
-  // stores vertices to reconstruct the path for best rate from vertex i to j
-  next: HashMap<Rc<Vertex>, HashMap<Rc<Vertex>, Rc<Vertex>>>
+  graph: graph::Graph<Arc<Vertex>, EdgeWeight<W>>,
+  // stores the best rate between each pair of vertex and the vertices to reconstruct its path
+  best_paths: ShortestPaths<Arc<Vertex>, W>,
+  // whether crossed markets are allowed, so that every best rate that can go around an arbitrage cycle is unbounded
+  allow_crossed: bool
 }
 
-impl GraphResult {
-  pub fn new() -> GraphResult {
+impl<W: Weight> Default for GraphResult<W> {
+  fn default() -> Self {
+    GraphResult::new()
+  }
+}
+
+impl<W: Weight> GraphResult<W> {
+  pub fn new() -> GraphResult<W> {
     GraphResult {
-      adj_matrix: HashMap::new(),
-      best_rate: HashMap::new(),
-      next: HashMap::new()
+      graph: graph::Graph::new(),
+      best_paths: ShortestPaths::new(),
+      allow_crossed: false
     }
   }
 
-  // Update next[i][j] to next[i][k]
-  pub fn update_next_vertex(&mut self, i: &Rc<Vertex>, j: &Rc<Vertex>, k: &Rc<Vertex>) {
-    let ik_next = self.next.get(i).unwrap().get(k).unwrap().clone();
+  // Report every best rate that can go around an arbitrage cycle as unbounded, see `is_unbounded`. Copies made with
+  // `map_edges` keep it
+  pub fn set_allow_crossed(&mut self, allow_crossed: bool) {
+    self.allow_crossed = allow_crossed;
+  }
 
-    match self.next.get_mut(i) {
-      Some(inner_map) => {
-        inner_map.entry(j.clone())
-          .and_modify(|vertex| { *vertex = ik_next.clone() })
-          .or_insert(ik_next);
-      },
-      // vertex `i` will always be found in `next`
-      None => ()
-    }
+  // The petgraph `Graph` holding the vertices and edges
+  #[cfg(feature = "petgraph")]
+  pub fn as_petgraph(&self) -> &petgraph::Graph<Arc<Vertex>, EdgeWeight<W>> {
+    self.graph.as_petgraph()
   }
 
-  // Add `to_vertex` in next[from_vertex][to_vertex]
-  fn add_next_vertex(next: &mut HashMap<Rc<Vertex>, HashMap<Rc<Vertex>, Rc<Vertex>>>,
-    from_vertex: &Rc<Vertex>, to_vertex: &Rc<Vertex>
-  ) {
-    match next.get_mut(from_vertex) {
-      Some(inner_map) => {
-        match inner_map.get_mut(to_vertex) {
-          Some(_) => (),
-          None => {
-            inner_map.insert(to_vertex.clone(), to_vertex.clone());
-          }
-        }
-      },
-      // No record of `from_vertex` in `next`
-      None => {
-        let mut inner_map: HashMap<Rc<Vertex>, Rc<Vertex>> = HashMap::new();
-        inner_map.insert(to_vertex.clone(), to_vertex.clone());
-        next.insert(from_vertex.clone(), inner_map);
-      }
-    }
+  // The edge from `from_vertex` to `to_vertex`, if any
+  pub fn get_edge(&self, from_vertex: &Arc<Vertex>, to_vertex: &Arc<Vertex>) -> Option<&EdgeWeight<W>> {
+    self.graph.get_edge(from_vertex, to_vertex)
   }
 
-  // Get the edge weight of adj_matrix[from_vertex][to_vertex]
-  pub fn get_edge_weight(&self, from_vertex: &Rc<Vertex>, to_vertex: &Rc<Vertex>) -> f64 {
-    match self.adj_matrix.get(from_vertex) {
-      Some(inner_map) => {
-        match inner_map.get(to_vertex) {
-          Some(edge) => edge.get_weight(),
-          // Return 0 if there is no edge between `from_vertex` and `to_vertex`
-          None => 0.0
-        }
-      },
-      // `from_vertex` will always be found in `adj_matrix`
-      None => 0.0
-    }
+  // Weight of the best path from `from_vertex` to `to_vertex`, None if there is no path
+  pub fn get_best_weight(&self, from_vertex: &Arc<Vertex>, to_vertex: &Arc<Vertex>) -> Option<W> {
+    self.best_paths.get_weight(from_vertex, to_vertex)
   }
 
-  // Set the edge weight of best_rate[from_vertex][to_vertex]
-  fn add_best_rate(best_rate: &mut HashMap<Rc<Vertex>, HashMap<Rc<Vertex>, f64>>,
-    from_vertex: &Rc<Vertex>, to_vertex: &Rc<Vertex>, weight: f64
-  ) {
-    match best_rate.get_mut(from_vertex) {
-      Some(inner_map) => {
-        inner_map.entry(to_vertex.clone())
-          .and_modify(|edge_weight| { *edge_weight = weight })
-          .or_insert(weight);
-      },
-      None => {
-        let mut inner_map: HashMap<Rc<Vertex>, f64> = HashMap::new();
-        inner_map.insert(to_vertex.clone(), weight);
-        best_rate.insert(from_vertex.clone(), inner_map);
-      }
-    }
+  // Whether the rate of any hop of `path` was derived rather than quoted
+  pub fn has_derived_edge(&self, path: &[Arc<Vertex>]) -> bool {
+    path.windows(2).any(|hop| self.get_edge(&hop[0], &hop[1]).is_some_and(|edge| edge.is_derived()))
   }
 
-  pub fn get_best_rate(&self, from_vertex: &Rc<Vertex>, to_vertex: &Rc<Vertex>) -> f64 {
-    *self.best_rate.get(from_vertex).unwrap().get(to_vertex).unwrap()
+  pub fn num_edges(&self) -> usize {
+    self.graph.num_edges()
   }
 
+  pub fn remove_edge(&mut self, from_vertex: &Arc<Vertex>, to_vertex: &Arc<Vertex>) {
+    self.graph.remove_edge(from_vertex, to_vertex);
+  }
 
-  // Add edge weight in adj_matrix[from_vertex][to_vertex]
+  // Remove `vertex` and every edge going in or out of it. Best rates are stale until found again
+  pub fn remove_vertex(&mut self, vertex: &Arc<Vertex>) {
+    self.graph.remove_node(vertex);
+  }
+
+  // Every edge as (from_vertex, to_vertex, edge)
+  pub fn edges(&self) -> impl Iterator<Item = (&Arc<Vertex>, &Arc<Vertex>, &EdgeWeight<W>)> {
+    self.graph.edges()
+  }
+
+  // Whether any exchange quotes `source_currency` to `dest_currency` directly
+  pub fn has_direct_quote(&self, source_currency: &str, dest_currency: &str) -> bool {
+    self.edges().any(|(from_vertex, to_vertex, _)| {
+      from_vertex.get_exchange() == to_vertex.get_exchange() && from_vertex.get_currency() == source_currency
+        && to_vertex.get_currency() == dest_currency
+    })
+  }
+
+  // Copy of this graph with the weight of every edge replaced by `weight(from_vertex, to_vertex, edge)`.
+  // Edges for which it returns None are left out
+  pub fn map_edges<F>(&self, weight: F) -> GraphResult<W>
+    where F: Fn(&Arc<Vertex>, &Arc<Vertex>, &EdgeWeight<W>) -> Option<W>
+  {
+    let graph = self.graph.map_edges(|from_vertex, to_vertex, edge| {
+      weight(from_vertex, to_vertex, edge)
+        .map(|weight| EdgeWeight {
+          weight, last_updated: edge.last_updated, sub_millis: edge.sub_millis, derived: edge.derived, first_updated: edge.first_updated,
+          num_updates: edge.num_updates
+        })
+    });
+    GraphResult { graph, best_paths: ShortestPaths::new(), allow_crossed: self.allow_crossed }
+  }
+
+  // Copy of this graph keeping only edges updated at or after `since`.
+  // Edges between the same currency on different exchanges are not market quotes, so they are always kept
+  pub fn edges_since(&self, since: u64) -> GraphResult<W> {
+    self.map_edges(|from_vertex, to_vertex, edge| {
+      if from_vertex.get_exchange() != to_vertex.get_exchange() || edge.get_last_updated() >= since {
+        Some(edge.get_weight())
+      } else {
+        None
+      }
+    })
+  }
+
+
+  // Add edge weight from `from_vertex` to `to_vertex`, unless the edge was updated after `sub_millis` nanoseconds past
+  // `datetime`
+  // `derived` tells whether the weight was derived from the quote in the other direction
   pub fn add_edge_weight(
-    &mut self, from_vertex: Rc<Vertex>, to_vertex: Rc<Vertex>,
-    weight: f64, datetime: u64
+    &mut self, from_vertex: Arc<Vertex>, to_vertex: Arc<Vertex>,
+    weight: W, datetime: u64, sub_millis: u32, derived: bool
   ) {
-    // Add edge from `from_vertex` to `to_vertex`
-    match self.adj_matrix.get_mut(&from_vertex) {
-      Some(inner_map) => {
-        match inner_map.get_mut(&to_vertex) {
-          Some(edge) => {
-            if datetime > edge.get_last_updated() {
-              edge.set_weight(weight);
-              edge.set_last_updated(datetime);
-            }
-          },
-          // No record of edge from `from_vertex` to `to_vertex`
-          None => {
-            inner_map.insert(to_vertex.clone(), EdgeWeight::new(weight, datetime));
-          }
-        }
-      },
-      // No record of `from_vertex` in `adj_matrix`
-      None => {
-        let mut inner_map: HashMap<Rc<Vertex>, EdgeWeight> = HashMap::new();
-        inner_map.insert(to_vertex.clone(), EdgeWeight::new(weight, datetime));
-        self.adj_matrix.insert(from_vertex.clone(), inner_map);
-      }
+    match self.graph.get_edge_mut(&from_vertex, &to_vertex) {
+      Some(edge) => edge.update(weight, datetime, sub_millis, derived),
+      // No record of edge from `from_vertex` to `to_vertex`
+      None => self.graph.set_edge(from_vertex, to_vertex, EdgeWeight {
+        weight, last_updated: datetime, sub_millis, derived, first_updated: datetime, num_updates: 1
+      })
+    }
+  }
+
+  // Set when the edge from `from_vertex` to `to_vertex` was first updated and how many times it was updated since,
+  // e.g. as saved in a checkpoint, see `EdgeWeight::get_confidence`
+  pub fn set_update_history(&mut self, from_vertex: &Arc<Vertex>, to_vertex: &Arc<Vertex>, first_updated: u64,
+    num_updates: u64
+  ) {
+    if let Some(edge) = self.graph.get_edge_mut(from_vertex, to_vertex) {
+      edge.first_updated = first_updated;
+      edge.num_updates = num_updates;
     }
   }
 
   // 1. Get a list of vertices with the same currency as the vertex that was just inserted
-  // 2. Add edge weight of 1 from vertex_inserted to other vertices[v1..vn] and vice versa
+  // 2. Add an edge with the identity weight, e.g. a rate of 1, from vertex_inserted to other vertices[v1..vn] and
+  // vice versa
   // Runtime: O(V + V2), V2 < V
   pub fn add_edge_weight_for_currency(
-    &mut self, vertex_inserted: Rc<Vertex>, vertices: &HashSet<Rc<Vertex>>
+    &mut self, vertex_inserted: Arc<Vertex>, vertices: &VertexSet
   ) {
     let currenncy_to_match = vertex_inserted.get_currency();
-    let mut vertices_for_currency: HashSet<Rc<Vertex>> = vertices.clone();
+    let mut vertices_for_currency: VertexSet = vertices.clone();
     // O(V)
     vertices_for_currency.retain(|v| { v.get_currency() == currenncy_to_match });
 
@@ -309,79 +685,242 @@ impl GraphResult {
     for vertex in vertices_for_currency {
       // Do not set edge to link to the same vertex
       if vertex != vertex_inserted {
-        // Set edge from vertex_inserted to vertex
-        match self.adj_matrix.get_mut(&vertex_inserted) {
-          Some(inner_map) => {
-            inner_map.entry(vertex.clone())
-              .or_insert(EdgeWeight::new(1.0, Utc::now().timestamp_millis() as u64));
-          },
-          // `vertex_inserted` will always be found in `adj_matrix` because it was just inserted
-          None => ()
-        }
-        // Set edge from vertex to vertex_inserted
-        match self.adj_matrix.get_mut(&vertex) {
-          Some(inner_map) => {
-            inner_map.entry(vertex_inserted.clone())
-              .or_insert(EdgeWeight::new(1.0, Utc::now().timestamp_millis() as u64));
-          },
-          // `vertex` will always be found in `adj_matrix` because edges and vertices were added before this step
-          None => ()
+        // Set edge from vertex_inserted to vertex, and from vertex to vertex_inserted
+        for (from_vertex, to_vertex) in [(&vertex_inserted, &vertex), (&vertex, &vertex_inserted)].iter() {
+          if self.graph.get_edge(from_vertex, to_vertex).is_none() {
+            self.graph.set_edge((*from_vertex).clone(), (*to_vertex).clone(),
+              EdgeWeight::new(W::identity(), Utc::now().timestamp_millis() as u64));
+          }
         }
       }
     }
   }
 
-  // Modified floyd warshall to get the best rate for every pair of vertices
-  pub fn find_best_rates(&mut self, vertices: &HashSet<Rc<Vertex>>) {
-    // For all edges, add edge in rate[i][j], add j in next[i][j]
-    for (i, inner_map) in self.adj_matrix.iter_mut() {
-      for (j, edge) in inner_map.iter() {
-        let edge_weight = edge.get_weight();
+  // Best rate for every pair of vertices, see `graph::Graph::shortest_paths`
+  pub fn find_best_rates(&mut self, vertices: &VertexSet) {
+    self.best_paths = self.graph.shortest_paths(vertices, EdgeWeight::get_weight);
+  }
 
-        GraphResult::add_best_rate(&mut self.best_rate, i, j, edge_weight);
-        GraphResult::add_next_vertex(&mut self.next, i, j);
-      }
-    }
+  pub fn best_rate_path(&self, from_vertex: &Arc<Vertex>, to_vertex: &Arc<Vertex>) -> Option<Vec<Arc<Vertex>>> {
+    self.best_paths.path(from_vertex, to_vertex)
+  }
 
-    for i in vertices.iter().cloned() {
-        for j in vertices.iter().cloned() {
-            for k in vertices.iter().cloned() {
-                // Skip weight comparison if any pair of ij, ik,, kj are the same vertices
-                if i != j && i != k && k != j {
-                    let ij_weight = self.get_edge_weight(&i, &j);
-                    let ik_weight = self.get_edge_weight(&i, &k);
-                    let kj_weight = self.get_edge_weight(&k, &j);
-
-                    if ij_weight < ik_weight * kj_weight {
-                        GraphResult::add_best_rate(&mut self.best_rate, &i, &j, ik_weight * kj_weight);
-                        self.update_next_vertex(&i, &j, &k);
-                    }
-                }
-            }
-        }
-    }
+  // (from_vertex, to_vertex, best weight, vertex after `from_vertex`) of every pair of vertices with a path, as of
+  // the last `find_best_rates`
+  pub fn best_paths(&self) -> impl Iterator<Item = (&Arc<Vertex>, &Arc<Vertex>, W, &Arc<Vertex>)> {
+    self.best_paths.entries()
   }
 
-  pub fn best_rate_path(&self, from_vertex: &Rc<Vertex>, to_vertex: &Rc<Vertex>) -> Option<Vec<Rc<Vertex>>> {
-    match self.next.get(from_vertex) {
-      Some(inner_map) => {
-        match inner_map.get(to_vertex) {
-          Some(_) => (),
-          None => return None
-        }
+  // Replace the best paths with those found before, e.g. saved in a checkpoint, instead of finding them again
+  pub fn restore_best_paths(&mut self, mut best_paths: ShortestPaths<Arc<Vertex>, W>) {
+    best_paths.find_cycles();
+    self.best_paths = best_paths;
+  }
+}
+
+// Rates, where no path has a rate of 0
+impl GraphResult {
+  // Whether the rate from `from_vertex` to `to_vertex` can grow without bound by going around an arbitrage cycle, so
+  // that the rate found is meaningless. With crossed markets allowed that is every pair that can reach such a cycle and
+  // get back from it. Otherwise arbitrage across exchanges usually still leaves a best path, so only pairs whose best
+  // rate went around the cycle are: it saturated, its path loops, or it is not the product of the rates of its path
+  pub fn is_unbounded(&self, from_vertex: &Arc<Vertex>, to_vertex: &Arc<Vertex>) -> bool {
+    if !self.best_paths.is_unbounded(from_vertex, to_vertex) {
+      return false;
+    }
+    if self.allow_crossed {
+      return true;
+    }
+    let best_rate = self.get_best_rate(from_vertex, to_vertex);
+    match self.best_rate_path(from_vertex, to_vertex) {
+      Some(path) => {
+        let path_rate: f64 = path.windows(2).map(|hop| self.get_edge_weight(&hop[0], &hop[1])).product();
+        best_rate == f64::MAX || (best_rate - path_rate).abs() > best_rate * constants::UNBOUNDED_RATE_TOLERANCE
       },
-      None => return None
+      None => true
     }
+  }
 
-    let mut path = Vec::new();
-    let mut from = from_vertex.clone();
-    path.push(from.clone());
-    
-    while from != to_vertex.clone() {
-      from = self.next.get(&from).unwrap().get(to_vertex).unwrap().clone();
-      path.push(from.clone());
+  // Get the edge weight of the edge from `from_vertex` to `to_vertex`, 0 if there is no edge
+  pub fn get_edge_weight(&self, from_vertex: &Arc<Vertex>, to_vertex: &Arc<Vertex>) -> f64 {
+    self.get_edge(from_vertex, to_vertex).map_or(0.0, EdgeWeight::get_weight)
+  }
+
+  // Return 0 if there is no path from `from_vertex` to `to_vertex`
+  pub fn get_best_rate(&self, from_vertex: &Arc<Vertex>, to_vertex: &Arc<Vertex>) -> f64 {
+    self.get_best_weight(from_vertex, to_vertex).unwrap_or(0.0)
+  }
+
+  // The best rate from `from_vertex` to `to_vertex` for each number of hops up to `max_hops`, as (hops, rate, path),
+  // keeping only those better than every route with fewer hops, i.e. the Pareto front of rate and hops.
+  // Each number of hops is computed from the best rates with one hop less, so a route may visit a vertex twice
+  pub fn pareto_front(&self, from_vertex: &Arc<Vertex>, to_vertex: &Arc<Vertex>, max_hops: usize)
+    -> Vec<ParetoRoute>
+  {
+    let mut vertices: Vec<&Arc<Vertex>> = self.graph.sources().collect();
+    vertices.sort();
+
+    // layers[k][v] is the best rate from `from_vertex` to `v` in exactly k hops, and the vertex before `v`
+    type Layer = VertexMap<(f64, Option<Arc<Vertex>>)>;
+    let mut layers: Vec<Layer> = Vec::new();
+    let mut first_layer = VertexMap::new();
+    first_layer.insert(from_vertex.clone(), (1.0, None));
+    layers.push(first_layer);
+
+    let mut front: Vec<ParetoRoute> = Vec::new();
+    for hops in 1..=max_hops {
+      let mut layer: Layer = VertexMap::new();
+      for from in vertices.iter() {
+        let from_rate = match layers[hops - 1].get(*from) {
+          Some((rate, _)) => *rate,
+          None => continue
+        };
+        for (to, edge) in self.graph.edges_from(*from) {
+          let rate = from_rate * edge.get_weight();
+          if layer.get(to).is_none_or(|(best_rate, _)| rate > *best_rate) {
+            layer.insert(to.clone(), (rate, Some((*from).clone())));
+          }
+        }
+      }
+      if layer.is_empty() {
+        break;
+      }
+      let rate = layer.get(to_vertex).map_or(0.0, |(rate, _)| *rate);
+      layers.push(layer);
+      if rate > 0.0 && front.last().is_none_or(|(_, best_rate, _)| rate > *best_rate) {
+        let mut path = vec![to_vertex.clone()];
+        for k in (1..=hops).rev() {
+          let previous = layers[k].get(&path[path.len() - 1]).unwrap().1.clone().unwrap();
+          path.push(previous);
+        }
+        path.reverse();
+        front.push((hops, rate, path));
+      }
+    }
+    front
+  }
+
+  // The `max_paths` best paths from `from_vertex` to `to_vertex` that visit a vertex at most once and have at most
+  // `max_hops` hops, best first, as (rate, path). Paths whose rate is not a finite number, e.g. through a NaN
+  // quote, are left out
+  // Runtime: exponential in `max_hops`, as every such path is enumerated
+  pub fn top_paths(&self, from_vertex: &Arc<Vertex>, to_vertex: &Arc<Vertex>, max_paths: usize, max_hops: usize)
+    -> Vec<(f64, Vec<Arc<Vertex>>)>
+  {
+    let mut paths = Vec::new();
+    self.collect_paths(to_vertex, max_hops, &mut vec![from_vertex.clone()], 1.0, &mut paths);
+    paths.sort_by(|(rate, path), (other_rate, other_path)| {
+      other_rate.total_cmp(rate)
+        .then_with(|| path.len().cmp(&other_path.len()))
+        .then_with(|| path.cmp(other_path))
+    });
+    paths.truncate(max_paths);
+    paths
+  }
+
+  fn collect_paths(&self, to_vertex: &Arc<Vertex>, max_hops: usize, path: &mut Vec<Arc<Vertex>>, rate: f64,
+    paths: &mut Vec<(f64, Vec<Arc<Vertex>>)>
+  ) {
+    let last = path[path.len() - 1].clone();
+    if last == *to_vertex {
+      if rate.is_finite() {
+        paths.push((rate, path.clone()));
+      }
+      return;
+    }
+    if path.len() > max_hops {
+      return;
+    }
+    let hops: Vec<(Arc<Vertex>, f64)> = self.graph.edges_from(&last)
+      .filter(|(next, _)| !path.contains(next))
+      .map(|(next, edge)| (next.clone(), edge.get_weight()))
+      .collect();
+    for (next, weight) in hops {
+      path.push(next);
+      self.collect_paths(to_vertex, max_hops, path, rate * weight, paths);
+      path.pop();
     }
-    Some(path)
   }
+}
 
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn display_model() {
+    let mut price_update = PriceUpdate::new(1509529343000, Exchange::new("KRAKEN").unwrap(),
+      Currency::new("BTC").unwrap(), Currency::new("USD").unwrap(), 1000.0, 0.0009);
+    assert_eq!(price_update.to_string(), "2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000 0.0009");
+    price_update.set_backward_derived(true);
+    assert_eq!(price_update.to_string(), "2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000");
+    let inverted = price_update.clone().invert();
+    assert!(inverted.is_forward_derived() && !inverted.is_backward_derived());
+    assert_eq!((inverted.get_source_currency(), inverted.get_forward_ratio()), ("USD", 0.0009));
+    assert_eq!(inverted.to_string(), price_update.to_string());
+    assert_eq!(price_update.get_source_vertex().to_string(), "<KRAKEN, BTC>");
+    assert_eq!(EdgeWeight::new(1000.0, 1509529343000).to_string(), "1000 UPDATED 2017-11-01T09:42:23+00:00");
+    price_update.set_sub_millis(Precision::Micros.truncate(456_789));
+    assert_eq!(price_update.to_string(), "2017-11-01T09:42:23.000456+00:00 KRAKEN BTC USD 1000");
+
+    let mut exchange_rate_request = ExchangeRateRequest::new(Exchange::new("KRAKEN").unwrap(),
+      Currency::new("BTC").unwrap(), Exchange::new("GDAX").unwrap(), Currency::new("USD").unwrap(), Some(300000));
+    exchange_rate_request.set_pareto(true);
+    exchange_rate_request.set_avoid(vec![Currency::new("ETH").unwrap(), Currency::new("XRP").unwrap()]);
+    exchange_rate_request.set_partition(Some("crypto".to_string()));
+    exchange_rate_request.set_request_id(Some("q-42".to_string()));
+    assert_eq!(exchange_rate_request.to_string(),
+      "EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD WINDOW 300000ms PARETO AVOID ETH AVOID XRP PARTITION crypto ID q-42");
+  }
+
+  #[test]
+  fn order_vertices() {
+    let vertex = |exchange: &str, currency: &str| Vertex::new(Exchange::new(exchange).unwrap(),
+      Currency::new(currency).unwrap());
+    let mut vertices = [vertex("KRAKEN", "BTC"), vertex("GDAX", "USD"), vertex("GDAX", "BTC")];
+    vertices.sort();
+    assert_eq!(vertices.iter().map(Vertex::to_string).collect::<Vec<_>>(), ["<GDAX, BTC>", "<GDAX, USD>", "<KRAKEN, BTC>"]);
+  }
+
+  #[test]
+  fn composite_weights() {
+    let vertex = |currency: &str| Arc::new(Vertex::new(Exchange::new("KRAKEN").unwrap(),
+      Currency::new(currency).unwrap()));
+    let (btc, eth, usd) = (vertex("BTC"), vertex("ETH"), vertex("USD"));
+    // BTC -> ETH -> USD is as good a rate as BTC -> USD but takes one more hop
+    let mut graph_result: GraphResult<(f64, usize)> = GraphResult::new();
+    graph_result.add_edge_weight(btc.clone(), eth.clone(), (2.0, 1), 0, 0, false);
+    graph_result.add_edge_weight(eth.clone(), usd.clone(), (500.0, 1), 0, 0, false);
+    graph_result.add_edge_weight(btc.clone(), usd.clone(), (1000.0, 1), 0, 0, false);
+    let vertices: VertexSet = vec![btc.clone(), eth.clone(), usd.clone()].into_iter().collect();
+    graph_result.find_best_rates(&vertices);
+    assert_eq!(graph_result.get_best_weight(&btc, &usd), Some((1000.0, 1)));
+    assert!(graph_result.best_rate_path(&btc, &usd) == Some(vec![btc.clone(), usd.clone()]));
+    assert_eq!(graph_result.get_best_weight(&usd, &btc), None);
+  }
+
+  #[test]
+  fn list_top_paths() {
+    let vertex = |currency: &str| Arc::new(Vertex::new(Exchange::new("KRAKEN").unwrap(),
+      Currency::new(currency).unwrap()));
+    let (btc, eth, eur, usd) = (vertex("BTC"), vertex("ETH"), vertex("EUR"), vertex("USD"));
+    let mut graph_result: GraphResult = GraphResult::new();
+    graph_result.add_edge_weight(btc.clone(), usd.clone(), 1000.0, 0, 0, false);
+    graph_result.add_edge_weight(btc.clone(), eth.clone(), 2.0, 0, 0, false);
+    graph_result.add_edge_weight(eth.clone(), usd.clone(), 490.0, 0, 0, false);
+    graph_result.add_edge_weight(btc.clone(), eur.clone(), 900.0, 0, 0, false);
+    graph_result.add_edge_weight(eur.clone(), usd.clone(), 1.1, 0, 0, false);
+    graph_result.add_edge_weight(usd.clone(), btc.clone(), 0.0009, 0, 0, false);
+    let paths = graph_result.top_paths(&btc, &usd, 2, 2);
+    assert_eq!(paths.len(), 2);
+    assert!(paths[0] == (1000.0, vec![btc.clone(), usd.clone()]));
+    assert!(paths[1].1 == vec![btc.clone(), eur.clone(), usd.clone()]);
+    assert!(graph_result.top_paths(&btc, &usd, 5, 1) == vec![(1000.0, vec![btc.clone(), usd.clone()])]);
+
+    // A NaN quote leaves out the paths through it
+    graph_result.add_edge_weight(btc.clone(), eth.clone(), f64::NAN, 1, 0, false);
+    let paths = graph_result.top_paths(&btc, &usd, 5, 2);
+    assert_eq!(paths.len(), 2);
+    assert!(paths.iter().all(|(rate, path)| rate.is_finite() && !path.contains(&eth)));
+  }
+}
@@ -0,0 +1,398 @@
+// Formatting of exchange rate request answers
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::model::{ParetoRoute, Vertex};
+
+#[derive(Clone, Copy)]
+pub enum OutputFormat {
+    // BEST_RATES_BEGIN ... BEST_RATES_END blocks
+    Text,
+    // One JSON object per line
+    Json
+}
+
+impl OutputFormat {
+    pub fn parse(format: &str) -> Option<OutputFormat> {
+        match format {
+            "text" => Some(OutputFormat::Text),
+            "json" => Some(OutputFormat::Json),
+            _ => None
+        }
+    }
+}
+
+// Why a path was chosen: the rate of every hop, and the best alternative that was rejected
+pub struct Explanation {
+    // (from, to, rate) of every hop of the chosen path
+    hops: Vec<(Arc<Vertex>, Arc<Vertex>, f64)>,
+    // best rate and path that avoids at least one hop of the chosen path
+    next_best: Option<(f64, Vec<Arc<Vertex>>)>
+}
+
+impl Explanation {
+    pub fn new(hops: Vec<(Arc<Vertex>, Arc<Vertex>, f64)>, next_best: Option<(f64, Vec<Arc<Vertex>>)>) -> Explanation {
+        Explanation { hops, next_best }
+    }
+
+    // Percentage by which the next best rate is worse than `best_rate`
+    fn worse_by(next_best_rate: f64, best_rate: f64) -> f64 {
+        (best_rate - next_best_rate) / best_rate * 100.0
+    }
+
+    fn format_text(&self, best_rate: f64) -> String {
+        let mut output = "EXPLAIN_BEGIN\n".to_string();
+        let mut product = 1.0;
+        for (from_vertex, to_vertex, rate) in &self.hops {
+            product *= rate;
+            output.push_str(&format!("HOP {} -> {} RATE {} PRODUCT {}\n", from_vertex, to_vertex, rate, product));
+        }
+        match &self.next_best {
+            Some((next_best_rate, path)) => {
+                output.push_str(&format!("NEXT_BEST {} WORSE_BY {}%\n", next_best_rate,
+                    Explanation::worse_by(*next_best_rate, best_rate)));
+                for vertex in path {
+                    output.push_str(&format!("{}\n", vertex));
+                }
+            },
+            None => output.push_str("NEXT_BEST NONE\n")
+        }
+        output.push_str("EXPLAIN_END\n");
+        output
+    }
+
+    fn format_json(&self, best_rate: f64) -> String {
+        let mut product = 1.0;
+        let hops: Vec<String> = self.hops.iter()
+            .map(|(from_vertex, to_vertex, rate)| {
+                product *= rate;
+                format!("{{\"from\":{},\"to\":{},\"rate\":{},\"product\":{}}}", json_vertex(from_vertex),
+                    json_vertex(to_vertex), rate, product)
+            })
+            .collect();
+        let next_best = match &self.next_best {
+            Some((next_best_rate, path)) => format!("{{\"rate\":{},\"path\":{},\"worse_by_percent\":{}}}",
+                next_best_rate, json_path(path), Explanation::worse_by(*next_best_rate, best_rate)),
+            None => "null".to_string()
+        };
+        format!("{{\"hops\":[{}],\"next_best\":{}}}", hops.join(","), next_best)
+    }
+}
+
+// Quote `value` as a JSON string
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn json_vertex(vertex: &Vertex) -> String {
+    format!("{{\"exchange\":{},\"currency\":{}}}", json_string(vertex.get_exchange()), json_string(vertex.get_currency()))
+}
+
+fn json_path(path: &[Arc<Vertex>]) -> String {
+    let vertices: Vec<String> = path.iter().map(|vertex| json_vertex(vertex)).collect();
+    format!("[{}]", vertices.join(","))
+}
+
+// Fees paid along the chosen path when routing net of fees
+pub struct FeeBreakdown {
+    // rate of the path before fees
+    gross_rate: f64,
+    // amount of the source currency traded
+    notional: f64
+}
+
+impl FeeBreakdown {
+    pub fn new(gross_rate: f64, notional: f64) -> FeeBreakdown {
+        FeeBreakdown { gross_rate, notional }
+    }
+
+    // Total fees in the destination currency
+    fn total_fees(&self, net_rate: f64) -> f64 {
+        self.notional * (self.gross_rate - net_rate)
+    }
+}
+
+// (amount, output, path) of the part of a conversion routed through a path
+pub type SplitAllocation = (f64, f64, Vec<Arc<Vertex>>);
+
+// A conversion split across the best paths, see `split::split`
+pub struct Split {
+    // amount of the source currency to convert
+    amount: f64,
+    // part of the amount that the markets on the paths are too shallow to convert
+    unfilled: f64,
+    // paths given part of the amount, in the order of their rates
+    allocations: Vec<SplitAllocation>
+}
+
+impl Split {
+    pub fn new(amount: f64, unfilled: f64, allocations: Vec<SplitAllocation>) -> Split {
+        Split { amount, unfilled, allocations }
+    }
+
+    // Total amount of the destination currency
+    pub fn output(&self) -> f64 {
+        self.allocations.iter().map(|(_, output, _)| output).sum()
+    }
+
+    pub fn get_allocations(&self) -> &[SplitAllocation] {
+        &self.allocations
+    }
+
+    fn format_text(&self) -> String {
+        let mut output = format!("SPLIT_BEGIN AMOUNT {} OUTPUT {} UNFILLED {}\n", self.amount, self.output(),
+            self.unfilled);
+        for (amount, allocation_output, path) in &self.allocations {
+            output.push_str(&format!("ALLOCATION {} OUTPUT {}\n", amount, allocation_output));
+            for vertex in path {
+                output.push_str(&format!("{}\n", vertex));
+            }
+        }
+        output.push_str("SPLIT_END\n");
+        output
+    }
+
+    fn format_json(&self) -> String {
+        let allocations: Vec<String> = self.allocations.iter()
+            .map(|(amount, output, path)| {
+                format!("{{\"amount\":{},\"output\":{},\"path\":{}}}", amount, output, json_path(path))
+            })
+            .collect();
+        format!("{{\"amount\":{},\"output\":{},\"unfilled\":{},\"allocations\":[{}]}}", self.amount, self.output(),
+            self.unfilled, allocations.join(","))
+    }
+}
+
+// The answer to an exchange rate request
+pub struct RateResult {
+    from_vertex: Arc<Vertex>,
+    to_vertex: Arc<Vertex>,
+    // 0 when there is no route, or when it is unbounded
+    best_rate: f64,
+    path: Option<Vec<Arc<Vertex>>>,
+    // graph version the answer was computed at
+    version: u64,
+    // only achieved by chaining quotes, no exchange quotes the pair directly
+    synthetic: bool,
+    // a hop uses a rate derived from the quote in the other direction
+    derived: bool,
+    // milliseconds since the oldest quote on the path was updated, when older than the stale threshold
+    stale_age: Option<u64>,
+    // product of the confidences in the quotes of the path, when reported
+    confidence: Option<f64>,
+    // the rate grows without bound by going around an arbitrage cycle, so no route is the best
+    unbounded: bool,
+    fees: Option<FeeBreakdown>,
+    explanation: Option<Explanation>,
+    // best route for each number of hops that beats every shorter route
+    pareto_front: Option<Vec<ParetoRoute>>,
+    split: Option<Split>
+}
+
+impl RateResult {
+    pub fn new(from_vertex: Arc<Vertex>, to_vertex: Arc<Vertex>, best_rate: f64, path: Option<Vec<Arc<Vertex>>>,
+        version: u64
+    ) -> RateResult {
+        RateResult {
+            from_vertex, to_vertex, best_rate, path, version, synthetic: false, derived: false, stale_age: None,
+            confidence: None, unbounded: false, fees: None, explanation: None, pareto_front: None, split: None
+        }
+    }
+
+    pub fn get_path(&self) -> Option<&[Arc<Vertex>]> {
+        self.path.as_deref()
+    }
+
+    pub fn set_synthetic(&mut self, synthetic: bool) {
+        self.synthetic = synthetic;
+    }
+
+    pub fn set_derived(&mut self, derived: bool) {
+        self.derived = derived;
+    }
+
+    pub fn set_stale_age(&mut self, stale_age: Option<u64>) {
+        self.stale_age = stale_age;
+    }
+
+    pub fn set_confidence(&mut self, confidence: Option<f64>) {
+        self.confidence = confidence;
+    }
+
+    pub fn set_unbounded(&mut self, unbounded: bool) {
+        self.unbounded = unbounded;
+    }
+
+    pub fn set_fees(&mut self, fees: FeeBreakdown) {
+        self.fees = Some(fees);
+    }
+
+    pub fn set_explanation(&mut self, explanation: Explanation) {
+        self.explanation = Some(explanation);
+    }
+
+    pub fn set_pareto_front(&mut self, pareto_front: Vec<ParetoRoute>) {
+        self.pareto_front = Some(pareto_front);
+    }
+
+    pub fn set_split(&mut self, split: Split) {
+        self.split = Some(split);
+    }
+}
+
+// The text answer: a `BEST_RATES_BEGIN ... BEST_RATES_END` block, then the `PARETO`, `SPLIT` and `EXPLAIN` blocks if
+// any
+impl fmt::Display for RateResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (from_vertex, to_vertex) = (&self.from_vertex, &self.to_vertex);
+        let fees = self.fees.as_ref().map_or(String::new(), |fees| {
+            format!(" GROSS {} FEES {}", fees.gross_rate, fees.total_fees(self.best_rate))
+        });
+        let stale = self.stale_age.map_or(String::new(), |age| format!(" STALE_DATA age={}", format_age(age)));
+        let confidence = self.confidence.map_or(String::new(), |confidence| format!(" CONFIDENCE {:.3}", confidence));
+        writeln!(f, "BEST_RATES_BEGIN {} {} {} {} {}{}{}{}{}{}{} VERSION {}",
+            from_vertex.get_exchange(), from_vertex.get_currency(), to_vertex.get_exchange(), to_vertex.get_currency(),
+            self.best_rate, fees, if self.synthetic { " SYNTHETIC" } else { "" },
+            if self.derived { " DERIVED" } else { "" }, stale, confidence,
+            if self.unbounded { " UNBOUNDED via=arbitrage_cycle" } else { "" }, self.version
+        )?;
+        for vertex in self.get_path().unwrap_or_default() {
+            writeln!(f, "{}", vertex)?;
+        }
+        writeln!(f, "BEST_RATES_END")?;
+        if let Some(pareto_front) = &self.pareto_front {
+            writeln!(f, "PARETO_BEGIN")?;
+            for (hops, rate, path) in pareto_front {
+                writeln!(f, "HOPS {} RATE {}", hops, rate)?;
+                for vertex in path {
+                    writeln!(f, "{}", vertex)?;
+                }
+            }
+            writeln!(f, "PARETO_END")?;
+        }
+        if let Some(split) = &self.split {
+            f.write_str(&split.format_text())?;
+        }
+        if let Some(explanation) = &self.explanation {
+            f.write_str(&explanation.format_text(self.best_rate))?;
+        }
+        Ok(())
+    }
+}
+
+// Age in the largest whole unit, rounded down, e.g. `42m` for 42.5 minutes
+fn format_age(millis: u64) -> String {
+    let units = [("d", 24 * 60 * 60 * 1000), ("h", 60 * 60 * 1000), ("m", 60 * 1000), ("s", 1000)];
+    match units.iter().find(|(_, unit_millis)| millis >= *unit_millis) {
+        Some((unit, unit_millis)) => format!("{}{}", millis / unit_millis, unit),
+        None => format!("{}ms", millis)
+    }
+}
+
+// `answer` formatted by `format_best_rates`, with the request ID given by the client if any: ` ID <request_id>` at the
+// end of the `BEST_RATES_BEGIN` line, or a leading `request_id` field in JSON
+pub fn echo_request_id(format: OutputFormat, mut answer: String, request_id: Option<&str>) -> String {
+    if let Some(request_id) = request_id {
+        match format {
+            OutputFormat::Text => {
+                let end_of_line = answer.find('\n').unwrap_or(answer.len());
+                answer.insert_str(end_of_line, &format!(" ID {}", request_id));
+            },
+            OutputFormat::Json => answer.insert_str(1, &format!("\"request_id\":{},", json_string(request_id)))
+        }
+    }
+    answer
+}
+
+// Format the answer to an exchange rate request
+pub fn format_best_rates(format: OutputFormat, rate_result: &RateResult) -> String {
+    let (from_vertex, to_vertex) = (&rate_result.from_vertex, &rate_result.to_vertex);
+    match format {
+        OutputFormat::Text => rate_result.to_string(),
+        OutputFormat::Json => {
+            let path = rate_result.get_path().map_or("null".to_string(), json_path);
+            let fees = rate_result.fees.as_ref().map_or(String::new(), |fees| {
+                format!(",\"gross_rate\":{},\"notional\":{},\"fees\":{}", fees.gross_rate, fees.notional,
+                    fees.total_fees(rate_result.best_rate))
+            });
+            let explain = rate_result.explanation.as_ref().map_or(String::new(), |explanation| {
+                format!(",\"explain\":{}", explanation.format_json(rate_result.best_rate))
+            });
+            let stale = rate_result.stale_age.map_or(String::new(), |age| format!(",\"stale_age_ms\":{}", age));
+            let confidence = rate_result.confidence
+                .map_or(String::new(), |confidence| format!(",\"confidence\":{}", confidence));
+            let unbounded = if rate_result.unbounded { ",\"unbounded\":\"arbitrage_cycle\"" } else { "" };
+            let pareto = rate_result.pareto_front.as_ref().map_or(String::new(), |pareto_front| {
+                let routes: Vec<String> = pareto_front.iter()
+                    .map(|(hops, rate, path)| format!("{{\"hops\":{},\"rate\":{},\"path\":{}}}", hops, rate, json_path(path)))
+                    .collect();
+                format!(",\"pareto\":[{}]", routes.join(","))
+            });
+            let split = rate_result.split.as_ref()
+                .map_or(String::new(), |split| format!(",\"split\":{}", split.format_json()));
+            format!("{{\"source_exchange\":{},\"source_currency\":{},\"dest_exchange\":{},\"dest_currency\":{},\"rate\":{}{},\"path\":{},\"synthetic\":{},\"derived\":{}{}{}{},\"version\":{}{}{}{}}}\n",
+                json_string(from_vertex.get_exchange()), json_string(from_vertex.get_currency()),
+                json_string(to_vertex.get_exchange()), json_string(to_vertex.get_currency()),
+                rate_result.best_rate, fees, path, rate_result.synthetic, rate_result.derived, stale,
+                confidence, unbounded, rate_result.version, pareto, split,
+                explain)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Currency, Exchange};
+
+    #[test]
+    fn format_json() {
+        let path = vec![Arc::new(Vertex::new(Exchange::new("KRAKEN").unwrap(), Currency::new("BTC").unwrap())),
+            Arc::new(Vertex::new(Exchange::new("GDAX").unwrap(), Currency::new("USD").unwrap()))];
+        let mut rate_result = RateResult::new(path[0].clone(), path[1].clone(), 1000.5, Some(path), 3);
+        rate_result.set_fees(FeeBreakdown::new(1002.5, 2.0));
+        assert_eq!(format_best_rates(OutputFormat::Json, &rate_result),
+            "{\"source_exchange\":\"KRAKEN\",\"source_currency\":\"BTC\",\"dest_exchange\":\"GDAX\",\"dest_currency\":\"USD\",\
+\"rate\":1000.5,\"gross_rate\":1002.5,\"notional\":2,\"fees\":4,\"path\":[{\"exchange\":\"KRAKEN\",\"currency\":\"BTC\"},{\"exchange\":\"GDAX\",\"currency\":\"USD\"}],\"synthetic\":false,\"derived\":false,\"version\":3}\n");
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+
+        rate_result.set_stale_age(Some(42 * 60 * 1000 + 30 * 1000));
+        assert!(format_best_rates(OutputFormat::Json, &rate_result).contains("\"derived\":false,\"stale_age_ms\":2550000,"));
+        assert!(rate_result.to_string().starts_with("BEST_RATES_BEGIN KRAKEN BTC GDAX USD 1000.5 GROSS 1002.5 FEES 4 \
+STALE_DATA age=42m VERSION 3\n"));
+        rate_result.set_confidence(Some(0.8124));
+        assert!(format_best_rates(OutputFormat::Json, &rate_result).contains("\"stale_age_ms\":2550000,\"confidence\":0.8124,"));
+        assert!(rate_result.to_string().contains(" STALE_DATA age=42m CONFIDENCE 0.812 VERSION 3\n"));
+
+        let answer = format_best_rates(OutputFormat::Text, &rate_result);
+        assert!(echo_request_id(OutputFormat::Text, answer.clone(), Some("q-1")).starts_with(&format!("{} ID q-1\n",
+            answer.lines().next().unwrap())));
+        assert_eq!(echo_request_id(OutputFormat::Text, answer.clone(), None), answer);
+        assert!(echo_request_id(OutputFormat::Json, format_best_rates(OutputFormat::Json, &rate_result), Some("q\"1"))
+            .starts_with("{\"request_id\":\"q\\\"1\",\"source_exchange\":\"KRAKEN\","));
+
+        let mut unbounded = RateResult::new(rate_result.from_vertex.clone(), rate_result.to_vertex.clone(), 0.0, None, 4);
+        unbounded.set_unbounded(true);
+        assert_eq!(unbounded.to_string(),
+            "BEST_RATES_BEGIN KRAKEN BTC GDAX USD 0 UNBOUNDED via=arbitrage_cycle VERSION 4\nBEST_RATES_END\n");
+        assert!(format_best_rates(OutputFormat::Json, &unbounded)
+            .contains("\"path\":null,\"synthetic\":false,\"derived\":false,\"unbounded\":\"arbitrage_cycle\",\"version\":4"));
+        assert_eq!((format_age(500), format_age(90 * 1000), format_age(3 * 24 * 60 * 60 * 1000)),
+            ("500ms".to_string(), "1m".to_string(), "3d".to_string()));
+    }
+}
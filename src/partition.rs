@@ -0,0 +1,66 @@
+// Partitions of the currencies, e.g. fiat-only or crypto-only, that a request can confine its route to, for when
+// regulation forbids bridging through some assets
+
+use std::collections::{HashMap, HashSet};
+
+use crate::alias::Aliases;
+use crate::constants;
+use crate::input;
+
+#[derive(Clone, Default)]
+pub struct Partitions {
+    // currencies of each partition by name, besides `ALL_PARTITION` which has every currency
+    currencies: HashMap<String, HashSet<String>>
+}
+
+impl Partitions {
+    // Only `ALL_PARTITION`
+    pub fn new() -> Partitions {
+        Partitions::default()
+    }
+
+    /// Parse partitions where each line is `<name> <currency> [<currency> ...]`, with currencies normalized with
+    /// `aliases`. A name listed on several lines has the currencies of all of them
+    pub fn parse(content: &str, aliases: &Aliases) -> Result<Partitions, String> {
+        let mut partitions = Partitions::new();
+        for line in input::content_lines(content) {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.len() < 2 {
+                return Err(format!("Invalid partition \"{}\"", line));
+            }
+            if tokens[0] == constants::ALL_PARTITION {
+                return Err(format!("Partition {} is built in and has every currency", constants::ALL_PARTITION));
+            }
+            partitions.currencies.entry(tokens[0].to_string()).or_default()
+                .extend(tokens[1..].iter().map(|currency| aliases.currency(currency).to_string()));
+        }
+        Ok(partitions)
+    }
+
+    pub fn is_defined(&self, name: &str) -> bool {
+        name == constants::ALL_PARTITION || self.currencies.contains_key(name)
+    }
+
+    // Whether partition `name` has `currency`, false for partitions that are not defined
+    pub fn contains(&self, name: &str, currency: &str) -> bool {
+        name == constants::ALL_PARTITION
+            || self.currencies.get(name).is_some_and(|currencies| currencies.contains(currency))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_partitions() {
+        let partitions = Partitions::parse("fiat USD EUR\ncrypto BTC ETH\nfiat GBP\n", &Aliases::none()).unwrap();
+        assert!(partitions.is_defined("fiat") && partitions.is_defined(constants::ALL_PARTITION));
+        assert!(!partitions.is_defined("stablecoin"));
+        assert!(partitions.contains("fiat", "GBP") && partitions.contains("crypto", "BTC"));
+        assert!(!partitions.contains("fiat", "BTC") && !partitions.contains("stablecoin", "USDT"));
+        assert!(partitions.contains(constants::ALL_PARTITION, "USDT"));
+        assert!(Partitions::parse("fiat\n", &Aliases::none()).is_err());
+        assert!(Partitions::parse("all USD\n", &Aliases::none()).is_err());
+    }
+}
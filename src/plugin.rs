@@ -0,0 +1,191 @@
+// Feed and sink adapters kept outside this repository: sources of input lines for URLs of their own scheme, and
+// sinks for changes to the best rate of watched pairs, registered in-process or loaded from dynamic libraries
+
+use std::io::{self, BufRead};
+use std::sync::{Arc, Mutex};
+
+use crate::bus::{Event, Subscriber};
+use crate::watch::RatePoint;
+
+// Incremented whenever `Source`, `Sink` or `Registry` change, a plugin built against another version is refused
+pub const API_VERSION: u32 = 1;
+
+// A feed of input lines, read from `<scheme>://...` input URLs
+pub trait Source: Send + Sync {
+    // Scheme of the URLs the source reads, without `://`
+    fn scheme(&self) -> &str;
+
+    // Open `url` to read input lines from byte `offset`, after a read error or when resuming from a checkpoint
+    // Sources that cannot resume ignore `offset`, like a broker channel
+    fn open(&self, url: &str, offset: u64) -> io::Result<Box<dyn BufRead + Send>>;
+}
+
+// A destination of changes to the best rate of watched pairs
+pub trait Sink: Send {
+    fn publish(&mut self, rate_point: &RatePoint) -> Result<(), String>;
+
+    // Deliver anything still buffered, at the end of the run
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl Subscriber for Box<dyn Sink> {
+    fn handle(&mut self, event: &Event) -> Result<(), String> {
+        match event {
+            Event::Result(rate_point) => self.publish(rate_point),
+            _ => Ok(())
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        Sink::flush(self.as_mut())
+    }
+}
+
+// Creates a sink from the argument of `--sink <name>:<argument>`, empty without one
+type SinkFactory = dyn Fn(&str) -> Result<Box<dyn Sink>, String> + Send + Sync;
+
+// The sources and sinks plugins registered
+pub struct Registry {
+    sources: Vec<Arc<dyn Source>>,
+    sinks: Vec<(String, Arc<SinkFactory>)>
+}
+
+impl Registry {
+    const fn new() -> Registry {
+        Registry { sources: Vec::new(), sinks: Vec::new() }
+    }
+
+    // A source registered later takes over the scheme of an earlier one
+    pub fn register_source<S: Source + 'static>(&mut self, source: S) {
+        self.sources.retain(|registered| registered.scheme() != source.scheme());
+        self.sources.push(Arc::new(source));
+    }
+
+    // A sink registered later takes over the name of an earlier one
+    pub fn register_sink<F>(&mut self, name: &str, factory: F)
+        where F: Fn(&str) -> Result<Box<dyn Sink>, String> + Send + Sync + 'static {
+        self.sinks.retain(|(registered, _)| registered != name);
+        self.sinks.push((name.to_string(), Arc::new(factory)));
+    }
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::new());
+
+// Register adapters compiled into the program, e.g. by a binary embedding the library
+pub fn register<F: FnOnce(&mut Registry)>(register: F) {
+    register(&mut REGISTRY.lock().unwrap());
+}
+
+// The source registered for the scheme of `url`, if any
+pub fn find_source(url: &str) -> Option<Arc<dyn Source>> {
+    let scheme = &url[..url.find("://")?];
+    REGISTRY.lock().unwrap().sources.iter().find(|source| source.scheme() == scheme).cloned()
+}
+
+// Create the sink named by `<name>` or `<name>:<argument>`
+pub fn create_sink(spec: &str) -> Result<Box<dyn Sink>, String> {
+    let (name, argument) = spec.split_once(':').unwrap_or((spec, ""));
+    let factory = REGISTRY.lock().unwrap().sinks.iter()
+        .find(|(registered, _)| registered == name)
+        .map(|(_, factory)| factory.clone())
+        .ok_or_else(|| format!("No sink named {} was registered by a plugin", name))?;
+    factory(argument)
+}
+
+// Define the entry points `load` looks for in a plugin, which is a `cdylib` crate depending on this library
+// `$register` is a `fn(&mut Registry)` registering the adapters of the plugin, e.g.
+// `tenx_challenge::export_plugin!(register);`
+#[macro_export]
+macro_rules! export_plugin {
+    ($register:path) => {
+        #[no_mangle]
+        pub fn tenx_plugin_api_version() -> u32 {
+            $crate::plugin::API_VERSION
+        }
+
+        #[no_mangle]
+        pub fn tenx_plugin_register(registry: &mut $crate::plugin::Registry) {
+            $register(registry)
+        }
+    };
+}
+
+// Load the plugin in the dynamic library `file_name` and register its adapters. The library stays loaded until exit
+// Entry points use the Rust ABI, so plugins must be built with the same compiler and version of this library
+#[cfg(feature = "plugins")]
+pub fn load(file_name: &str) -> Result<(), String> {
+    let error = |e: libloading::Error| format!("Error encountered while loading plugin {}: {}", file_name, e);
+    // Safety: loading runs the initializers of the library, and the entry points are trusted to have the signatures
+    // `export_plugin!` gives them
+    unsafe {
+        let library = libloading::Library::new(file_name).map_err(error)?;
+        let api_version = library.get::<fn() -> u32>(b"tenx_plugin_api_version").map_err(error)?();
+        if api_version != API_VERSION {
+            return Err(format!("Plugin {} was built for plugin API version {}, expected {}", file_name, api_version,
+                API_VERSION));
+        }
+        let register_plugin = library.get::<fn(&mut Registry)>(b"tenx_plugin_register").map_err(error)?;
+        register(*register_plugin);
+        // Registered adapters point into the library
+        std::mem::forget(library);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "plugins"))]
+pub fn load(file_name: &str) -> Result<(), String> {
+    Err(format!("Loading plugin {} requires the `plugins` feature", file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct EchoSource;
+
+    impl Source for EchoSource {
+        fn scheme(&self) -> &str {
+            "echo"
+        }
+
+        fn open(&self, url: &str, _offset: u64) -> io::Result<Box<dyn BufRead + Send>> {
+            Ok(Box::new(io::Cursor::new(format!("{}\n", &url["echo://".len()..]).into_bytes())))
+        }
+    }
+
+    struct NullSink;
+
+    impl Sink for NullSink {
+        fn publish(&mut self, _rate_point: &RatePoint) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    fn register_test_plugin(registry: &mut Registry) {
+        registry.register_source(EchoSource);
+        registry.register_sink("null", |argument| match argument {
+            "" => Ok(Box::new(NullSink) as Box<dyn Sink>),
+            _ => Err(format!("Unexpected argument {}", argument))
+        });
+    }
+
+    export_plugin!(register_test_plugin);
+
+    #[test]
+    fn register_adapters() {
+        assert_eq!(tenx_plugin_api_version(), API_VERSION);
+        register(tenx_plugin_register);
+
+        let mut line = String::new();
+        find_source("echo://hello").unwrap().open("echo://hello", 0).unwrap().read_line(&mut line).unwrap();
+        assert_eq!(line, "hello\n");
+        assert!(find_source("unknown://hello").is_none());
+        assert!(find_source("input.txt").is_none());
+
+        assert!(create_sink("null").unwrap().flush().is_ok());
+        assert!(create_sink("null:x").is_err());
+        assert!(create_sink("unknown").is_err());
+    }
+}
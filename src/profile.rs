@@ -0,0 +1,145 @@
+// Sampling profiler of the engine, for attaching performance data to reports about slow inputs
+// The thread running the engine marks what it is doing with nested spans, and a sampler thread records its current
+// stack of spans at a fixed interval. Samples are written in the folded stack format that flamegraph.pl, inferno
+// and speedscope read, e.g. `tenx-challenge;answer;find_best_rates 42`
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle, ThreadId};
+use std::time::Duration;
+
+// Name of the root frame of every sample
+const ROOT: &str = "tenx-challenge";
+
+// Whether a profiler is running, checked before taking the lock so spans cost next to nothing otherwise
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// The profiled thread and its current stack of spans, outermost first
+static STACK: Mutex<Option<(ThreadId, Vec<&'static str>)>> = Mutex::new(None);
+
+// Marks the profiled thread as busy with `name` until dropped, spans of other threads are ignored
+pub struct Span {
+    entered: bool
+}
+
+pub fn span(name: &'static str) -> Span {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Span { entered: false };
+    }
+    match &mut *STACK.lock().unwrap() {
+        Some((thread_id, stack)) if *thread_id == thread::current().id() => {
+            stack.push(name);
+            Span { entered: true }
+        },
+        _ => Span { entered: false }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if self.entered {
+            if let Some((_, stack)) = &mut *STACK.lock().unwrap() {
+                stack.pop();
+            }
+        }
+    }
+}
+
+// Number of samples of every stack of spans, keyed by the folded stack
+pub struct Profile {
+    samples: HashMap<String, u64>
+}
+
+impl Profile {
+    pub fn get_num_samples(&self) -> u64 {
+        self.samples.values().sum()
+    }
+
+    // One `<stack> <count>` line per stack, in the order of the stacks
+    pub fn write_folded<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut stacks: Vec<_> = self.samples.iter().collect();
+        stacks.sort();
+        for (stack, count) in stacks {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+        writer.flush()
+    }
+
+    pub fn save(&self, file_name: &str) -> io::Result<()> {
+        self.write_folded(BufWriter::new(File::create(file_name)?))
+    }
+}
+
+// Samples the spans of the thread that started it until finished, only one profiler runs at a time
+pub struct Profiler {
+    stop: Arc<AtomicBool>,
+    sampler: JoinHandle<HashMap<String, u64>>
+}
+
+impl Profiler {
+    pub fn start(interval: Duration) -> Result<Profiler, String> {
+        {
+            let mut state = STACK.lock().unwrap();
+            if state.is_some() {
+                return Err("A profiler is already running".to_string());
+            }
+            *state = Some((thread::current().id(), Vec::new()));
+        }
+        ENABLED.store(true, Ordering::Relaxed);
+        let stop = Arc::new(AtomicBool::new(false));
+        let sampler_stop = Arc::clone(&stop);
+        let sampler = thread::spawn(move || {
+            let mut samples = HashMap::new();
+            while !sampler_stop.load(Ordering::Relaxed) {
+                thread::sleep(interval);
+                let folded = match &*STACK.lock().unwrap() {
+                    Some((_, stack)) => std::iter::once(ROOT).chain(stack.iter().copied()).collect::<Vec<_>>().join(";"),
+                    None => break
+                };
+                *samples.entry(folded).or_insert(0) += 1;
+            }
+            samples
+        });
+        Ok(Profiler { stop, sampler })
+    }
+
+    pub fn finish(self) -> Profile {
+        self.stop.store(true, Ordering::Relaxed);
+        let samples = self.sampler.join().unwrap_or_default();
+        ENABLED.store(false, Ordering::Relaxed);
+        *STACK.lock().unwrap() = None;
+        Profile { samples }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_spans_of_profiled_thread() {
+        let profiler = Profiler::start(Duration::from_millis(1)).unwrap();
+        assert!(Profiler::start(Duration::from_millis(1)).is_err());
+        {
+            let _outer = span("outer");
+            let _inner = span("inner");
+            // Spans of other threads are left out of the profile
+            thread::spawn(|| {
+                let _other = span("other");
+                thread::sleep(Duration::from_millis(20));
+            }).join().unwrap();
+        }
+        let profile = profiler.finish();
+        assert!(profile.get_num_samples() > 0);
+        let mut folded = Vec::new();
+        profile.write_folded(&mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        assert!(folded.lines().all(|line| line.starts_with("tenx-challenge")));
+        assert!(folded.contains("tenx-challenge;outer;inner "));
+        assert!(!folded.contains("other"));
+        // Spans cost nothing once the profiler is finished
+        assert!(!span("after").entered);
+    }
+}
@@ -0,0 +1,137 @@
+// Holding back price updates that deviate wildly from the recent quotes of their market, e.g. a fat-fingered tick,
+// instead of rerouting the whole graph through them. A held update is released once the next update of the market
+// confirms the new level, and dropped if the market goes back to its usual level instead
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::constants;
+use crate::model::PriceUpdate;
+
+// What to do with a price update
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    // In line with the recent quotes of its market, or the market has too few of them to tell
+    Accept,
+    // Deviates from the recent quotes of its market by more than the multiple, held back
+    Quarantine,
+    // Deviates like the update held back before it, so the market did move: the held update is released, and this
+    // one, being newer, is applied
+    Confirm,
+    // In line with the recent quotes of its market, unlike the update held back before it, which is dropped
+    Reject
+}
+
+pub struct Quarantine {
+    // an update whose rate is more than `multiple` times, or less than 1 / `multiple` times, the median of the recent
+    // rates of its market is held back
+    multiple: f64,
+    // latest rates of every market, (exchange, currency, currency) with the currencies in alphabetical order
+    history: HashMap<(String, String, String), VecDeque<f64>>,
+    // rate of the update held back for each market
+    held: HashMap<(String, String, String), f64>,
+    num_quarantined: u64,
+    num_released: u64
+}
+
+// The market of `price_update`, and its rate from the first currency of the market to the second one
+fn market_rate(price_update: &PriceUpdate) -> ((String, String, String), f64) {
+    let (source_currency, dest_currency) = (price_update.get_source_currency(), price_update.get_dest_currency());
+    if source_currency < dest_currency {
+        ((price_update.get_exchange().to_string(), source_currency.to_string(), dest_currency.to_string()),
+            price_update.get_forward_ratio())
+    } else {
+        ((price_update.get_exchange().to_string(), dest_currency.to_string(), source_currency.to_string()),
+            1.0 / price_update.get_forward_ratio())
+    }
+}
+
+impl Quarantine {
+    pub fn new(multiple: f64) -> Quarantine {
+        Quarantine { multiple, history: HashMap::new(), held: HashMap::new(), num_quarantined: 0, num_released: 0 }
+    }
+
+    // Whether `rate` is within `multiple` times of `reference`, either way
+    fn is_in_line(&self, rate: f64, reference: f64) -> bool {
+        rate <= reference * self.multiple && rate >= reference / self.multiple
+    }
+
+    // Judge `price_update` against the recent rates of its market, and remember it unless it is held back
+    pub fn check(&mut self, price_update: &PriceUpdate) -> Verdict {
+        let (market, rate) = market_rate(price_update);
+        let history = self.history.entry(market.clone()).or_default();
+        let median = if history.len() >= constants::QUARANTINE_MIN_HISTORY {
+            let mut rates: Vec<f64> = history.iter().copied().collect();
+            rates.sort_by(f64::total_cmp);
+            Some(rates[rates.len() / 2])
+        } else {
+            None
+        };
+        let verdict = match (median, self.held.get(&market)) {
+            (None, _) => Verdict::Accept,
+            (Some(median), held) if self.is_in_line(rate, median) => {
+                if held.is_some() { Verdict::Reject } else { Verdict::Accept }
+            },
+            (Some(_), Some(&held)) if self.is_in_line(rate, held) => Verdict::Confirm,
+            (Some(_), _) => Verdict::Quarantine
+        };
+
+        let history = self.history.get_mut(&market).unwrap();
+        match verdict {
+            Verdict::Quarantine => {
+                self.held.insert(market, rate);
+                self.num_quarantined += 1;
+                return verdict;
+            },
+            // The market moved, so its earlier rates no longer tell what is usual
+            Verdict::Confirm => {
+                history.clear();
+                history.push_back(self.held.remove(&market).unwrap());
+                self.num_released += 1;
+            },
+            Verdict::Reject => {
+                self.held.remove(&market);
+            },
+            Verdict::Accept => ()
+        }
+        history.push_back(rate);
+        if history.len() > constants::QUARANTINE_HISTORY {
+            history.pop_front();
+        }
+        verdict
+    }
+
+    pub fn get_num_quarantined(&self) -> u64 {
+        self.num_quarantined
+    }
+
+    // Quarantined updates which a later update confirmed
+    pub fn get_num_released(&self) -> u64 {
+        self.num_released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::quote;
+
+    #[test]
+    fn quarantine_outliers() {
+        let mut quarantine = Quarantine::new(1.5);
+        for datetime in 0..3 {
+            assert_eq!(quarantine.check(&quote(datetime, "KRAKEN", "BTC", "USD", 1000.0, 0.0009)), Verdict::Accept);
+        }
+        // Quoted the other way round, in line
+        assert_eq!(quarantine.check(&quote(3, "KRAKEN", "USD", "BTC", 0.00099, 1000.0)), Verdict::Accept);
+        // A fat-fingered tick is held, then dropped when the market quotes its usual level again
+        assert_eq!(quarantine.check(&quote(4, "KRAKEN", "BTC", "USD", 10000.0, 0.00009)), Verdict::Quarantine);
+        assert_eq!(quarantine.check(&quote(5, "KRAKEN", "BTC", "USD", 1001.0, 0.0009)), Verdict::Reject);
+        // A real move is held, then confirmed by the next update, which becomes the new usual level
+        assert_eq!(quarantine.check(&quote(6, "KRAKEN", "BTC", "USD", 2000.0, 0.00045)), Verdict::Quarantine);
+        assert_eq!(quarantine.check(&quote(7, "KRAKEN", "BTC", "USD", 2100.0, 0.00045)), Verdict::Confirm);
+        assert_eq!(quarantine.check(&quote(8, "KRAKEN", "BTC", "USD", 2050.0, 0.00045)), Verdict::Accept);
+        // Other markets have a history of their own
+        assert_eq!(quarantine.check(&quote(9, "GDAX", "BTC", "USD", 10000.0, 0.00009)), Verdict::Accept);
+        assert_eq!((quarantine.get_num_quarantined(), quarantine.get_num_released()), (2, 1));
+    }
+}
@@ -0,0 +1,198 @@
+// Bounded queue of input lines between the reader thread and the main loop
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use crate::constants;
+
+// What the reader does when the queue is full because processing can't keep up
+#[derive(Clone, Copy)]
+pub enum Backpressure {
+    // Wait until there is room
+    Block,
+    // Discard the oldest queued price update
+    DropOldest,
+    // Merge a price update into a queued update of the same edge, waiting when the queue is full and there
+    // is none to merge into
+    Coalesce
+}
+
+impl Backpressure {
+    pub fn parse(policy: &str) -> Option<Backpressure> {
+        match policy {
+            "block" => Some(Backpressure::Block),
+            "drop-oldest" => Some(Backpressure::DropOldest),
+            "coalesce" => Some(Backpressure::Coalesce),
+            _ => None
+        }
+    }
+}
+
+// A line of input and the byte offset following it
+type Line = Result<(String, u64), io::Error>;
+
+struct State {
+    lines: VecDeque<Line>,
+    reader_done: bool,
+    receiver_gone: bool,
+    num_dropped: u64,
+    num_coalesced: u64
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+    capacity: usize,
+    policy: Backpressure
+}
+
+pub(crate) struct LineSender {
+    shared: Arc<Shared>
+}
+
+pub struct LineReceiver {
+    shared: Arc<Shared>
+}
+
+pub(crate) fn channel(capacity: usize, policy: Backpressure) -> (LineSender, LineReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            lines: VecDeque::new(), reader_done: false, receiver_gone: false, num_dropped: 0, num_coalesced: 0
+        }),
+        changed: Condvar::new(),
+        capacity,
+        policy
+    });
+    (LineSender { shared: shared.clone() }, LineReceiver { shared })
+}
+
+// The exchange and currencies of a price update line, in either direction, used to coalesce updates
+fn edge_key(line: &Line) -> Option<(&str, &str, &str)> {
+    let tokens: Vec<&str> = match line {
+        Ok((line, _)) => line.split_whitespace().collect(),
+        Err(_) => return None
+    };
+    let num_tokens = tokens.len() as u32;
+    if (num_tokens != constants::NUM_TOKEN_PRICE_UPDATE && num_tokens != constants::NUM_TOKEN_SHORTHAND_PRICE_UPDATE)
+        || tokens[0] == constants::EXCHANGE_RATE_REQUEST {
+        return None;
+    }
+    Some((tokens[1], tokens[2].min(tokens[3]), tokens[2].max(tokens[3])))
+}
+
+impl LineSender {
+    // Queue a line, applying the backpressure policy when the queue is full
+    // Fails once the receiver is gone
+    pub fn send(&self, line: Line) -> Result<(), ()> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if state.receiver_gone {
+                return Err(());
+            }
+            if let (Backpressure::Coalesce, Some(key)) = (self.shared.policy, edge_key(&line)) {
+                // Only updates queued after the last other line, so that every request still sees the
+                // updates that came before it
+                let position = state.lines.iter().rposition(|queued| edge_key(queued).is_none());
+                let start = position.map_or(0, |position| position + 1);
+                let same_edge = (start..state.lines.len()).find(|&i| edge_key(&state.lines[i]) == Some(key));
+                if let Some(i) = same_edge {
+                    state.lines[i] = line;
+                    state.num_coalesced += 1;
+                    return Ok(());
+                }
+            }
+            if state.lines.len() < self.shared.capacity {
+                state.lines.push_back(line);
+                self.shared.changed.notify_all();
+                return Ok(());
+            }
+            if let Backpressure::DropOldest = self.shared.policy {
+                if let Some(i) = state.lines.iter().position(|queued| edge_key(queued).is_some()) {
+                    state.lines.remove(i);
+                    state.num_dropped += 1;
+                    continue;
+                }
+            }
+            state = self.shared.changed.wait(state).unwrap();
+        }
+    }
+}
+
+impl Drop for LineSender {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().reader_done = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+impl LineReceiver {
+    // Take the next line, like `mpsc::Receiver::recv_timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Line, RecvTimeoutError> {
+        let state = self.shared.state.lock().unwrap();
+        let (mut state, _) = self.shared.changed
+            .wait_timeout_while(state, timeout, |state| state.lines.is_empty() && !state.reader_done)
+            .unwrap();
+        match state.lines.pop_front() {
+            Some(line) => {
+                self.shared.changed.notify_all();
+                Ok(line)
+            },
+            None if state.reader_done => Err(RecvTimeoutError::Disconnected),
+            None => Err(RecvTimeoutError::Timeout)
+        }
+    }
+
+    pub fn get_num_dropped(&self) -> u64 {
+        self.shared.state.lock().unwrap().num_dropped
+    }
+
+    pub fn get_num_coalesced(&self) -> u64 {
+        self.shared.state.lock().unwrap().num_coalesced
+    }
+}
+
+impl Drop for LineReceiver {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receiver_gone = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive_all(receiver: &LineReceiver) -> Vec<String> {
+        let mut lines = Vec::new();
+        while let Ok(Ok((line, _))) = receiver.recv_timeout(Duration::from_millis(0)) {
+            lines.push(line);
+        }
+        lines
+    }
+
+    #[test]
+    fn drop_oldest_price_update() {
+        let (sender, receiver) = channel(2, Backpressure::DropOldest);
+        for line in &["EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD", "1 KRAKEN BTC USD 1000 0.0009",
+            "2 GDAX BTC USD 1001 0.0009"] {
+            sender.send(Ok((line.to_string(), 0))).unwrap();
+        }
+        assert_eq!(receive_all(&receiver), vec!["EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD", "2 GDAX BTC USD 1001 0.0009"]);
+        assert_eq!(receiver.get_num_dropped(), 1);
+    }
+
+    #[test]
+    fn coalesce_updates_of_same_edge() {
+        let (sender, receiver) = channel(10, Backpressure::Coalesce);
+        for line in &["1 KRAKEN BTC USD 1000 0.0009", "2 KRAKEN USD BTC 0.0009 1001",
+            "EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD", "3 KRAKEN BTC USD 1002 0.0009", "4 KRAKEN BTC USD 1003 0.0009"] {
+            sender.send(Ok((line.to_string(), 0))).unwrap();
+        }
+        assert_eq!(receive_all(&receiver), vec!["2 KRAKEN USD BTC 0.0009 1001",
+            "EXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD", "4 KRAKEN BTC USD 1003 0.0009"]);
+        assert_eq!(receiver.get_num_coalesced(), 2);
+    }
+}
@@ -0,0 +1,162 @@
+// Reading input from object stores such as S3 and GCS, and from HTTP(S) URLs
+
+use std::io::{self, BufRead};
+
+// Whether `file_name` names an object in a store rather than a local file
+pub fn is_object_url(file_name: &str) -> bool {
+    file_name.starts_with("s3://") || file_name.starts_with("gs://")
+}
+
+pub fn is_http_url(file_name: &str) -> bool {
+    file_name.starts_with("http://") || file_name.starts_with("https://")
+}
+
+// Whether `file_name` is read from the network rather than a local file
+pub fn is_url(file_name: &str) -> bool {
+    is_object_url(file_name) || is_http_url(file_name)
+}
+
+// Parse a `<name>: <value>` header, e.g. `Authorization: Bearer <token>`
+pub fn parse_header(header: &str) -> Result<(String, String), String> {
+    match header.trim().split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => Err("Invalid header, expected `<name>: <value>`".to_string())
+    }
+}
+
+#[cfg(feature = "object-store")]
+mod store {
+    use std::io::{self, Read};
+
+    use futures::stream::{BoxStream, StreamExt};
+    use object_store::aws::AmazonS3Builder;
+    use object_store::gcp::GoogleCloudStorageBuilder;
+    use object_store::path::Path;
+    use object_store::{GetOptions, GetRange, ObjectStore};
+    use tokio::runtime::{Builder, Runtime};
+
+    // Streams an object chunk by chunk, so large objects are parsed as they download
+    pub struct ObjectReader {
+        runtime: Runtime,
+        stream: BoxStream<'static, object_store::Result<bytes::Bytes>>,
+        chunk: Vec<u8>,
+        // bytes of `chunk` already read
+        position: usize
+    }
+
+    impl Read for ObjectReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            while self.position == self.chunk.len() {
+                match self.runtime.block_on(self.stream.next()) {
+                    Some(Ok(chunk)) => {
+                        self.chunk = chunk.to_vec();
+                        self.position = 0;
+                    },
+                    Some(Err(e)) => return Err(io::Error::other(e)),
+                    None => return Ok(0)
+                }
+            }
+            let num_bytes = buf.len().min(self.chunk.len() - self.position);
+            buf[..num_bytes].copy_from_slice(&self.chunk[self.position..self.position + num_bytes]);
+            self.position += num_bytes;
+            Ok(num_bytes)
+        }
+    }
+
+    // Start reading the object at `url` from byte `offset`. Credentials are taken from the environment,
+    // e.g. `AWS_ACCESS_KEY_ID` or `GOOGLE_APPLICATION_CREDENTIALS`
+    // None when the object still has the ETag in `etag` or has nothing after `offset`
+    pub fn open(url: &str, offset: u64, etag: &mut Option<String>) -> io::Result<Option<ObjectReader>> {
+        let (bucket_url, key) = match url.find("://").and_then(|scheme_end| {
+            url[scheme_end + 3..].find('/').map(|bucket_end| url.split_at(scheme_end + 3 + bucket_end))
+        }) {
+            Some((bucket_url, key)) => (bucket_url, &key[1..]),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Missing object key in {}", url)))
+        };
+        let store: Box<dyn ObjectStore> = if url.starts_with("s3://") {
+            Box::new(AmazonS3Builder::from_env().with_url(bucket_url).build().map_err(io::Error::other)?)
+        } else {
+            Box::new(GoogleCloudStorageBuilder::from_env().with_url(bucket_url).build().map_err(io::Error::other)?)
+        };
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let path = Path::from(key);
+        let meta = runtime.block_on(store.head(&path)).map_err(io::Error::other)?;
+        if (meta.e_tag.is_some() && meta.e_tag == *etag) || (offset > 0 && meta.size <= offset) {
+            *etag = meta.e_tag;
+            return Ok(None);
+        }
+        let options = GetOptions {
+            range: if offset > 0 { Some(GetRange::Offset(offset)) } else { None },
+            ..GetOptions::default()
+        };
+        let result = runtime.block_on(store.get_opts(&path, options)).map_err(io::Error::other)?;
+        *etag = result.meta.e_tag.clone();
+        Ok(Some(ObjectReader { runtime, stream: result.into_stream(), chunk: Vec::new(), position: 0 }))
+    }
+}
+
+// Open the object at an `s3://<bucket>/<key>` or `gs://<bucket>/<key>` URL, reading from byte `offset`
+// `etag` is the ETag of the previous fetch, if any, and is updated. None when there is nothing new to read
+#[cfg(feature = "object-store")]
+pub fn open_object(url: &str, offset: u64, etag: &mut Option<String>) -> io::Result<Option<Box<dyn BufRead + Send>>> {
+    Ok(store::open(url, offset, etag)?.map(|reader| Box::new(io::BufReader::new(reader)) as Box<dyn BufRead + Send>))
+}
+
+#[cfg(not(feature = "object-store"))]
+pub fn open_object(url: &str, _offset: u64, _etag: &mut Option<String>) -> io::Result<Option<Box<dyn BufRead + Send>>> {
+    Err(io::Error::other(format!("reading {} requires the `object-store` feature", url)))
+}
+
+// Fetch `url`, reading the body as it arrives from byte `offset`, with an optional `header` such as credentials
+// `etag` is the ETag of the previous fetch, if any, and is updated. None when there is nothing new to read
+#[cfg(feature = "http")]
+pub fn open_http(url: &str, offset: u64, header: Option<&(String, String)>, etag: &mut Option<String>)
+    -> io::Result<Option<Box<dyn BufRead + Send>>> {
+    use std::io::Read;
+
+    let mut request = ureq::get(url);
+    if let Some((name, value)) = header {
+        request = request.set(name, value);
+    }
+    if offset > 0 {
+        request = request.set("Range", &format!("bytes={}-", offset));
+    }
+    if let Some(etag) = etag {
+        request = request.set("If-None-Match", etag);
+    }
+    let response = match request.call() {
+        Ok(response) if response.status() == 304 => return Ok(None),
+        Ok(response) => response,
+        // Nothing after `offset`
+        Err(ureq::Error::Status(416, _)) => return Ok(None),
+        Err(ureq::Error::Status(status, _)) => return Err(io::Error::other(format!("{} returned HTTP {}", url, status))),
+        Err(e) => return Err(io::Error::other(e))
+    };
+    *etag = response.header("ETag").map(str::to_string);
+    let partial = response.status() == 206;
+    let mut reader = io::BufReader::new(response.into_reader());
+    // The server ignored the range, skip to `offset` instead
+    if offset > 0 && !partial {
+        io::copy(&mut reader.by_ref().take(offset), &mut io::sink())?;
+    }
+    Ok(Some(Box::new(reader)))
+}
+
+#[cfg(not(feature = "http"))]
+pub fn open_http(url: &str, _offset: u64, _header: Option<&(String, String)>, _etag: &mut Option<String>)
+    -> io::Result<Option<Box<dyn BufRead + Send>>> {
+    Err(io::Error::other(format!("reading {} requires the `http` feature", url)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_headers() {
+        assert_eq!(parse_header("Authorization: Bearer abc:def\n"),
+            Ok(("Authorization".to_string(), "Bearer abc:def".to_string())));
+        assert!(parse_header("Bearer abc").is_err());
+        assert!(parse_header(": abc").is_err());
+    }
+}
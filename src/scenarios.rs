@@ -0,0 +1,116 @@
+// Golden-file scenarios: every subdirectory of a scenarios directory holds an `input.txt` and the
+// `expected_output.txt` that processing it with the default options should print
+
+use std::fs;
+use std::path::Path;
+
+use tenx_challenge::{engine, model};
+use tenx_challenge::output::OutputFormat;
+
+pub const INPUT_FILE: &str = "input.txt";
+pub const EXPECTED_OUTPUT_FILE: &str = "expected_output.txt";
+
+// A scenario and the first line where its output differs from the expected output, if any
+pub struct ScenarioResult {
+    name: String,
+    difference: Option<String>
+}
+
+impl ScenarioResult {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_difference(&self) -> Option<&str> {
+        self.difference.as_deref()
+    }
+}
+
+// What `input_file` prints, answering requests like a run without options. Invalid lines are skipped
+pub fn run_scenario(input_file: &str) -> Result<String, String> {
+    let mut engine = engine::Engine::new(OutputFormat::Text);
+    let mut output = String::new();
+    let mut pending_requests = Vec::new();
+    crate::replay(input_file, |input| match input {
+        model::InputType::PriceUpdate(price_update) => {
+            answer(&mut engine, &mut pending_requests, &mut output);
+            engine.apply_price_update(price_update);
+        },
+        model::InputType::ExchangeRateRequest(exchange_rate_request) => pending_requests.push(exchange_rate_request),
+        model::InputType::Invalid(_) => ()
+    })?;
+    answer(&mut engine, &mut pending_requests, &mut output);
+    Ok(output)
+}
+
+fn answer(engine: &mut engine::Engine, pending_requests: &mut Vec<model::ExchangeRateRequest>, output: &mut String) {
+    for (answer, _) in engine.answer(pending_requests, None, 1) {
+        output.push_str(&answer);
+    }
+    pending_requests.clear();
+}
+
+// The first line where `output` differs from `expected_output`
+fn first_difference(output: &str, expected_output: &str) -> Option<String> {
+    let mut output_lines = output.lines();
+    let mut expected_lines = expected_output.lines();
+    for line_number in 1.. {
+        match (output_lines.next(), expected_lines.next()) {
+            (None, None) => return None,
+            (output_line, expected_line) if output_line != expected_line => return Some(format!(
+                "line {}: expected {}, got {}", line_number,
+                expected_line.map_or("end of output".to_string(), |line| format!("\"{}\"", line)),
+                output_line.map_or("end of output".to_string(), |line| format!("\"{}\"", line))
+            )),
+            _ => ()
+        }
+    }
+    None
+}
+
+// Run every scenario in `dir`, in the order of their names
+pub fn verify_scenarios(dir: &str) -> Result<Vec<ScenarioResult>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Error encountered while reading {}: {}", dir, e))?;
+    let mut scenario_dirs: Vec<_> = entries.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.join(INPUT_FILE).is_file())
+        .collect();
+    scenario_dirs.sort();
+    if scenario_dirs.is_empty() {
+        return Err(format!("No scenarios in {}, expected directories with an {}", dir, INPUT_FILE));
+    }
+    let mut results = Vec::new();
+    for scenario_dir in scenario_dirs {
+        let name = scenario_dir.file_name().map_or_else(String::new, |name| name.to_string_lossy().to_string());
+        let expected_output = read_expected_output(&scenario_dir)?;
+        let output = run_scenario(&scenario_dir.join(INPUT_FILE).to_string_lossy())?;
+        results.push(ScenarioResult { name, difference: first_difference(&output, &expected_output) });
+    }
+    Ok(results)
+}
+
+fn read_expected_output(scenario_dir: &Path) -> Result<String, String> {
+    let file_name = scenario_dir.join(EXPECTED_OUTPUT_FILE);
+    fs::read_to_string(&file_name)
+        .map_err(|e| format!("Error encountered while reading {}: {}", file_name.to_string_lossy(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn golden_scenarios() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/scenarios");
+        for result in verify_scenarios(dir).unwrap_or_else(|e| panic!("{}", e)) {
+            assert!(result.get_difference().is_none(), "scenario {} failed at {}", result.get_name(),
+                result.get_difference().unwrap());
+        }
+    }
+
+    #[test]
+    fn report_first_difference() {
+        assert_eq!(first_difference("a\nb\n", "a\nb\n"), None);
+        assert_eq!(first_difference("a\nc\n", "a\nb\n"), Some("line 2: expected \"b\", got \"c\"".to_string()));
+        assert_eq!(first_difference("a\n", "a\nb\n"), Some("line 2: expected \"b\", got end of output".to_string()));
+    }
+}
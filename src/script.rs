@@ -0,0 +1,111 @@
+// Scripts that turn the ratio of every quote into the weight of its edge, e.g. to apply custom fees or haircuts,
+// or to leave out quotes, without recompiling
+
+#[cfg(feature = "scripting")]
+pub use self::rhai_script::WeightScript;
+#[cfg(not(feature = "scripting"))]
+pub use self::unsupported::WeightScript;
+
+#[cfg(feature = "scripting")]
+mod rhai_script {
+    use std::fs;
+
+    use rhai::{Dynamic, Engine, Scope, AST, INT};
+
+    use crate::model::Vertex;
+
+    // A rhai script evaluated once per edge, with the variables `exchange`, `source_currency`, `dest_currency`,
+    // `ratio`, `datetime` and `derived` describing the quote. Its value is the weight of the edge, or `()` to leave
+    // the edge out
+    pub struct WeightScript {
+        engine: Engine,
+        ast: AST
+    }
+
+    impl WeightScript {
+        pub fn load(file_name: &str) -> Result<WeightScript, String> {
+            let script = fs::read_to_string(file_name)
+                .map_err(|e| format!("Error encountered while reading weight script: {}", e))?;
+            WeightScript::parse(&script)
+        }
+
+        pub fn parse(script: &str) -> Result<WeightScript, String> {
+            let engine = Engine::new();
+            let ast = engine.compile(script).map_err(|e| format!("Invalid weight script: {}", e))?;
+            Ok(WeightScript { engine, ast })
+        }
+
+        // Weight of the edge from `from_vertex` to `to_vertex` quoted at `ratio`, None to leave it out
+        pub fn weight(&self, from_vertex: &Vertex, to_vertex: &Vertex, ratio: f64, datetime: u64, derived: bool)
+            -> Result<Option<f64>, String> {
+            let mut scope = Scope::new();
+            scope.push_constant("exchange", from_vertex.get_exchange().to_string());
+            scope.push_constant("source_currency", from_vertex.get_currency().to_string());
+            scope.push_constant("dest_currency", to_vertex.get_currency().to_string());
+            scope.push_constant("ratio", ratio);
+            scope.push_constant("datetime", datetime as INT);
+            scope.push_constant("derived", derived);
+            let value: Dynamic = self.engine.eval_ast_with_scope(&mut scope, &self.ast)
+                .map_err(|e| format!("Weight script failed: {}", e))?;
+            let weight = if value.is_unit() {
+                return Ok(None);
+            } else if let Ok(weight) = value.as_float() {
+                weight
+            } else if let Ok(weight) = value.as_int() {
+                weight as f64
+            } else {
+                return Err(format!("Weight script returned a {} instead of a number", value.type_name()));
+            };
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(format!("Weight script returned {}, weights must be finite and at least 0", weight));
+            }
+            Ok(Some(weight))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::testutil::vertex;
+
+        #[test]
+        fn transform_ratios() {
+            let script = WeightScript::parse("
+                if exchange == \"SHADY\" { return (); }
+                if derived { ratio * 0.99 } else if source_currency == \"BTC\" { ratio - 1 } else { ratio }
+            ").unwrap();
+            let (kraken_btc, kraken_usd) = (vertex("KRAKEN", "BTC"), vertex("KRAKEN", "USD"));
+            assert_eq!(script.weight(&kraken_btc, &kraken_usd, 1000.0, 1, false), Ok(Some(999.0)));
+            assert_eq!(script.weight(&kraken_usd, &kraken_btc, 0.001, 1, false), Ok(Some(0.001)));
+            assert_eq!(script.weight(&kraken_usd, &kraken_btc, 100.0, 1, true), Ok(Some(99.0)));
+            assert_eq!(script.weight(&vertex("SHADY", "BTC"), &vertex("SHADY", "USD"), 1000.0, 1, false), Ok(None));
+
+            assert!(WeightScript::parse("ratio *").is_err());
+            assert!(WeightScript::parse("\"ratio\"").unwrap().weight(&kraken_btc, &kraken_usd, 1.0, 1, false).is_err());
+            assert!(WeightScript::parse("-ratio").unwrap().weight(&kraken_btc, &kraken_usd, 1.0, 1, false).is_err());
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+mod unsupported {
+    use crate::model::Vertex;
+
+    // Stand-in for builds without the `scripting` feature, it can never be loaded
+    pub struct WeightScript;
+
+    impl WeightScript {
+        pub fn load(file_name: &str) -> Result<WeightScript, String> {
+            Err(format!("Weight script {} requires the `scripting` feature", file_name))
+        }
+
+        pub fn parse(_script: &str) -> Result<WeightScript, String> {
+            Err("Weight scripts require the `scripting` feature".to_string())
+        }
+
+        pub fn weight(&self, _from_vertex: &Vertex, _to_vertex: &Vertex, ratio: f64, _datetime: u64, _derived: bool)
+            -> Result<Option<f64>, String> {
+            Ok(Some(ratio))
+        }
+    }
+}
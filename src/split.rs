@@ -0,0 +1,193 @@
+// Splitting a large conversion across the best paths, when the markets of the best one are too shallow to fill it at
+// the quoted rate
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::alias::Aliases;
+use crate::constants;
+use crate::input;
+use crate::model::{GraphResult, Vertex};
+use crate::output::Split;
+
+// Part of the depth of a market, converted at its quoted rate less `slippage`
+#[derive(Clone)]
+struct LiquidityTier {
+    depth: f64,
+    slippage: f64
+}
+
+// (exchange, source currency, destination currency)
+type Market = (String, String, String);
+
+#[derive(Clone, Default)]
+pub struct Liquidity {
+    // tiers of each market, in the order they are filled
+    tiers: HashMap<Market, Vec<LiquidityTier>>
+}
+
+impl Liquidity {
+    // Markets as deep as needed at their quoted rate
+    pub fn new() -> Liquidity {
+        Liquidity::default()
+    }
+
+    /// Parse liquidity tiers where each line is `<exchange> <source_currency> <destination_currency> <depth> <slippage>`:
+    /// the next `depth` of the source currency converts at the quoted rate less `slippage`, a fraction, once the tiers
+    /// on the earlier lines of the market are filled. Markets without tiers are as deep as needed at the quoted rate
+    pub fn parse(content: &str, aliases: &Aliases) -> Result<Liquidity, String> {
+        let mut liquidity = Liquidity::new();
+        for line in input::content_lines(content) {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let tier = match tokens[..] {
+                [_, _, _, depth, slippage] => match (depth.parse(), slippage.parse()) {
+                    (Ok(depth), Ok(slippage)) => LiquidityTier { depth, slippage },
+                    _ => return Err(format!("Invalid liquidity tier \"{}\"", line))
+                },
+                _ => return Err(format!("Invalid liquidity tier \"{}\"", line))
+            };
+            if !(tier.depth > 0.0 && tier.depth.is_finite() && (0.0..1.0).contains(&tier.slippage)) {
+                return Err(format!("Depth must be more than 0 and slippage at least 0 and less than 1 \"{}\"", line));
+            }
+            let market = (aliases.exchange(tokens[0]).to_string(), aliases.currency(tokens[1]).to_string(),
+                aliases.currency(tokens[2]).to_string());
+            liquidity.tiers.entry(market).or_default().push(tier);
+        }
+        Ok(liquidity)
+    }
+
+    // Amount of the destination currency that converting `amount` on `market` quoted at `rate` gives, once `filled` of
+    // its source currency was already converted, None if its tiers are too shallow
+    fn convert(&self, market: &Market, rate: f64, filled: f64, amount: f64) -> Option<f64> {
+        let tiers = match self.tiers.get(market) {
+            Some(tiers) => tiers,
+            None => return Some(amount * rate)
+        };
+        let (mut start, mut remaining, mut output) = (0.0, amount, 0.0);
+        for tier in tiers {
+            let end = start + tier.depth;
+            let converted = (end - filled.max(start)).max(0.0).min(remaining);
+            output += converted * rate * (1.0 - tier.slippage);
+            remaining -= converted;
+            start = end;
+        }
+        // up to rounding of the amounts filled so far
+        if remaining <= amount * 1e-9 { Some(output) } else { None }
+    }
+}
+
+// Market of a hop, none for moving a currency between exchanges, which is not limited
+fn market(from_vertex: &Vertex, to_vertex: &Vertex) -> Option<Market> {
+    if from_vertex.get_exchange() == to_vertex.get_exchange() {
+        Some((from_vertex.get_exchange().to_string(), from_vertex.get_currency().to_string(),
+            to_vertex.get_currency().to_string()))
+    } else {
+        None
+    }
+}
+
+// Amount of the destination currency that converting `amount` along `path` gives, given what `filled` already
+// converted on each market, along with the amount it converts on each market of the path, None if one is too shallow
+fn convert_path(rates: &GraphResult, liquidity: &Liquidity, path: &[Arc<Vertex>], filled: &HashMap<Market, f64>,
+    amount: f64
+) -> Option<(f64, Vec<(Market, f64)>)> {
+    let mut fills = Vec::new();
+    let mut output = amount;
+    for hop in path.windows(2) {
+        let rate = rates.get_edge_weight(&hop[0], &hop[1]);
+        output = match market(&hop[0], &hop[1]) {
+            Some(market) => {
+                let converted = liquidity.convert(&market, rate, filled.get(&market).copied().unwrap_or(0.0), output)?;
+                fills.push((market, output));
+                converted
+            },
+            None => output * rate
+        };
+    }
+    Some((output, fills))
+}
+
+// Split converting `amount` from the first to the last vertex of `paths`, e.g. those of `GraphResult::top_paths`,
+// to get the most of the destination currency out of the markets of `liquidity`. The amount is allocated in
+// `SPLIT_SLICES` equal slices, each to the path that converts it best given the depth that earlier slices used, so
+// paths sharing a market compete for its depth
+pub fn split(rates: &GraphResult, liquidity: &Liquidity, paths: Vec<Vec<Arc<Vertex>>>, amount: f64) -> Split {
+    let slice = amount / constants::SPLIT_SLICES as f64;
+    let mut filled: HashMap<Market, f64> = HashMap::new();
+    // number of slices and output of each path
+    let mut allocated: Vec<(u32, f64)> = vec![(0, 0.0); paths.len()];
+    let mut num_slices = 0;
+    while num_slices < constants::SPLIT_SLICES {
+        let best = paths.iter().enumerate()
+            .filter_map(|(i, path)| {
+                convert_path(rates, liquidity, path, &filled, slice).map(|converted| (i, converted))
+            })
+            .filter(|(_, (output, _))| output.is_finite())
+            .max_by(|(_, (output, _)), (_, (other_output, _))| output.total_cmp(other_output));
+        let (i, (output, fills)) = match best {
+            Some(best) => best,
+            None => break
+        };
+        for (market, converted) in fills {
+            *filled.entry(market).or_insert(0.0) += converted;
+        }
+        allocated[i].0 += 1;
+        allocated[i].1 += output;
+        num_slices += 1;
+    }
+    let allocations = paths.into_iter().zip(allocated)
+        .filter(|(_, (path_slices, _))| *path_slices > 0)
+        .map(|(path, (path_slices, output))| {
+            (amount * path_slices as f64 / constants::SPLIT_SLICES as f64, output, path)
+        })
+        .collect();
+    let unfilled = amount * (constants::SPLIT_SLICES - num_slices) as f64 / constants::SPLIT_SLICES as f64;
+    Split::new(amount, unfilled, allocations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Currency, Exchange};
+
+    #[test]
+    fn split_across_paths() {
+        let vertex = |exchange: &str, currency: &str| Arc::new(Vertex::new(Exchange::new(exchange).unwrap(),
+            Currency::new(currency).unwrap()));
+        let (kraken_btc, kraken_usd) = (vertex("KRAKEN", "BTC"), vertex("KRAKEN", "USD"));
+        let (gdax_btc, gdax_usd) = (vertex("GDAX", "BTC"), vertex("GDAX", "USD"));
+        let mut rates: GraphResult = GraphResult::new();
+        rates.add_edge_weight(kraken_btc.clone(), kraken_usd.clone(), 1000.0, 0, 0, false);
+        rates.add_edge_weight(kraken_btc.clone(), gdax_btc.clone(), 1.0, 0, 0, false);
+        rates.add_edge_weight(gdax_btc.clone(), gdax_usd.clone(), 990.0, 0, 0, false);
+        rates.add_edge_weight(gdax_usd.clone(), kraken_usd.clone(), 1.0, 0, 0, false);
+        let paths = vec![vec![kraken_btc.clone(), kraken_usd.clone()],
+            vec![kraken_btc.clone(), gdax_btc.clone(), gdax_usd.clone(), kraken_usd.clone()]];
+
+        // Unlimited markets take the whole amount on the best path
+        let split_result = split(&rates, &Liquidity::new(), paths.clone(), 10.0);
+        assert_eq!(split_result.get_allocations().len(), 1);
+        assert_eq!(split_result.get_allocations()[0].0, 10.0);
+        assert!((split_result.output() - 10000.0).abs() < 1e-6);
+
+        // KRAKEN fills 4 BTC at its rate and 2 more 2% worse, which is worse than GDAX, and GDAX fills 3 BTC
+        let liquidity = "KRAKEN BTC USD 4 0\nKRAKEN BTC USD 2 0.02\nGDAX BTC USD 3 0.0\n";
+        let liquidity = Liquidity::parse(liquidity, &Aliases::none()).unwrap();
+        let split_result = split(&rates, &liquidity, paths.clone(), 10.0);
+        let allocations: Vec<(f64, usize)> = split_result.get_allocations().iter()
+            .map(|(amount, _, path)| (*amount, path.len()))
+            .collect();
+        assert_eq!(allocations, [(6.0, 2), (3.0, 4)]);
+        assert!((split_result.output() - (4000.0 + 2.0 * 980.0 + 3.0 * 990.0)).abs() < 1e-6);
+
+        // A path through a NaN quote gets no slice
+        rates.add_edge_weight(kraken_btc.clone(), kraken_usd.clone(), f64::NAN, 1, 0, false);
+        let split_result = split(&rates, &liquidity, paths.clone(), 10.0);
+        assert_eq!(split_result.get_allocations().len(), 1);
+        assert_eq!(split_result.get_allocations()[0].2.len(), 4);
+
+        assert!(Liquidity::parse("KRAKEN BTC USD 4\n", &Aliases::none()).is_err());
+        assert!(Liquidity::parse("KRAKEN BTC USD 0 0.01\n", &Aliases::none()).is_err());
+        assert!(Liquidity::parse("KRAKEN BTC USD 4 1\n", &Aliases::none()).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+// SQL queries over the state built from an input, in an in-memory SQLite database with the tables
+// `edges`, `best_rates` and `updates_history`
+
+#[cfg(feature = "sqlite")]
+pub use self::database::Database;
+#[cfg(not(feature = "sqlite"))]
+pub use self::unsupported::Database;
+
+#[cfg(feature = "sqlite")]
+mod database {
+    use rusqlite::types::Value;
+    use rusqlite::{params, Connection};
+
+    use tenx_challenge::engine::Engine;
+    use tenx_challenge::export;
+    use tenx_challenge::model::PriceUpdate;
+
+    const SCHEMA: &str = "
+        CREATE TABLE edges (from_exchange TEXT, from_currency TEXT, to_exchange TEXT, to_currency TEXT, rate REAL,
+            last_updated TEXT, derived INTEGER);
+        CREATE TABLE best_rates (from_exchange TEXT, from_currency TEXT, to_exchange TEXT, to_currency TEXT,
+            best_rate REAL, hops INTEGER);
+        CREATE TABLE updates_history (datetime TEXT, exchange TEXT, source_currency TEXT, dest_currency TEXT,
+            forward_ratio REAL, backward_ratio REAL, backward_derived INTEGER);";
+
+    pub struct Database {
+        connection: Connection
+    }
+
+    impl Database {
+        // Tables of the edges and best rates before fees of `engine`, and of the `price_updates` that built it
+        pub fn load(engine: &mut Engine, price_updates: &[PriceUpdate]) -> Result<Database, String> {
+            Database::create(engine, price_updates).map_err(|e| format!("Error encountered while loading tables: {}", e))
+        }
+
+        fn create(engine: &mut Engine, price_updates: &[PriceUpdate]) -> rusqlite::Result<Database> {
+            let mut connection = Connection::open_in_memory()?;
+            connection.execute_batch(SCHEMA)?;
+            engine.find_best_rates();
+            let transaction = connection.transaction()?;
+            {
+                let mut insert = transaction.prepare("INSERT INTO edges VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+                for (from_vertex, to_vertex, edge) in engine.get_graph_result().edges() {
+                    insert.execute(params![from_vertex.get_exchange(), from_vertex.get_currency(),
+                        to_vertex.get_exchange(), to_vertex.get_currency(), edge.get_weight(),
+                        export::format_datetime(edge.get_last_updated()), edge.is_derived()])?;
+                }
+
+                let mut insert = transaction.prepare("INSERT INTO best_rates VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+                let graph_result = engine.get_graph_result();
+                for from_vertex in engine.get_vertices() {
+                    for to_vertex in engine.get_vertices() {
+                        let best_rate = graph_result.get_best_rate(from_vertex, to_vertex);
+                        if from_vertex == to_vertex || best_rate == 0.0 {
+                            continue;
+                        }
+                        let hops = graph_result.best_rate_path(from_vertex, to_vertex).map(|path| path.len() - 1);
+                        insert.execute(params![from_vertex.get_exchange(), from_vertex.get_currency(),
+                            to_vertex.get_exchange(), to_vertex.get_currency(), best_rate, hops])?;
+                    }
+                }
+
+                let mut insert = transaction.prepare("INSERT INTO updates_history VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+                for price_update in price_updates {
+                    insert.execute(params![export::format_datetime(price_update.get_datetime()),
+                        price_update.get_exchange(), price_update.get_source_currency(),
+                        price_update.get_dest_currency(), price_update.get_forward_ratio(),
+                        price_update.get_backward_ratio(), price_update.is_backward_derived()])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(Database { connection })
+        }
+
+        // The result of `query` as a line of column names followed by a line per row, with tab-separated values
+        pub fn query(&self, query: &str) -> Result<String, String> {
+            self.run(query).map_err(|e| format!("Error encountered while running query: {}", e))
+        }
+
+        fn run(&self, query: &str) -> rusqlite::Result<String> {
+            let mut statement = self.connection.prepare(query)?;
+            let num_columns = statement.column_count();
+            let mut output = statement.column_names().join("\t");
+            output.push('\n');
+            let mut rows = statement.query([])?;
+            while let Some(row) = rows.next()? {
+                let values = (0..num_columns)
+                    .map(|index| row.get::<_, Value>(index).map(|value| match value {
+                        Value::Null => "NULL".to_string(),
+                        Value::Integer(value) => value.to_string(),
+                        Value::Real(value) => value.to_string(),
+                        Value::Text(value) => value,
+                        Value::Blob(value) => format!("<{} bytes>", value.len())
+                    }))
+                    .collect::<rusqlite::Result<Vec<String>>>()?;
+                output.push_str(&values.join("\t"));
+                output.push('\n');
+            }
+            Ok(output)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use tenx_challenge::model::{Currency, Exchange};
+        use tenx_challenge::output::OutputFormat;
+
+        #[test]
+        fn query_tables() {
+            let price_updates = vec![PriceUpdate::new(1509529343000, Exchange::new("KRAKEN").unwrap(),
+                Currency::new("BTC").unwrap(), Currency::new("USD").unwrap(), 1000.0, 0.0009)];
+            let mut engine = Engine::new(OutputFormat::Text);
+            engine.apply_price_update(price_updates[0].clone());
+            let database = Database::load(&mut engine, &price_updates).unwrap();
+            assert_eq!(database.query("SELECT exchange, 1 - forward_ratio * backward_ratio AS spread \
+                FROM updates_history").unwrap(), "exchange\tspread\nKRAKEN\t0.09999999999999998\n");
+            assert_eq!(database.query("SELECT COUNT(*) FROM edges").unwrap(), "COUNT(*)\n2\n");
+            assert_eq!(database.query("SELECT best_rate, hops FROM best_rates WHERE to_currency = 'USD'").unwrap(),
+                "best_rate\thops\n1000\t1\n");
+            assert!(database.query("SELECT * FROM quotes").is_err());
+        }
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+mod unsupported {
+    use tenx_challenge::engine::Engine;
+    use tenx_challenge::model::PriceUpdate;
+
+    // Stand-in for builds without the `sqlite` feature, it can never be loaded
+    pub struct Database;
+
+    impl Database {
+        pub fn load(_engine: &mut Engine, _price_updates: &[PriceUpdate]) -> Result<Database, String> {
+            Err("SQL is not supported by this build, rebuild with `--features sqlite`".to_string())
+        }
+
+        pub fn query(&self, _query: &str) -> Result<String, String> {
+            Ok(String::new())
+        }
+    }
+}
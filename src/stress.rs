@@ -0,0 +1,85 @@
+// Stress tests of the algorithm alone: random graphs are built in memory, without reading or parsing input, and the
+// time taken to find the best rates between every pair of vertices and to answer requests is measured
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use tenx_challenge::{engine, generate};
+use tenx_challenge::output::OutputFormat;
+
+use crate::cli::StressOptions;
+
+// Timings of one stress test
+pub struct StressReport {
+    num_vertices: usize,
+    num_edges: usize,
+    build: Duration,
+    best_rates: Duration,
+    num_queries: usize,
+    queries: Duration,
+    num_routes: usize
+}
+
+impl fmt::Display for StressReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let per_second = |count: usize, duration: Duration| count as f64 / duration.as_secs_f64().max(1e-9);
+        writeln!(f, "STRESS_BEGIN")?;
+        writeln!(f, "Graph: {} vertices, {} edges", self.num_vertices, self.num_edges)?;
+        writeln!(f, "Build: {:.3}ms", self.build.as_secs_f64() * 1000.0)?;
+        writeln!(f, "Best rates: {:.3}ms", self.best_rates.as_secs_f64() * 1000.0)?;
+        writeln!(f, "Queries: {} in {:.3}ms, {:.0} per second, {} with a route", self.num_queries,
+            self.queries.as_secs_f64() * 1000.0, per_second(self.num_queries, self.queries), self.num_routes)?;
+        write!(f, "STRESS_END")
+    }
+}
+
+// Build the random graph `options` describe and time finding its best rates and answering random requests
+pub fn run(options: &StressOptions) -> Result<StressReport, String> {
+    let price_updates = generate::random_graph(options.get_num_exchanges(), options.get_num_currencies(),
+        options.get_density(), options.get_seed())?;
+    let exchange_rate_requests = generate::random_requests(options.get_num_exchanges(), options.get_num_currencies(),
+        options.get_num_queries(), options.get_seed())?;
+
+    let started = Instant::now();
+    let mut engine = engine::Engine::new(OutputFormat::Text);
+    for price_update in price_updates {
+        engine.apply_price_update(price_update);
+    }
+    let build = started.elapsed();
+
+    let started = Instant::now();
+    engine.find_best_rates();
+    let best_rates = started.elapsed();
+
+    let started = Instant::now();
+    let answers = engine.answer(&exchange_rate_requests, None, options.get_threads());
+    let queries = started.elapsed();
+
+    Ok(StressReport {
+        num_vertices: engine.get_vertices().len(),
+        num_edges: engine.get_graph_result().edges().count(),
+        build, best_rates, num_queries: answers.len(), queries,
+        num_routes: answers.iter().filter(|(_, route_found)| *route_found).count()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{parse_command, Command};
+
+    #[test]
+    fn stress_small_graph() {
+        let args: Vec<String> = ["stress", "--exchanges", "2", "--currencies", "3", "--density", "1", "--queries", "10"]
+            .iter().map(|arg| arg.to_string()).collect();
+        let options = match parse_command(&args).unwrap() {
+            Command::Stress(options) => options,
+            _ => panic!("Expected stress subcommand")
+        };
+        let report = run(&options).unwrap();
+        // 3 pairs quoted both ways on each exchange, and every currency moved both ways between them
+        assert_eq!((report.num_vertices, report.num_edges), (6, 18));
+        assert_eq!((report.num_queries, report.num_routes), (10, 10));
+        assert!(report.to_string().starts_with("STRESS_BEGIN\nGraph: 6 vertices, 18 edges\n"));
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::chaos::FaultCounts;
+use crate::timing::{Phase, PhaseTimings};
+
+// Counts of what happened during a run, printed when the run ends
+pub struct RunSummary {
+    started: Instant,
+    num_lines: u64,
+    num_price_updates: u64,
+    num_exchange_rate_requests: u64,
+    num_invalid: u64,
+    num_no_route: u64,
+    num_cache_hits: u64,
+    num_dropped: u64,
+    num_coalesced: u64,
+    // pairs whose best rate differed from an exhaustive search, None without `--verify`
+    num_divergences: Option<u64>,
+    // repeated price updates dropped, None without `--dedup`
+    num_duplicates: Option<u64>,
+    // (quarantined, released) price updates, None without `--quarantine`
+    quarantined: Option<(u64, u64)>,
+    // faults injected into the input, None without `--chaos`
+    faults: Option<FaultCounts>,
+    phase_timings: PhaseTimings,
+    interrupted: bool
+}
+
+impl Default for RunSummary {
+    fn default() -> Self {
+        RunSummary::new()
+    }
+}
+
+impl RunSummary {
+    pub fn new() -> RunSummary {
+        RunSummary {
+            started: Instant::now(), num_lines: 0, num_price_updates: 0, num_exchange_rate_requests: 0,
+            num_invalid: 0, num_no_route: 0, num_cache_hits: 0, num_dropped: 0, num_coalesced: 0,
+            num_divergences: None, num_duplicates: None, quarantined: None, faults: None,
+            phase_timings: PhaseTimings::new(), interrupted: false
+        }
+    }
+
+    pub fn record_line(&mut self) {
+        self.num_lines += 1;
+    }
+
+    pub fn record_price_update(&mut self) {
+        self.num_price_updates += 1;
+    }
+
+    pub fn record_exchange_rate_request(&mut self, route_found: bool) {
+        self.num_exchange_rate_requests += 1;
+        if !route_found {
+            self.num_no_route += 1;
+        }
+    }
+
+    pub fn record_invalid(&mut self) {
+        self.num_invalid += 1;
+    }
+
+    pub fn record_parse(&mut self, duration: Duration) {
+        self.phase_timings.record(Phase::Parse, duration);
+    }
+
+    // Add the durations of the phases the engine went through
+    pub fn add_phase_timings(&mut self, phase_timings: &PhaseTimings) {
+        self.phase_timings.merge(phase_timings);
+    }
+
+    // Requests answered from the cache instead of being computed
+    pub fn set_num_cache_hits(&mut self, num_cache_hits: u64) {
+        self.num_cache_hits = num_cache_hits;
+    }
+
+    // Price updates the input buffer dropped or merged because processing fell behind
+    pub fn set_backpressure(&mut self, num_dropped: u64, num_coalesced: u64) {
+        self.num_dropped = num_dropped;
+        self.num_coalesced = num_coalesced;
+    }
+
+    // Price updates dropped by `--dedup` as repeats of earlier ones
+    pub fn set_num_duplicates(&mut self, num_duplicates: u64) {
+        self.num_duplicates = Some(num_duplicates);
+    }
+
+    // Price updates held back by `--quarantine`, and how many of them a later update confirmed
+    pub fn set_quarantined(&mut self, num_quarantined: u64, num_released: u64) {
+        self.quarantined = Some((num_quarantined, num_released));
+    }
+
+    // Faults that `--chaos` injected into the input
+    pub fn set_faults(&mut self, faults: FaultCounts) {
+        self.faults = Some(faults);
+    }
+
+    // Add pairs found to differ by `--verify`, the summary only shows them once something was recorded
+    pub fn record_divergences(&mut self, num_divergences: u64) {
+        *self.num_divergences.get_or_insert(0) += num_divergences;
+    }
+
+    pub fn set_interrupted(&mut self) {
+        self.interrupted = true;
+    }
+
+    pub fn get_num_invalid(&self) -> u64 {
+        self.num_invalid
+    }
+
+    pub fn get_num_no_route(&self) -> u64 {
+        self.num_no_route
+    }
+
+    pub fn get_num_divergences(&self) -> u64 {
+        self.num_divergences.unwrap_or(0)
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "RUN_SUMMARY_BEGIN")?;
+        if self.interrupted {
+            writeln!(f, "Interrupted before the end of input")?;
+        }
+        writeln!(f, "Lines: {}", self.num_lines)?;
+        writeln!(f, "Price updates: {}", self.num_price_updates)?;
+        writeln!(f, "Exchange rate requests: {} ({} without a route, {} from cache)", self.num_exchange_rate_requests,
+            self.num_no_route, self.num_cache_hits)?;
+        writeln!(f, "Invalid lines: {}", self.num_invalid)?;
+        writeln!(f, "Price updates dropped: {}, coalesced: {}", self.num_dropped, self.num_coalesced)?;
+        if let Some(num_divergences) = self.num_divergences {
+            writeln!(f, "Verification divergences: {}", num_divergences)?;
+        }
+        if let Some(num_duplicates) = self.num_duplicates {
+            writeln!(f, "Price updates deduplicated: {}", num_duplicates)?;
+        }
+        if let Some((num_quarantined, num_released)) = self.quarantined {
+            writeln!(f, "Price updates quarantined: {} ({} confirmed by a later update)", num_quarantined,
+                num_released)?;
+        }
+        if let Some(faults) = &self.faults {
+            writeln!(f, "Faults injected: {}", faults)?;
+        }
+        writeln!(f, "Phases:")?;
+        for phase in Phase::ALL.iter() {
+            writeln!(f, "  {}: {}", phase.name(), self.phase_timings.get(*phase))?;
+        }
+        writeln!(f, "Elapsed: {:.3}s", self.started.elapsed().as_secs_f64())?;
+        write!(f, "RUN_SUMMARY_END")
+    }
+}
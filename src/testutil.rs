@@ -0,0 +1,140 @@
+// Building scenarios for tests: vertices, price updates and requests from plain names, engines fed with quotes,
+// and assertions on answers. Names must be valid, anything else panics as a failing test would
+
+use std::sync::Arc;
+
+use crate::engine::Engine;
+use crate::fees::FeeModel;
+use crate::model::{Currency, Exchange, ExchangeRateRequest, PriceUpdate, Vertex};
+use crate::output::OutputFormat;
+
+// `<exchange, currency>`
+pub fn vertex(exchange: &str, currency: &str) -> Arc<Vertex> {
+    Arc::new(Vertex::new(exchange_name(exchange), currency_name(currency)))
+}
+
+// A price update as if read from `<datetime> <exchange> <source_currency> <dest_currency> <forward_ratio> <backward_ratio>`
+pub fn quote(datetime: u64, exchange: &str, source_currency: &str, dest_currency: &str, forward_ratio: f64,
+    backward_ratio: f64
+) -> PriceUpdate {
+    PriceUpdate::new(datetime, exchange_name(exchange), currency_name(source_currency), currency_name(dest_currency),
+        forward_ratio, backward_ratio)
+}
+
+// An exchange rate request without a window
+pub fn request(source_exchange: &str, source_currency: &str, dest_exchange: &str, dest_currency: &str)
+    -> ExchangeRateRequest {
+    ExchangeRateRequest::new(exchange_name(source_exchange), currency_name(source_currency),
+        exchange_name(dest_exchange), currency_name(dest_currency), None)
+}
+
+fn exchange_name(exchange: &str) -> Exchange {
+    Exchange::new(exchange).unwrap_or_else(|e| panic!("{}", e))
+}
+
+fn currency_name(currency: &str) -> Currency {
+    Currency::new(currency).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// Builds an engine from quotes, each one a millisecond after the previous one unless given a datetime
+pub struct EngineBuilder {
+    output_format: OutputFormat,
+    price_updates: Vec<PriceUpdate>,
+    datetime: u64
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        EngineBuilder::new()
+    }
+}
+
+impl EngineBuilder {
+    pub fn new() -> EngineBuilder {
+        EngineBuilder { output_format: OutputFormat::Text, price_updates: Vec::new(), datetime: 0 }
+    }
+
+    pub fn output_format(mut self, output_format: OutputFormat) -> EngineBuilder {
+        self.output_format = output_format;
+        self
+    }
+
+    pub fn quote(self, exchange: &str, source_currency: &str, dest_currency: &str, forward_ratio: f64,
+        backward_ratio: f64
+    ) -> EngineBuilder {
+        let datetime = self.datetime + 1;
+        self.quote_at(datetime, exchange, source_currency, dest_currency, forward_ratio, backward_ratio)
+    }
+
+    pub fn quote_at(mut self, datetime: u64, exchange: &str, source_currency: &str, dest_currency: &str,
+        forward_ratio: f64, backward_ratio: f64
+    ) -> EngineBuilder {
+        self.price_updates.push(quote(datetime, exchange, source_currency, dest_currency, forward_ratio, backward_ratio));
+        self.datetime = datetime;
+        self
+    }
+
+    pub fn build(self) -> Engine {
+        let mut engine = Engine::new(self.output_format);
+        for price_update in self.price_updates {
+            engine.apply_price_update(price_update);
+        }
+        engine
+    }
+}
+
+// Answer `request` alone with one worker
+pub fn answer(engine: &mut Engine, request: &ExchangeRateRequest, fee_model: Option<&FeeModel>) -> String {
+    engine.answer(std::slice::from_ref(request), fee_model, 1).remove(0).0
+}
+
+// Assert that `answer` is `expected` once the indentation and blank lines of `expected` are removed, so expected
+// answers can be written as indented multi-line strings
+pub fn assert_answer(answer: &str, expected: &str) {
+    let expected: String = expected.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| format!("{}\n", line))
+        .collect();
+    assert_eq!(answer, expected, "\nanswer:\n{}\nexpected:\n{}", answer, expected);
+}
+
+// Assert that the best rate and path of a text `answer` are `rate` and `path`, given as `EXCHANGE CURRENCY` hops
+pub fn assert_best_rate(answer: &str, rate: f64, path: &[&str]) {
+    let mut lines = answer.lines();
+    let header: Vec<&str> = lines.next().unwrap_or("").split_whitespace().collect();
+    assert!(header.first() == Some(&"BEST_RATES_BEGIN") && header.len() > 5, "not a best rate answer:\n{}", answer);
+    assert_eq!(header[5].parse::<f64>().ok(), Some(rate), "best rate of:\n{}", answer);
+    let answer_path: Vec<String> = lines.take_while(|line| line.starts_with('<')).map(str::to_string).collect();
+    let expected_path: Vec<String> = path.iter()
+        .map(|hop| match hop.split_whitespace().collect::<Vec<_>>()[..] {
+            [exchange, currency] => vertex(exchange, currency).to_string(),
+            _ => panic!("expected `EXCHANGE CURRENCY`, got \"{}\"", hop)
+        })
+        .collect();
+    assert_eq!(answer_path, expected_path, "best path of:\n{}", answer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_scenarios() {
+        let mut engine = EngineBuilder::new()
+            .quote("KRAKEN", "BTC", "USD", 1000.0, 0.0009)
+            .quote("GDAX", "BTC", "USD", 1001.0, 0.0008)
+            .build();
+        assert_eq!(engine.get_latest_datetime(), 2);
+        let answer = answer(&mut engine, &request("KRAKEN", "BTC", "KRAKEN", "USD"), None);
+        assert_best_rate(&answer, 1001.0, &["KRAKEN BTC", "GDAX BTC", "GDAX USD", "KRAKEN USD"]);
+        assert_answer(&answer, "
+            BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 1001 VERSION 2
+            <KRAKEN, BTC>
+            <GDAX, BTC>
+            <GDAX, USD>
+            <KRAKEN, USD>
+            BEST_RATES_END
+        ");
+    }
+}
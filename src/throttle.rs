@@ -0,0 +1,56 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Limits how many updates are processed per second, spacing them evenly instead of in bursts
+pub struct Throttle {
+    interval: Duration,
+    // earliest time the next update may be processed
+    next: Option<Instant>
+}
+
+impl Throttle {
+    pub fn new(max_per_sec: f64) -> Throttle {
+        Throttle {
+            interval: Duration::from_secs_f64(1.0 / max_per_sec),
+            next: None
+        }
+    }
+
+    // How long to wait at `now` before processing the next update
+    fn delay(&mut self, now: Instant) -> Duration {
+        let next = match self.next {
+            // An idle period does not build up credit for a later burst
+            Some(next) if next > now => next,
+            _ => now
+        };
+        self.next = Some(next + self.interval);
+        next - now
+    }
+
+    // Wait until the next update may be processed
+    pub fn acquire(&mut self) {
+        let delay = self.delay(Instant::now());
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn space_updates_evenly() {
+        let mut throttle = Throttle::new(4.0);
+        let start = Instant::now();
+        assert_eq!(throttle.delay(start), Duration::from_millis(0));
+        assert_eq!(throttle.delay(start), Duration::from_millis(250));
+        assert_eq!(throttle.delay(start + Duration::from_millis(100)), Duration::from_millis(400));
+
+        // After being idle, updates are not let through in a burst
+        let later = start + Duration::from_secs(10);
+        assert_eq!(throttle.delay(later), Duration::from_millis(0));
+        assert_eq!(throttle.delay(later), Duration::from_millis(250));
+    }
+}
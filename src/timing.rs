@@ -0,0 +1,281 @@
+// Durations of the phases of processing input, reported in the run summary, and end-to-end latencies of a run
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Buckets per doubling of durations, so that percentiles are within 1/16 of the recorded durations
+const SUB_BUCKETS: u64 = 16;
+const NUM_BUCKETS: usize = (64 - 3) * SUB_BUCKETS as usize;
+
+// Counts of durations in buckets of exponentially growing width, which keeps memory constant however long the run
+#[derive(Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    total_nanos: u128,
+    max_nanos: u64
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+// Durations under `SUB_BUCKETS` nanoseconds have a bucket each, longer ones share one with those within 1/16
+fn bucket(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS {
+        return nanos as usize;
+    }
+    let exponent = 63 - nanos.leading_zeros() as u64;
+    ((exponent - 3) * SUB_BUCKETS + ((nanos >> (exponent - 4)) & (SUB_BUCKETS - 1))) as usize
+}
+
+// Largest duration of `bucket`
+fn bucket_max(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    if bucket < SUB_BUCKETS {
+        return bucket;
+    }
+    let exponent = bucket / SUB_BUCKETS + 3;
+    let lowest = (SUB_BUCKETS + bucket % SUB_BUCKETS) << (exponent - 4);
+    lowest + ((1 << (exponent - 4)) - 1)
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram { counts: vec![0; NUM_BUCKETS], count: 0, total_nanos: 0, max_nanos: 0 }
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+        self.counts[bucket(nanos)] += 1;
+        self.count += 1;
+        self.total_nanos += nanos as u128;
+        self.max_nanos = self.max_nanos.max(nanos);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other_count;
+        }
+        self.count += other.count;
+        self.total_nanos += other.total_nanos;
+        self.max_nanos = self.max_nanos.max(other.max_nanos);
+    }
+
+    pub fn get_count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn get_total(&self) -> Duration {
+        Duration::from_nanos(self.total_nanos.min(u64::MAX as u128) as u64)
+    }
+
+    pub fn get_average(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos((self.total_nanos / count as u128) as u64)
+        }
+    }
+
+    // Duration that a `percentile` % of the recorded durations do not exceed, rounded up to the end of its bucket
+    pub fn get_percentile(&self, percentile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(bucket_max(bucket).min(self.max_nanos));
+            }
+        }
+        Duration::from_nanos(self.max_nanos)
+    }
+}
+
+// e.g. 850ns, 12.5us, 3.200ms or 1.250s
+pub fn format_duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    if nanos < 1_000 {
+        format!("{}ns", nanos)
+    } else if nanos < 1_000_000 {
+        format!("{:.1}us", nanos as f64 / 1e3)
+    } else if nanos < 1_000_000_000 {
+        format!("{:.3}ms", nanos as f64 / 1e6)
+    } else {
+        format!("{:.3}s", nanos as f64 / 1e9)
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in {}", self.count, format_duration(self.get_total()))?;
+        if self.count > 0 {
+            write!(f, ", average {}, p50 {}, p95 {}, p99 {}, max {}", format_duration(self.get_average()),
+                format_duration(self.get_percentile(50.0)), format_duration(self.get_percentile(95.0)),
+                format_duration(self.get_percentile(99.0)), format_duration(Duration::from_nanos(self.max_nanos)))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+    // Parsing an input line
+    Parse,
+    // Adding the edges of a price update
+    EdgeInsertion,
+    // Adding the edges between the same currency on different exchanges
+    CurrencyFanOut,
+    // Finding the best rates between every pair of vertices
+    BestRates,
+    // Finding the best path of a request and formatting its answer
+    PathReconstruction
+}
+
+impl Phase {
+    pub const ALL: [Phase; 5] = [
+        Phase::Parse, Phase::EdgeInsertion, Phase::CurrencyFanOut, Phase::BestRates, Phase::PathReconstruction
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Parse => "Parse",
+            Phase::EdgeInsertion => "Edge insertion",
+            Phase::CurrencyFanOut => "Same-currency edges",
+            Phase::BestRates => "Best rates",
+            Phase::PathReconstruction => "Path reconstruction"
+        }
+    }
+}
+
+// A histogram of the durations of every phase
+#[derive(Clone, Default)]
+pub struct PhaseTimings {
+    histograms: [Histogram; 5]
+}
+
+impl PhaseTimings {
+    pub fn new() -> PhaseTimings {
+        PhaseTimings::default()
+    }
+
+    pub fn record(&mut self, phase: Phase, duration: Duration) {
+        self.histograms[phase as usize].record(duration);
+    }
+
+    pub fn merge(&mut self, other: &PhaseTimings) {
+        for (histogram, other_histogram) in self.histograms.iter_mut().zip(other.histograms.iter()) {
+            histogram.merge(other_histogram);
+        }
+    }
+
+    pub fn get(&self, phase: Phase) -> &Histogram {
+        &self.histograms[phase as usize]
+    }
+}
+
+// End-to-end latencies for `--latency`: from taking a price update off the input buffer to its edges being in the
+// graph, and from taking an exchange rate request off it to its answer being published, batching included
+#[derive(Default)]
+pub struct Latencies {
+    updates: Histogram,
+    queries: Histogram,
+    // when each request waiting to be answered was taken off the input buffer, in the order they are answered
+    pending: VecDeque<Instant>
+}
+
+impl Latencies {
+    pub fn new() -> Latencies {
+        Latencies::default()
+    }
+
+    pub fn record_update(&mut self, duration: Duration) {
+        self.updates.record(duration);
+    }
+
+    // A request taken off the input buffer at `received` now waits to be answered
+    pub fn start_query(&mut self, received: Instant) {
+        self.pending.push_back(received);
+    }
+
+    // The `num_answered` requests that waited longest were answered
+    pub fn finish_queries(&mut self, num_answered: usize) {
+        for received in self.pending.drain(..num_answered.min(self.pending.len())) {
+            self.queries.record(received.elapsed());
+        }
+    }
+
+    pub fn get_updates(&self) -> &Histogram {
+        &self.updates
+    }
+
+    pub fn get_queries(&self) -> &Histogram {
+        &self.queries
+    }
+}
+
+impl fmt::Display for Latencies {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "LATENCY_BEGIN")?;
+        writeln!(f, "Price updates: {}", self.updates)?;
+        writeln!(f, "Exchange rate requests: {}", self.queries)?;
+        write!(f, "LATENCY_END")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_durations() {
+        for nanos in (0..100_000).chain([u64::MAX / 3, u64::MAX]) {
+            let bucket = bucket(nanos);
+            assert!(bucket < NUM_BUCKETS);
+            assert!(bucket_max(bucket) >= nanos, "{}", nanos);
+            assert!(bucket == 0 || bucket_max(bucket - 1) < nanos, "{}", nanos);
+            assert!((bucket_max(bucket) - nanos) as f64 <= nanos as f64 / SUB_BUCKETS as f64, "{}", nanos);
+        }
+    }
+
+    #[test]
+    fn percentiles_of_phases() {
+        let mut timings = PhaseTimings::new();
+        for micros in 1..=100 {
+            timings.record(Phase::BestRates, Duration::from_micros(micros));
+        }
+        let mut other = PhaseTimings::new();
+        other.record(Phase::Parse, Duration::from_nanos(500));
+        timings.merge(&other);
+
+        let best_rates = timings.get(Phase::BestRates);
+        assert_eq!(best_rates.get_count(), 100);
+        assert_eq!(best_rates.get_average(), Duration::from_nanos(50_500));
+        let p50 = best_rates.get_percentile(50.0).as_nanos() as f64;
+        assert!((50_000.0..=50_000.0 * 1.07).contains(&p50), "{}", p50);
+        assert_eq!(best_rates.get_percentile(100.0), Duration::from_micros(100));
+        assert_eq!(timings.get(Phase::Parse).get_percentile(99.0), Duration::from_nanos(500));
+        assert_eq!(timings.get(Phase::PathReconstruction).to_string(), "0 in 0ns");
+        assert!(best_rates.to_string().starts_with("100 in 5.050ms, average 50.5us, p50 "));
+    }
+
+    #[test]
+    fn latencies_of_queries() {
+        let mut latencies = Latencies::new();
+        latencies.record_update(Duration::from_micros(20));
+        let received = Instant::now() - Duration::from_millis(5);
+        latencies.start_query(received);
+        latencies.start_query(Instant::now());
+        latencies.finish_queries(1);
+        assert_eq!(latencies.get_queries().get_count(), 1);
+        assert!(latencies.get_queries().get_percentile(99.0) >= Duration::from_millis(5));
+        latencies.finish_queries(5);
+        assert_eq!(latencies.get_queries().get_count(), 2);
+        assert!(latencies.to_string().starts_with("LATENCY_BEGIN\nPrice updates: 1 in 20.0us, average 20.0us, p50 "));
+    }
+}
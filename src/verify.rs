@@ -0,0 +1,127 @@
+// Checking the best rates found by floyd warshall against an exhaustive search of every path, on small graphs
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::model::{GraphResult, Vertex, VertexMap, VertexSet};
+
+// A pair of vertices whose best rate differs between floyd warshall and the exhaustive search
+pub struct Divergence {
+    from_vertex: Arc<Vertex>,
+    to_vertex: Arc<Vertex>,
+    best_rate: f64,
+    brute_force_rate: f64
+}
+
+impl Divergence {
+    pub fn get_from_vertex(&self) -> &Arc<Vertex> {
+        &self.from_vertex
+    }
+
+    pub fn get_to_vertex(&self) -> &Arc<Vertex> {
+        &self.to_vertex
+    }
+
+    pub fn get_best_rate(&self) -> f64 {
+        self.best_rate
+    }
+
+    pub fn get_brute_force_rate(&self) -> f64 {
+        self.brute_force_rate
+    }
+}
+
+// `DIVERGENCE <KRAKEN, BTC> -> <GDAX, USD> BEST_RATE 1001 BRUTE_FORCE 1002`
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DIVERGENCE {} -> {} BEST_RATE {} BRUTE_FORCE {}", self.from_vertex, self.to_vertex, self.best_rate,
+            self.brute_force_rate)
+    }
+}
+
+// Best rate from `from_vertex` to every vertex it reaches, by enumerating every path that visits a vertex at most once
+// Runtime: O(V!), so only for graphs of a few vertices
+pub fn brute_force_best_rates(graph_result: &GraphResult, from_vertex: &Arc<Vertex>) -> VertexMap<f64> {
+    let mut best_rates = VertexMap::new();
+    let mut path = vec![from_vertex.clone()];
+    extend_paths(graph_result, &mut path, 1.0, &mut best_rates);
+    best_rates
+}
+
+fn extend_paths(graph_result: &GraphResult, path: &mut Vec<Arc<Vertex>>, rate: f64, best_rates: &mut VertexMap<f64>) {
+    let last = path[path.len() - 1].clone();
+    let hops: Vec<(Arc<Vertex>, f64)> = graph_result.edges()
+        .filter(|(from_vertex, to_vertex, _)| **from_vertex == last && !path.contains(to_vertex))
+        .map(|(_, to_vertex, edge)| (to_vertex.clone(), edge.get_weight()))
+        .collect();
+    for (to_vertex, weight) in hops {
+        let rate = rate * weight;
+        let best_rate = best_rates.entry(to_vertex.clone()).or_insert(rate);
+        if rate > *best_rate {
+            *best_rate = rate;
+        }
+        path.push(to_vertex);
+        extend_paths(graph_result, path, rate, best_rates);
+        path.pop();
+    }
+}
+
+// Pairs of `vertices` whose best rate in `graph_result`, after `find_best_rates`, differs from the exhaustive search by
+// more than `epsilon` relative to the larger of the two. Pairs without a best path, e.g. because it could loop through
+// an arbitrage cycle, are skipped
+pub fn verify(graph_result: &GraphResult, vertices: &VertexSet, epsilon: f64) -> Vec<Divergence> {
+    let mut vertices: Vec<&Arc<Vertex>> = vertices.iter().collect();
+    vertices.sort();
+    let mut divergences = Vec::new();
+    for from_vertex in vertices.iter() {
+        let brute_force_rates = brute_force_best_rates(graph_result, from_vertex);
+        for to_vertex in vertices.iter() {
+            if from_vertex == to_vertex || graph_result.best_rate_path(from_vertex, to_vertex).is_none() {
+                continue;
+            }
+            let best_rate = graph_result.get_best_rate(from_vertex, to_vertex);
+            let brute_force_rate = brute_force_rates.get(*to_vertex).copied().unwrap_or(0.0);
+            if (best_rate - brute_force_rate).abs() > epsilon * best_rate.abs().max(brute_force_rate.abs()) {
+                divergences.push(Divergence {
+                    from_vertex: (*from_vertex).clone(), to_vertex: (*to_vertex).clone(), best_rate, brute_force_rate
+                });
+            }
+        }
+    }
+    divergences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::vertex;
+
+    #[test]
+    fn compare_with_brute_force() {
+        let (kraken_btc, kraken_usd, gdax_btc, gdax_usd) =
+            (vertex("KRAKEN", "BTC"), vertex("KRAKEN", "USD"), vertex("GDAX", "BTC"), vertex("GDAX", "USD"));
+        let mut graph_result = GraphResult::new();
+        let vertices: VertexSet = vec![kraken_btc.clone(), kraken_usd.clone(), gdax_btc.clone(), gdax_usd.clone()]
+            .into_iter().collect();
+        graph_result.add_edge_weight(kraken_btc.clone(), kraken_usd.clone(), 1000.0, 1, 0, false);
+        graph_result.add_edge_weight(kraken_usd.clone(), kraken_btc.clone(), 0.0009, 1, 0, false);
+        graph_result.add_edge_weight(gdax_btc.clone(), gdax_usd.clone(), 1001.0, 2, 0, false);
+        graph_result.add_edge_weight(gdax_usd.clone(), gdax_btc.clone(), 0.0008, 2, 0, false);
+        for vertex in vertices.iter() {
+            graph_result.add_edge_weight_for_currency(vertex.clone(), &vertices);
+        }
+        graph_result.find_best_rates(&vertices);
+        assert_eq!(brute_force_best_rates(&graph_result, &kraken_btc).get(&kraken_usd), Some(&1001.0));
+        assert!(verify(&graph_result, &vertices, 1e-9).is_empty());
+
+        // Best rates left from before a quote changed no longer match
+        graph_result.add_edge_weight(gdax_btc.clone(), gdax_usd.clone(), 1002.0, 3, 0, false);
+        let divergences = verify(&graph_result, &vertices, 1e-9);
+        assert_eq!(divergences.iter().map(Divergence::to_string).collect::<Vec<_>>(), [
+            "DIVERGENCE <GDAX, BTC> -> <GDAX, USD> BEST_RATE 1001 BRUTE_FORCE 1002",
+            "DIVERGENCE <GDAX, BTC> -> <KRAKEN, USD> BEST_RATE 1001 BRUTE_FORCE 1002",
+            "DIVERGENCE <KRAKEN, BTC> -> <GDAX, USD> BEST_RATE 1001 BRUTE_FORCE 1002",
+            "DIVERGENCE <KRAKEN, BTC> -> <KRAKEN, USD> BEST_RATE 1001 BRUTE_FORCE 1002"
+        ]);
+    }
+}
@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use crate::alias::Aliases;
+use crate::input;
+use crate::model::{Currency, Exchange, GraphResult, Vertex};
+
+// A pair of vertices whose best rate is tracked over the run
+pub struct WatchedPair {
+    from_vertex: Arc<Vertex>,
+    to_vertex: Arc<Vertex>,
+    // last observed best rate, 0 if there is no path yet
+    best_rate: f64,
+    // last observed best rate path
+    path: Vec<Arc<Vertex>>
+}
+
+impl WatchedPair {
+    pub fn new(from_vertex: Arc<Vertex>, to_vertex: Arc<Vertex>) -> WatchedPair {
+        WatchedPair {
+            from_vertex, to_vertex, best_rate: 0.0, path: Vec::new()
+        }
+    }
+
+    pub fn get_from_vertex(&self) -> &Arc<Vertex> {
+        &self.from_vertex
+    }
+
+    pub fn get_to_vertex(&self) -> &Arc<Vertex> {
+        &self.to_vertex
+    }
+
+    pub fn get_best_rate(&self) -> f64 {
+        self.best_rate
+    }
+
+    pub fn get_path(&self) -> &[Arc<Vertex>] {
+        &self.path
+    }
+
+    // e.g. `KRAKEN:BTC->GDAX:USD`
+    pub fn label(&self) -> String {
+        format!("{}:{}->{}:{}", self.from_vertex.get_exchange(), self.from_vertex.get_currency(),
+            self.to_vertex.get_exchange(), self.to_vertex.get_currency())
+    }
+}
+
+// A change in the best rate of a watched pair
+#[derive(Clone)]
+pub struct RatePoint {
+    datetime: u64,  // millisecond
+    pair: String,
+    best_rate: f64,
+    hops: usize
+}
+
+impl RatePoint {
+    pub fn get_datetime(&self) -> u64 {
+        self.datetime
+    }
+
+    pub fn get_pair(&self) -> &str {
+        &self.pair
+    }
+
+    pub fn get_best_rate(&self) -> f64 {
+        self.best_rate
+    }
+
+    pub fn get_hops(&self) -> usize {
+        self.hops
+    }
+}
+
+pub struct WatchList {
+    pairs: Vec<WatchedPair>
+}
+
+impl Default for WatchList {
+    fn default() -> Self {
+        WatchList::new()
+    }
+}
+
+impl WatchList {
+    pub fn new() -> WatchList {
+        WatchList {
+            pairs: Vec::new()
+        }
+    }
+
+    /// Parse a watch list where each line is
+    /// `<source_exchange> <source_currency> <destination_exchange> <destination_currency>`
+    /// Exchanges and currencies are normalized with `aliases` like the input
+    pub fn parse(content: &str, aliases: &Aliases) -> Result<WatchList, String> {
+        let mut watch_list = WatchList::new();
+        for line in input::content_lines(content) {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.len() != 4 {
+                return Err(format!("Invalid watched pair \"{}\"", line));
+            }
+            let vertex = |exchange: &str, currency: &str| -> Result<Arc<Vertex>, String> {
+                let exchange = Exchange::new(aliases.exchange(exchange))?;
+                let currency = Currency::new(aliases.currency(currency))?;
+                Ok(Arc::new(Vertex::new(exchange, currency)))
+            };
+            match (vertex(tokens[0], tokens[1]), vertex(tokens[2], tokens[3])) {
+                (Ok(from_vertex), Ok(to_vertex)) => watch_list.pairs.push(WatchedPair::new(from_vertex, to_vertex)),
+                (Err(e), _) | (_, Err(e)) => return Err(format!("Invalid watched pair \"{}\": {}", line, e))
+            }
+        }
+        Ok(watch_list)
+    }
+
+    pub fn get_pairs(&self) -> &[WatchedPair] {
+        &self.pairs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    // Carry over the last observed best rate and path of the pairs also watched by `previous`, so that only pairs
+    // new to the list are reported by the next `update` as if their rate changed
+    pub fn keep_rates(&mut self, previous: &WatchList) {
+        for pair in self.pairs.iter_mut() {
+            let previous_pair = previous.pairs.iter().find(|previous_pair| {
+                previous_pair.get_from_vertex() == pair.get_from_vertex()
+                    && previous_pair.get_to_vertex() == pair.get_to_vertex()
+            });
+            if let Some(previous_pair) = previous_pair {
+                pair.best_rate = previous_pair.best_rate;
+                pair.path = previous_pair.path.clone();
+            }
+        }
+    }
+
+    // Compare the best rate of every watched pair against the last observed rate
+    // and return the pairs whose rate changed. `graph_result` must have up to date best rates
+    pub fn update(&mut self, datetime: u64, graph_result: &GraphResult) -> Vec<RatePoint> {
+        let mut rate_points = Vec::new();
+        for pair in self.pairs.iter_mut() {
+            let best_rate = graph_result.get_best_rate(pair.get_from_vertex(), pair.get_to_vertex());
+            if best_rate == pair.get_best_rate() {
+                continue;
+            }
+            pair.best_rate = best_rate;
+            pair.path = graph_result.best_rate_path(pair.get_from_vertex(), pair.get_to_vertex()).unwrap_or_default();
+
+            let hops = pair.get_path().len().saturating_sub(1);
+            rate_points.push(RatePoint {
+                datetime, pair: pair.label(), best_rate, hops
+            });
+        }
+        rate_points
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alias::AliasMap;
+
+    #[test]
+    fn parse_watch_list() {
+        let watch_list = WatchList::parse("KRAKEN BTC GDAX USD\nGDAX ETH KRAKEN XBT\n",
+            &Aliases::new(AliasMap::parse("currency", "XBT BTC").unwrap(), AliasMap::new("exchange"))).unwrap();
+        assert_eq!(watch_list.get_pairs().len(), 2);
+        assert_eq!(watch_list.get_pairs()[0].label(), "KRAKEN:BTC->GDAX:USD");
+
+        assert_eq!(watch_list.get_pairs()[1].label(), "GDAX:ETH->KRAKEN:BTC");
+
+        assert!(WatchList::parse("KRAKEN BTC GDAX", &Aliases::none()).is_err());
+    }
+
+    #[test]
+    fn keep_rates_on_reload() {
+        let mut engine = crate::testutil::EngineBuilder::new()
+            .quote("KRAKEN", "BTC", "USD", 1000.0, 0.0009)
+            .build();
+        engine.find_best_rates();
+        let mut watch_list = WatchList::parse("KRAKEN BTC KRAKEN USD\n", &Aliases::none()).unwrap();
+        assert_eq!(watch_list.update(1, engine.get_graph_result()).len(), 1);
+
+        let mut reloaded = WatchList::parse("KRAKEN BTC KRAKEN USD\nKRAKEN USD KRAKEN BTC\n", &Aliases::none()).unwrap();
+        reloaded.keep_rates(&watch_list);
+        let rate_points = reloaded.update(2, engine.get_graph_result());
+        assert_eq!(rate_points.iter().map(RatePoint::get_pair).collect::<Vec<_>>(), ["KRAKEN:USD->KRAKEN:BTC"]);
+    }
+}